@@ -0,0 +1,119 @@
+//! Times small synthetic programs on each backend, to catch regressions in
+//! the run loops and compare the ways of dispatching instructions.
+//!
+//! Run with `cargo bench`. Arguments filter the benchmarks by name, as in
+//! `cargo bench -- churn`. Criterion reports throughput in instructions
+//! executed per second, and the change since the last run.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use um_32::{program::ProgramBuilder, Backend, Machine};
+
+const ZERO: u32 = 0;
+const N: u32 = 1;
+const ONE: u32 = 2;
+
+// A loop of arithmetic and NAND, counting N down to zero.
+fn arithmetic(iterations: u32) -> Vec<u8> {
+    let mut p = ProgramBuilder::new();
+    p.load(N, 3, iterations);
+    p.ortho(ONE, 1);
+    let top = p.here();
+    p.mul(3, 3, N);
+    p.add(4, 4, 3);
+    p.nand(5, 4, 3);
+    p.div(6, 5, ONE);
+    p.sub(N, N, ONE, 7);
+    p.jump_if(N, top, ZERO, [6, 7]);
+    p.halt();
+    p.build_image()
+}
+
+// Allocates, amends and abandons an array each time around the loop.
+fn churn(iterations: u32) -> Vec<u8> {
+    let mut p = ProgramBuilder::new();
+    p.load(N, 3, iterations);
+    p.ortho(ONE, 1);
+    p.ortho(4, 64);
+    let top = p.here();
+    p.alloc(3, 4);
+    p.amend(3, ONE, N);
+    p.index(5, 3, ONE);
+    p.abandon(3);
+    p.sub(N, N, ONE, 7);
+    p.jump_if(N, top, ZERO, [6, 7]);
+    p.halt();
+    p.build_image()
+}
+
+// Copies itself to array 1 and then loops by loading that copy as the
+// program each time around, so every iteration replaces array 0.
+fn load_storm(iterations: u32) -> Vec<u8> {
+    let mut p = ProgramBuilder::new();
+    let end = p.label();
+    p.load(N, 3, iterations);
+    p.ortho(ONE, 1);
+    // r4 = length, r5 = copy, r3 = offset counting down from the length.
+    p.ortho_label(4, end);
+    p.alloc(5, 4);
+    p.cmov(3, 4, 4);
+    let copy = p.here();
+    p.sub(3, 3, ONE, 7);
+    p.index(6, ZERO, 3);
+    p.amend(5, 3, 6);
+    p.jump_if(3, copy, ZERO, [6, 7]);
+    let top = p.here();
+    p.sub(N, N, ONE, 7);
+    let again = p.label();
+    p.jump_if(N, again, ZERO, [6, 7]);
+    p.halt();
+    p.bind(again);
+    p.ortho_label(6, top);
+    p.load_program(5, 6);
+    p.bind(end);
+    p.build_image()
+}
+
+fn backends() -> Vec<(&'static str, Backend)> {
+    vec![
+        ("interpreter", Backend::Interpreter),
+        ("decoded", Backend::Decoded),
+        ("threaded", Backend::Threaded),
+    ]
+}
+
+fn executed(image: &[u8]) -> u64 {
+    let mut machine = Machine::builder().build();
+    machine.extend_from(image).unwrap();
+    machine.run().unwrap();
+    machine.executed()
+}
+
+fn bench(c: &mut Criterion) {
+    let programs = [
+        ("arithmetic", arithmetic(200_000)),
+        ("churn", churn(50_000)),
+        ("load-storm", load_storm(20_000)),
+    ];
+    for (program, image) in &programs {
+        let mut group = c.benchmark_group(*program);
+        group.throughput(Throughput::Elements(executed(image)));
+        group.sample_size(20);
+        for (name, backend) in backends() {
+            group.bench_function(name, |b| {
+                b.iter_batched(
+                    || {
+                        let mut machine = Machine::builder().backend(backend).build();
+                        machine.extend_from(&image[..]).unwrap();
+                        machine
+                    },
+                    |mut machine| machine.run().unwrap(),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,489 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    process::{Command, Stdio},
+};
+
+use um_core::{Error, Machine, Stop};
+
+use crate::{charset::Charset, parse_u32};
+
+/// Something done when the machine reaches a breakpoint, after which it
+/// carries on, so that breakpoints can trace long runs without stopping them.
+#[derive(Clone)]
+pub enum Action {
+    /// Writes a message; see [`Format`].
+    Log(Format),
+    /// Adds one to the named counter.
+    Count(String),
+    /// Saves a snapshot to the path, which is a [`Format`] so that each hit
+    /// can go to its own file.
+    Snapshot(Format),
+    /// Runs a shell command with the machine's state in `UM_PC`,
+    /// `UM_EXECUTED` and `UM_R0` through `UM_R7`. A command that fails stops
+    /// the machine.
+    Exec(String),
+}
+
+impl Action {
+    /// Parses `log FORMAT`, `count NAME`, `snapshot PATH` or `exec COMMAND`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (kind, arg) = text.trim().split_once(' ').unwrap_or((text.trim(), ""));
+        let arg = arg.trim();
+        if arg.is_empty() {
+            return Err(format!(
+                "expected log, count, snapshot or exec and an argument, got {text:?}"
+            ));
+        }
+        match kind {
+            "log" => Ok(Action::Log(Format::parse(arg)?)),
+            "count" => Ok(Action::Count(arg.to_string())),
+            "snapshot" => Ok(Action::Snapshot(Format::parse(arg)?)),
+            "exec" => Ok(Action::Exec(arg.to_string())),
+            _ => Err(format!("unknown breakpoint action {kind:?}")),
+        }
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Action::Log(format) => write!(f, "log {}", format.text),
+            Action::Count(name) => write!(f, "count {name}"),
+            Action::Snapshot(path) => write!(f, "snapshot {}", path.text),
+            Action::Exec(command) => write!(f, "exec {command}"),
+        }
+    }
+}
+
+/// Text with values from the machine filled in: `{pc}`, `{executed}`,
+/// `{r0}` through `{r7}`, and `{ID[OFFSET]}` for a platter, each optionally
+/// followed by `:x` for hex, `:d` for decimal or `:c` for a character shown
+/// as [`Charset`] does, e.g.
+/// `r3={r3:d} at {pc}`. Values are shown in hex by default, and the count
+/// of executed instructions in decimal. `{{` and `}}` stand for braces.
+#[derive(Clone)]
+pub struct Format {
+    text: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Clone)]
+enum Part {
+    Text(String),
+    Value(Value, Style),
+}
+
+#[derive(Clone, Copy)]
+enum Value {
+    Pc,
+    Executed,
+    Register(usize),
+    Platter(u32, u32),
+}
+
+#[derive(Clone, Copy)]
+enum Style {
+    Hex,
+    Decimal,
+    Char,
+}
+
+impl Format {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                literal.push(c);
+                rest = &rest[2..];
+            } else if c == '{' {
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| format!("unclosed {{ in {text:?}"))?;
+                parts.push(Part::Text(std::mem::take(&mut literal)));
+                parts.push(Self::value(&rest[1..end])?);
+                rest = &rest[end + 1..];
+            } else if c == '}' {
+                return Err(format!("unmatched }} in {text:?}"));
+            } else {
+                literal.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        parts.push(Part::Text(literal));
+        Ok(Self {
+            text: text.to_string(),
+            parts,
+        })
+    }
+
+    fn value(spec: &str) -> Result<Part, String> {
+        let (name, style) = match spec.split_once(':') {
+            Some((name, "x")) => (name, Some(Style::Hex)),
+            Some((name, "d")) => (name, Some(Style::Decimal)),
+            Some((name, "c")) => (name, Some(Style::Char)),
+            Some((_, style)) => return Err(format!("expected x, d or c after :, got {style:?}")),
+            None => (spec, None),
+        };
+        let name = name.trim();
+        let value = if name == "pc" {
+            Value::Pc
+        } else if name == "executed" {
+            Value::Executed
+        } else if let Some(r) = name
+            .strip_prefix('r')
+            .and_then(|r| r.parse().ok())
+            .filter(|r| *r < 8)
+        {
+            Value::Register(r)
+        } else {
+            let (array, offset) = name
+                .strip_suffix(']')
+                .and_then(|name| name.split_once('['))
+                .ok_or_else(|| format!("expected pc, executed, rN or ID[OFFSET], got {name:?}"))?;
+            Value::Platter(parse_u32(array.trim())?, parse_u32(offset.trim())?)
+        };
+        let default = match value {
+            Value::Executed => Style::Decimal,
+            _ => Style::Hex,
+        };
+        Ok(Part::Value(value, style.unwrap_or(default)))
+    }
+
+    pub fn expand(&self, machine: &Machine, charset: Charset) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            let (value, style) = match part {
+                Part::Text(text) => {
+                    out.push_str(text);
+                    continue;
+                }
+                Part::Value(value, style) => (value, style),
+            };
+            let value = match *value {
+                Value::Pc => machine.pc() as u64,
+                Value::Executed => machine.executed(),
+                Value::Register(r) => machine.registers()[r] as u64,
+                Value::Platter(array, offset) => {
+                    match machine.array(array).and_then(|a| a.get(offset as usize)) {
+                        Some(value) => *value as u64,
+                        None => {
+                            out.push('?');
+                            continue;
+                        }
+                    }
+                }
+            };
+            match style {
+                Style::Hex => out.push_str(&format!("{value:#x}")),
+                Style::Decimal => out.push_str(&value.to_string()),
+                Style::Char => charset.push(&mut out, u32::try_from(value).unwrap_or(!0)),
+            }
+        }
+        out
+    }
+}
+
+/// The actions attached to breakpoints, and the counters they keep.
+///
+/// Breakpoints with actions are set on the machine like any other; use
+/// [`Actions::run_until_stop`] in place of [`Machine::run_until_stop`] to
+/// carry them out. A breakpoint only stops the machine once its actions are
+/// done if it was also set with [`Actions::add_breakpoint`] or one of its
+/// commands failed.
+pub struct Actions {
+    at: BTreeMap<u32, Vec<Action>>,
+    stops: BTreeSet<u32>,
+    counters: BTreeMap<String, u64>,
+    charset: Charset,
+    log: Box<dyn FnMut(&str) + Send>,
+}
+
+impl Actions {
+    /// Logged messages are passed to `log`.
+    pub fn new(log: impl FnMut(&str) + Send + 'static) -> Self {
+        Self {
+            at: BTreeMap::new(),
+            stops: BTreeSet::new(),
+            counters: BTreeMap::new(),
+            charset: Charset::default(),
+            log: Box::new(log),
+        }
+    }
+
+    /// Sets a breakpoint that stops the machine.
+    pub fn add_breakpoint(&mut self, machine: &mut Machine, pc: u32) {
+        self.stops.insert(pc);
+        machine.add_breakpoint(pc);
+    }
+
+    pub fn add_action(&mut self, machine: &mut Machine, pc: u32, action: Action) {
+        self.at.entry(pc).or_default().push(action);
+        machine.add_breakpoint(pc);
+    }
+
+    /// Removes the breakpoint at `pc` and its actions, returning whether
+    /// there was one.
+    pub fn remove(&mut self, machine: &mut Machine, pc: u32) -> bool {
+        self.stops.remove(&pc);
+        self.at.remove(&pc);
+        machine.remove_breakpoint(pc)
+    }
+
+    /// Whether the machine stops at `pc` once the actions there are done.
+    pub fn stops(&self, pc: u32) -> bool {
+        self.stops.contains(&pc) || !self.at.contains_key(&pc)
+    }
+
+    pub fn actions(&self, pc: u32) -> &[Action] {
+        self.at.get(&pc).map_or(&[], Vec::as_slice)
+    }
+
+    /// How logged messages and snapshot paths show `:c` values.
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+
+    pub fn set_charset(&mut self, charset: Charset) {
+        self.charset = charset;
+    }
+
+    pub fn counters(&self) -> &BTreeMap<String, u64> {
+        &self.counters
+    }
+
+    /// Sets the named counter, defining it if need be, so that it is
+    /// reported even if nothing counts it.
+    pub fn set_counter(&mut self, name: &str, count: u64) {
+        self.counters.insert(name.to_string(), count);
+    }
+
+    /// Sets every counter back to zero.
+    pub fn reset_counters(&mut self) {
+        self.counters.values_mut().for_each(|count| *count = 0);
+    }
+
+    /// Runs until the machine stops for anything but a breakpoint whose
+    /// actions let it carry on.
+    pub fn run_until_stop(&mut self, machine: &mut Machine) -> Result<Stop, Error> {
+        loop {
+            let stop = machine.run_until_stop()?;
+            match stop {
+                Stop::Breakpoint { pc } if self.at.contains_key(&pc) => {
+                    if self.perform(machine, pc)? || self.stops(pc) {
+                        return Ok(stop);
+                    }
+                }
+                stop => return Ok(stop),
+            }
+        }
+    }
+
+    /// Like [`Actions::run_until_stop`], but also stops with a breakpoint
+    /// on reaching `pc`.
+    pub fn run_to(&mut self, machine: &mut Machine, pc: u32) -> Result<Stop, Error> {
+        let temporary = !machine.breakpoints().contains(&pc);
+        let added = self.stops.insert(pc);
+        if temporary {
+            machine.add_breakpoint(pc);
+        }
+        let res = self.run_until_stop(machine);
+        if added {
+            self.stops.remove(&pc);
+        }
+        if temporary {
+            machine.remove_breakpoint(pc);
+        }
+        res
+    }
+
+    // Carries out the actions at `pc`, returning whether one of them asked
+    // to stop.
+    fn perform(&mut self, machine: &Machine, pc: u32) -> Result<bool, Error> {
+        let mut stop = false;
+        for action in &self.at[&pc] {
+            match action {
+                Action::Log(format) => (self.log)(&format.expand(machine, self.charset)),
+                Action::Count(name) => *self.counters.entry(name.clone()).or_default() += 1,
+                Action::Snapshot(path) => {
+                    machine.save_snapshot(path.expand(machine, self.charset))?
+                }
+                Action::Exec(command) => {
+                    let mut command_line = Command::new("sh");
+                    command_line
+                        .arg("-c")
+                        .arg(command)
+                        .stdin(Stdio::null())
+                        .env("UM_PC", machine.pc().to_string())
+                        .env("UM_EXECUTED", machine.executed().to_string());
+                    for (r, value) in machine.registers().iter().enumerate() {
+                        command_line.env(format!("UM_R{r}"), value.to_string());
+                    }
+                    let status = command_line.status()?;
+                    if !status.success() {
+                        (self.log)(&format!("exec {command}: {status} at pc={pc:#x}"));
+                        stop = true;
+                    }
+                }
+            }
+        }
+        Ok(stop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn format_error(text: &str) -> String {
+        match Format::parse(text) {
+            Ok(_) => panic!("{text:?} parsed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn formats() {
+        let mut machine = Machine::default();
+        machine
+            .extend_from(&[0xd2, 0, 0, 0x41, 0x70, 0, 0, 0][..])
+            .unwrap();
+        machine.set_pc(0x1a);
+        machine.registers_mut()[3] = 255;
+        machine.registers_mut()[4] = 0xe9;
+        let expand = |text: &str, charset| Format::parse(text).unwrap().expand(&machine, charset);
+
+        assert_eq!(
+            expand("at {pc} after {executed}", Charset::Ascii),
+            "at 0x1a after 0"
+        );
+        assert_eq!(
+            expand("{r3:d} {r3:x} {r3} {pc:d}", Charset::Ascii),
+            "255 0xff 0xff 26"
+        );
+        assert_eq!(
+            expand("{0[0]} {0[0x1]:d}", Charset::Ascii),
+            "0xd2000041 1879048192"
+        );
+        assert_eq!(expand("{ 0 [ 3 ] } {9[0]}", Charset::Ascii), "? ?");
+        assert_eq!(expand("{0[0]:c}", Charset::Ascii), ".");
+        assert_eq!(expand("{r4:c}", Charset::Ascii), "\\xe9");
+        assert_eq!(expand("{r4:c}", Charset::Latin1), "\u{e9}");
+        assert_eq!(expand("{{r3}} = {r3:d}}}", Charset::Ascii), "{r3} = 255}");
+        assert_eq!(expand("", Charset::Ascii), "");
+    }
+
+    #[test]
+    fn bad_formats() {
+        assert_eq!(format_error("{pc"), r#"unclosed { in "{pc""#);
+        assert_eq!(format_error("pc}"), r#"unmatched } in "pc}""#);
+        assert_eq!(
+            format_error("{pc:q}"),
+            r#"expected x, d or c after :, got "q""#
+        );
+        assert_eq!(
+            format_error("{r8}"),
+            r#"expected pc, executed, rN or ID[OFFSET], got "r8""#
+        );
+        assert_eq!(
+            format_error("{}"),
+            r#"expected pc, executed, rN or ID[OFFSET], got """#
+        );
+        assert!(format_error("{0[x]}").starts_with("invalid digit"));
+    }
+
+    #[test]
+    fn parse_actions() {
+        for text in [
+            "log r1={r1:d}",
+            "count loops",
+            "snapshot /tmp/s-{executed}",
+            "exec echo hi",
+        ] {
+            assert_eq!(Action::parse(text).unwrap().to_string(), text);
+        }
+        assert_eq!(
+            Action::parse("  count  loops ").unwrap().to_string(),
+            "count loops"
+        );
+        for text in ["log", "count ", ""] {
+            assert!(Action::parse(text)
+                .err()
+                .unwrap()
+                .starts_with("expected log, count, snapshot or exec"));
+        }
+        assert_eq!(
+            Action::parse("jump 0x10").err().unwrap(),
+            r#"unknown breakpoint action "jump""#
+        );
+        assert_eq!(
+            Action::parse("log {r9}").err().unwrap(),
+            r#"expected pc, executed, rN or ID[OFFSET], got "r9""#
+        );
+    }
+
+    #[test]
+    fn actions() {
+        // ORTHO r1, 1 ; ORTHO r1, 2 ; ORTHO r1, 3 ; HALT
+        let program = [0xd200_0001u32, 0xd200_0002, 0xd200_0003, 0x7000_0000];
+        let image: Vec<u8> = program.iter().flat_map(|w| w.to_be_bytes()).collect();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let logged = log.clone();
+        let mut actions = Actions::new(move |line| logged.lock().unwrap().push(line.to_string()));
+        let mut machine = Machine::default();
+        machine.extend_from(&image[..]).unwrap();
+        actions.add_action(&mut machine, 0, Action::parse("log entry {pc}").unwrap());
+        actions.add_action(&mut machine, 1, Action::parse("log r1={r1:d}").unwrap());
+        actions.add_action(&mut machine, 1, Action::parse("count ones").unwrap());
+        actions.add_action(&mut machine, 2, Action::parse("count twos").unwrap());
+        actions.add_breakpoint(&mut machine, 2);
+        actions.set_counter("never", 0);
+
+        // Actions alone carry on, even at the first instruction; with a
+        // breakpoint as well they stop.
+        assert_eq!(
+            actions.run_until_stop(&mut machine).unwrap(),
+            Stop::Breakpoint { pc: 2 }
+        );
+        assert_eq!(*log.lock().unwrap(), ["entry 0x0", "r1=1"]);
+        assert_eq!(actions.counters()["ones"], 1);
+        assert_eq!(actions.counters()["twos"], 1);
+        assert_eq!(actions.counters()["never"], 0);
+        assert!(!actions.stops(1) && actions.stops(2) && actions.stops(3));
+        assert_eq!(actions.run_until_stop(&mut machine).unwrap(), Stop::Halt);
+
+        actions.reset_counters();
+        assert_eq!(actions.counters()["ones"], 0);
+        assert!(actions.remove(&mut machine, 1));
+        assert!(actions.actions(1).is_empty());
+        assert!(!actions.remove(&mut machine, 1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_actions() {
+        // ORTHO r1, 7 ; ORTHO r2, 0 ; HALT
+        let program = [0xd200_0007u32, 0xd400_0000, 0x7000_0000];
+        let image: Vec<u8> = program.iter().flat_map(|w| w.to_be_bytes()).collect();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let logged = log.clone();
+        let mut actions = Actions::new(move |line| logged.lock().unwrap().push(line.to_string()));
+        let mut machine = Machine::default();
+        machine.extend_from(&image[..]).unwrap();
+        let check = r#"exec test "$UM_PC $UM_EXECUTED $UM_R1" = "1 1 7""#;
+        actions.add_action(&mut machine, 1, Action::parse(check).unwrap());
+        actions.add_action(&mut machine, 2, Action::parse("exec exit 3").unwrap());
+
+        // A command that fails stops the machine.
+        assert_eq!(
+            actions.run_until_stop(&mut machine).unwrap(),
+            Stop::Breakpoint { pc: 2 }
+        );
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 1);
+        assert!(log[0].starts_with("exec exit 3: ") && log[0].ends_with(" at pc=0x2"));
+    }
+}
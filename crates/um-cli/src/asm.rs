@@ -0,0 +1,17 @@
+use std::path::PathBuf;
+
+use um_tools::asm;
+
+use crate::Error;
+
+pub fn asm(source: PathBuf, output: PathBuf) -> Result<(), Error> {
+    let text = std::fs::read_to_string(&source)?;
+    let image = asm::assemble_image(&text).map_err(|e| match e {
+        asm::Error { line, message } if line > 0 => {
+            Error::InvalidArgument(format!("{}:{line}: {message}", source.display()))
+        }
+        e => e.into(),
+    })?;
+    std::fs::write(output, image)?;
+    Ok(())
+}
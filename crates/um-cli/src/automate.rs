@@ -0,0 +1,314 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::script::{anchor, quote, unquote};
+
+/// Automation scripts, read by `--automate`: like `expect`, they watch the
+/// program's output and answer it, without waiting for it to ask for
+/// input.
+///
+/// Each line is a comment starting with `#`, `expect "TEXT"` to wait until
+/// the program prints TEXT, `send "TEXT"` to queue TEXT as input, or
+/// `on "TEXT" send "REPLY"` to queue REPLY every time the program prints
+/// TEXT from then on:
+///
+/// ```text
+/// expect "login: "
+/// send "guest\n"
+/// on "--More--" send " "
+/// expect "% "
+/// send "ls\n"
+/// ```
+///
+/// If the program asks for input while a step is still waiting for its
+/// text, the run fails. Once the steps are done, input comes from the
+/// terminal again, with the `on` rules still answering. What is sent is
+/// shown with the output, as a terminal echoing it would.
+#[derive(Clone)]
+pub struct Automation(Arc<Mutex<State>>);
+
+struct State {
+    // What the line numbers of the steps are lines of, for errors.
+    source: String,
+    steps: VecDeque<(usize, Step)>,
+    rules: Vec<(Vec<u8>, Vec<u8>)>,
+    // The end of the output, as long as the longest text watched for.
+    seen: Vec<u8>,
+    longest: usize,
+    // The output since the last `expect` matched, for errors.
+    recent: Vec<u8>,
+    // Input queued, and input consumed but not shown yet.
+    pending: VecDeque<u8>,
+    echo: Vec<u8>,
+}
+
+pub enum Step {
+    Expect(Vec<u8>),
+    Send(Vec<u8>),
+    On(Vec<u8>, Vec<u8>),
+}
+
+// How much of the output since the last `expect` matched errors show.
+const RECENT_LIMIT: usize = 4096;
+
+impl Automation {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::parse(&text).map_err(|e| format!("{}:{e}", path.display()))
+    }
+
+    /// Carries out `steps`, each with the line of `source` it came from.
+    pub fn new(source: String, steps: impl IntoIterator<Item = (usize, Step)>) -> Self {
+        let steps: VecDeque<_> = steps.into_iter().collect();
+        let longest = steps
+            .iter()
+            .map(|(_, step)| match step {
+                Step::Expect(text) | Step::On(text, _) => text.len(),
+                Step::Send(_) => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        let mut state = State {
+            source,
+            steps,
+            rules: Vec::new(),
+            seen: Vec::new(),
+            longest,
+            recent: Vec::new(),
+            pending: VecDeque::new(),
+            echo: Vec::new(),
+        };
+        state.advance();
+        Self(Arc::new(Mutex::new(state)))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let expected = || format!("{}: expected a quoted string", i + 1);
+            let (word, arg) = line.split_once(' ').unwrap_or((line, ""));
+            let step = match word {
+                "expect" => Step::Expect(unquote(arg.trim()).ok_or_else(expected)?),
+                "send" => Step::Send(unquote(arg.trim()).ok_or_else(expected)?),
+                "on" => {
+                    let Some((text, reply)) = split_on(arg.trim()) else {
+                        return Err(format!("{}: expected on \"TEXT\" send \"REPLY\"", i + 1));
+                    };
+                    Step::On(
+                        unquote(text).ok_or_else(expected)?,
+                        unquote(reply).ok_or_else(expected)?,
+                    )
+                }
+                _ => return Err(format!("{}: unknown step {word:?}", i + 1)),
+            };
+            if matches!(&step, Step::Expect(text) | Step::On(text, _) if text.is_empty()) {
+                return Err(format!("{}: nothing to wait for", i + 1));
+            }
+            steps.push((i + 1, step));
+        }
+        Ok(Self::new("automation".into(), steps))
+    }
+
+    /// Takes input from the script, then from `inner`.
+    pub fn input<R: Read>(&self, inner: R) -> AutomatedIn<R> {
+        AutomatedIn {
+            inner,
+            automation: self.clone(),
+        }
+    }
+
+    /// Watches what is written to `inner`.
+    pub fn output<W: Write>(&self, inner: W) -> AutomatedOut<W> {
+        AutomatedOut {
+            inner,
+            automation: self.clone(),
+        }
+    }
+}
+
+// Splits `"TEXT" send "REPLY"`, with quotes left on.
+fn split_on(arg: &str) -> Option<(&str, &str)> {
+    // The closing quote is the first not escaped by a backslash.
+    let mut escaped = false;
+    let end = arg.char_indices().skip(1).find_map(|(i, c)| {
+        let closes = c == '"' && !escaped;
+        escaped = c == '\\' && !escaped;
+        closes.then_some(i)
+    })?;
+    let (text, rest) = arg.split_at(end + 1);
+    let reply = rest.trim_start().strip_prefix("send")?;
+    Some((text, reply.trim()))
+}
+
+impl State {
+    // Carries out the steps up to the next `expect`.
+    fn advance(&mut self) {
+        while let Some((_, step)) = self.steps.front() {
+            match step {
+                Step::Expect(_) => return,
+                Step::Send(text) => self.pending.extend(text),
+                Step::On(text, reply) => self.rules.push((text.clone(), reply.clone())),
+            }
+            self.steps.pop_front();
+        }
+    }
+
+    fn watch(&mut self, output: &[u8]) {
+        for &byte in output {
+            self.seen.push(byte);
+            if self.seen.len() > self.longest {
+                self.seen.remove(0);
+            }
+            self.recent.push(byte);
+            for (text, reply) in &self.rules {
+                if self.seen.ends_with(text) {
+                    self.pending.extend(reply);
+                }
+            }
+            if let Some((_, Step::Expect(text))) = self.steps.front() {
+                if self.seen.ends_with(text) {
+                    self.steps.pop_front();
+                    self.recent.clear();
+                    self.advance();
+                }
+            }
+        }
+        if self.recent.len() > RECENT_LIMIT {
+            self.recent.drain(..self.recent.len() - RECENT_LIMIT);
+        }
+    }
+}
+
+pub struct AutomatedIn<R> {
+    inner: R,
+    automation: Automation,
+}
+
+impl<R: Read> Read for AutomatedIn<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.automation.0.lock().unwrap();
+        if !state.pending.is_empty() {
+            let n = buf.len().min(state.pending.len());
+            for (i, byte) in state.pending.drain(..n).enumerate() {
+                buf[i] = byte;
+            }
+            state.echo.extend_from_slice(&buf[..n]);
+            return Ok(n);
+        }
+        if let Some((line, Step::Expect(text))) = state.steps.front() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} line {line}: the program wants input while waiting for {}, having printed {}",
+                    state.source,
+                    quote(text),
+                    quote(anchor(&state.recent))
+                ),
+            ));
+        }
+        drop(state);
+        self.inner.read(buf)
+    }
+}
+
+pub struct AutomatedOut<W> {
+    inner: W,
+    automation: Automation,
+}
+
+impl<W: Write> AutomatedOut<W> {
+    // Shows the input sent since the last output, which the program has
+    // consumed by the time it writes again.
+    fn show_sent(&mut self) -> io::Result<()> {
+        let echo = std::mem::take(&mut self.automation.0.lock().unwrap().echo);
+        self.inner.write_all(&echo)
+    }
+}
+
+impl<W: Write> Write for AutomatedOut<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.show_sent()?;
+        let n = self.inner.write(buf)?;
+        self.automation.0.lock().unwrap().watch(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.show_sent()?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        match Automation::parse(text) {
+            Ok(_) => panic!("{text:?} parsed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn bad_scripts() {
+        assert_eq!(error("# hello\nwait \"x\""), "2: unknown step \"wait\"");
+        assert_eq!(error("send x"), "1: expected a quoted string");
+        assert_eq!(error("expect \"x"), "1: expected a quoted string");
+        assert_eq!(error("expect \"\""), "1: nothing to wait for");
+        assert_eq!(error("on \"\" send \"x\""), "1: nothing to wait for");
+        assert_eq!(
+            error("on \"x\" \"y\""),
+            "1: expected on \"TEXT\" send \"REPLY\""
+        );
+        assert_eq!(error("on \"x\" send y"), "1: expected a quoted string");
+    }
+
+    #[test]
+    fn automation() {
+        let script =
+            "send \"a\"\nexpect \"login: \"\nsend \"guest\\n\"\non \"--More--\" send \" \"\n";
+        let automation = Automation::parse(script).unwrap();
+        let mut input = automation.input(&b"typed"[..]);
+        let mut output = automation.output(Vec::new());
+        let mut buf = [0; 16];
+
+        // Sends before the first expect are queued at once.
+        assert_eq!(input.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"a");
+        output.write_all(b"welcome\nlog").unwrap();
+        let e = input.read(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            e.to_string(),
+            "automation line 2: the program wants input while waiting for \"login: \", having printed \"log\""
+        );
+
+        // The expect matches across writes, and queues what follows it.
+        output.write_all(b"in: ").unwrap();
+        assert_eq!(input.read(&mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"guest\n");
+
+        // The rules answer every time, and the terminal comes after.
+        for _ in 0..2 {
+            output.write_all(b"--More--").unwrap();
+            assert_eq!(input.read(&mut buf).unwrap(), 1);
+            assert_eq!(&buf[..1], b" ");
+        }
+        assert_eq!(input.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"typed");
+
+        // What was sent shows up before the next output, or on a flush.
+        output.flush().unwrap();
+        assert_eq!(output.inner, b"awelcome\nlogin: guest\n--More-- --More-- ");
+    }
+}
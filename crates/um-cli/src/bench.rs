@@ -0,0 +1,103 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use um_core::{FlushPolicy, Machine};
+
+use crate::{run, BenchArgs, Error};
+
+// Collects the program's output for checking against the transcript.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Sample {
+    time: Duration,
+    executed: u64,
+}
+
+impl Sample {
+    fn mips(&self) -> f64 {
+        self.executed as f64 / self.time.as_secs_f64() / 1e6
+    }
+}
+
+pub fn bench(args: BenchArgs) -> Result<(), Error> {
+    // sandmark.umz is checked against sandmark.out next to it.
+    let expect = args.expect.clone().or_else(|| {
+        let path = args.machine.files.first()?.with_extension("out");
+        path.exists().then_some(path)
+    });
+    let expected = expect.as_ref().map(std::fs::read).transpose()?;
+
+    let mut samples = Vec::new();
+    for n in 1..=args.repeat.max(1) {
+        let capture = Capture::default();
+        let builder = Machine::builder()
+            .stdout(capture.clone())
+            .flush_policy(FlushPolicy::Halt);
+        let mut machine = run::load(&args.machine, builder)?;
+        let start = Instant::now();
+        machine.run()?;
+        let sample = Sample {
+            time: start.elapsed(),
+            executed: machine.executed(),
+        };
+        println!(
+            "run {n}: {:.3}s, {} instructions, {:.1} MIPS",
+            sample.time.as_secs_f64(),
+            sample.executed,
+            sample.mips()
+        );
+        if let (Some(expected), Some(path)) = (&expected, &expect) {
+            check(&capture.0.lock().unwrap(), expected, path)?;
+        }
+        samples.push(sample);
+    }
+    match &expect {
+        Some(path) => println!("output matches {}", path.display()),
+        None => println!("output not checked: no transcript given with --expect"),
+    }
+    if samples.len() > 1 {
+        samples.sort_by_key(|s| s.time);
+        let [min, median, max] = [0, samples.len() / 2, samples.len() - 1].map(|i| &samples[i]);
+        println!(
+            "min {:.3}s ({:.1} MIPS), median {:.3}s ({:.1} MIPS), max {:.3}s ({:.1} MIPS)",
+            min.time.as_secs_f64(),
+            min.mips(),
+            median.time.as_secs_f64(),
+            median.mips(),
+            max.time.as_secs_f64(),
+            max.mips()
+        );
+    }
+    Ok(())
+}
+
+fn check(output: &[u8], expected: &[u8], path: &Path) -> Result<(), Error> {
+    if output == expected {
+        return Ok(());
+    }
+    let at = output
+        .iter()
+        .zip(expected)
+        .position(|(a, b)| a != b)
+        .unwrap_or(output.len().min(expected.len()));
+    Err(Error::InvalidArgument(format!(
+        "output differs from {} at byte {at}",
+        path.display()
+    )))
+}
@@ -0,0 +1,169 @@
+//! `run --cast`: the session recorded as an asciinema v2 cast, for
+//! `asciinema play` and the web player.
+//!
+//! The file is a JSON header followed by a line for each write of console
+//! output, `[seconds, "o", "text"]`. Input is only shown if the program
+//! echoes it, or with --cast-input, which records it as output as it comes
+//! in from the console, a line at a time from a terminal, the way the
+//! terminal echoing it showed it.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{run, Error};
+
+#[derive(Clone)]
+pub struct Cast(Arc<Mutex<Recording>>);
+
+struct Recording {
+    out: BufWriter<File>,
+    start: Instant,
+    // The start of a UTF-8 sequence the next write completes.
+    partial: Vec<u8>,
+}
+
+impl Cast {
+    pub fn create(path: &Path) -> Result<Cast, Error> {
+        let mut out = BufWriter::new(File::create(path).map_err(run::naming(path))?);
+        let (height, width) = console::Term::stdout().size_checked().unwrap_or((24, 80));
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        write!(
+            out,
+            r#"{{"version":2,"width":{width},"height":{height},"timestamp":{timestamp}"#
+        )?;
+        if let Ok(term) = std::env::var("TERM") {
+            write!(out, r#","env":{{"TERM":"{}"}}"#, escape(&term))?;
+        }
+        writeln!(out, "}}")?;
+        Ok(Cast(Arc::new(Mutex::new(Recording {
+            out,
+            start: Instant::now(),
+            partial: Vec::new(),
+        }))))
+    }
+
+    /// Records what is written to `inner`.
+    pub fn output<W: Write>(&self, inner: W) -> CastOut<W> {
+        CastOut {
+            inner,
+            cast: self.clone(),
+        }
+    }
+
+    /// Records what is read from `inner`.
+    pub fn input<R: Read>(&self, inner: R) -> CastIn<R> {
+        CastIn {
+            inner,
+            cast: self.clone(),
+        }
+    }
+
+    fn record(&self, new: &[u8]) -> io::Result<()> {
+        let mut recording = self.0.lock().unwrap();
+        let time = recording.start.elapsed().as_secs_f64();
+        let mut bytes = std::mem::take(&mut recording.partial);
+        bytes.extend_from_slice(new);
+        let text = decode(&mut bytes);
+        recording.partial = bytes;
+        if text.is_empty() {
+            return Ok(());
+        }
+        writeln!(recording.out, r#"[{time:.6},"o","{}"]"#, escape(&text))
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if let Err(e) = self.out.flush() {
+            eprintln!("um-32: could not finish the cast: {e}");
+        }
+    }
+}
+
+pub struct CastOut<W> {
+    inner: W,
+    cast: Cast,
+}
+
+impl<W: Write> Write for CastOut<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.cast.record(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct CastIn<R> {
+    inner: R,
+    cast: Cast,
+}
+
+impl<R: Read> Read for CastIn<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.cast.record(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+// Takes the text from the start of `bytes`, leaving an incomplete UTF-8
+// sequence at the end, and replacing invalid bytes with U+FFFD. Newlines
+// become CRLF, as a terminal shows them.
+fn decode(bytes: &mut Vec<u8>) -> String {
+    let mut text = String::new();
+    let mut rest = &bytes[..];
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                rest = &[];
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap());
+                match e.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        rest = &after[len..];
+                    }
+                    None => {
+                        rest = after;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    *bytes = rest.to_vec();
+    text.replace("\r\n", "\n").replace('\n', "\r\n")
+}
+
+/// Escapes `text` for use inside a JSON string.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c < ' ' || c == '\u{7f}' => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,50 @@
+use std::fmt::Write;
+
+use clap::ValueEnum;
+
+/// How platters holding characters are shown in dumps.
+///
+/// Printable ASCII is shown as itself, control characters in caret
+/// notation (`^J` for a newline, `^?` for DEL), and platters too large to
+/// be a byte as `.`, so that a dump never sends the terminal anything but
+/// printable ASCII, whatever the data.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Charset {
+    /// Show bytes above 0x7f in hex, as `\xe9`
+    #[default]
+    Ascii,
+    /// Show bytes 0xa0 to 0xff as the Latin-1 characters they encode, and
+    /// the rest above 0x7f in hex
+    Latin1,
+}
+
+impl Charset {
+    /// Appends platter `value` to `out`.
+    pub fn push(self, out: &mut String, value: u32) {
+        let Ok(byte) = u8::try_from(value) else {
+            out.push('.');
+            return;
+        };
+        match byte {
+            b' '..=b'~' => out.push(byte as char),
+            0x00..=0x1f => {
+                out.push('^');
+                out.push((byte + 0x40) as char);
+            }
+            0x7f => out.push_str("^?"),
+            0xa0..=0xff if self == Charset::Latin1 => out.push(byte as char),
+            _ => {
+                let _ = write!(out, "\\x{byte:02x}");
+            }
+        }
+    }
+
+    /// Shows `values` as text, one character or escape per platter.
+    pub fn show(self, values: &[u32]) -> String {
+        let mut out = String::new();
+        for value in values {
+            self.push(&mut out, *value);
+        }
+        out
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+#[derive(Default)]
+pub struct Progress {
+    pub written: usize,
+    // The byte written where the expected output has another, or nothing.
+    pub diverged: Option<u8>,
+}
+
+/// Checks each byte of output against what is expected as it is written,
+/// failing the write at the first that differs, so that with
+/// [`FlushPolicy::Byte`](um_core::FlushPolicy::Byte) the Output
+/// instruction that wrote it stops the machine.
+pub struct Compare {
+    pub expected: Vec<u8>,
+    pub progress: Arc<Mutex<Progress>>,
+}
+
+impl Write for Compare {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut progress = self.progress.lock().unwrap();
+        for &byte in buf {
+            if self.expected.get(progress.written) != Some(&byte) {
+                progress.diverged = Some(byte);
+                return Err(io::Error::other("output diverged from what was expected"));
+            }
+            progress.written += 1;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A byte of output for reports, e.g. `0x41 'A'`.
+pub fn show(byte: u8) -> String {
+    format!("{:#04x} '{}'", byte, byte.escape_ascii())
+}
@@ -0,0 +1,17 @@
+use std::path::{Path, PathBuf};
+
+use um_tools::{compile, disasm};
+
+use crate::Error;
+
+pub fn compile(file: PathBuf, output: PathBuf, stages: &[PathBuf]) -> Result<(), Error> {
+    let read = |path: &Path| Ok::<_, Error>(disasm::image_words(&std::fs::read(path)?));
+    let program = read(&file)?;
+    let stages = stages
+        .iter()
+        .map(|path| read(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let stages: Vec<&[u32]> = stages.iter().map(Vec::as_slice).collect();
+    std::fs::write(output, compile::compile(&program, &stages))?;
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use crate::parse_u32;
+
+/// A breakpoint condition compiled for [`um_core::Machine::add_condition`].
+pub type Condition = Box<dyn FnMut(u32, &[u32; 8]) -> bool + Send>;
+
+/// Compiles a condition such as `pc == 0x1234 && r3 == 0`.
+///
+/// Conditions compare `pc`, `r0` through `r7` and numbers with `==`, `!=`,
+/// `<`, `<=`, `>` and `>=`, combine comparisons with `&&`, `||`, `!` and
+/// parentheses, and may use `rN changes` to hold whenever register N differs
+/// from the last time the condition was checked.
+pub fn compile(text: &str) -> Result<Condition, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let condition = parser.or()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(condition),
+        Some(token) => Err(format!("unexpected {token:?} in condition")),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Pc,
+    Register(usize),
+    Number(u32),
+}
+
+impl Operand {
+    #[inline(always)]
+    fn value(self, pc: u32, registers: &[u32; 8]) -> u32 {
+        match self {
+            Operand::Pc => pc,
+            Operand::Register(r) => registers[r],
+            Operand::Number(n) => n,
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<&str, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or("unexpected end of condition")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    // Both sides of && and || are always checked, so that `changes` sees
+    // every instruction wherever it appears.
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some("||") {
+            self.pos += 1;
+            let mut right = self.and()?;
+            left = Box::new(move |pc, regs| left(pc, regs) | right(pc, regs));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut left = self.unary()?;
+        while self.peek() == Some("&&") {
+            self.pos += 1;
+            let mut right = self.unary()?;
+            left = Box::new(move |pc, regs| left(pc, regs) & right(pc, regs));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Condition, String> {
+        match self.peek() {
+            Some("!") => {
+                self.pos += 1;
+                let mut inner = self.unary()?;
+                Ok(Box::new(move |pc, regs| !inner(pc, regs)))
+            }
+            Some("(") => {
+                self.pos += 1;
+                let inner = self.or()?;
+                match self.next()? {
+                    ")" => Ok(inner),
+                    token => Err(format!("expected ), got {token:?}")),
+                }
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Condition, String> {
+        let left = self.operand()?;
+        let op = self.next()?.to_string();
+        if op == "changes" {
+            let Operand::Register(r) = left else {
+                return Err("only registers can be watched for changes".to_string());
+            };
+            let mut last = None;
+            return Ok(Box::new(move |_, regs| {
+                let changed = last.is_some_and(|last| last != regs[r]);
+                last = Some(regs[r]);
+                changed
+            }));
+        }
+        let right = self.operand()?;
+        let compare: fn(u32, u32) -> bool = match op.as_str() {
+            "==" => |a, b| a == b,
+            "!=" => |a, b| a != b,
+            "<" => |a, b| a < b,
+            "<=" => |a, b| a <= b,
+            ">" => |a, b| a > b,
+            ">=" => |a, b| a >= b,
+            _ => return Err(format!("expected a comparison, got {op:?}")),
+        };
+        // The common shapes skip the operand dispatch.
+        Ok(match (left, right) {
+            (Operand::Pc, Operand::Number(n)) => Box::new(move |pc, _| compare(pc, n)),
+            (Operand::Register(r), Operand::Number(n)) => {
+                Box::new(move |_, regs| compare(regs[r], n))
+            }
+            _ => Box::new(move |pc, regs| compare(left.value(pc, regs), right.value(pc, regs))),
+        })
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        let token = self.next()?;
+        if token == "pc" {
+            return Ok(Operand::Pc);
+        }
+        if let Some(r) = token.strip_prefix('r') {
+            return match r.parse() {
+                Ok(r) if r < 8 => Ok(Operand::Register(r)),
+                _ => Err(format!("expected a register r0-r7, got {token:?}")),
+            };
+        }
+        parse_u32(token)
+            .map(Operand::Number)
+            .map_err(|_| format!("expected pc, a register or a number, got {token:?}"))
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_alphanumeric() || c == '_' {
+            rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len())
+        } else if ["==", "!=", "<=", ">=", "&&", "||"]
+            .iter()
+            .any(|op| rest.starts_with(op))
+        {
+            2
+        } else if "<>!()".contains(c) {
+            1
+        } else {
+            return Err(format!("unexpected {c:?} in condition"));
+        };
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        match compile(text) {
+            Ok(_) => panic!("{text:?} compiled"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn comparisons() {
+        let mut regs = [0; 8];
+        regs[3] = 7;
+        for (text, holds) in [
+            ("pc == 0x10", true),
+            ("pc == 16", true),
+            ("pc != 16", false),
+            ("r3 < 8", true),
+            ("r3 <= 7", true),
+            ("r3 > 7", false),
+            ("r3 >= 7", true),
+            ("r3 > pc", false),
+            ("pc>r3", true),
+            ("8 > r3", true),
+        ] {
+            assert_eq!(compile(text).unwrap()(16, &regs), holds, "{text}");
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let regs = [0; 8];
+        // && binds tighter than ||.
+        let mut or_and = compile("pc == 1 || pc == 2 && r0 == 5").unwrap();
+        assert!(or_and(1, &regs));
+        assert!(!or_and(2, &regs));
+        let mut grouped = compile("(pc == 1 || pc == 2) && r0 == 5").unwrap();
+        assert!(!grouped(1, &regs));
+        // ! applies to the comparison after it, not to pc alone.
+        let mut not = compile("!pc == 1 && !(r0 != 0)").unwrap();
+        assert!(not(2, &regs));
+        assert!(!not(1, &regs));
+        assert!(compile("!!(pc == 1)").unwrap()(1, &regs));
+    }
+
+    #[test]
+    fn changes() {
+        let mut regs = [0; 8];
+        let mut changes = compile("r2 changes").unwrap();
+        assert!(!changes(0, &regs));
+        assert!(!changes(0, &regs));
+        regs[2] = 1;
+        assert!(changes(0, &regs));
+        assert!(!changes(0, &regs));
+
+        // Both sides of || are checked, so the change is seen even while
+        // the left side holds.
+        let mut either = compile("pc == 0 || r2 changes").unwrap();
+        assert!(either(0, &regs));
+        regs[2] = 2;
+        assert!(either(0, &regs));
+        assert!(!either(1, &regs));
+        regs[2] = 3;
+        assert!(either(1, &regs));
+    }
+
+    #[test]
+    fn bad_conditions() {
+        assert_eq!(error(""), "unexpected end of condition");
+        assert_eq!(error("pc =="), "unexpected end of condition");
+        assert_eq!(error("pc = 1"), "unexpected '=' in condition");
+        assert_eq!(error("pc == 1)"), r#"unexpected ")" in condition"#);
+        assert_eq!(error("(pc == 1"), "unexpected end of condition");
+        assert_eq!(error("(pc == 1 pc"), r#"expected ), got "pc""#);
+        assert_eq!(error("r8 == 0"), r#"expected a register r0-r7, got "r8""#);
+        assert_eq!(
+            error("pc == sp"),
+            r#"expected pc, a register or a number, got "sp""#
+        );
+        assert_eq!(error("pc r1 2"), r#"expected a comparison, got "r1""#);
+        assert_eq!(
+            error("pc changes"),
+            "only registers can be watched for changes"
+        );
+        assert_eq!(error("pc == 1 &&"), "unexpected end of condition");
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    readline::LineEditor,
+    script::{Prompt, Recorder, Script},
+    signals,
+};
+
+pub const HELP: &str = "\
+console commands, typed at the start of an input line:
+  ~page          show the output up to the next input request in $PAGER
+  ~page N        show the next N output bytes in $PAGER
+  ~page /TEXT    show the output up to and including TEXT in $PAGER
+  ~edit ...      the same, but open the output in $EDITOR, from a file
+                 removed when it exits
+  ~record FILE   record the lines typed from now on as an input script
+  ~record        stop recording
+  ~~             send a line starting with ~
+  ~help          show this list
+";
+
+/// Sits between the machine and the terminal: interprets `~` commands in
+/// console input, diverts captured program output to a pager or editor,
+/// replays input scripts, records typed input, and lets lines be edited
+/// before the machine reads them.
+///
+/// The machine reads input through [`Console::stdin`] and writes output
+/// through [`Console::stdout`]; the two share the state below.
+#[derive(Clone)]
+pub struct Console {
+    shared: Arc<Mutex<Shared>>,
+    // Whether the last byte written to the terminal was not a newline.
+    mid_line: Arc<AtomicBool>,
+}
+
+struct Shared {
+    commands: bool,
+    capture: Option<Capture>,
+    script: Option<Script>,
+    recorder: Option<Recorder>,
+    editor: Option<LineEditor>,
+    // The shell prompt that ~record anchors on.
+    prompt: Prompt,
+    // Output since the last line of input, for scripts and recording.
+    recent: Vec<u8>,
+}
+
+// How much of the output since the last input is kept for scripts.
+const RECENT_LIMIT: usize = 64 * 1024;
+
+struct Capture {
+    until: Until,
+    viewer: Viewer,
+    bytes: Vec<u8>,
+}
+
+enum Until {
+    Input,
+    Bytes(usize),
+    Text(Vec<u8>),
+}
+
+#[derive(Clone, Copy)]
+enum Viewer {
+    Pager,
+    Editor,
+}
+
+impl Console {
+    /// With `commands`, lines starting with `~` are console commands.
+    pub fn new(commands: bool) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                commands,
+                capture: None,
+                script: None,
+                recorder: None,
+                editor: None,
+                prompt: Prompt::default(),
+                recent: Vec::new(),
+            })),
+            mid_line: Arc::default(),
+        }
+    }
+
+    /// Feeds input from `script` until it runs out, then from the terminal.
+    pub fn replay(&self, script: Script) {
+        self.shared.lock().unwrap().script = Some(script);
+    }
+
+    pub fn record(&self, recorder: Recorder) {
+        self.shared.lock().unwrap().recorder = Some(recorder);
+    }
+
+    /// Reads lines from the terminal with `editor`.
+    pub fn edit_lines(&self, editor: LineEditor) {
+        self.shared.lock().unwrap().editor = Some(editor);
+    }
+
+    /// Sets the shell prompt that recordings started with `~record` use.
+    pub fn set_prompt(&self, prompt: Prompt) {
+        self.shared.lock().unwrap().prompt = prompt;
+    }
+
+    pub fn stdin(&self) -> ConsoleIn {
+        ConsoleIn {
+            console: self.clone(),
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub fn stdout(&self) -> ConsoleOut {
+        ConsoleOut {
+            console: self.clone(),
+        }
+    }
+
+    /// Ends the program's current output line, if it has one, so that
+    /// messages from the host start on a line of their own.
+    pub fn end_line(&self) {
+        if self.mid_line.swap(false, Ordering::Relaxed) {
+            println!();
+        }
+    }
+
+    /// Shows whatever has been captured so far, for when the machine stops
+    /// before the capture is complete.
+    pub fn finish(&self) -> io::Result<()> {
+        let capture = self.shared.lock().unwrap().capture.take();
+        match capture {
+            Some(capture) => capture.show(),
+            None => Ok(()),
+        }
+    }
+
+    // Handles a line starting with `~`, returning the line to pass on to
+    // the machine, if any.
+    fn command(shared: &mut Shared, line: &[u8]) -> Option<Vec<u8>> {
+        if line.starts_with(b"~~") {
+            return Some(line[1..].to_vec());
+        }
+        let line = String::from_utf8_lossy(line);
+        let mut words = line[1..].trim().splitn(2, ' ');
+        let viewer = match words.next() {
+            Some("record") => {
+                match words.next().map(str::trim) {
+                    None | Some("") if shared.recorder.take().is_some() => {
+                        eprintln!("um-32: recording stopped")
+                    }
+                    None | Some("") => eprintln!("um-32: not recording"),
+                    Some(path) => match Recorder::create(path, Some(shared.prompt.clone())) {
+                        Ok(recorder) => shared.recorder = Some(recorder),
+                        Err(e) => eprintln!("um-32: could not record to {path}: {e}"),
+                    },
+                }
+                return None;
+            }
+            Some("page") => Viewer::Pager,
+            Some("edit") => Viewer::Editor,
+            Some("help") => {
+                eprint!("{HELP}");
+                return None;
+            }
+            _ => {
+                eprintln!(
+                    "um-32: unknown console command {:?}, try ~help",
+                    line.trim()
+                );
+                return None;
+            }
+        };
+        let until = match words.next().map(str::trim) {
+            None | Some("") => Until::Input,
+            Some(arg) => match arg.strip_prefix('/') {
+                Some(text) if !text.is_empty() => Until::Text(text.as_bytes().to_vec()),
+                Some(_) => {
+                    eprintln!("um-32: ~page /TEXT needs some text to wait for");
+                    return None;
+                }
+                None => match arg.parse() {
+                    Ok(n) if n > 0 => Until::Bytes(n),
+                    _ => {
+                        eprintln!("um-32: expected a byte count or /TEXT, got {arg:?}");
+                        return None;
+                    }
+                },
+            },
+        };
+        shared.capture = Some(Capture {
+            until,
+            viewer,
+            bytes: Vec::new(),
+        });
+        None
+    }
+}
+
+impl Capture {
+    // Takes bytes from the front of `buf` until the capture is complete,
+    // returning how many were taken and whether it is complete.
+    fn feed(&mut self, buf: &[u8]) -> (usize, bool) {
+        match &self.until {
+            Until::Input => {
+                self.bytes.extend_from_slice(buf);
+                (buf.len(), false)
+            }
+            Until::Bytes(n) => {
+                let take = (n - self.bytes.len()).min(buf.len());
+                self.bytes.extend_from_slice(&buf[..take]);
+                (take, self.bytes.len() == *n)
+            }
+            Until::Text(text) => {
+                for (i, b) in buf.iter().enumerate() {
+                    self.bytes.push(*b);
+                    if self.bytes.ends_with(text) {
+                        return (i + 1, true);
+                    }
+                }
+                (buf.len(), false)
+            }
+        }
+    }
+
+    fn show(self) -> io::Result<()> {
+        let (var, default) = match self.viewer {
+            Viewer::Pager => ("PAGER", "less"),
+            Viewer::Editor => ("EDITOR", "vi"),
+        };
+        let (path, mut file) = temp_file()?;
+        let written = file.write_all(&self.bytes);
+        drop(file);
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+
+        let command = std::env::var(var).unwrap_or_else(|_| default.to_string());
+        let mut words = command.split_whitespace();
+        let program = words.next().unwrap_or(default);
+        let status = Command::new(program).args(words).arg(&path).status();
+        std::fs::remove_file(&path)?;
+        if let Err(e) = status {
+            eprintln!("um-32: could not run {program}: {e}");
+        }
+        Ok(())
+    }
+}
+
+// Creates a new file in the temporary directory that only this user can
+// read, never opening one that is already there, such as a link planted
+// by someone else.
+fn temp_file() -> io::Result<(PathBuf, File)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    for n in 0u64.. {
+        let path = std::env::temp_dir().join(format!("um-32-{}-{n}.txt", std::process::id()));
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+pub struct ConsoleIn {
+    console: Console,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl ConsoleIn {
+    // Gets the next line of input from the script or the terminal,
+    // handling console commands and recording along the way.
+    fn next_line(&mut self) -> io::Result<bool> {
+        let mut guard = self.console.shared.lock().unwrap();
+        let shared = &mut *guard;
+        if let Some(script) = shared.script.as_mut() {
+            match script.next_line(&shared.recent)? {
+                Some(line) => {
+                    self.line = line;
+                    shared.recent.clear();
+                    return Ok(true);
+                }
+                None => {
+                    shared.script = None;
+                    if shared.commands {
+                        eprintln!("um-32: end of script, reading from the terminal");
+                    }
+                }
+            }
+        }
+        loop {
+            self.line.clear();
+            let read = match shared.editor.as_mut() {
+                Some(editor) => {
+                    // The program's prompt, which editing redraws.
+                    let start = shared.recent.iter().rposition(|b| *b == b'\n');
+                    let prompt = &shared.recent[start.map_or(0, |i| i + 1)..];
+                    editor.read_line(&String::from_utf8_lossy(prompt), &mut self.line)?
+                }
+                None => signals::read_until(&mut io::stdin().lock(), b'\n', &mut self.line)?,
+            };
+            if read == 0 {
+                return Ok(false);
+            }
+            if shared.commands && self.line.starts_with(b"~") {
+                match Console::command(shared, &self.line) {
+                    Some(line) => self.line = line,
+                    None => continue,
+                }
+            }
+            if let Some(recorder) = shared.recorder.as_mut() {
+                recorder.record(&shared.recent, &self.line)?;
+            }
+            shared.recent.clear();
+            return Ok(true);
+        }
+    }
+}
+
+impl Read for ConsoleIn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            // The machine is asking for input, which ends a capture of the
+            // last command's output.
+            let done = {
+                let mut shared = self.console.shared.lock().unwrap();
+                match shared.capture.as_ref().map(|c| &c.until) {
+                    Some(Until::Input) => shared.capture.take(),
+                    _ => None,
+                }
+            };
+            if let Some(capture) = done {
+                capture.show()?;
+            }
+            self.pos = 0;
+            if !self.next_line()? {
+                self.line.clear();
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+pub struct ConsoleOut {
+    console: Console,
+}
+
+impl Write for ConsoleOut {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut shared = self.console.shared.lock().unwrap();
+        let n = match shared.capture.as_mut() {
+            None => {
+                let n = io::stdout().write(buf)?;
+                if n > 0 {
+                    self.console
+                        .mid_line
+                        .store(buf[n - 1] != b'\n', Ordering::Relaxed);
+                }
+                n
+            }
+            Some(capture) => {
+                let (taken, done) = capture.feed(buf);
+                if done {
+                    let capture = shared.capture.take().unwrap();
+                    io::stdout().flush()?;
+                    capture.show()?;
+                }
+                taken
+            }
+        };
+        let recent = &mut shared.recent;
+        recent.extend_from_slice(&buf[..n]);
+        if recent.len() > RECENT_LIMIT {
+            recent.drain(..recent.len() - RECENT_LIMIT);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
@@ -0,0 +1,901 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Read, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use serde_json::{json, Value};
+use um_core::{Interrupter, Machine, MachineBuilder, Stop, Watch};
+use um_tools::disasm;
+
+use crate::{
+    action::{Action, Actions, Format},
+    charset::Charset,
+    condition, parse_u32, run, Error, MachineArgs,
+};
+
+// The machine is presented as a single thread with a single frame, whose
+// source is the disassembly of array 0: line N holds the platter at N - 1.
+const THREAD: u32 = 1;
+const REGISTERS: i64 = 1;
+const ARRAYS: i64 = 2;
+// Array N's platters are the children of variable ARRAY_BASE + N.
+const ARRAY_BASE: i64 = 16;
+
+const HISTORY_INTERVAL: u64 = 1_000_000;
+const HISTORY_KEEP: usize = 64;
+
+/// Serves the Debug Adapter Protocol on stdin and stdout until the client
+/// disconnects.
+///
+/// Requests that only need to look at the machine are answered by the
+/// thread that owns it, in order with the runs and steps, so they wait
+/// while it runs. `threads`, `pause`, program input typed into the debug
+/// console, and the end of the session are handled here, so they work while
+/// it is running.
+pub fn dap() -> Result<(), Error> {
+    let client = Client::default();
+    let mut server = Server {
+        client: client.clone(),
+        session: None,
+        configured: false,
+        started: false,
+    };
+    let mut stdin = io::stdin().lock();
+    while let Some(request) = read_message(&mut stdin)? {
+        if request["type"] != "request" {
+            continue;
+        }
+        if !server.handle(request)? {
+            break;
+        }
+    }
+    server.end();
+    Ok(())
+}
+
+fn read_message(r: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::other("message without a Content-Length"))?;
+    let mut body = vec![0; length];
+    r.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+/// Sends messages to the client from either thread.
+#[derive(Clone, Default)]
+struct Client(Arc<Mutex<i64>>);
+
+impl Client {
+    // A client that has gone away is noticed when reading its next request,
+    // so write errors are not reported here.
+    fn send(&self, mut message: Value) {
+        let mut seq = self.0.lock().unwrap();
+        *seq += 1;
+        message["seq"] = json!(*seq);
+        let body = message.to_string();
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "Content-Length: {}\r\n\r\n{body}", body.len());
+        let _ = stdout.flush();
+    }
+
+    fn respond(&self, request: &Value, res: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": res.is_ok(),
+        });
+        match res {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn event(&self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+// Program output, sent as output events.
+struct ClientOut(Client);
+
+impl Write for ClientOut {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // One output value per byte, as with input files.
+        let output: String = buf.iter().copied().map(char::from).collect();
+        self.0
+            .event("output", json!({ "category": "stdout", "output": output }));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Program input typed into the debug console.
+#[derive(Clone, Default)]
+struct Input(Arc<(Mutex<InputState>, Condvar)>);
+
+#[derive(Default)]
+struct InputState {
+    bytes: VecDeque<u8>,
+    // Set by pause, for a machine waiting for input.
+    paused: bool,
+    closed: bool,
+}
+
+impl Input {
+    fn update(&self, f: impl FnOnce(&mut InputState)) {
+        f(&mut self.0 .0.lock().unwrap());
+        self.0 .1.notify_all();
+    }
+
+    fn closed(&self) -> bool {
+        self.0 .0.lock().unwrap().closed
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (state, ready) = &*self.0;
+        let mut state = state.lock().unwrap();
+        loop {
+            if !state.bytes.is_empty() {
+                let n = buf.len().min(state.bytes.len());
+                for (b, v) in buf.iter_mut().zip(state.bytes.drain(..n)) {
+                    *b = v;
+                }
+                return Ok(n);
+            }
+            if state.paused {
+                // Input leaves pc in place when reading fails, so the
+                // machine stops before the Input instruction.
+                state.paused = false;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            if state.closed {
+                return Ok(0);
+            }
+            state = ready.wait(state).unwrap();
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Session) + Send>;
+
+struct Server {
+    client: Client,
+    session: Option<Handle>,
+    configured: bool,
+    started: bool,
+}
+
+// The launched machine's thread, and what is shared with it.
+struct Handle {
+    jobs: mpsc::Sender<Job>,
+    thread: JoinHandle<()>,
+    interrupter: Interrupter,
+    input: Input,
+    running: Arc<AtomicBool>,
+    stop_on_entry: bool,
+}
+
+impl Server {
+    // Returns false once the client has disconnected.
+    fn handle(&mut self, request: Value) -> io::Result<bool> {
+        let args = &request["arguments"];
+        let res = match request["command"].as_str().unwrap_or_default() {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsLogPoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsDataBreakpoints": true,
+                "supportsDisassembleRequest": true,
+                "supportsSetVariable": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "configurationDone" => {
+                self.configured = true;
+                self.start();
+                Ok(Value::Null)
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "um-32" }] })),
+            "pause" => {
+                // Answered before the stopped event the pause leads to.
+                self.client.respond(&request, Ok(Value::Null));
+                if let Some(session) = &self.session {
+                    if session.running.load(Ordering::Relaxed) {
+                        session.interrupter.interrupt();
+                        session.input.update(|state| state.paused = true);
+                    }
+                }
+                return Ok(true);
+            }
+            "evaluate" if args["context"] == "repl" && !is_query(args) => match &self.session {
+                Some(session) => {
+                    let line = format!("{}\n", args["expression"].as_str().unwrap_or_default());
+                    session
+                        .input
+                        .update(|state| state.bytes.extend(line.as_bytes()));
+                    Ok(json!({ "result": "", "variablesReference": 0 }))
+                }
+                None => Err("no program has been launched".to_string()),
+            },
+            "terminate" => {
+                self.end();
+                self.client.event("terminated", json!({}));
+                Ok(Value::Null)
+            }
+            "disconnect" => {
+                self.client.respond(&request, Ok(Value::Null));
+                return Ok(false);
+            }
+            _ => {
+                match &self.session {
+                    Some(session) => {
+                        let job: Job = Box::new(move |session| session.handle(request));
+                        // The thread only ends after the session does.
+                        let _ = session.jobs.send(job);
+                    }
+                    None => self
+                        .client
+                        .respond(&request, Err("no program has been launched".to_string())),
+                }
+                return Ok(true);
+            }
+        };
+        self.client.respond(&request, res);
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        if self.session.is_some() {
+            return Err("a program has already been launched".to_string());
+        }
+        let paths = |value: &Value| -> Vec<PathBuf> {
+            match value {
+                Value::String(path) => vec![path.into()],
+                Value::Array(paths) => paths
+                    .iter()
+                    .filter_map(|path| path.as_str().map(PathBuf::from))
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+        let machine_args = MachineArgs {
+            files: paths(&args["program"]),
+            resume: args["resume"].as_str().map(PathBuf::from),
+            key: None,
+            codex: false,
+            inputs: paths(&args["inputFiles"]),
+            journal: None,
+            replay: args["replay"].as_str().map(PathBuf::from),
+            entry: match &args["entry"] {
+                Value::Null => None,
+                Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+                Value::String(pc) => Some(parse_u32(pc)?),
+                _ => return Err("entry must be a number".to_string()),
+            },
+            trace: false,
+            max_alloc: MachineBuilder::DEFAULT_MAX_ALLOC,
+            max_memory: None,
+            detect_hangs: false,
+            extensions: args["extensions"].as_bool().unwrap_or(false),
+            display_charset: Charset::Ascii,
+        };
+        if machine_args.files.is_empty() && machine_args.resume.is_none() {
+            return Err("launch needs a program or a snapshot to resume".to_string());
+        }
+
+        let input = Input::default();
+        let loads = Arc::new(AtomicU32::new(0));
+        let mut builder = Machine::builder()
+            .stdin(input.clone())
+            .stdout(ClientOut(self.client.clone()))
+            // The debug console shows what was typed already.
+            .echo(false);
+        if let Some(text) = args["input"].as_str() {
+            builder = builder.input(text);
+        }
+        let counter = loads.clone();
+        builder = builder.on_load_program(move |_, _, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        });
+        let mut machine = run::load(&machine_args, builder).map_err(|e| e.to_string())?;
+        let history = args["history"].as_bool().unwrap_or(false);
+        if history {
+            machine.record_history(HISTORY_INTERVAL, HISTORY_KEEP);
+        }
+
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let interrupter = machine.interrupter();
+        let running = Arc::new(AtomicBool::new(false));
+        let log = self.client.clone();
+        let actions = Actions::new(move |line| {
+            log.event(
+                "output",
+                json!({ "category": "console", "output": format!("{line}\n") }),
+            )
+        });
+        let mut session = Session {
+            machine,
+            actions,
+            client: self.client.clone(),
+            interrupter: interrupter.clone(),
+            input: input.clone(),
+            running: running.clone(),
+            loads,
+            breakpoints: Default::default(),
+            data_breakpoints: Vec::new(),
+            halted: false,
+        };
+        let thread = thread::spawn(move || {
+            for job in queue {
+                if session.input.closed() {
+                    break;
+                }
+                job(&mut session);
+            }
+        });
+        self.session = Some(Handle {
+            jobs,
+            thread,
+            interrupter,
+            input,
+            running,
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+        });
+        if history {
+            self.client.event(
+                "capabilities",
+                json!({ "capabilities": { "supportsStepBack": true } }),
+            );
+        }
+        // Breakpoints can only be set once there is a machine to set them on.
+        self.client.event("initialized", json!({}));
+        self.start();
+        Ok(Value::Null)
+    }
+
+    // Starts the program once it is launched and the client has set its
+    // breakpoints, in whichever order those happen.
+    fn start(&mut self) {
+        let Some(session) = self.session.as_ref().filter(|_| self.configured) else {
+            return;
+        };
+        if std::mem::replace(&mut self.started, true) {
+            return;
+        }
+        let job: Job = if session.stop_on_entry {
+            Box::new(|session| session.stopped("entry", None))
+        } else {
+            Box::new(|session| session.resume("continue"))
+        };
+        let _ = session.jobs.send(job);
+    }
+
+    // Stops the machine's thread: a run in progress is interrupted and
+    // input ends, and the thread finishes the requests already sent to it.
+    fn end(&mut self) {
+        if let Some(session) = self.session.take() {
+            session.interrupter.interrupt();
+            session.input.update(|state| state.closed = true);
+            drop(session.jobs);
+            let _ = session.thread.join();
+        }
+    }
+}
+
+// Debug console lines starting with `?` are evaluated rather than sent to
+// the program.
+fn is_query(args: &Value) -> bool {
+    args["expression"]
+        .as_str()
+        .is_some_and(|text| text.starts_with('?'))
+}
+
+// The client's breakpoints for each kind of request that sets them, since
+// each request replaces only its own kind.
+#[derive(Default)]
+struct Breakpoints {
+    lines: Vec<Breakpoint>,
+    instructions: Vec<Breakpoint>,
+}
+
+enum Breakpoint {
+    At(u32),
+    If(u32),
+    Log(u32, Format),
+}
+
+enum DataBreakpoint {
+    Array(u32),
+    Cell(u32, u32),
+}
+
+// The machine and the client's settings for it, owned by the machine's
+// thread.
+struct Session {
+    machine: Machine,
+    // Carries out logpoints.
+    actions: Actions,
+    client: Client,
+    interrupter: Interrupter,
+    input: Input,
+    running: Arc<AtomicBool>,
+    // The number of times Load Program has replaced array 0.
+    loads: Arc<AtomicU32>,
+    breakpoints: Breakpoints,
+    data_breakpoints: Vec<DataBreakpoint>,
+    halted: bool,
+}
+
+impl Session {
+    fn handle(&mut self, request: Value) {
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        let res = match command {
+            "continue" | "next" | "stepIn" | "stepOut" | "stepBack" | "reverseContinue" => {
+                if self.halted {
+                    Err("the program has halted".to_string())
+                } else {
+                    self.client
+                        .respond(&request, Ok(json!({ "allThreadsContinued": true })));
+                    self.resume(command);
+                    return;
+                }
+            }
+            "setBreakpoints" => self.set_breakpoints(args, false),
+            "setInstructionBreakpoints" => self.set_breakpoints(args, true),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "dataBreakpointInfo" => Ok(self.data_breakpoint_info(args)),
+            "setDataBreakpoints" => self.set_data_breakpoints(args),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Arrays", "variablesReference": ARRAYS, "expensive": false },
+            ] })),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "source" => Ok(self.source()),
+            "disassemble" => self.disassemble(args),
+            "evaluate" => self.evaluate(args),
+            command => Err(format!("{command} is not supported")),
+        };
+        self.client.respond(&request, res);
+    }
+
+    fn resume(&mut self, command: &str) {
+        self.running.store(true, Ordering::Relaxed);
+        let res = match command {
+            "continue" => self.actions.run_until_stop(&mut self.machine),
+            "stepBack" => match self.machine.executed().checked_sub(1) {
+                Some(executed) => self.machine.rewind(executed).map(|_| Stop::Step),
+                None => Ok(Stop::Step),
+            },
+            "reverseContinue" => self
+                .machine
+                .reverse_continue()
+                .map(|stop| stop.unwrap_or(Stop::Step)),
+            _ => self.machine.step(),
+        };
+        self.running.store(false, Ordering::Relaxed);
+        // A pause that came too late to stop this run is dropped.
+        self.interrupter.take();
+        self.input.update(|state| state.paused = false);
+        if self.input.closed() {
+            // The session is ending, and the run was stopped for that.
+            return;
+        }
+
+        match res {
+            Ok(Stop::Halt) => {
+                self.halted = true;
+                self.client.event("exited", json!({ "exitCode": 0 }));
+                self.client.event("terminated", json!({}));
+            }
+            Ok(Stop::Breakpoint { .. } | Stop::Condition { .. }) => {
+                self.stopped("breakpoint", None)
+            }
+            Ok(stop @ Stop::Watch { .. }) => {
+                self.stopped("data breakpoint", run::describe_watch(&stop))
+            }
+            Ok(Stop::Interrupted { .. }) => self.stopped("pause", None),
+            Ok(_) => self.stopped("step", None),
+            Err(um_core::Error::IO(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.stopped("pause", None)
+            }
+            Err(e) => self.stopped("exception", Some(e.to_string())),
+        }
+    }
+
+    fn stopped(&self, reason: &str, text: Option<String>) {
+        let mut body = json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["description"] = json!(text);
+            body["text"] = json!(text);
+        }
+        self.client.event("stopped", body);
+    }
+
+    fn set_breakpoints(&mut self, args: &Value, instructions: bool) -> Result<Value, String> {
+        let old = match instructions {
+            false => std::mem::take(&mut self.breakpoints.lines),
+            true => std::mem::take(&mut self.breakpoints.instructions),
+        };
+        for breakpoint in old {
+            if let Breakpoint::If(id) = breakpoint {
+                self.machine.remove_condition(id);
+            }
+        }
+
+        let mut set = Vec::new();
+        let mut results = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let pc = if instructions {
+                let reference = requested["instructionReference"]
+                    .as_str()
+                    .unwrap_or_default();
+                let offset = requested["offset"].as_i64().unwrap_or(0);
+                parse_u32(reference)
+                    .ok()
+                    .and_then(|pc| u32::try_from(pc as i64 + offset).ok())
+            } else {
+                requested["line"]
+                    .as_u64()
+                    .and_then(|line| u32::try_from(line.checked_sub(1)?).ok())
+            };
+            let Some(pc) = pc else {
+                results.push(json!({ "verified": false, "message": "not an address" }));
+                continue;
+            };
+            let mut result = json!({
+                "verified": true,
+                "line": pc as u64 + 1,
+                "instructionReference": format!("{pc:#x}"),
+            });
+            let condition = requested["condition"]
+                .as_str()
+                .filter(|text| !text.trim().is_empty());
+            let log = requested["logMessage"].as_str();
+            match (condition, log) {
+                (None, None) => set.push(Breakpoint::At(pc)),
+                (None, Some(text)) => match Format::parse(text) {
+                    Ok(format) => set.push(Breakpoint::Log(pc, format)),
+                    Err(e) => {
+                        result["verified"] = json!(false);
+                        result["message"] = json!(e);
+                    }
+                },
+                (Some(_), Some(_)) => {
+                    result["verified"] = json!(false);
+                    result["message"] = json!("logpoints cannot have conditions");
+                }
+                (Some(text), None) => match condition::compile(text) {
+                    Ok(mut condition) => {
+                        let id = self.machine.add_condition(move |at, registers| {
+                            (at == pc) & condition(at, registers)
+                        });
+                        set.push(Breakpoint::If(id));
+                    }
+                    Err(e) => {
+                        result["verified"] = json!(false);
+                        result["message"] = json!(e);
+                    }
+                },
+            }
+            results.push(result);
+        }
+        match instructions {
+            false => self.breakpoints.lines = set,
+            true => self.breakpoints.instructions = set,
+        }
+
+        // The machine has one set of plain breakpoints for both kinds.
+        for pc in self.machine.breakpoints() {
+            self.actions.remove(&mut self.machine, pc);
+        }
+        let all = self
+            .breakpoints
+            .lines
+            .iter()
+            .chain(&self.breakpoints.instructions);
+        for breakpoint in all {
+            match breakpoint {
+                Breakpoint::At(pc) => self.actions.add_breakpoint(&mut self.machine, *pc),
+                Breakpoint::Log(pc, format) => {
+                    let action = Action::Log(format.clone());
+                    self.actions.add_action(&mut self.machine, *pc, action);
+                }
+                Breakpoint::If(_) => {}
+            }
+        }
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    // Platters and whole arrays can be watched; registers are left to
+    // conditions such as `r3 changes`.
+    fn data_breakpoint_info(&self, args: &Value) -> Value {
+        let reference = args["variablesReference"].as_i64().unwrap_or(0);
+        let name = args["name"].as_str().unwrap_or_default();
+        let access = json!(["read", "write", "readWrite"]);
+        match (reference, parse_u32(name)) {
+            (ARRAYS, Ok(array)) => json!({
+                "dataId": format!("{array}"),
+                "description": format!("array {array}"),
+                "accessTypes": access,
+            }),
+            (reference, Ok(offset)) if reference >= ARRAY_BASE => {
+                let array = reference - ARRAY_BASE;
+                json!({
+                    "dataId": format!("{array}:{offset}"),
+                    "description": format!("array {array} at offset {offset:#x}"),
+                    "accessTypes": access,
+                })
+            }
+            _ => json!({
+                "dataId": null,
+                "description": "only arrays and platters can be watched; \
+                                use a condition such as `r3 changes` for registers",
+            }),
+        }
+    }
+
+    fn set_data_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        for watch in self.data_breakpoints.drain(..) {
+            match watch {
+                DataBreakpoint::Array(array) => self.machine.unwatch_array(array),
+                DataBreakpoint::Cell(array, offset) => self.machine.unwatch_cell(array, offset),
+            }
+        }
+        let mut results = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let watch = match requested["accessType"].as_str() {
+                Some("read") => Watch::Read,
+                Some("write") => Watch::Write,
+                _ => Watch::ReadWrite,
+            };
+            let data_id = requested["dataId"].as_str().unwrap_or_default();
+            let parsed = match data_id.split_once(':') {
+                Some((array, offset)) => parse_u32(array)
+                    .and_then(|array| Ok(DataBreakpoint::Cell(array, parse_u32(offset)?))),
+                None => parse_u32(data_id).map(DataBreakpoint::Array),
+            };
+            match parsed {
+                Ok(data_breakpoint) => {
+                    match data_breakpoint {
+                        DataBreakpoint::Array(array) => self.machine.watch_array(array, watch),
+                        DataBreakpoint::Cell(array, offset) => {
+                            self.machine.watch_cell(array, offset, watch)
+                        }
+                    }
+                    self.data_breakpoints.push(data_breakpoint);
+                    results.push(json!({ "verified": true }));
+                }
+                Err(e) => results.push(json!({ "verified": false, "message": e })),
+            }
+        }
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn stack_trace(&self) -> Value {
+        let pc = self.machine.pc();
+        let name = match self
+            .machine
+            .array(0)
+            .and_then(|program| program.get(pc as usize))
+        {
+            Some(word) => disasm::mnemonic(*word, false),
+            None => "outside the program".to_string(),
+        };
+        json!({
+            "stackFrames": [{
+                "id": 0,
+                "name": name,
+                "source": self.source_ref(),
+                "line": pc as u64 + 1,
+                "column": 1,
+                "instructionPointerReference": format!("{pc:#x}"),
+            }],
+            "totalFrames": 1,
+        })
+    }
+
+    // Each program that Load Program puts in array 0 is a new source.
+    fn source_ref(&self) -> Value {
+        let loads = self.loads.load(Ordering::Relaxed);
+        let name = match loads {
+            0 => "array 0".to_string(),
+            n => format!("array 0 (load {n})"),
+        };
+        json!({ "name": name, "sourceReference": loads + 1 })
+    }
+
+    fn source(&self) -> Value {
+        let mut content = Vec::new();
+        let program = self.machine.array(0).unwrap_or_default();
+        let _ = disasm::disassemble(&mut content, 0, program, false);
+        json!({ "content": String::from_utf8_lossy(&content) })
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let variables = match args["variablesReference"].as_i64().unwrap_or(0) {
+            REGISTERS => {
+                let mut variables = vec![
+                    variable("pc", self.machine.pc()),
+                    json!({
+                        "name": "executed",
+                        "value": self.machine.executed().to_string(),
+                        "variablesReference": 0,
+                        "presentationHint": { "attributes": ["readOnly"] },
+                    }),
+                ];
+                for (r, value) in self.machine.registers().iter().enumerate() {
+                    variables.push(variable(&format!("r{r}"), *value));
+                }
+                variables
+            }
+            ARRAYS => self
+                .machine
+                .arrays()
+                .map(|(array, platters)| {
+                    json!({
+                        "name": array.to_string(),
+                        "value": format!("{} platters", platters.len()),
+                        "variablesReference": ARRAY_BASE + array as i64,
+                        "indexedVariables": platters.len(),
+                    })
+                })
+                .collect(),
+            reference if reference >= ARRAY_BASE => {
+                let platters = self.array(reference)?;
+                let start = args["start"].as_u64().unwrap_or(0) as usize;
+                let count = match args["count"].as_u64() {
+                    Some(0) | None => platters.len(),
+                    Some(count) => count as usize,
+                };
+                platters
+                    .iter()
+                    .enumerate()
+                    .skip(start)
+                    .take(count)
+                    .map(|(offset, value)| variable(&format!("{offset:#x}"), *value))
+                    .collect()
+            }
+            reference => return Err(format!("unknown variables reference {reference}")),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn array(&self, reference: i64) -> Result<&[u32], String> {
+        let array = u32::try_from(reference - ARRAY_BASE).map_err(|e| e.to_string())?;
+        self.machine
+            .array(array)
+            .ok_or_else(|| format!("array {array} is not active"))
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let name = args["name"].as_str().unwrap_or_default();
+        let value = parse_u32(args["value"].as_str().unwrap_or_default().trim())?;
+        match args["variablesReference"].as_i64().unwrap_or(0) {
+            REGISTERS if name == "pc" => self.machine.set_pc(value),
+            REGISTERS => match register(name) {
+                Some(r) => self.machine.registers_mut()[r] = value,
+                None => return Err(format!("{name} cannot be changed")),
+            },
+            reference if reference >= ARRAY_BASE => {
+                let array = u32::try_from(reference - ARRAY_BASE).map_err(|e| e.to_string())?;
+                let offset = parse_u32(name)? as usize;
+                let platter = self
+                    .machine
+                    .array_mut(array)
+                    .and_then(|platters| platters.get_mut(offset))
+                    .ok_or_else(|| format!("no platter {name} in array {array}"))?;
+                *platter = value;
+            }
+            _ => return Err(format!("{name} cannot be changed")),
+        }
+        Ok(json!({ "value": format!("{value:#010x}") }))
+    }
+
+    fn disassemble(&self, args: &Value) -> Result<Value, String> {
+        let reference = args["memoryReference"].as_str().unwrap_or_default();
+        // Addresses count platters, not bytes.
+        let start = parse_u32(reference)? as i64
+            + args["offset"].as_i64().unwrap_or(0)
+            + args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_i64().unwrap_or(0);
+        let program = self.machine.array(0).unwrap_or_default();
+        let instructions: Vec<Value> = (start..start + count)
+            .map(|addr| {
+                let word = usize::try_from(addr)
+                    .ok()
+                    .and_then(|addr| program.get(addr));
+                match word {
+                    Some(word) => json!({
+                        "address": format!("{addr:#x}"),
+                        "instructionBytes": format!("{word:08x}"),
+                        "instruction": disasm::mnemonic(*word, false),
+                        "location": self.source_ref(),
+                        "line": addr + 1,
+                    }),
+                    None => json!({
+                        "address": format!("{addr:#x}"),
+                        "instruction": "",
+                        "presentationHint": "invalid",
+                    }),
+                }
+            })
+            .collect();
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    // Evaluates `pc`, `executed`, `rN`, `ID[OFFSET]` for a platter, or a
+    // breakpoint condition against the current state.
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let text = args["expression"].as_str().unwrap_or_default();
+        let text = text.strip_prefix('?').unwrap_or(text).trim();
+        let result = if text == "executed" {
+            self.machine.executed().to_string()
+        } else if let Some(value) = self.value(text) {
+            format!("{value:#010x} ({value})")
+        } else {
+            let mut condition = condition::compile(text)?;
+            condition(self.machine.pc(), self.machine.registers()).to_string()
+        };
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+
+    fn value(&self, text: &str) -> Option<u32> {
+        if text == "pc" {
+            return Some(self.machine.pc());
+        }
+        if let Some(r) = register(text) {
+            return Some(self.machine.registers()[r]);
+        }
+        let (array, offset) = text.strip_suffix(']')?.split_once('[')?;
+        let platters = self.machine.array(parse_u32(array.trim()).ok()?)?;
+        platters
+            .get(parse_u32(offset.trim()).ok()? as usize)
+            .copied()
+    }
+}
+
+fn register(name: &str) -> Option<usize> {
+    name.strip_prefix('r')?.parse().ok().filter(|r| *r < 8)
+}
+
+fn variable(name: &str, value: u32) -> Value {
+    json!({
+        "name": name,
+        "value": format!("{value:#010x}"),
+        "variablesReference": 0,
+    })
+}
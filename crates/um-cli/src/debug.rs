@@ -0,0 +1,757 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufRead, IsTerminal, Write},
+};
+
+use console::style;
+use um_core::{CoreDump, Machine, Stop, Watch};
+use um_tools::disasm;
+
+use crate::{
+    action::{Action, Actions},
+    charset::Charset,
+    condition,
+    console::Console,
+    parse_mode, parse_u32, profile, run, DebugArgs, Error,
+};
+
+const HELP: &str = "\
+commands:
+  s, step [N]              execute N instructions (default 1)
+  n, next [N]              like step, but run through a LOADPROG that looks
+                           like a call until it returns to the instruction
+                           after it; see below
+  c, continue              run until a breakpoint, watch, halt, or fault
+  rs, reverse-step [N]     go back N instructions (default 1)
+  rc, reverse-continue     go back to the previous breakpoint or watch
+  goto COUNT               go back or forward to the state after COUNT
+                           instructions
+  history on [INTERVAL [KEEP]]
+                           keep a checkpoint every INTERVAL instructions
+                           (default 1000000), at most KEEP (default 64), so
+                           that the commands above can go back
+  history off              stop keeping history
+  history                  show how far back the history goes
+  b, break [PC]            set a breakpoint at PC, or list breakpoints
+  b, break PC do ACTION    carry out ACTION at PC and continue, unless
+                           there is also a plain breakpoint there; see below
+  b, break if CONDITION    stop before any instruction where CONDITION
+                           holds, e.g. `pc == 0x1234 && r3 == 0` or
+                           `r7 changes`; see below
+  d, delete PC             remove the breakpoint at PC and its actions
+  d, delete if ID          remove the condition numbered ID
+  w, watch-array ID [r|w|rw]
+                           stop when array ID is accessed (default rw)
+  unwatch ID               stop watching array ID
+  wc, watch-cell ID OFFSET [r|w|rw]
+                           stop when platter OFFSET of array ID is read or
+                           amended (default rw)
+  unwatch-cell ID OFFSET   stop watching that platter
+  watches                  list array and cell watches
+  call PC                  treat the LOADPROG at PC as a call for next
+  uncall PC                stop treating it as a call
+  calls                    list the call sites marked with call
+  counters                 show the counts kept by breakpoint actions
+  counters reset           set every counter to zero
+  counter NAME [N]         define a counter, or set it to N (default 0)
+  r, regs                  show pc and registers
+  x, array ID [START [N]]  show N platters of array ID (default 0, 16)
+  l, list [PC [N]]         disassemble N instructions around PC
+  set rN|pc VALUE          change a register or pc
+  diff                     show what changed since the reference state: by
+                           default the state at the previous stop
+  diff on|off              show the changes after every stop
+  diff mark                use the current state as the reference
+  diff snapshot FILE       use a saved snapshot as the reference
+  diff last                go back to the previous stop as the reference
+  h, help                  show this list
+  q, quit                  leave the debugger
+Conditions compare pc, r0-r7 and numbers with == != < <= > >=, combine
+them with && || ! and parentheses, and `rN changes` holds after any
+instruction that changed rN.
+Breakpoint actions are `log FORMAT` to print a message, `count NAME` to count
+hits, `snapshot PATH` to save a snapshot, and `exec COMMAND` to run a shell
+command with the state in UM_PC, UM_EXECUTED and UM_R0-UM_R7, stopping if it
+fails. FORMAT and PATH may include {pc}, {executed}, {r0}-{r7} and
+{ID[OFFSET]}, with :x, :d or :c for hex, decimal or a character.
+A LOADPROG looks like a call to next if it jumps within array 0 and
+another register holds the address of the instruction after it, or if it
+was marked with call. A recursive call stops at the first return to that
+address, which may be from an inner call.
+Going back replays from a checkpoint with the program's output discarded
+and its input taken from what was typed the first time; input typed after
+the point gone back to is fed to the program again as it runs on.
+Values changed since the reference are highlighted by regs and array.
+An empty line repeats the previous command. Program input is read from the
+same terminal; lines starting with ~ are console commands (see ~help).
+";
+
+const HISTORY_INTERVAL: u64 = 1_000_000;
+const HISTORY_KEEP: usize = 64;
+
+pub fn debug(args: DebugArgs) -> Result<(), Error> {
+    let console = Console::new(true);
+    let builder = Machine::builder()
+        .stdin(console.stdin())
+        .stdout(console.stdout())
+        .immediate_output(io::stdout().is_terminal());
+    let (mut machine, core) = run::load_core(&args.machine, builder)?;
+    let log = console.clone();
+    let mut actions = Actions::new(move |line| {
+        log.end_line();
+        println!("{line}");
+    });
+    actions.set_charset(args.machine.display_charset);
+    for pc in args.breakpoints {
+        actions.add_breakpoint(&mut machine, pc);
+    }
+    for (pc, action) in args.actions {
+        actions.add_action(&mut machine, pc, action);
+    }
+    for name in args.counters {
+        actions.set_counter(&name, 0);
+    }
+    if args.history {
+        machine.record_history(HISTORY_INTERVAL, HISTORY_KEEP);
+    }
+    let mut calls = BTreeSet::new();
+    let mut conditions = BTreeMap::new();
+    for text in args.conditions {
+        let condition = condition::compile(&text).map_err(Error::InvalidArgument)?;
+        conditions.insert(machine.add_condition(condition), text);
+    }
+
+    println!("um-32 debugger, type h for help");
+    if let Some(core) = core {
+        show_core(&core);
+    }
+    show_position(&machine);
+    let mut diff = Diff {
+        base: State::of(&machine),
+        follow: true,
+        auto: false,
+    };
+    let mut last = String::new();
+    loop {
+        print!("{} ", style("(um)").bold());
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            println!();
+            break;
+        }
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+        last.clone_from(&line);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match command(
+            &mut machine,
+            &console,
+            &mut diff,
+            &mut conditions,
+            &mut actions,
+            &mut calls,
+            &words,
+        ) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("{} {e}", style("error:").red()),
+        }
+    }
+    console.finish()?;
+    Ok(())
+}
+
+// The state `diff`, `regs` and `array` compare against.
+struct Diff {
+    base: State,
+    // Whether `base` moves to each new stop, rather than staying at a mark or
+    // snapshot.
+    follow: bool,
+    // Whether to show the changes after every stop.
+    auto: bool,
+}
+
+struct State {
+    pc: u32,
+    registers: [u32; 8],
+    arrays: BTreeMap<u32, Vec<u32>>,
+}
+
+impl State {
+    fn of(machine: &Machine) -> Self {
+        Self {
+            pc: machine.pc(),
+            registers: *machine.registers(),
+            arrays: machine
+                .arrays()
+                .map(|(id, platters)| (id, platters.to_vec()))
+                .collect(),
+        }
+    }
+}
+
+impl Diff {
+    // Called before the machine runs.
+    fn resume(&mut self, machine: &Machine) {
+        if self.follow {
+            self.base = State::of(machine);
+        }
+    }
+
+    // Called after the machine stops, once the stop has been reported.
+    fn stopped(&self, machine: &Machine) {
+        if self.auto {
+            self.show(machine);
+        }
+    }
+
+    fn show(&self, machine: &Machine) {
+        let base = &self.base;
+        let mut changes = 0;
+        if base.pc != machine.pc() {
+            println!("pc  {}", change(base.pc, machine.pc()));
+            changes += 1;
+        }
+        for (i, (old, new)) in base.registers.iter().zip(machine.registers()).enumerate() {
+            if old != new {
+                println!("r{i}  {}", change(*old, *new));
+                changes += 1;
+            }
+        }
+        let mut arrays = machine.arrays().peekable();
+        let mut old_arrays = base.arrays.iter().peekable();
+        loop {
+            let order = match (old_arrays.peek(), arrays.peek()) {
+                (None, None) => break,
+                (Some((old, _)), Some((new, _))) => (*old).cmp(new),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+            };
+            changes += 1;
+            match order {
+                Ordering::Less => {
+                    let (id, _) = old_arrays.next().unwrap();
+                    println!("array {id} {}", style("abandoned").red());
+                }
+                Ordering::Greater => {
+                    let (id, platters) = arrays.next().unwrap();
+                    println!(
+                        "array {id} {} ({} platters)",
+                        style("allocated").green(),
+                        platters.len()
+                    );
+                }
+                Ordering::Equal => {
+                    let (id, old) = old_arrays.next().unwrap();
+                    let (_, new) = arrays.next().unwrap();
+                    if old.len() != new.len() {
+                        println!(
+                            "array {id} replaced ({} -> {} platters)",
+                            old.len(),
+                            new.len()
+                        );
+                    } else if !show_cells(*id, old, new) {
+                        changes -= 1;
+                    }
+                }
+            }
+        }
+        if changes == 0 {
+            println!("no changes");
+        }
+    }
+
+    fn changed(&self, array: u32, offset: usize, value: u32) -> bool {
+        self.base
+            .arrays
+            .get(&array)
+            .and_then(|platters| platters.get(offset))
+            != Some(&value)
+    }
+}
+
+// The most changed platters listed for one array.
+const CELL_LIMIT: usize = 32;
+
+// Lists the platters that differ between two versions of an array,
+// returning whether there were any.
+fn show_cells(array: u32, old: &[u32], new: &[u32]) -> bool {
+    let mut changed = old
+        .iter()
+        .zip(new)
+        .enumerate()
+        .filter(|(_, (old, new))| old != new);
+    let mut count = 0;
+    for (offset, (old, new)) in changed.by_ref().take(CELL_LIMIT) {
+        println!("array {array} [{offset:#x}]  {}", change(*old, *new));
+        count += 1;
+    }
+    let more = changed.count();
+    if more > 0 {
+        println!("array {array}: {more} more changed platters");
+    }
+    count > 0
+}
+
+fn change(old: u32, new: u32) -> String {
+    format!(
+        "{} -> {}",
+        style(format!("{old:#010x}")).red(),
+        style(format!("{new:#010x}")).green()
+    )
+}
+
+// Returns false when the debugger should exit.
+fn command(
+    machine: &mut Machine,
+    console: &Console,
+    diff: &mut Diff,
+    conditions: &mut BTreeMap<u32, String>,
+    actions: &mut Actions,
+    calls: &mut BTreeSet<u32>,
+    words: &[&str],
+) -> Result<bool, Error> {
+    let Some((&cmd, args)) = words.split_first() else {
+        return Ok(true);
+    };
+    let arg = |i: usize| args.get(i).map(|s| number(s)).transpose();
+    match cmd {
+        "s" | "step" => {
+            let count = arg(0)?.unwrap_or(1);
+            diff.resume(machine);
+            for _ in 0..count {
+                match machine.step() {
+                    Ok(Stop::Step) => {}
+                    res => {
+                        console.end_line();
+                        report(machine, conditions, res);
+                        diff.stopped(machine);
+                        return Ok(true);
+                    }
+                }
+            }
+            console.end_line();
+            show_position(machine);
+            diff.stopped(machine);
+        }
+        "n" | "next" => {
+            let count = arg(0)?.unwrap_or(1);
+            diff.resume(machine);
+            for _ in 0..count {
+                let res = if is_call(machine, calls) {
+                    let ret = machine.pc().wrapping_add(1);
+                    match actions.run_to(machine, ret) {
+                        Ok(Stop::Breakpoint { pc })
+                            if pc == ret && !machine.breakpoints().contains(&pc) =>
+                        {
+                            Ok(Stop::Step)
+                        }
+                        res => res,
+                    }
+                } else {
+                    machine.step()
+                };
+                if !matches!(res, Ok(Stop::Step)) {
+                    console.end_line();
+                    report(machine, conditions, res);
+                    diff.stopped(machine);
+                    return Ok(true);
+                }
+            }
+            console.end_line();
+            show_position(machine);
+            diff.stopped(machine);
+        }
+        "call" => {
+            let pc = arg(0)?.ok_or_else(|| usage("call PC"))?;
+            calls.insert(pc);
+        }
+        "uncall" => {
+            let pc = arg(0)?.ok_or_else(|| usage("uncall PC"))?;
+            if !calls.remove(&pc) {
+                println!("no call marked at {pc:#x}");
+            }
+        }
+        "calls" if calls.is_empty() => println!("no calls marked"),
+        "calls" => {
+            for pc in calls.iter() {
+                list(machine, *pc, 1);
+            }
+        }
+        "c" | "continue" => {
+            diff.resume(machine);
+            let res = actions.run_until_stop(machine);
+            console.end_line();
+            report(machine, conditions, res);
+            diff.stopped(machine);
+        }
+        "rs" | "reverse-step" => {
+            let count = args.first().map(|s| count(s)).transpose()?.unwrap_or(1);
+            let target = machine.executed().saturating_sub(count);
+            diff.resume(machine);
+            machine.rewind(target)?;
+            show_position(machine);
+            diff.stopped(machine);
+        }
+        "rc" | "reverse-continue" => {
+            diff.resume(machine);
+            match machine.reverse_continue()? {
+                Some(stop) => report(machine, conditions, Ok(stop)),
+                None => {
+                    println!("reached the start of the history");
+                    show_position(machine);
+                }
+            }
+            diff.stopped(machine);
+        }
+        "goto" => {
+            let target = count(args.first().ok_or_else(|| usage("goto COUNT"))?)?;
+            diff.resume(machine);
+            if target <= machine.executed() {
+                machine.rewind(target)?;
+            }
+            while machine.executed() < target {
+                match machine.step() {
+                    Ok(Stop::Step) => {}
+                    res => {
+                        console.end_line();
+                        report(machine, conditions, res);
+                        diff.stopped(machine);
+                        return Ok(true);
+                    }
+                }
+            }
+            console.end_line();
+            show_position(machine);
+            diff.stopped(machine);
+        }
+        "history" => match args {
+            [] => match machine.history_start() {
+                Some(start) => println!(
+                    "history goes back to instruction {start}, now at {}",
+                    machine.executed()
+                ),
+                None => println!("no history is being kept"),
+            },
+            ["on", rest @ ..] if rest.len() <= 2 => {
+                let interval = rest.first().map(|s| count(s)).transpose()?;
+                let keep = rest.get(1).map(|s| count(s)).transpose()?;
+                machine.record_history(
+                    interval.unwrap_or(HISTORY_INTERVAL),
+                    keep.map_or(HISTORY_KEEP, |keep| keep as usize),
+                );
+            }
+            ["off"] => machine.forget_history(),
+            _ => return Err(usage("history [on [INTERVAL [KEEP]]|off]")),
+        },
+        "b" | "break" if args.first() == Some(&"if") => {
+            let text = args[1..].join(" ");
+            let condition = condition::compile(&text).map_err(Error::InvalidArgument)?;
+            let id = machine.add_condition(condition);
+            println!("condition {id}: {text}");
+            conditions.insert(id, text);
+        }
+        "b" | "break" if args.get(1) == Some(&"do") => {
+            let pc = arg(0)?.unwrap_or_default();
+            let action = Action::parse(&args[2..].join(" ")).map_err(Error::InvalidArgument)?;
+            actions.add_action(machine, pc, action);
+        }
+        "b" | "break" => match arg(0)? {
+            Some(pc) => actions.add_breakpoint(machine, pc),
+            None if machine.breakpoints().is_empty() && conditions.is_empty() => {
+                println!("no breakpoints")
+            }
+            None => {
+                for pc in machine.breakpoints() {
+                    list(machine, pc, 1);
+                    for action in actions.actions(pc) {
+                        println!("      do {action}");
+                    }
+                    if !actions.stops(pc) {
+                        println!("      and continue");
+                    }
+                }
+                for (id, text) in conditions.iter() {
+                    println!("condition {id}: {text}");
+                }
+            }
+        },
+        "d" | "delete" if args.first() == Some(&"if") => {
+            let id = arg(1)?.ok_or_else(|| usage("delete if ID"))?;
+            if !machine.remove_condition(id) {
+                println!("no condition {id}");
+            }
+            conditions.remove(&id);
+        }
+        "d" | "delete" => {
+            let pc = arg(0)?.ok_or_else(|| usage("delete PC"))?;
+            if !actions.remove(machine, pc) {
+                println!("no breakpoint at {pc:#x}");
+            }
+        }
+        "counter" => {
+            let name = args.first().ok_or_else(|| usage("counter NAME [N]"))?;
+            let count = args.get(1).map(|s| count(s)).transpose()?;
+            actions.set_counter(name, count.unwrap_or(0));
+        }
+        "w" | "watch-array" => {
+            const USAGE: &str = "watch-array ID [r|w|rw]";
+            let array = arg(0)?.ok_or_else(|| usage(USAGE))?;
+            let watch = mode(args.get(1)).map_err(|_| usage(USAGE))?;
+            machine.watch_array(array, watch);
+        }
+        "unwatch" => {
+            let array = arg(0)?.ok_or_else(|| usage("unwatch ID"))?;
+            machine.unwatch_array(array);
+        }
+        "wc" | "watch-cell" => {
+            const USAGE: &str = "watch-cell ID OFFSET [r|w|rw]";
+            let (Some(array), Some(offset)) = (arg(0)?, arg(1)?) else {
+                return Err(usage(USAGE));
+            };
+            let watch = mode(args.get(2)).map_err(|_| usage(USAGE))?;
+            machine.watch_cell(array, offset, watch);
+        }
+        "unwatch-cell" => {
+            let (Some(array), Some(offset)) = (arg(0)?, arg(1)?) else {
+                return Err(usage("unwatch-cell ID OFFSET"));
+            };
+            machine.unwatch_cell(array, offset);
+        }
+        "watches" => show_watches(machine),
+        "counters" if args == ["reset"] => actions.reset_counters(),
+        "counters" if actions.counters().is_empty() => println!("no counters"),
+        "counters" => {
+            for (name, count) in actions.counters() {
+                println!("{name}: {count}");
+            }
+        }
+        "r" | "regs" => show_registers(machine, diff),
+        "x" | "array" => {
+            let array = arg(0)?.ok_or_else(|| usage("array ID [START [N]]"))?;
+            let start = arg(1)?.unwrap_or(0);
+            let count = arg(2)?.unwrap_or(16);
+            show_array(machine, diff, actions.charset(), array, start, count);
+        }
+        "diff" => match (args.first().copied(), args.get(1)) {
+            (None, _) => diff.show(machine),
+            (Some("on"), None) => diff.auto = true,
+            (Some("off"), None) => diff.auto = false,
+            (Some("mark"), None) => {
+                diff.base = State::of(machine);
+                diff.follow = false;
+            }
+            (Some("last"), None) => diff.follow = true,
+            (Some("snapshot"), Some(path)) => {
+                diff.base = State::of(&Machine::load_snapshot(path)?);
+                diff.follow = false;
+            }
+            _ => return Err(usage("diff [on|off|mark|last|snapshot FILE]")),
+        },
+        "l" | "list" => {
+            let count = arg(1)?.unwrap_or(11);
+            let center = arg(0)?.unwrap_or(machine.pc());
+            list(machine, center.saturating_sub(count / 2), count);
+        }
+        "set" => {
+            let (Some(target), Some(value)) = (args.first(), arg(1)?) else {
+                return Err(usage("set rN|pc VALUE"));
+            };
+            match *target {
+                "pc" => machine.set_pc(value),
+                reg => match reg.strip_prefix('r').map(str::parse::<usize>) {
+                    Some(Ok(r)) if r < 8 => machine.registers_mut()[r] = value,
+                    _ => return Err(usage("set rN|pc VALUE")),
+                },
+            }
+        }
+        "h" | "help" => print!("{HELP}"),
+        "q" | "quit" => return Ok(false),
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "unknown command {cmd:?}, type h for help"
+            )))
+        }
+    }
+    Ok(true)
+}
+
+fn count(s: &str) -> Result<u64, Error> {
+    s.parse()
+        .map_err(|_| Error::InvalidArgument(format!("expected a count, got {s:?}")))
+}
+
+fn number(s: &str) -> Result<u32, Error> {
+    parse_u32(s).map_err(|_| Error::InvalidArgument(format!("expected a number, got {s:?}")))
+}
+
+fn mode(arg: Option<&&str>) -> Result<Watch, String> {
+    arg.map_or(Ok(Watch::ReadWrite), |mode| parse_mode(mode))
+}
+
+fn usage(usage: &str) -> Error {
+    Error::InvalidArgument(format!("usage: {usage}"))
+}
+
+// Whether the instruction at pc is a LOADPROG that next should run
+// through: one marked as a call, or one that looks like a call.
+fn is_call(machine: &Machine, calls: &BTreeSet<u32>) -> bool {
+    let pc = machine.pc();
+    let Some(word) = machine.array(0).and_then(|a| a.get(pc as usize)) else {
+        return false;
+    };
+    word >> 28 == 12
+        && (calls.contains(&pc) || profile::looks_like_call(pc, *word, machine.registers()))
+}
+
+fn report(
+    machine: &Machine,
+    conditions: &BTreeMap<u32, String>,
+    res: Result<Stop, um_core::Error>,
+) {
+    match res {
+        Ok(Stop::Halt) => println!("program halted"),
+        Ok(Stop::Breakpoint { pc }) => println!("breakpoint at {pc:#x}"),
+        Ok(Stop::Condition { pc, id }) => println!(
+            "condition {id} at {pc:#x}: {}",
+            conditions.get(&id).map_or("", String::as_str)
+        ),
+        Ok(stop @ Stop::Watch { .. }) => {
+            println!("watch: {}", run::describe_watch(&stop).unwrap_or_default())
+        }
+        Ok(_) => {}
+        Err(e) => println!("{} {e}", style("fault:").red()),
+    }
+    show_position(machine);
+}
+
+fn show_core(core: &CoreDump) {
+    println!("{} {}", style("core:").red(), core.fault);
+    if !core.recent.is_empty() {
+        println!("the last {} instructions executed:", core.recent.len());
+        let _ = run::write_recent(&mut io::stdout(), &core.recent);
+    }
+}
+
+fn show_position(machine: &Machine) {
+    list(machine, machine.pc(), 1);
+}
+
+fn list(machine: &Machine, start: u32, count: u32) {
+    let program = machine.array(0).unwrap_or_default();
+    let breakpoints = machine.breakpoints();
+    for addr in start..start.saturating_add(count) {
+        let Some(word) = program.get(addr as usize) else {
+            if addr == machine.pc() {
+                println!(
+                    "=>  {addr:08x}: outside the program ({} platters)",
+                    program.len()
+                );
+            }
+            break;
+        };
+        let line = format!("{addr:08x}: {word:08x}  {}", disasm::mnemonic(*word, false));
+        let mark = if breakpoints.contains(&addr) {
+            '*'
+        } else {
+            ' '
+        };
+        if addr == machine.pc() {
+            println!("{}{mark} {}", style("=>").green(), style(line).bold());
+        } else {
+            println!("  {mark} {line}");
+        }
+    }
+}
+
+fn show_registers(machine: &Machine, diff: &Diff) {
+    println!(
+        "pc={}  executed={}",
+        highlight(
+            format!("{:#010x}", machine.pc()),
+            diff.base.pc != machine.pc()
+        ),
+        machine.executed()
+    );
+    for (i, chunk) in machine.registers().chunks(4).enumerate() {
+        let regs: Vec<String> = chunk
+            .iter()
+            .enumerate()
+            .map(|(j, v)| {
+                let r = i * 4 + j;
+                let value = format!("{v:#010x}");
+                format!("r{r}={}", highlight(value, diff.base.registers[r] != *v))
+            })
+            .collect();
+        println!("{}", regs.join("  "));
+    }
+}
+
+fn highlight(text: String, changed: bool) -> String {
+    if changed {
+        style(text).yellow().bold().to_string()
+    } else {
+        text
+    }
+}
+
+fn show_watches(machine: &Machine) {
+    let arrays = machine.watched_arrays();
+    let cells = machine.watched_cells();
+    if arrays.is_empty() && cells.is_empty() {
+        println!("no watches");
+    }
+    for (array, watch) in arrays {
+        println!("array {array} ({})", mode_name(watch));
+    }
+    for (array, offset, watch) in cells {
+        println!("array {array} offset {offset} ({})", mode_name(watch));
+    }
+}
+
+fn mode_name(watch: Watch) -> &'static str {
+    match watch {
+        Watch::Read => "r",
+        Watch::Write => "w",
+        Watch::ReadWrite => "rw",
+    }
+}
+
+fn show_array(
+    machine: &Machine,
+    diff: &Diff,
+    charset: Charset,
+    array: u32,
+    start: u32,
+    count: u32,
+) {
+    let Some(platters) = machine.array(array) else {
+        println!("array {array} is not active");
+        return;
+    };
+    let end = (start as usize)
+        .saturating_add(count as usize)
+        .min(platters.len());
+    let start = (start as usize).min(end);
+    println!("array {array}: {} platters", platters.len());
+    for (i, row) in platters[start..end].chunks(8).enumerate() {
+        let row_start = start + i * 8;
+        let words: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(j, w)| highlight(format!("{w:08x}"), diff.changed(array, row_start + j, *w)))
+            .collect();
+        // Padded so that the text of a short last row lines up.
+        let pad = 9 * (8 - row.len());
+        println!(
+            "{row_start:08x}: {}{:pad$}  {}",
+            words.join(" "),
+            "",
+            charset.show(row)
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    io::{self, Write},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+};
+
+use um_core::{FlushPolicy, Machine};
+
+use crate::{
+    compare::{show, Compare, Progress},
+    run, DifferentialArgs, Error,
+};
+
+/// Runs the program under the reference implementation and then here,
+/// reporting the first difference in their output or in how they ended.
+/// Returns whether they agreed.
+pub fn differential(args: DifferentialArgs) -> Result<bool, Error> {
+    let [file] = &args.machine.files[..] else {
+        return Err(Error::InvalidArgument(
+            "differential testing needs exactly one program image".into(),
+        ));
+    };
+    if args.machine.resume.is_some() {
+        return Err(Error::InvalidArgument(
+            "the reference can't resume a snapshot".into(),
+        ));
+    }
+    let input = run::input(&args.machine)?;
+
+    let mut child = Command::new(&args.reference)
+        .arg(file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(run::naming(&args.reference))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let feeder = std::thread::spawn(move || match stdin.write_all(&input) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        res => res,
+    });
+    let reference = child.wait_with_output()?;
+    feeder.join().unwrap()?;
+    let expected = reference.stdout;
+
+    let progress = Arc::new(Mutex::new(Progress::default()));
+    let builder = Machine::builder()
+        .stdin(io::empty())
+        .echo(false)
+        .stdout(Compare {
+            expected: expected.clone(),
+            progress: progress.clone(),
+        })
+        .flush_policy(FlushPolicy::Byte);
+    let mut machine = run::load(&args.machine, builder)?;
+    let res = machine.run();
+    let progress = progress.lock().unwrap();
+    let at = format!(
+        "at pc={:#010x} after {} instructions",
+        machine.pc(),
+        machine.executed()
+    );
+
+    let report = if let Some(got) = progress.diverged {
+        let written = progress.written;
+        match expected.get(written) {
+            Some(want) => format!(
+                "output differs at byte {written}: the reference wrote {}, um-32 wrote {} {at}",
+                show(*want),
+                show(got)
+            ),
+            None => format!(
+                "um-32 wrote {} after the reference's {written} bytes of output {at}",
+                show(got)
+            ),
+        }
+    } else if progress.written < expected.len() {
+        let ended = match &res {
+            Ok(()) => "halted".to_string(),
+            Err(e) => format!("stopped ({e})"),
+        };
+        format!(
+            "um-32 {ended} {at}, having written {} of the reference's {} bytes of output",
+            progress.written,
+            expected.len()
+        )
+    } else {
+        match (&res, reference.status.success()) {
+            (Ok(()), false) => {
+                format!("um-32 halted {at}, but the reference {}", reference.status)
+            }
+            (Err(e), true) => {
+                format!("um-32 stopped {at} ({e}), but the reference halted")
+            }
+            _ => {
+                let ended = if res.is_ok() { "halted" } else { "failed" };
+                println!(
+                    "output matches the reference: {} bytes; both {ended}, um-32 {at}",
+                    expected.len()
+                );
+                return Ok(true);
+            }
+        }
+    };
+    eprintln!("{report}");
+    Ok(false)
+}
@@ -0,0 +1,64 @@
+use std::{io::Write, path::PathBuf};
+
+use console::style;
+use um_core::Machine;
+use um_tools::disasm;
+
+use crate::{DisasmArgs, Error};
+
+pub fn disasm(args: DisasmArgs) -> Result<(), Error> {
+    let words = match (args.file, args.snapshot) {
+        (Some(file), None) => disasm::image_words(&std::fs::read(file)?),
+        (None, Some(snapshot)) => {
+            let machine = Machine::load_snapshot(snapshot)?;
+            machine
+                .array(args.array)
+                .ok_or_else(|| {
+                    Error::InvalidArgument(format!("array {} is not active", args.array))
+                })?
+                .to_vec()
+        }
+        _ => unreachable!("clap requires exactly one of FILE and --snapshot"),
+    };
+
+    let start = (args.start as usize).min(words.len());
+    let end = match args.count {
+        Some(count) => start.saturating_add(count as usize).min(words.len()),
+        None => words.len(),
+    };
+    let mut out = std::io::stdout().lock();
+    disasm::disassemble(&mut out, args.start, &words[start..end], args.extensions)?;
+    out.flush()?;
+    Ok(())
+}
+
+pub fn diff(files: &[PathBuf], context: usize, extensions: bool) -> Result<(), Error> {
+    let mut out = std::io::stdout().lock();
+    for pair in files.windows(2) {
+        let old = disasm::image_words(&std::fs::read(&pair[0])?);
+        let new = disasm::image_words(&std::fs::read(&pair[1])?);
+        let mut text = Vec::new();
+        disasm::diff(&mut text, &old, &new, context, extensions)?;
+        writeln!(
+            out,
+            "{}",
+            style(format!("--- {}", pair[0].display())).bold()
+        )?;
+        writeln!(
+            out,
+            "{}",
+            style(format!("+++ {}", pair[1].display())).bold()
+        )?;
+        for line in String::from_utf8_lossy(&text).lines() {
+            let line = match line.as_bytes()[0] {
+                b'-' => style(line).red(),
+                b'+' => style(line).green(),
+                b'@' => style(line).cyan(),
+                _ => style(line),
+            };
+            writeln!(out, "{line}")?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! What a command fails with: an error from the machine, from the
+//! assembler, or one in how the command was used.
+
+use std::io;
+
+use um_tools::asm;
+
+#[derive(Debug)]
+pub enum Error {
+    Machine(um_core::Error),
+    Assembly(asm::Error),
+    // A command line, script or other input the command can't use.
+    InvalidArgument(String),
+}
+
+impl From<um_core::Error> for Error {
+    fn from(e: um_core::Error) -> Self {
+        Self::Machine(e)
+    }
+}
+
+impl From<asm::Error> for Error {
+    fn from(e: asm::Error) -> Self {
+        Self::Assembly(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Machine(um_core::Error::IO(e))
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Machine(e) => e.fmt(f),
+            Self::Assembly(e) => e.fmt(f),
+            Self::InvalidArgument(msg) => write!(f, "invalid argument: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Machine(e) => e.source(),
+            Self::Assembly(_) | Self::InvalidArgument(_) => None,
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! `run --extract`: carves a program image out of a run's output, as the
+//! codex writes the UM program it holds after a banner ending in
+//! `UM program follows colon:`.
+//!
+//! Output up to and including the text that ends the banner goes through
+//! as usual; everything after it, until the program halts, goes to the
+//! file instead.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{run, Error};
+
+/// The text the codex's banner ends with.
+pub const CODEX_MARKER: &str = "UM program follows colon:";
+
+#[derive(Clone)]
+pub struct Extraction(Arc<Mutex<State>>);
+
+struct State {
+    path: PathBuf,
+    marker: Vec<u8>,
+    // The end of the output so far, until the marker is found.
+    seen: Vec<u8>,
+    file: Option<BufWriter<File>>,
+    extracted: u64,
+}
+
+impl Extraction {
+    /// Creates `path` now, so that a run that can't write it fails before
+    /// it starts.
+    pub fn create(path: &Path, marker: &str) -> Result<Extraction, Error> {
+        File::create(path).map_err(run::naming(path))?;
+        Ok(Extraction(Arc::new(Mutex::new(State {
+            path: path.to_path_buf(),
+            marker: marker.as_bytes().to_vec(),
+            seen: Vec::new(),
+            file: None,
+            extracted: 0,
+        }))))
+    }
+
+    /// Passes output to `inner` until the marker, then to the file.
+    pub fn output<W: Write>(&self, inner: W) -> Extract<W> {
+        Extract {
+            inner,
+            extraction: self.clone(),
+        }
+    }
+
+    /// Finishes the file and reports what was extracted on stderr,
+    /// returning false, with the empty file removed, if the output never
+    /// reached the marker.
+    pub fn finish(&self) -> io::Result<bool> {
+        let mut state = self.0.lock().unwrap();
+        let path = state.path.display().to_string();
+        let Some(file) = state.file.as_mut() else {
+            eprintln!(
+                "um-32: the output never reached {:?}, so there was nothing to extract",
+                String::from_utf8_lossy(&state.marker)
+            );
+            std::fs::remove_file(&state.path)?;
+            return Ok(false);
+        };
+        file.flush()?;
+        let extracted = state.extracted;
+        eprintln!("um-32: extracted {extracted} bytes to {path}");
+        if !extracted.is_multiple_of(4) {
+            eprintln!("um-32: that isn't a whole number of platters, so it may be incomplete");
+        }
+        Ok(true)
+    }
+}
+
+pub struct Extract<W> {
+    inner: W,
+    extraction: Extraction,
+}
+
+impl<W: Write> Write for Extract<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.extraction.0.lock().unwrap();
+        let state = &mut *state;
+        if let Some(file) = state.file.as_mut() {
+            file.write_all(buf)?;
+            state.extracted += buf.len() as u64;
+            return Ok(buf.len());
+        }
+        // Up to the end of the marker, if it ends in `buf`.
+        let end = buf.iter().position(|&byte| {
+            state.seen.push(byte);
+            if state.seen.len() > state.marker.len() {
+                state.seen.remove(0);
+            }
+            state.seen == state.marker
+        });
+        let Some(end) = end else {
+            self.inner.write_all(buf)?;
+            return Ok(buf.len());
+        };
+        self.inner.write_all(&buf[..=end])?;
+        self.inner.flush()?;
+        let mut file = BufWriter::new(File::create(&state.path)?);
+        file.write_all(&buf[end + 1..])?;
+        state.extracted = (buf.len() - end - 1) as u64;
+        state.file = Some(file);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self.extraction.0.lock().unwrap();
+        match state.file.as_mut() {
+            Some(file) => file.flush(),
+            None => self.inner.flush(),
+        }
+    }
+}
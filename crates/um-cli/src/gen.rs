@@ -0,0 +1,13 @@
+use std::path::PathBuf;
+
+use um_tools::program;
+
+use crate::{Error, Generated};
+
+pub fn generate(program: Generated, path: PathBuf) -> Result<(), Error> {
+    let image = match program {
+        Generated::Tour => program::tour(),
+    };
+    std::fs::write(path, image)?;
+    Ok(())
+}
@@ -0,0 +1,409 @@
+//! `serve --inspect`: a web page for watching and controlling sessions,
+//! served over HTTP with the endpoints behind it:
+//!
+//! ```text
+//! GET  /                        the page
+//! GET  /sessions                the sessions running, [{"id":1,"peer":"..."}]
+//! GET  /sessions/ID             its pc, registers, statistics and the end
+//!                               of its console output, as JSON
+//! POST /sessions/ID/pause       stops executing instructions until resumed
+//! POST /sessions/ID/resume
+//! POST /sessions/ID/snapshot    a snapshot, as Machine::write_snapshot
+//!                               writes it
+//! ```
+//!
+//! A request about a session interrupts its machine, which carries it out
+//! between two instructions, or while it waits for input. Input the session
+//! has received but the program hasn't read yet is not in its snapshots.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use um_core::{Interrupter, Machine, Stop};
+
+use crate::cast;
+
+const PAGE: &str = include_str!("inspect.html");
+
+// How much of each session's output the page is shown.
+const CONSOLE: usize = 16 * 1024;
+
+// How often a session waiting for input checks for requests.
+pub const POLL: Duration = Duration::from_millis(50);
+
+// How long a request waits for the session to answer.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long a browser has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The sessions running, for the page to look at.
+#[derive(Clone, Default)]
+pub struct Inspector(Arc<Mutex<BTreeMap<u64, Handle>>>);
+
+#[derive(Clone)]
+struct Handle {
+    peer: String,
+    requests: Sender<Request>,
+    shared: Arc<Shared>,
+    interrupter: Interrupter,
+}
+
+enum Request {
+    Status(Sender<String>),
+    Snapshot(Sender<Result<Vec<u8>, um_core::Error>>),
+    Pause,
+    Resume,
+}
+
+/// What a session shares with the inspector through its connection: the
+/// end of its output, and whether a request is waiting for the machine.
+#[derive(Default)]
+pub struct Shared {
+    requested: AtomicBool,
+    console: Mutex<VecDeque<u8>>,
+}
+
+impl Shared {
+    pub fn record(&self, output: &[u8]) {
+        let mut console = self.console.lock().unwrap();
+        console.extend(output);
+        let excess = console.len().saturating_sub(CONSOLE);
+        console.drain(..excess);
+    }
+
+    /// Whether a request is waiting, for a session waiting for input.
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+// What Input waiting for a byte fails with when a request comes, as an
+// ErrorKind::WouldBlock error so that the machine executes the Input again
+// without tracing it twice.
+#[derive(Debug)]
+struct Requested;
+
+impl std::fmt::Display for Requested {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("interrupted for the inspector")
+    }
+}
+
+impl std::error::Error for Requested {}
+
+/// The error for a session's stdin to fail with when a request comes.
+pub fn requested() -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, Requested)
+}
+
+impl Inspector {
+    /// Serves the page and its endpoints on `listener`, on a thread of its
+    /// own, answering each request on another.
+    pub fn start(listener: TcpListener) -> Inspector {
+        let inspector = Inspector::default();
+        let serving = inspector.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let inspector = serving.clone();
+                thread::spawn(move || {
+                    if let Err(e) = inspector.answer(stream) {
+                        eprintln!("um-32: inspector: {e}");
+                    }
+                });
+            }
+        });
+        inspector
+    }
+
+    /// Lists session `id` until the returned [`Inspected`] is dropped.
+    pub fn add(
+        &self,
+        id: u64,
+        peer: String,
+        shared: Arc<Shared>,
+        interrupter: Interrupter,
+    ) -> Inspected {
+        let (requests, received) = mpsc::channel();
+        let handle = Handle {
+            peer,
+            requests,
+            shared: shared.clone(),
+            interrupter,
+        };
+        self.0.lock().unwrap().insert(id, handle);
+        Inspected {
+            id,
+            inspector: self.clone(),
+            requests: received,
+            shared,
+            paused: false,
+        }
+    }
+
+    fn handle(&self, id: &str) -> Option<Handle> {
+        let id = id.parse().ok()?;
+        self.0.lock().unwrap().get(&id).cloned()
+    }
+
+    // Reads one request from `stream` and answers it.
+    fn answer(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        // The headers say nothing the endpoints need.
+        let mut header = String::new();
+        while !matches!(header.as_str(), "\r\n" | "\n") {
+            header.clear();
+            if reader.read_line(&mut header)? == 0 {
+                break;
+            }
+        }
+        let mut words = line.split_whitespace();
+        let (method, target) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+        let path = target.split('?').next().unwrap_or("");
+        let response = self.route(method, path);
+        response.write_to(&stream)
+    }
+
+    fn route(&self, method: &str, path: &str) -> Response {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("GET", [""]) => Response::new(200, "text/html; charset=utf-8", PAGE),
+            ("GET", ["sessions"]) => {
+                let sessions = self.0.lock().unwrap();
+                let list: Vec<String> = sessions
+                    .iter()
+                    .map(|(id, handle)| {
+                        format!(r#"{{"id":{id},"peer":"{}"}}"#, cast::escape(&handle.peer))
+                    })
+                    .collect();
+                Response::json(format!("[{}]", list.join(",")))
+            }
+            ("GET", ["sessions", id]) => match self.handle(id) {
+                Some(handle) => match handle.ask(Request::Status) {
+                    Some(status) => Response::json(status),
+                    None => Response::gone(),
+                },
+                None => Response::not_found(),
+            },
+            ("POST", ["sessions", id, action @ ("pause" | "resume")]) => match self.handle(id) {
+                Some(handle) => {
+                    handle.send(match *action {
+                        "pause" => Request::Pause,
+                        _ => Request::Resume,
+                    });
+                    Response::new(204, "text/plain", "")
+                }
+                None => Response::not_found(),
+            },
+            ("POST", ["sessions", id, "snapshot"]) => match self.handle(id) {
+                Some(handle) => match handle.ask(Request::Snapshot) {
+                    Some(Ok(bytes)) => Response {
+                        status: 200,
+                        content_type: "application/octet-stream",
+                        disposition: Some(format!(
+                            "attachment; filename=\"session-{id}.snapshot\""
+                        )),
+                        body: bytes,
+                    },
+                    Some(Err(e)) => Response::new(500, "text/plain", &format!("{e}\n")),
+                    None => Response::gone(),
+                },
+                None => Response::not_found(),
+            },
+            (_, [""] | ["sessions", ..]) => {
+                Response::new(405, "text/plain", "method not allowed\n")
+            }
+            _ => Response::not_found(),
+        }
+    }
+}
+
+impl Handle {
+    fn send(&self, request: Request) {
+        // Fails only once the session has ended.
+        let _ = self.requests.send(request);
+        self.shared.requested.store(true, Ordering::SeqCst);
+        self.interrupter.interrupt();
+    }
+
+    // Sends the request `make` makes and waits for the answer, or None if
+    // the session ended or took too long.
+    fn ask<T>(&self, make: impl FnOnce(Sender<T>) -> Request) -> Option<T> {
+        let (reply, answer) = mpsc::channel();
+        self.send(make(reply));
+        answer.recv_timeout(REPLY_TIMEOUT).ok()
+    }
+}
+
+/// A session the inspector lists, until it is dropped.
+pub struct Inspected {
+    id: u64,
+    inspector: Inspector,
+    requests: Receiver<Request>,
+    shared: Arc<Shared>,
+    paused: bool,
+}
+
+impl Inspected {
+    /// Runs `machine` as [`Machine::run_until_stop`] would, carrying out
+    /// requests whenever one interrupts the run. While paused, it waits
+    /// for the next request until `deadline`.
+    pub fn run(
+        &mut self,
+        machine: &mut Machine,
+        deadline: Option<Instant>,
+    ) -> Result<Stop, um_core::Error> {
+        loop {
+            match machine.run_until_stop() {
+                Ok(Stop::Interrupted { .. }) => {}
+                Err(um_core::Error::IO(e)) if e.get_ref().is_some_and(|e| e.is::<Requested>()) => {}
+                res => return res,
+            }
+            self.serve(machine, deadline);
+        }
+    }
+
+    fn serve(&mut self, machine: &mut Machine, deadline: Option<Instant>) {
+        let interrupter = machine.interrupter();
+        loop {
+            self.shared.requested.store(false, Ordering::SeqCst);
+            loop {
+                let request = match (self.paused, deadline) {
+                    (false, _) => self.requests.try_recv().ok(),
+                    (true, None) => self.requests.recv().ok(),
+                    (true, Some(deadline)) => {
+                        let left = deadline.saturating_duration_since(Instant::now());
+                        match self.requests.recv_timeout(left) {
+                            Ok(request) => Some(request),
+                            // The run stops again for the deadline.
+                            Err(RecvTimeoutError::Timeout) => return,
+                            Err(RecvTimeoutError::Disconnected) => None,
+                        }
+                    }
+                };
+                let Some(request) = request else {
+                    break;
+                };
+                match request {
+                    Request::Status(reply) => {
+                        let _ = reply.send(self.status(machine));
+                    }
+                    Request::Snapshot(reply) => {
+                        let mut bytes = Vec::new();
+                        let _ = reply.send(machine.write_snapshot(&mut bytes).map(|()| bytes));
+                    }
+                    Request::Pause => self.paused = true,
+                    Request::Resume => self.paused = false,
+                }
+            }
+            // An interrupt for a request already carried out is stale; one
+            // for a request sent since is not.
+            interrupter.take();
+            if !self.shared.requested() {
+                return;
+            }
+        }
+    }
+
+    fn status(&self, machine: &Machine) -> String {
+        let console = {
+            let console = self.shared.console.lock().unwrap();
+            let (front, back) = console.as_slices();
+            String::from_utf8_lossy(&[front, back].concat()).into_owned()
+        };
+        let registers: Vec<String> = machine.registers().iter().map(u32::to_string).collect();
+        let memory = machine.memory_stats();
+        format!(
+            concat!(
+                r#"{{"id":{},"paused":{},"pc":{},"executed":{},"registers":[{}],"#,
+                r#""live_arrays":{},"live_platters":{},"peak_platters":{},"allocations":{},"#,
+                r#""console":"{}"}}"#
+            ),
+            self.id,
+            self.paused,
+            machine.pc(),
+            machine.executed(),
+            registers.join(","),
+            memory.live_arrays,
+            memory.live_platters,
+            memory.peak_platters,
+            memory.allocations,
+            cast::escape(&console),
+        )
+    }
+}
+
+impl Drop for Inspected {
+    fn drop(&mut self) {
+        self.inspector.0.lock().unwrap().remove(&self.id);
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    disposition: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: &str) -> Response {
+        Response {
+            status,
+            content_type,
+            disposition: None,
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn json(body: String) -> Response {
+        Response::new(200, "application/json", &body)
+    }
+
+    fn not_found() -> Response {
+        Response::new(404, "text/plain", "no such session\n")
+    }
+
+    // The session ended while the request was waiting, or is stuck.
+    fn gone() -> Response {
+        Response::new(503, "text/plain", "the session did not answer\n")
+    }
+
+    fn write_to(&self, mut stream: &TcpStream) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            204 => "No Content",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            500 => "Internal Server Error",
+            _ => "Service Unavailable",
+        };
+        let mut head = format!(
+            "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        );
+        head.push_str("Cache-Control: no-store\r\nConnection: close\r\n");
+        if let Some(disposition) = &self.disposition {
+            head.push_str(&format!("Content-Disposition: {disposition}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
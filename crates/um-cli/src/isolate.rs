@@ -0,0 +1,115 @@
+use std::ffi::OsString;
+
+use clap::Args;
+
+#[cfg(unix)]
+use crate::{Error, EXIT_LIMIT};
+
+#[derive(Args)]
+pub struct IsolationArgs {
+    /// Run the machine in a child process under resource limits
+    #[arg(long)]
+    pub isolate: bool,
+    /// CPU time limit in seconds when isolated
+    #[arg(long, value_name = "SECONDS", requires = "isolate")]
+    pub cpu_limit: Option<u64>,
+    /// Address space limit in MiB when isolated [default: 4096]
+    #[arg(long, value_name = "MIB", requires = "isolate")]
+    pub memory_limit: Option<u64>,
+}
+
+const DEFAULT_MEMORY_LIMIT: u64 = 4096;
+
+// The child gets the same command line minus the isolation options, so it
+// runs the machine directly.
+fn child_args(args: &[OsString]) -> Vec<OsString> {
+    let mut rest = Vec::new();
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--isolate") => {}
+            Some("--cpu-limit" | "--memory-limit") => {
+                args.next();
+            }
+            Some(a) if a.starts_with("--cpu-limit=") || a.starts_with("--memory-limit=") => {}
+            _ => rest.push(arg.clone()),
+        }
+    }
+    rest
+}
+
+// Re-runs this interpreter in a child process with CPU time and
+// address-space limits applied. The child inherits stdin and stdout, and
+// its exit status becomes ours.
+#[cfg(unix)]
+pub fn isolate(limits: &IsolationArgs, args: &[OsString]) -> Result<(), Error> {
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    type Resource = libc::__rlimit_resource_t;
+    #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+    type Resource = libc::c_int;
+
+    fn set_limit(resource: Resource, soft: u64, hard: u64) -> std::io::Result<()> {
+        let limit = libc::rlimit {
+            rlim_cur: soft as libc::rlim_t,
+            rlim_max: hard as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    let cpu_seconds = limits.cpu_limit;
+    let memory_mib = limits.memory_limit.unwrap_or(DEFAULT_MEMORY_LIMIT);
+    let memory = memory_mib << 20;
+    let mut cmd = std::process::Command::new(std::env::current_exe()?);
+    cmd.args(child_args(args));
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(secs) = cpu_seconds {
+                // SIGXCPU at the soft limit, SIGKILL a second later if the
+                // signal is ignored.
+                set_limit(libc::RLIMIT_CPU, secs, secs + 1)?;
+            }
+            set_limit(libc::RLIMIT_AS, memory, memory)
+        });
+    }
+
+    let mut child = cmd.spawn()?;
+    // Ctrl-C reaches the child too, which saves its state and exits; this
+    // process waits for it to finish.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+    }
+    let status = child.wait()?;
+    let code = match status.signal() {
+        Some(libc::SIGXCPU) | Some(libc::SIGKILL) if cpu_seconds.is_some() => {
+            eprintln!(
+                "um-32: stopped after exceeding the CPU time limit ({}s)",
+                cpu_seconds.unwrap_or_default()
+            );
+            EXIT_LIMIT
+        }
+        Some(libc::SIGABRT) | Some(libc::SIGSEGV) => {
+            eprintln!(
+                "um-32: aborted, most likely by exceeding the memory limit ({memory_mib} MiB)"
+            );
+            EXIT_LIMIT
+        }
+        Some(sig) => {
+            eprintln!("um-32: killed by signal {sig}");
+            128 + sig
+        }
+        None => status.code().unwrap_or_default(),
+    };
+    std::process::exit(code);
+}
+
+#[cfg(not(unix))]
+pub fn isolate(_: &IsolationArgs, _: &[OsString]) -> Result<(), Error> {
+    Err(Error::InvalidArgument(
+        "--isolate is only supported on Unix".into(),
+    ))
+}
@@ -0,0 +1,212 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    automate::{Automation, Step},
+    script::unquote,
+};
+
+/// Login profiles, read by `--profiles`, for `--login NAME` to log in to
+/// UMIX as NAME once it boots and run the profile's commands, before
+/// handing over to the terminal.
+///
+/// Each profile is a `[NAME]` line, then lines of `user "USER"`, an
+/// optional `password "PASSWORD"`, and any number of `run "COMMAND"`, for
+/// commands to run at the shell prompt in turn. Lines starting with `#`
+/// are comments:
+///
+/// ```text
+/// [guest]
+/// user "guest"
+///
+/// [ftd]
+/// user "ftd"
+/// password "falderal90"
+/// run "cd code"
+/// run "ls"
+/// ```
+pub struct Profiles {
+    profiles: HashMap<String, Profile>,
+}
+
+struct Profile {
+    // Each with the line it was on. Every profile has a user once parsed.
+    user: Option<(usize, Vec<u8>)>,
+    password: Option<(usize, Vec<u8>)>,
+    commands: Vec<(usize, Vec<u8>)>,
+}
+
+// What UMIX prints to ask for each.
+const LOGIN_PROMPT: &[u8] = b"login: ";
+const PASSWORD_PROMPT: &[u8] = b"password: ";
+const SHELL_PROMPT: &[u8] = b"% ";
+
+impl Profiles {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::parse(&text).map_err(|e| format!("{}:{e}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        // Each profile, with the line its name is on.
+        let mut read: Vec<(usize, String, Profile)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err(format!("{}: a profile needs a name", i + 1));
+                }
+                if read.iter().any(|(_, other, ..)| other == name) {
+                    return Err(format!("{}: there is already a profile {name:?}", i + 1));
+                }
+                let profile = Profile {
+                    user: None,
+                    password: None,
+                    commands: Vec::new(),
+                };
+                read.push((i + 1, name.to_string(), profile));
+                continue;
+            }
+            let Some((_, _, profile)) = read.last_mut() else {
+                return Err(format!(
+                    "{}: expected a [NAME] line to start a profile",
+                    i + 1
+                ));
+            };
+            let (word, arg) = line.split_once(' ').unwrap_or((line, ""));
+            let value = unquote(arg.trim())
+                .ok_or_else(|| format!("{}: expected a quoted string", i + 1))?;
+            match word {
+                "user" if profile.user.is_none() => profile.user = Some((i + 1, value)),
+                "password" if profile.password.is_none() => profile.password = Some((i + 1, value)),
+                "user" | "password" => return Err(format!("{}: {word} is already set", i + 1)),
+                "run" => profile.commands.push((i + 1, value)),
+                _ => return Err(format!("{}: unknown setting {word:?}", i + 1)),
+            }
+        }
+        let mut profiles = HashMap::new();
+        for (line, name, profile) in read {
+            if profile.user.is_none() {
+                return Err(format!("{line}: profile {name:?} has no user"));
+            }
+            profiles.insert(name, profile);
+        }
+        Ok(Profiles { profiles })
+    }
+
+    /// Automation that logs in with the profile `name`, then runs its
+    /// commands.
+    pub fn login(&self, name: &str) -> Result<Automation, String> {
+        let Some(profile) = self.profiles.get(name) else {
+            let mut names: Vec<_> = self.profiles.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            return Err(format!(
+                "no login profile {name:?}; there are {}",
+                names.join(", ")
+            ));
+        };
+        let line = |text: &[u8]| [text, b"\n"].concat();
+        let (at, user) = profile.user.as_ref().expect("checked when parsed");
+        let mut steps = vec![
+            (*at, Step::Expect(LOGIN_PROMPT.to_vec())),
+            (*at, Step::Send(line(user))),
+        ];
+        if let Some((at, password)) = &profile.password {
+            steps.push((*at, Step::Expect(PASSWORD_PROMPT.to_vec())));
+            steps.push((*at, Step::Send(line(password))));
+        }
+        for (at, command) in &profile.commands {
+            steps.push((*at, Step::Expect(SHELL_PROMPT.to_vec())));
+            steps.push((*at, Step::Send(line(command))));
+        }
+        Ok(Automation::new(format!("login profile {name:?}"), steps))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use super::*;
+
+    const PROFILES: &str = "\
+[guest]
+user \"guest\"
+
+# Logs in and looks around.
+[ftd]
+user \"ftd\"
+password \"falderal90\"
+run \"cd code\"
+run \"ls\"
+";
+
+    fn error(text: &str) -> String {
+        match Profiles::parse(text) {
+            Ok(_) => panic!("{text:?} parsed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn bad_profiles() {
+        assert_eq!(error("[ ]"), "1: a profile needs a name");
+        assert_eq!(
+            error("[a]\nuser \"a\"\n[a]"),
+            "3: there is already a profile \"a\""
+        );
+        assert_eq!(
+            error("# users\nuser \"a\""),
+            "2: expected a [NAME] line to start a profile"
+        );
+        assert_eq!(
+            error("[a]\nuser \"a\"\nuser \"b\""),
+            "3: user is already set"
+        );
+        assert_eq!(
+            error("[a]\nuser \"a\"\npassword \"b\"\npassword \"c\""),
+            "4: password is already set"
+        );
+        assert_eq!(error("[a]\nshell \"sh\""), "2: unknown setting \"shell\"");
+        assert_eq!(error("[a]\nuser a"), "2: expected a quoted string");
+        assert_eq!(
+            error("[a]\nuser \"a\"\n[b]\nrun \"ls\""),
+            "3: profile \"b\" has no user"
+        );
+    }
+
+    #[test]
+    fn login() {
+        let profiles = Profiles::parse(PROFILES).unwrap();
+        match profiles.login("root") {
+            Ok(_) => panic!("logged in as root"),
+            Err(e) => assert_eq!(e, "no login profile \"root\"; there are ftd, guest"),
+        }
+
+        let automation = profiles.login("ftd").unwrap();
+        let mut input = automation.input(io::empty());
+        let mut output = automation.output(io::sink());
+        let mut buf = [0; 16];
+        let e = input.read(&mut buf).unwrap_err();
+        assert!(
+            e.to_string().starts_with("login profile \"ftd\" line 6: "),
+            "{e}"
+        );
+        for (prompt, sent) in [
+            ("login: ", "ftd\n"),
+            ("password: ", "falderal90\n"),
+            ("% ", "cd code\n"),
+            ("% ", "ls\n"),
+        ] {
+            output.write_all(prompt.as_bytes()).unwrap();
+            let n = input.read(&mut buf).unwrap();
+            assert_eq!(&buf[..n], sent.as_bytes());
+        }
+        // Then the terminal takes over.
+        assert_eq!(input.read(&mut buf).unwrap(), 0);
+    }
+}
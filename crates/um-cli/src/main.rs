@@ -0,0 +1,807 @@
+use std::{ffi::OsString, num::NonZeroUsize, path::PathBuf, time::Duration};
+
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use um_core::{FlushPolicy, MachineBuilder, Watch};
+
+use crate::{action::Action, charset::Charset, error::Error};
+
+mod action;
+mod asm;
+mod automate;
+mod bench;
+mod cast;
+mod charset;
+mod compare;
+mod compile;
+mod condition;
+mod console;
+mod coverage;
+#[cfg(feature = "dap")]
+mod dap;
+mod debug;
+mod differential;
+mod disasm;
+mod error;
+mod extract;
+mod gen;
+#[cfg(feature = "inspector")]
+mod inspect;
+mod isolate;
+mod login;
+mod profile;
+mod raw;
+mod readline;
+mod run;
+mod script;
+mod serve;
+mod signals;
+mod state;
+mod trace;
+mod verify;
+
+/// An interpreter and toolkit for the UM-32 Universal Machine.
+#[derive(Parser)]
+#[command(
+    version,
+    after_help = "Exit status: 0 on success, 1 for other errors, 2 for an invalid command line, \
+                  3 when a file is not found, 4 when the program faults, 5 when it exceeds a \
+                  limit, 6 for I/O errors and 130 when interrupted."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a program (the default when no subcommand is given)
+    ///
+    /// Ctrl-C stops the program cleanly, saving a snapshot if --save is
+    /// given, and SIGUSR1 writes its pc, registers and memory statistics to
+    /// stderr without stopping it.
+    Run(Box<RunArgs>),
+    /// Run a program under an interactive debugger
+    Debug(DebugArgs),
+    /// Time a program, such as sandmark.umz, and check its output
+    ///
+    /// Reports the wall time, the instructions executed and the MIPS of each
+    /// run, and with --repeat the fastest, median and slowest run. The
+    /// program's output is compared with --expect, or with the file next
+    /// to the first image with the extension `.out` if there is one.
+    Bench(BenchArgs),
+    /// Run a program under a reference UM implementation and then here, and
+    /// compare their output
+    ///
+    /// The reference is run as `REFERENCE FILE` with the --input files on
+    /// its stdin. The first byte of output that differs is reported with
+    /// the pc and instruction count of the Output instruction that wrote
+    /// it, as are output one of them stops short of and a difference in
+    /// whether they halted or failed. Exits with status 1 if they differ.
+    Differential(DifferentialArgs),
+    /// Run a program for clients connecting over TCP, with its console on
+    /// the connection, so that it can be used with `nc HOST PORT`
+    ///
+    /// Each client gets a fresh machine, loaded from the program images or
+    /// the --resume snapshot. Disconnecting ends its input, unless
+    /// --reconnect is given; the connection is closed when the program
+    /// halts or fails, and then the next client is served. With --sessions,
+    /// several clients are served at once, each with a machine of its own.
+    /// With --websocket, clients such as web pages connect with the
+    /// WebSocket protocol instead.
+    Serve(ServeArgs),
+    /// Serve the Debug Adapter Protocol on stdin and stdout, for debugging
+    /// from an editor such as VS Code
+    ///
+    /// The editor's launch configuration names the program and options:
+    /// `program` (a path or list of paths), `resume` (a snapshot), `input`
+    /// (text to queue as console input), `inputFiles`, `replay` (an input
+    /// journal to take input from), `entry`, `extensions`,
+    /// `stopOnEntry`, and `history` (keep history for stepping back). The
+    /// program is shown as the disassembly of array 0, one platter per line.
+    /// Lines typed into the debug console are sent to the program as input;
+    /// lines starting with `?` are evaluated instead, e.g. `?r3`, `?1[0x10]`
+    /// or `?pc == 0x40 && r0 != 0`.
+    #[cfg(feature = "dap")]
+    Dap,
+    /// Assemble a text source file into a program image
+    Asm {
+        source: PathBuf,
+        /// Image file to write
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// List the instructions in a program image or a snapshot's array
+    Disasm(DisasmArgs),
+    /// Translate a program image into a standalone Rust program, to build
+    /// with `rustc -O` for running a fixed program as fast as possible
+    ///
+    /// Code that the program amends, or that it loads from another array,
+    /// is interpreted by the translated program instead, except for the
+    /// stages given with --stage.
+    Compile {
+        file: PathBuf,
+        /// Rust source file to write
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+        /// Also translate a program the image loads from another array, as
+        /// written by `run --dump-overlays`; may be repeated
+        #[arg(long = "stage", value_name = "FILE")]
+        stages: Vec<PathBuf>,
+    },
+    /// Compare the instructions in program images, such as overlays written
+    /// by `run --dump-overlays`, each against the next
+    DisasmDiff {
+        /// Program images, in order
+        #[arg(num_args = 2.., required = true)]
+        files: Vec<PathBuf>,
+        /// Unchanged instructions to show around each change
+        #[arg(short = 'U', long, value_name = "N", default_value_t = 3)]
+        context: usize,
+        /// Show opcode 14 as Host Call, as with the run options'
+        /// --extensions
+        #[arg(long)]
+        extensions: bool,
+    },
+    /// Check a program image before running it: whether its platters
+    /// decode, where its code and data seem to be, and whether code runs on
+    /// into data or off the end
+    ///
+    /// Exits with status 1 if the image is empty, has bytes after its last
+    /// whole platter, or ends in code that runs off the end.
+    Verify(VerifyArgs),
+    /// Write one of the bundled program images
+    Gen { program: Generated, output: PathBuf },
+    /// Work with snapshot files
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+}
+
+/// Options for setting up a machine, shared by `run` and `debug`.
+#[derive(Args)]
+struct MachineArgs {
+    /// Program images, concatenated into array 0
+    #[arg(required_unless_present = "resume")]
+    files: Vec<PathBuf>,
+    /// Start from a snapshot instead of a fresh machine
+    #[arg(long, value_name = "FILE")]
+    resume: Option<PathBuf>,
+    /// Queue STRING as console input, before any --input files, such as a
+    /// decryption key the program asks for
+    #[arg(long, value_name = "STRING")]
+    key: Option<String>,
+    /// Queue the key that unpacks the contest's codex.umz, and the command
+    /// that then dumps the UM image it holds, as --key would
+    #[arg(long, conflicts_with = "key")]
+    codex: bool,
+    /// Queue the contents of FILE as console input; may be repeated
+    #[arg(long = "input", value_name = "FILE")]
+    inputs: Vec<PathBuf>,
+    /// Record each byte of console input the program consumes, with the
+    /// instruction count it was consumed at, and the program's output, in
+    /// FILE, for --replay and `run --verify`
+    #[arg(long, value_name = "FILE")]
+    journal: Option<PathBuf>,
+    /// Take console input from a --journal recording instead, repeating
+    /// that run exactly; the run fails if it consumes input anywhere else
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["key", "codex", "inputs"])]
+    replay: Option<PathBuf>,
+    /// Start execution at PC instead of 0
+    #[arg(long, value_name = "PC", value_parser = parse_u32)]
+    entry: Option<u32>,
+    /// Write a line describing each instruction to stderr before it
+    /// executes
+    #[arg(long)]
+    trace: bool,
+    /// Largest single allocation the program may make, in platters
+    #[arg(long, value_name = "N", value_parser = parse_u32, default_value_t = MachineBuilder::DEFAULT_MAX_ALLOC)]
+    max_alloc: u32,
+    /// Most platters the program may have allocated at once, across all
+    /// its arrays
+    #[arg(long, value_name = "N")]
+    max_memory: Option<u64>,
+    /// Stop with an error when the program jumps to the instruction doing
+    /// the jumping, which would otherwise spin there until interrupted
+    #[arg(long)]
+    detect_hangs: bool,
+    /// Make opcode 14 Host Call, which gives the program the time, random
+    /// numbers and the host's files, instead of an invalid opcode
+    #[arg(long)]
+    extensions: bool,
+    /// How memory dumps and `:c` in action formats show platters holding
+    /// characters. Control characters are shown in caret notation, as `^J`
+    #[arg(long, value_name = "CHARSET", value_enum, default_value_t = Charset::Ascii)]
+    display_charset: Charset,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Replay the input recorded in a --journal transcript and check that
+    /// the program writes the recorded output again, byte for byte,
+    /// reporting the offset and pc of the first difference
+    #[arg(
+        long,
+        value_name = "TRANSCRIPT",
+        conflicts_with_all = ["journal", "replay", "key", "codex", "inputs"]
+    )]
+    verify: Option<PathBuf>,
+    /// Save a snapshot to FILE when console input reaches end of file,
+    /// stopping there instead of giving the program the end-of-input value;
+    /// when --max-steps or --timeout stops the run, or on Ctrl-C
+    #[arg(long, value_name = "FILE")]
+    save: Option<PathBuf>,
+    /// Stop after executing N instructions, reporting the pc and the count
+    /// on stderr. Instructions executed before a --save snapshot that is
+    /// resumed count too
+    #[arg(long, value_name = "N")]
+    max_steps: Option<u64>,
+    /// Stop after running for SECONDS, which may be fractional, reporting
+    /// the pc on stderr. Time spent waiting for console input counts, but
+    /// the run only stops once the program is executing again
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+    /// Also write program output to FILE
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Write each program loaded from another array to DIR
+    #[arg(long, value_name = "DIR")]
+    dump_overlays: Option<PathBuf>,
+    /// Report accesses to array ID on stderr: r for reads, w for writes, rw
+    /// (the default) for both; allocation and abandonment are always
+    /// reported. May be repeated
+    #[arg(long = "watch-array", value_name = "ID[:r|w|rw]", value_parser = parse_watch)]
+    watch_arrays: Vec<(u32, Watch)>,
+    /// Report reads (r), writes (w) or both (rw, the default) of platter
+    /// OFFSET of array ID on stderr. May be repeated
+    #[arg(long = "watch-cell", value_name = "ID:OFFSET[:r|w|rw]", value_parser = parse_cell_watch)]
+    watch_cells: Vec<((u32, u32), Watch)>,
+    /// Set a breakpoint at PC that carries out ACTION and continues; may be
+    /// repeated. ACTION is `log FORMAT` to write a message to stderr,
+    /// `count NAME` to count hits (reported at the end), `snapshot PATH` to
+    /// save a snapshot, or `exec COMMAND` to run a shell command, which ends
+    /// the run if it fails. FORMAT and PATH may include `{pc}`,
+    /// `{executed}`, `{r0}`-`{r7}` or `{ID[OFFSET]}`, with `:x`, `:d` or
+    /// `:c` for hex, decimal or a character
+    #[arg(long = "action", value_name = "PC:ACTION", value_parser = parse_action)]
+    actions: Vec<(u32, Action)>,
+    /// Define a counter, so that it is reported at the end even if no
+    /// action counts it; may be repeated
+    #[arg(long = "counter", value_name = "NAME")]
+    counters: Vec<String>,
+    /// Feed console input from an input script, then from the terminal
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+    /// Record the lines typed into the console as an input script
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+    /// Answer the program's output as an automation script says, e.g.
+    /// sending a password once it prints `login: `, then hand over to the
+    /// terminal. Lines are `expect "TEXT"`, `send "TEXT"`, or
+    /// `on "TEXT" send "REPLY"` to answer TEXT whenever it is printed
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["script", "verify", "replay"])]
+    automate: Option<PathBuf>,
+    /// Log in to UMIX once it boots with the profile NAME from --profiles,
+    /// and run the profile's commands, then hand over to the terminal
+    #[arg(
+        long,
+        value_name = "NAME",
+        requires = "profiles",
+        conflicts_with_all = ["automate", "script", "verify", "replay"]
+    )]
+    login: Option<String>,
+    /// Read --login profiles from FILE: a `[NAME]` line for each, then
+    /// `user "USER"`, optionally `password "PASSWORD"`, and a
+    /// `run "COMMAND"` line for each command to run once logged in
+    #[arg(long, value_name = "FILE")]
+    profiles: Option<PathBuf>,
+    /// Record the session's console output, with its timing, as an
+    /// asciinema cast for `asciinema play`
+    #[arg(long, value_name = "FILE")]
+    cast: Option<PathBuf>,
+    /// Also show the input the program reads in the --cast recording, as
+    /// the terminal echoed it
+    #[arg(long, requires = "cast")]
+    cast_input: bool,
+    /// Write the output that follows the codex's banner, the UM image it
+    /// dumps, to FILE instead of the terminal, as `--codex --extract
+    /// codex.um` does
+    #[arg(long, value_name = "FILE")]
+    extract: Option<PathBuf>,
+    /// The text --extract takes to end the banner
+    #[arg(long, value_name = "TEXT", requires = "extract", default_value = extract::CODEX_MARKER)]
+    extract_after: String,
+    /// Put the terminal in raw mode, passing each key to the program as it
+    /// is pressed, for UMIX's editors and games. The terminal no longer
+    /// echoes what is typed (add --echo for programs that don't either),
+    /// Backspace sends BS, the arrow keys send ^P ^N ^F ^B, and Ctrl-D ends
+    /// the input
+    #[arg(long, conflicts_with_all = ["script", "record", "readline", "history"])]
+    raw: bool,
+    /// Edit console input a line at a time before the program reads it,
+    /// with the usual Emacs keys: the arrow keys, kill and yank with ^K ^U
+    /// ^W and ^Y, and ^R to search the history
+    #[arg(long)]
+    readline: bool,
+    /// Keep the history of lines typed with --readline in FILE, which it
+    /// implies
+    #[arg(long, value_name = "FILE")]
+    history: Option<PathBuf>,
+    /// Leave out the expect and prompt lines that --record infers from the
+    /// output preceding each input line
+    #[arg(long, requires = "record")]
+    no_anchors: bool,
+    /// The text the program's output ends with when it waits at its shell
+    /// prompt, which `prompt` lines in input scripts check for
+    #[arg(long, value_name = "TEXT", default_value = "% ")]
+    prompt: String,
+    /// Pass lines starting with `~` to the program instead of treating them
+    /// as console commands (see `~help`); commands are only read from a
+    /// terminal anyway
+    #[arg(long)]
+    no_console_commands: bool,
+    /// Count and time the instructions executed with each opcode, and write
+    /// a table of them and the counters to stderr, or to FILE, when the run
+    /// ends. Makes the machine several times slower
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true, default_missing_value = "-")]
+    stats: Option<PathBuf>,
+    /// Write the --stats report as a table, as JSON, or in the Prometheus
+    /// text format, for a node exporter's textfile collector to pick up
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = StatsFormat::Text, requires = "stats")]
+    stats_format: StatsFormat,
+    /// Report the arrays and platters live when the run ends, the most
+    /// live at once, the allocations made and the free list on stderr
+    #[arg(long)]
+    memory_stats: bool,
+    /// Record the pc of every allocation and abandonment, and list on
+    /// stderr when the run ends the arrays never abandoned, grouped by the
+    /// pc that allocated them
+    #[arg(long)]
+    audit_allocations: bool,
+    /// Count how often each pc executes, and list the N (default 20) most
+    /// executed on stderr when the run ends
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "20")]
+    hot_spots: Option<usize>,
+    /// Count how often each pc executes, and write every pc that did with
+    /// its count to FILE when the run ends, one `PC COUNT` line each
+    #[arg(long, value_name = "FILE")]
+    pc_histogram: Option<PathBuf>,
+    /// Write which platters of array 0 were executed to FILE when the run
+    /// ends, as ranges of executed and never executed platters
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<PathBuf>,
+    /// Add the platters executed in this run to those already in the
+    /// --coverage FILE, to cover several runs
+    #[arg(long, requires = "coverage")]
+    coverage_merge: bool,
+    /// Count executed instructions by call stack, and write them to FILE
+    /// in the folded format that inferno and flamegraph.pl turn into flame
+    /// graphs. Calls are guessed: a LOADPROG within array 0 while another
+    /// register holds the address after it, returning when control gets
+    /// back there. A call made again before it returns is folded into the
+    /// first
+    #[arg(long, value_name = "FILE")]
+    flamegraph: Option<PathBuf>,
+    /// If the program faults, save a core file to FILE: a snapshot of the
+    /// machine at the faulting instruction with the error and the last 64
+    /// instructions executed, which `um-32 debug --resume FILE` shows.
+    /// Keeping the instructions slows the run down
+    #[arg(long, value_name = "FILE")]
+    core: Option<PathBuf>,
+    /// When the run fails, also show the last 64 instructions executed on
+    /// stderr. Keeping them slows the run down
+    #[arg(long)]
+    verbose_errors: bool,
+    /// If the interpreter panics, which is a bug in um-32, save a snapshot
+    /// of the machine to FILE before exiting, so that the run can be
+    /// resumed and the bug reproduced. The snapshot is taken at the
+    /// instruction that was executing
+    #[arg(long, value_name = "FILE")]
+    panic_save: Option<PathBuf>,
+    /// Keep the last N instructions executed and, if the interpreter
+    /// panics, write them to the --panic-save FILE with `.trace` appended,
+    /// in the format of --trace-file
+    #[arg(long, value_name = "N", requires = "panic_save")]
+    panic_trace: Option<usize>,
+    /// What to do when the program's output is a pipe that closes, as in
+    /// `um-32 prog.um | head`
+    #[arg(long, value_name = "POLICY", value_enum, default_value_t = SigpipePolicy::Halt)]
+    on_sigpipe: SigpipePolicy,
+    /// Also write each input character the program reads to stdout, for
+    /// terminals that do not echo what is typed
+    #[arg(long)]
+    echo: bool,
+    /// When to write program output: after every byte, at each newline,
+    /// before the program reads input, or only when it halts. Defaults to
+    /// byte when stdout is a terminal and newline otherwise
+    #[arg(long, value_name = "POLICY", value_enum)]
+    flush: Option<Flush>,
+    #[command(flatten)]
+    trace: trace::TraceArgs,
+    #[command(flatten)]
+    isolation: isolate::IsolationArgs,
+}
+
+#[derive(Args)]
+struct DebugArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Stop before executing the instruction at PC; may be repeated
+    #[arg(long = "break", value_name = "PC", value_parser = parse_u32)]
+    breakpoints: Vec<u32>,
+    /// Set a breakpoint at PC that carries out ACTION and continues, as for
+    /// `run --action`; may be repeated
+    #[arg(long = "action", value_name = "PC:ACTION", value_parser = parse_action)]
+    actions: Vec<(u32, Action)>,
+    /// Define a counter for the `counters` command; may be repeated
+    #[arg(long = "counter", value_name = "NAME")]
+    counters: Vec<String>,
+    /// Stop before any instruction where CONDITION holds, e.g.
+    /// `pc == 0x1234 && r3 == 0` or `r7 changes`; may be repeated
+    #[arg(long = "break-if", value_name = "CONDITION")]
+    conditions: Vec<String>,
+    /// Keep history from the start so that the debugger can go back
+    /// (the `history on` command)
+    #[arg(long)]
+    history: bool,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Transcript the program's output must match
+    #[arg(long, value_name = "FILE")]
+    expect: Option<PathBuf>,
+    /// Run the program N times
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    repeat: usize,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Address and port to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:2300")]
+    listen: String,
+    /// Keep the machine when the client disconnects, for the next client
+    /// to carry on with, instead of giving it the end of input
+    #[arg(long, conflicts_with = "sessions")]
+    reconnect: bool,
+    /// Stop after the first session, exiting with its status
+    #[arg(long, conflicts_with = "sessions")]
+    once: bool,
+    /// Serve up to N clients at once, each with a machine of its own,
+    /// turning away any more
+    #[arg(long, value_name = "N")]
+    sessions: Option<NonZeroUsize>,
+    /// End each session after SECONDS, which may be fractional, whether
+    /// the program is running or waiting for input
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    session_timeout: Option<Duration>,
+    /// When to write program output to the client: after every byte, at
+    /// each newline, before the program reads input, or only when it halts
+    #[arg(long, value_name = "POLICY", value_enum, default_value_t = Flush::Newline)]
+    flush: Flush,
+    /// Speak the WebSocket protocol, for clients such as web pages: input
+    /// may come in text or binary messages, and output goes out in binary
+    /// messages
+    #[cfg(feature = "websocket")]
+    #[arg(long)]
+    websocket: bool,
+    /// Serve a web page on ADDR that shows each session's console,
+    /// registers and statistics, with buttons to pause, resume and
+    /// snapshot it. Anyone who can reach ADDR can control every session
+    #[cfg(feature = "inspector")]
+    #[arg(long, value_name = "ADDR")]
+    inspect: Option<String>,
+}
+
+#[derive(Args)]
+struct DifferentialArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// The reference implementation's executable
+    #[arg(long, value_name = "REFERENCE")]
+    reference: PathBuf,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// Program image to check
+    file: PathBuf,
+    /// Count opcode 14, Host Call, as an instruction, as with the run
+    /// options' --extensions
+    #[arg(long)]
+    extensions: bool,
+    /// Write the report as a single line of JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct DisasmArgs {
+    /// Program image to list
+    #[arg(required_unless_present = "snapshot", conflicts_with = "snapshot")]
+    file: Option<PathBuf>,
+    /// List an array of the machine saved in a snapshot instead
+    #[arg(long, value_name = "FILE")]
+    snapshot: Option<PathBuf>,
+    /// Array to list from the snapshot
+    #[arg(long, value_name = "ID", default_value_t = 0, requires = "snapshot", value_parser = parse_u32)]
+    array: u32,
+    /// Address of the first platter to list
+    #[arg(long, value_name = "ADDR", default_value_t = 0, value_parser = parse_u32)]
+    start: u32,
+    /// Number of platters to list
+    #[arg(long, value_name = "N", value_parser = parse_u32)]
+    count: Option<u32>,
+    /// Show opcode 14 as Host Call, as with the run options' --extensions
+    #[arg(long)]
+    extensions: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SigpipePolicy {
+    /// Stop as if the program had halted: counters are reported and --save
+    /// saves a snapshot of where the output failed
+    Halt,
+    /// Keep running and discard the rest of the output
+    Ignore,
+    /// Stop with an error
+    Error,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum StatsFormat {
+    Text,
+    Json,
+    Prometheus,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Flush {
+    Byte,
+    Newline,
+    Input,
+    Halt,
+}
+
+impl From<Flush> for FlushPolicy {
+    fn from(flush: Flush) -> Self {
+        match flush {
+            Flush::Byte => FlushPolicy::Byte,
+            Flush::Newline => FlushPolicy::Newline,
+            Flush::Input => FlushPolicy::Input,
+            Flush::Halt => FlushPolicy::Halt,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Generated {
+    Tour,
+}
+
+#[derive(Subcommand)]
+enum StateCommand {
+    /// Rewrite a snapshot of any supported version in the current format
+    Upgrade { from: PathBuf, to: PathBuf },
+}
+
+fn parse_u32(s: &str) -> Result<u32, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(
+        s.parse()
+            .map_err(|e: std::num::ParseFloatError| e.to_string())?,
+    )
+    .map_err(|e| e.to_string())
+}
+
+fn parse_watch(s: &str) -> Result<(u32, Watch), String> {
+    let (array, mode) = s.split_once(':').unwrap_or((s, "rw"));
+    Ok((parse_u32(array)?, parse_mode(mode)?))
+}
+
+fn parse_cell_watch(s: &str) -> Result<((u32, u32), Watch), String> {
+    let (array, rest) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected ID:OFFSET, got {s:?}"))?;
+    let (offset, mode) = rest.split_once(':').unwrap_or((rest, "rw"));
+    Ok(((parse_u32(array)?, parse_u32(offset)?), parse_mode(mode)?))
+}
+
+fn parse_action(s: &str) -> Result<(u32, Action), String> {
+    let (pc, action) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected PC:ACTION, got {s:?}"))?;
+    Ok((parse_u32(pc)?, Action::parse(action)?))
+}
+
+pub fn parse_mode(mode: &str) -> Result<Watch, String> {
+    match mode {
+        "r" => Ok(Watch::Read),
+        "w" => Ok(Watch::Write),
+        "rw" => Ok(Watch::ReadWrite),
+        _ => Err(format!("expected r, w or rw, got {mode:?}")),
+    }
+}
+
+// `um-32 prog.um` predates the subcommands, so a command line that doesn't
+// start with one is treated as arguments to `run`.
+fn normalize_args() -> Vec<OsString> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let explicit = args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| {
+        matches!(arg, "help" | "-h" | "--help" | "-V" | "--version")
+            || Cli::command().find_subcommand(arg).is_some()
+    });
+    if args.len() > 1 && !explicit {
+        args.insert(1, "run".into());
+    }
+    args
+}
+
+// Exit statuses, besides 0 and clap's 2 for an invalid command line,
+// which includes one missing the program.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_MISSING_FILE: i32 = 3;
+const EXIT_FAULT: i32 = 4;
+pub const EXIT_LIMIT: i32 = 5;
+const EXIT_IO: i32 = 6;
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Whether `e` is the program's fault rather than the host's.
+pub fn guest_fault(e: &um_core::Error) -> bool {
+    use um_core::Error as Machine;
+    matches!(
+        e,
+        Machine::AbandonedProgramArray { .. }
+            | Machine::DivisionByZero { .. }
+            | Machine::DoubleAbandon { .. }
+            | Machine::InactiveArray { .. }
+            | Machine::InfiniteLoop { .. }
+            | Machine::InvalidChar { .. }
+            | Machine::InvalidOp { .. }
+            | Machine::OutOfBounds { .. }
+            | Machine::UnknownHostCall { .. }
+    )
+}
+
+fn exit_status(e: &Error) -> i32 {
+    use um_core::Error as Machine;
+    match e {
+        Error::Machine(Machine::IO(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            EXIT_MISSING_FILE
+        }
+        Error::Machine(Machine::IO(_)) => EXIT_IO,
+        Error::Machine(e) if guest_fault(e) => EXIT_FAULT,
+        Error::Machine(Machine::AllocationTooLarge { .. } | Machine::OutOfMemory { .. }) => {
+            EXIT_LIMIT
+        }
+        _ => EXIT_FAILURE,
+    }
+}
+
+fn execute(command: Command, args: &[OsString]) -> Result<i32, Error> {
+    match command {
+        Command::Run(run) if run.isolation.isolate => isolate::isolate(&run.isolation, args)?,
+        Command::Run(run) => {
+            return Ok(match run::run(*run)? {
+                run::Ending::Finished => 0,
+                run::Ending::Limited => EXIT_LIMIT,
+                run::Ending::Interrupted => EXIT_INTERRUPTED,
+                run::Ending::Diverged | run::Ending::NotExtracted | run::Ending::Stopped => {
+                    EXIT_FAILURE
+                }
+            })
+        }
+        Command::Debug(args) => debug::debug(args)?,
+        Command::Bench(args) => bench::bench(args)?,
+        Command::Serve(args) => serve::serve(args)?,
+        Command::Differential(args) => {
+            if !differential::differential(args)? {
+                return Ok(EXIT_FAILURE);
+            }
+        }
+        #[cfg(feature = "dap")]
+        Command::Dap => dap::dap()?,
+        Command::Asm { source, output } => asm::asm(source, output)?,
+        Command::Disasm(args) => disasm::disasm(args)?,
+        Command::Compile {
+            file,
+            output,
+            stages,
+        } => compile::compile(file, output, &stages)?,
+        Command::DisasmDiff {
+            files,
+            context,
+            extensions,
+        } => disasm::diff(&files, context, extensions)?,
+        Command::Verify(args) => {
+            if !verify::image(args)? {
+                return Ok(EXIT_FAILURE);
+            }
+        }
+        Command::Gen { program, output } => gen::generate(program, output)?,
+        Command::State { command } => state::state(command)?,
+    }
+    Ok(0)
+}
+
+fn main() {
+    let args = normalize_args();
+    let cli = Cli::parse_from(&args);
+    let status = execute(cli.command, &args).unwrap_or_else(|e| {
+        eprintln!("um-32: {e}");
+        exit_status(&e)
+    });
+    std::process::exit(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A program file that loads 0 into r0 three times and halts, printing
+    // nothing, removed again when dropped.
+    #[cfg(unix)]
+    struct Quiet(PathBuf);
+
+    #[cfg(unix)]
+    impl Quiet {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("um-32-main-{}.um", std::process::id()));
+            let words = [0xd000_0000u32, 0xd000_0000, 0xd000_0000, 0x7000_0000];
+            let image: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+            std::fs::write(&path, image).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    #[cfg(unix)]
+    impl Drop for Quiet {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    // The exit status of `um-32 ARGS`.
+    fn status(args: &[&str]) -> i32 {
+        let args: Vec<OsString> = ["um-32"].iter().chain(args).map(OsString::from).collect();
+        let cli = Cli::try_parse_from(&args).unwrap();
+        execute(cli.command, &args).unwrap_or_else(|e| exit_status(&e))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_exec_action() {
+        let quiet = Quiet::new();
+        assert_eq!(status(&["run", quiet.path(), "--action", "3:exec true"]), 0);
+        assert_eq!(
+            status(&["run", quiet.path(), "--action", "3:exec false"]),
+            EXIT_FAILURE
+        );
+    }
+
+    #[test]
+    fn missing_file() {
+        // clap's status for an invalid command line.
+        let err = Cli::try_parse_from(["um-32", "run"]).err().unwrap();
+        assert_eq!(err.exit_code(), 2);
+        assert_eq!(status(&["run", "/nonexistent/prog.um"]), EXIT_MISSING_FILE);
+    }
+}
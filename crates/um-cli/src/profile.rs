@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Whether `inst`, about to execute at `pc`, looks like a call: a Load
+/// Program that jumps within array 0 while another register holds the
+/// address of the next instruction, to come back to.
+pub fn looks_like_call(pc: u32, inst: u32, registers: &[u32; 8]) -> bool {
+    let (b, c) = ((inst >> 3 & 0b111) as usize, (inst & 0b111) as usize);
+    inst >> 28 == 12
+        && registers[b] == 0
+        && (0..8).any(|r| r != c && registers[r] == pc.wrapping_add(1))
+}
+
+// Calls nested deeper than this are counted in the deepest frame, so that
+// a loop that only looks like a call cannot grow the stack forever.
+const MAX_DEPTH: usize = 256;
+
+/// Counts executed instructions by call stack, for flame graphs.
+///
+/// The UM has no call instruction, so calls are guessed with
+/// [`looks_like_call`], and a call returns when control reaches the
+/// instruction after it, along with any calls it made that have not
+/// returned, as happens with tail calls. A call made again from the same
+/// place before it returns unwinds to the first, which folds recursion and
+/// keeps loops that only look like calls from nesting. A program loaded
+/// from another array starts a new stack.
+pub struct Profiler {
+    out: BufWriter<File>,
+    // Each stack seen, as its caller's index and the called address.
+    frames: Vec<(usize, u32)>,
+    callees: HashMap<(usize, u32), usize>,
+    counts: Vec<u64>,
+    // The current stack: each frame with the address it returns to, and
+    // how many frames return to each address.
+    stack: Vec<(usize, u32)>,
+    returns: HashMap<u32, usize>,
+}
+
+impl Profiler {
+    /// The profile is written to `path` when the profiler is dropped.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            frames: vec![(0, 0)],
+            callees: HashMap::new(),
+            counts: vec![0],
+            stack: Vec::new(),
+            returns: HashMap::new(),
+        })
+    }
+
+    pub fn event(&mut self, pc: u32, inst: u32, registers: &[u32; 8]) {
+        if self.returns.contains_key(&pc) {
+            if let Some(call) = self.stack.iter().rposition(|(_, ret)| *ret == pc) {
+                self.unwind(call);
+            }
+        }
+        let frame = self.stack.last().map_or(0, |(frame, _)| *frame);
+        self.counts[frame] += 1;
+        if inst >> 28 != 12 {
+            return;
+        }
+        if registers[(inst >> 3 & 0b111) as usize] != 0 {
+            self.stack.clear();
+            self.returns.clear();
+        } else if looks_like_call(pc, inst, registers) && self.stack.len() < MAX_DEPTH {
+            let target = registers[(inst & 0b111) as usize];
+            let ret = pc.wrapping_add(1);
+            // The same call is still open: recursion, which is folded into
+            // the open frame, or more likely a loop that only looks like a
+            // call, which would otherwise nest on every pass.
+            let frames = &self.frames;
+            if let Some(open) = self
+                .stack
+                .iter()
+                .rposition(|(f, r)| *r == ret && frames[*f].1 == target)
+            {
+                self.unwind(open + 1);
+                return;
+            }
+            let next = self.frames.len();
+            let callee = *self.callees.entry((frame, target)).or_insert(next);
+            if callee == next {
+                self.frames.push((frame, target));
+                self.counts.push(0);
+            }
+            self.stack.push((callee, ret));
+            *self.returns.entry(ret).or_default() += 1;
+        }
+    }
+
+    // Pops frames until `depth` are left.
+    fn unwind(&mut self, depth: usize) {
+        while self.stack.len() > depth {
+            let (_, ret) = self.stack.pop().unwrap();
+            self.forget_return(ret);
+        }
+    }
+
+    fn forget_return(&mut self, ret: u32) {
+        if let Some(n) = self.returns.get_mut(&ret) {
+            *n -= 1;
+            if *n == 0 {
+                self.returns.remove(&ret);
+            }
+        }
+    }
+
+    // Writes one line per stack in the folded format inferno and
+    // flamegraph.pl read: `main;fn_00001082;fn_000011e4 1234`.
+    fn write(&mut self) -> io::Result<()> {
+        for (frame, count) in self.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let mut names = Vec::new();
+            let mut at = frame;
+            while at != 0 {
+                let (caller, target) = self.frames[at];
+                names.push(format!("fn_{target:08x}"));
+                at = caller;
+            }
+            names.push("main".to_string());
+            names.reverse();
+            writeln!(self.out, "{} {count}", names.join(";"))?;
+        }
+        self.out.flush()
+    }
+}
+
+// The hook is dropped with the machine, however the run ends.
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        if let Err(e) = self.write() {
+            eprintln!("um-32: could not write the profile: {e}");
+        }
+    }
+}
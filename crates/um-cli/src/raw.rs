@@ -0,0 +1,156 @@
+//! `run --raw`: the terminal in raw mode, for programs that want each key
+//! as it is pressed rather than a line at a time.
+//!
+//! The terminal stops buffering lines and echoing what is typed, and Ctrl-Z
+//! goes to the program instead of suspending it; Ctrl-C still interrupts
+//! the run. Its settings are put back when the run ends, on a panic, and
+//! before a second Ctrl-C kills the process.
+//!
+//! Keys the program can't make sense of are translated: Backspace sends
+//! BS (8), the arrow keys send the Emacs motion keys (^P ^N ^F ^B), Home
+//! and End send ^A and ^E, Delete sends DEL (127), and other escape
+//! sequences are dropped. Ctrl-D ends the input.
+
+use std::io::{self, Read};
+
+use crate::Error;
+
+/// Raw mode for as long as it is held.
+pub struct RawMode(());
+
+#[cfg(unix)]
+static ORIGINAL: std::sync::OnceLock<libc::termios> = std::sync::OnceLock::new();
+
+impl RawMode {
+    /// Puts the terminal on stdin into raw mode.
+    #[cfg(unix)]
+    pub fn enable() -> Result<RawMode, Error> {
+        let mut termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            let e = io::Error::last_os_error();
+            return Err(Error::InvalidArgument(format!(
+                "--raw needs a terminal on stdin: {e}"
+            )));
+        }
+        let original = *ORIGINAL.get_or_init(|| termios);
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            hook(info)
+        }));
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::IEXTEN);
+        termios.c_iflag &= !(libc::IXON | libc::ISTRIP | libc::INLCR);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        termios.c_cc[libc::VSUSP] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+            let e = io::Error::last_os_error();
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &original) };
+            return Err(e.into());
+        }
+        Ok(RawMode(()))
+    }
+
+    #[cfg(not(unix))]
+    pub fn enable() -> Result<RawMode, Error> {
+        Err(Error::InvalidArgument(
+            "--raw is only supported on Unix".into(),
+        ))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Puts back the terminal settings raw mode replaced, if it was entered.
+/// Safe to call from a signal handler.
+pub fn restore() {
+    #[cfg(unix)]
+    if let Some(original) = ORIGINAL.get() {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+    }
+}
+
+/// Console input from a terminal in raw mode, with keys translated as
+/// described above.
+pub struct Keys<R> {
+    inner: R,
+    ended: bool,
+}
+
+impl<R> Keys<R> {
+    pub fn new(inner: R) -> Self {
+        Keys {
+            inner,
+            ended: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Keys<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.ended {
+            let n = self.inner.read(buf)?;
+            if n == 0 {
+                self.ended = true;
+                break;
+            }
+            // Translations never lengthen the input, so they are done in
+            // place.
+            let (mut i, mut j) = (0, 0);
+            while i < n {
+                let (key, len) = match buf[i] {
+                    0x04 => {
+                        self.ended = true;
+                        break;
+                    }
+                    0x7f => (Some(0x08), 1),
+                    0x1b => escape(&buf[i..n]),
+                    b => (Some(b), 1),
+                };
+                if let Some(key) = key {
+                    buf[j] = key;
+                    j += 1;
+                }
+                i += len;
+            }
+            if j > 0 {
+                return Ok(j);
+            }
+        }
+        Ok(0)
+    }
+}
+
+// Translates the escape sequence at the start of `seq`, a lone ESC if it
+// is incomplete, returning the key and the sequence's length.
+fn escape(seq: &[u8]) -> (Option<u8>, usize) {
+    let (params, rest) = match seq.get(1) {
+        Some(b'[') => {
+            let params = seq[2..]
+                .iter()
+                .take_while(|b| (0x20..0x40).contains(*b))
+                .count();
+            (&seq[2..2 + params], &seq[2 + params..])
+        }
+        Some(b'O') => (&[][..], &seq[2..]),
+        _ => return (Some(0x1b), 1),
+    };
+    let Some(&last) = rest.first().filter(|b| (0x40..0x7f).contains(*b)) else {
+        return (Some(0x1b), 1);
+    };
+    let key = match (params, last) {
+        (_, b'A') => Some(0x10),
+        (_, b'B') => Some(0x0e),
+        (_, b'C') => Some(0x06),
+        (_, b'D') => Some(0x02),
+        (_, b'H') | (b"1" | b"7", b'~') => Some(0x01),
+        (_, b'F') | (b"4" | b"8", b'~') => Some(0x05),
+        (b"3", b'~') => Some(0x7f),
+        _ => None,
+    };
+    (key, seq.len() - rest.len() + 1)
+}
@@ -0,0 +1,65 @@
+//! `run --readline`: console input typed with line editing, kill and yank,
+//! and history, which --history keeps in a file from one run to the next.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{run, signals, Error};
+
+pub struct LineEditor {
+    editor: DefaultEditor,
+    history: Option<PathBuf>,
+}
+
+impl LineEditor {
+    /// Loads the history in `history`, if it exists yet.
+    pub fn new(history: Option<&Path>) -> Result<LineEditor, Error> {
+        let mut editor = DefaultEditor::new().map_err(other)?;
+        if let Some(path) = history.filter(|path| path.exists()) {
+            editor
+                .load_history(path)
+                .map_err(|e| run::naming(path)(other(e)))?;
+        }
+        Ok(LineEditor {
+            editor,
+            history: history.map(Path::to_path_buf),
+        })
+    }
+
+    /// Reads a line into `line`, with its newline, returning 0 at the end
+    /// of input. `prompt` is what the program has written of the current
+    /// line, which is redrawn as the line is edited.
+    pub fn read_line(&mut self, prompt: &str, line: &mut Vec<u8>) -> io::Result<usize> {
+        let text = match self.editor.readline(prompt) {
+            Ok(text) => text,
+            Err(ReadlineError::Eof) => return Ok(0),
+            // Ctrl-C is a key while the line is edited.
+            Err(ReadlineError::Interrupted) => return Err(signals::interrupt()),
+            Err(e) => return Err(other(e)),
+        };
+        if !text.trim().is_empty() {
+            self.editor.add_history_entry(&text).map_err(other)?;
+            if let Some(path) = &self.history {
+                // Appended line by line, so that a run that dies keeps its
+                // history.
+                if let Err(e) = self.editor.append_history(path) {
+                    eprintln!("um-32: could not save history to {}: {e}", path.display());
+                }
+            }
+        }
+        line.extend_from_slice(text.as_bytes());
+        line.push(b'\n');
+        Ok(text.len() + 1)
+    }
+}
+
+fn other(e: ReadlineError) -> io::Error {
+    match e {
+        ReadlineError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
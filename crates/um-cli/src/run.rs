@@ -0,0 +1,908 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, IsTerminal, Read, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use um_core::{Access, CoreDump, Journal, Machine, MachineBuilder, OpStats, Stop};
+use um_tools::{disasm, overlay::OverlayDumper};
+
+use crate::{
+    action::Actions,
+    automate::Automation,
+    cast::{self, Cast},
+    console::Console,
+    coverage,
+    extract::Extraction,
+    login::Profiles,
+    profile::Profiler,
+    raw::{Keys, RawMode},
+    readline::LineEditor,
+    script::{Prompt, Recorder, Script},
+    signals::{self, Interruptible},
+    trace::{self, Hook},
+    verify, Error, MachineArgs, RunArgs, SigpipePolicy, StatsFormat,
+};
+
+/// Builds the machine described by `args`, on top of the I/O set up in
+/// `builder`.
+pub fn load(args: &MachineArgs, builder: MachineBuilder) -> Result<Machine, Error> {
+    Ok(load_core(args, builder)?.0)
+}
+
+// What --codex queues: the key, then `p` at the menu that follows.
+const CODEX_KEY: &[u8] = b"(\\b.bb)(\\v.vv)06FHPVboundvarHRAkp";
+
+/// The console input `args` queues before the terminal's.
+pub fn input(args: &MachineArgs) -> Result<Vec<u8>, Error> {
+    let mut input = Vec::new();
+    if args.codex {
+        input.extend_from_slice(CODEX_KEY);
+    }
+    if let Some(key) = &args.key {
+        input.extend_from_slice(key.as_bytes());
+    }
+    for path in args.inputs.iter() {
+        input.extend(std::fs::read(path).map_err(naming(path))?);
+    }
+    Ok(input)
+}
+
+/// [`load`], also returning what the snapshot resumed from adds if it is a
+/// core file.
+pub fn load_core(
+    args: &MachineArgs,
+    mut builder: MachineBuilder,
+) -> Result<(Machine, Option<CoreDump>), Error> {
+    builder = builder
+        .max_alloc(args.max_alloc)
+        .max_memory(args.max_memory.unwrap_or(u64::MAX))
+        .detect_hangs(args.detect_hangs);
+    if args.extensions {
+        #[cfg(feature = "extensions")]
+        {
+            builder = builder.extensions(true);
+        }
+        #[cfg(not(feature = "extensions"))]
+        return Err(Error::InvalidArgument(
+            "--extensions needs um-32 built with the extensions feature".to_string(),
+        ));
+    }
+    if args.trace {
+        builder = builder.trace(std::io::stderr());
+    }
+    builder = builder.input_bytes(&input(args)?);
+    if let Some(path) = &args.journal {
+        builder = builder.record_journal(std::fs::File::create(path).map_err(naming(path))?);
+    }
+    if let Some(path) = &args.replay {
+        let file = std::fs::File::open(path).map_err(naming(path))?;
+        builder = builder.replay_journal(Journal::read(io::BufReader::new(file))?);
+    }
+    let (mut machine, core) = match &args.resume {
+        Some(path) => {
+            let (machine, core) = Machine::load_core(path).map_err(|e| match e {
+                um_core::Error::IO(e) => naming(path)(e),
+                e => e.into(),
+            })?;
+            (builder.build_from(machine), core)
+        }
+        None => (builder.build(), None),
+    };
+    for file in args.files.iter() {
+        machine.extend_from(std::fs::File::open(file).map_err(naming(file))?)?;
+    }
+    if let Some(pc) = args.entry {
+        machine.set_pc(pc);
+    }
+    Ok((machine, core))
+}
+
+/// Adds `path` to I/O errors from opening or reading it.
+pub fn naming(path: &Path) -> impl Fn(io::Error) -> Error + '_ {
+    move |e| io::Error::new(e.kind(), format!("{}: {e}", path.display())).into()
+}
+
+/// How a run that did not fail ended, for the exit status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ending {
+    /// The program halted, or stopped where --save freezes it.
+    Finished,
+    /// --max-steps or --timeout stopped it.
+    Limited,
+    /// Ctrl-C stopped it.
+    Interrupted,
+    /// A failed `exec` action stopped it.
+    Stopped,
+    /// --verify found output other than the transcript's.
+    Diverged,
+    /// --extract found nothing to extract.
+    NotExtracted,
+}
+
+pub fn run(args: RunArgs) -> Result<Ending, Error> {
+    if let Some(path) = &args.verify {
+        let matched = verify::verify(&args.machine, path)?;
+        return Ok(if matched {
+            Ending::Finished
+        } else {
+            Ending::Diverged
+        });
+    }
+    let mut builder = Machine::builder();
+    let commands = !args.no_console_commands && !args.raw && std::io::stdin().is_terminal();
+    let readline = args.readline || args.history.is_some();
+    let console = (commands || readline || args.script.is_some() || args.record.is_some())
+        .then(|| Console::new(commands));
+    if let Some(console) = &console {
+        let prompt = Prompt::new(&args.prompt);
+        console.set_prompt(prompt.clone());
+        if let Some(path) = &args.script {
+            console.replay(Script::load(path, prompt.clone()).map_err(Error::InvalidArgument)?);
+        }
+        if let Some(path) = &args.record {
+            let anchors = (!args.no_anchors).then_some(prompt);
+            console.record(Recorder::create(path, anchors)?);
+        }
+        if readline {
+            console.edit_lines(LineEditor::new(args.history.as_deref())?);
+        }
+    }
+    let ignore_pipe = args.on_sigpipe == SigpipePolicy::Ignore;
+    let (mut stdin, mut stdout): (Box<dyn Read + Send>, Box<dyn Write + Send>) = match &console {
+        Some(console) => (
+            Box::new(console.stdin()),
+            Box::new(PipeGuard::new(console.stdout(), ignore_pipe)),
+        ),
+        None if args.raw => (
+            Box::new(Keys::new(Interruptible(io::stdin()))),
+            Box::new(PipeGuard::new(io::stdout(), ignore_pipe)),
+        ),
+        None => (
+            Box::new(Interruptible(io::stdin())),
+            Box::new(PipeGuard::new(io::stdout(), ignore_pipe)),
+        ),
+    };
+    let automation = match (&args.automate, &args.login) {
+        (Some(path), _) => Some(Automation::load(path).map_err(Error::InvalidArgument)?),
+        (None, Some(name)) => {
+            // --login requires --profiles.
+            let profiles = Profiles::load(args.profiles.as_ref().unwrap());
+            Some(
+                profiles
+                    .and_then(|profiles| profiles.login(name))
+                    .map_err(Error::InvalidArgument)?,
+            )
+        }
+        (None, None) => None,
+    };
+    if let Some(automation) = automation {
+        stdin = Box::new(automation.input(stdin));
+        stdout = Box::new(automation.output(stdout));
+    }
+    if let Some(path) = &args.cast {
+        let cast = Cast::create(path)?;
+        if args.cast_input {
+            stdin = Box::new(cast.input(stdin));
+        }
+        stdout = Box::new(cast.output(stdout));
+    }
+    // Outside the cast, so that the image is kept out of it too.
+    let extraction = match &args.extract {
+        Some(path) => Some(Extraction::create(path, &args.extract_after)?),
+        None => None,
+    };
+    if let Some(extraction) = &extraction {
+        stdout = Box::new(extraction.output(stdout));
+    }
+    builder = builder.stdin(stdin).stdout(stdout);
+    builder = builder.echo(args.echo).eof_error(args.save.is_some());
+    builder = match args.flush {
+        Some(flush) => builder.flush_policy(flush.into()),
+        None => builder.immediate_output(io::stdout().is_terminal()),
+    };
+    if let Some(path) = args.output {
+        builder = builder.tee_output(std::fs::File::create(path)?);
+    }
+    if let Some(dir) = args.dump_overlays {
+        let mut dumper = OverlayDumper::new(dir)?;
+        builder = builder
+            .on_load_program(move |array, entry, program| dumper.dump(array, entry, program));
+    }
+    builder = builder
+        .stats(args.stats.is_some())
+        .profile(args.hot_spots.is_some() || args.pc_histogram.is_some() || args.coverage.is_some())
+        .audit(args.audit_allocations);
+    if args.core.is_some() || args.verbose_errors {
+        builder = builder.recent_instructions(RECENT_INSTRUCTIONS);
+    }
+    let mut hooks: Vec<Hook> = Vec::new();
+    hooks.extend(args.trace.hook()?);
+    if let Some(path) = &args.flamegraph {
+        let mut profiler = Profiler::create(path)?;
+        hooks.push(Box::new(move |pc, inst, registers| {
+            profiler.event(pc, inst, registers);
+            Ok(())
+        }));
+    }
+    let recent = args
+        .panic_trace
+        .map(|n| Arc::new(Mutex::new(Recent::new(n))));
+    if let Some(recent) = &recent {
+        let recent = recent.clone();
+        hooks.push(Box::new(move |pc, inst, registers| {
+            recent.lock().unwrap().push(pc, inst, registers);
+            Ok(())
+        }));
+    }
+    let builder = match hooks.len() {
+        0 => builder,
+        1 => builder.on_instruction(hooks.pop().unwrap()),
+        _ => builder.on_instruction(move |pc, inst, registers| {
+            hooks
+                .iter_mut()
+                .try_for_each(|hook| hook(pc, inst, registers))
+        }),
+    };
+    let mut machine = load(&args.machine, builder)?;
+    // Held until the run is over.
+    let _raw = args.raw.then(RawMode::enable).transpose()?;
+    machine.set_step_limit(args.max_steps);
+    machine.set_deadline(args.timeout.map(|timeout| Instant::now() + timeout));
+    signals::install(machine.interrupter());
+    for (array, watch) in args.watch_arrays {
+        machine.watch_array(array, watch);
+    }
+    for ((array, offset), watch) in args.watch_cells {
+        machine.watch_cell(array, offset, watch);
+    }
+    let mut actions = Actions::new(|line| eprintln!("{line}"));
+    actions.set_charset(args.machine.display_charset);
+    for (pc, action) in args.actions {
+        actions.add_action(&mut machine, pc, action);
+    }
+    for name in args.counters {
+        actions.set_counter(&name, 0);
+    }
+
+    let res = match &args.panic_save {
+        Some(path) => {
+            let run = || run_reporting_watches(&mut machine, &mut actions);
+            match panic::catch_unwind(AssertUnwindSafe(run)) {
+                Ok(res) => res,
+                Err(panic) => {
+                    save_after_panic(&machine, path, recent.as_deref());
+                    panic::resume_unwind(panic)
+                }
+            }
+        }
+        None => run_reporting_watches(&mut machine, &mut actions),
+    };
+    for (name, count) in actions.counters() {
+        eprintln!("um-32: {name}: {count}");
+    }
+    if let Some(path) = &args.stats {
+        let counters = actions.counters();
+        if path.as_os_str() == "-" {
+            write_stats(&machine, counters, args.stats_format, &mut io::stderr())?;
+        } else {
+            let mut file = std::fs::File::create(path)?;
+            write_stats(&machine, counters, args.stats_format, &mut file)?;
+        }
+    }
+    if args.memory_stats {
+        write_memory_stats(&machine, &mut io::stderr())?;
+    }
+    if args.audit_allocations {
+        write_allocation_audit(&machine, &mut io::stderr())?;
+    }
+    if let Some(top) = args.hot_spots {
+        write_hot_spots(&machine, &mut io::stderr(), top)?;
+    }
+    if let Some(path) = &args.pc_histogram {
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        let counts = machine.pc_counts().unwrap_or_default();
+        for (pc, count) in counts.iter().enumerate().filter(|(_, n)| **n > 0) {
+            writeln!(file, "{pc:08x} {count}")?;
+        }
+        file.flush()?;
+    }
+    if let Some(path) = &args.coverage {
+        let counts = machine.pc_counts().unwrap_or_default();
+        let len = machine.array(0).map_or(0, <[u32]>::len).max(counts.len());
+        let mut executed: Vec<bool> = counts.iter().map(|n| *n > 0).collect();
+        executed.resize(len, false);
+        // The instruction the run ended at, a HALT or one that failed, was
+        // reached but is not counted as executed.
+        if let Some(reached) = executed.get_mut(machine.pc() as usize) {
+            *reached = true;
+        }
+        coverage::write(path, executed, args.coverage_merge)?;
+    }
+    if let Some(console) = &console {
+        console.finish()?;
+    }
+    let extracted = match &extraction {
+        Some(extraction) => extraction.finish()?,
+        None => true,
+    };
+    if let Err(e) = &res {
+        if args.verbose_errors {
+            let recent = machine.recent_instructions();
+            eprintln!("um-32: the last {} instructions executed:", recent.len());
+            write_recent(&mut io::stderr(), &recent)?;
+        }
+        if let Some(path) = args.core.as_ref().filter(|_| crate::guest_fault(e)) {
+            let core = CoreDump {
+                fault: e.to_string(),
+                recent: machine.recent_instructions(),
+            };
+            machine.save_core(path, &core)?;
+            eprintln!("um-32: saved a core file to {}", path.display());
+        }
+    }
+    let broken_pipe = |e: &io::Error| e.kind() == io::ErrorKind::BrokenPipe;
+    let mut ending = Ending::Finished;
+    match (res, args.save) {
+        // The Input instruction leaves pc in place when stdin is exhausted,
+        // so the machine can be frozen here and resumed with more input.
+        (Err(um_core::Error::IO(e)), Some(path)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            machine.save_snapshot(path)?;
+        }
+        (Err(um_core::Error::IO(e)), save) if broken_pipe(&e) => match args.on_sigpipe {
+            SigpipePolicy::Error => {
+                return Err(
+                    io::Error::new(e.kind(), "output closed before the program finished").into(),
+                )
+            }
+            _ => {
+                if let Some(path) = save {
+                    machine.save_snapshot(path)?;
+                }
+            }
+        },
+        (Ok(Stop::StepLimit { pc, executed }), save) => {
+            eprintln!("um-32: stopped after {executed} instructions at pc={pc:#06x}");
+            if let Some(path) = save {
+                machine.save_snapshot(path)?;
+            }
+            ending = Ending::Limited;
+        }
+        (Ok(Stop::Timeout { pc, executed }), save) => {
+            eprintln!("um-32: timed out after {executed} instructions at pc={pc:#06x}");
+            if let Some(path) = save {
+                machine.save_snapshot(path)?;
+            }
+            ending = Ending::Limited;
+        }
+        (Ok(Stop::Breakpoint { pc }), save) => {
+            eprintln!("um-32: stopped by an action at pc={pc:#06x}");
+            if let Some(path) = save {
+                machine.save_snapshot(path)?;
+            }
+            ending = Ending::Stopped;
+        }
+        // Reads blocked on the console fail once Ctrl-C is pressed, leaving
+        // the Input instruction to be executed again.
+        (Ok(Stop::Interrupted { .. }) | Err(um_core::Error::IO(_)), save)
+            if signals::interrupted() =>
+        {
+            eprintln!("um-32: interrupted at pc={:#06x}", machine.pc());
+            match save {
+                Some(path) => machine.save_snapshot(path)?,
+                None => eprintln!("um-32: no snapshot saved; run with --save FILE to keep one"),
+            }
+            ending = Ending::Interrupted;
+        }
+        (res, _) => {
+            res?;
+        }
+    }
+    if ending == Ending::Finished && !extracted {
+        ending = Ending::NotExtracted;
+    }
+
+    Ok(ending)
+}
+
+fn write_stats(
+    machine: &Machine,
+    counters: &BTreeMap<String, u64>,
+    format: StatsFormat,
+    w: &mut impl Write,
+) -> io::Result<()> {
+    let Some(stats) = machine.op_stats() else {
+        return Ok(());
+    };
+    match format {
+        StatsFormat::Text => write_stats_table(stats, counters, w),
+        StatsFormat::Json => write_stats_json(stats, counters, w),
+        StatsFormat::Prometheus => write_stats_prometheus(stats, counters, w),
+    }
+}
+
+fn write_stats_table(
+    stats: &[OpStats; 14],
+    counters: &BTreeMap<String, u64>,
+    w: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(
+        w,
+        "{:>2}  {:<6}  {:>14}  {:>16}  {:>10}",
+        "op", "name", "count", "cycles", "avg cycles"
+    )?;
+    for (op, stats) in stats.iter().enumerate() {
+        writeln!(
+            w,
+            "{op:>2}  {:<6}  {:>14}  {:>16}  {:>10.2}",
+            disasm::op_name(op as u32).unwrap_or_default(),
+            stats.count,
+            stats.cycles,
+            stats.average_cycles()
+        )?;
+    }
+    let count: u64 = stats.iter().map(|s| s.count).sum();
+    let cycles: u64 = stats.iter().map(|s| s.cycles).sum();
+    writeln!(
+        w,
+        "{:>2}  {:<6}  {count:>14}  {cycles:>16}  {:>10.2}",
+        "",
+        "total",
+        cycles as f64 / count.max(1) as f64
+    )?;
+    if !counters.is_empty() {
+        writeln!(w, "\n{:<24}  {:>14}", "counter", "count")?;
+        for (name, count) in counters {
+            writeln!(w, "{name:<24}  {count:>14}")?;
+        }
+    }
+    Ok(())
+}
+
+// One line: {"ops":[{"op":0,"name":"CMOV","count":N,"cycles":N},...],
+// "counters":{"NAME":N,...}}, with opcodes that never ran left out.
+fn write_stats_json(
+    stats: &[OpStats; 14],
+    counters: &BTreeMap<String, u64>,
+    w: &mut impl Write,
+) -> io::Result<()> {
+    let ops: Vec<String> = ran(stats)
+        .map(|(op, name, stats)| {
+            format!(
+                r#"{{"op":{op},"name":"{name}","count":{},"cycles":{}}}"#,
+                stats.count, stats.cycles
+            )
+        })
+        .collect();
+    let counters: Vec<String> = counters
+        .iter()
+        .map(|(name, count)| format!(r#""{}":{count}"#, cast::escape(name)))
+        .collect();
+    writeln!(
+        w,
+        r#"{{"ops":[{}],"counters":{{{}}}}}"#,
+        ops.join(","),
+        counters.join(",")
+    )
+}
+
+// The Prometheus text exposition format.
+fn write_stats_prometheus(
+    stats: &[OpStats; 14],
+    counters: &BTreeMap<String, u64>,
+    w: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(
+        w,
+        "# HELP um32_instructions_total Instructions executed, by opcode."
+    )?;
+    writeln!(w, "# TYPE um32_instructions_total counter")?;
+    for (op, name, stats) in ran(stats) {
+        writeln!(
+            w,
+            r#"um32_instructions_total{{op="{op}",name="{name}"}} {}"#,
+            stats.count
+        )?;
+    }
+    writeln!(
+        w,
+        "# HELP um32_cycles_total Processor cycles spent, by opcode."
+    )?;
+    writeln!(w, "# TYPE um32_cycles_total counter")?;
+    for (op, name, stats) in ran(stats) {
+        writeln!(
+            w,
+            r#"um32_cycles_total{{op="{op}",name="{name}"}} {}"#,
+            stats.cycles
+        )?;
+    }
+    if !counters.is_empty() {
+        writeln!(w, "# HELP um32_counter Counters counted by --action count.")?;
+        writeln!(w, "# TYPE um32_counter counter")?;
+        for (name, count) in counters {
+            let name = name
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            writeln!(w, r#"um32_counter{{name="{name}"}} {count}"#)?;
+        }
+    }
+    Ok(())
+}
+
+// The opcodes that executed at least once, with their names.
+fn ran(stats: &[OpStats; 14]) -> impl Iterator<Item = (usize, &'static str, &OpStats)> {
+    stats
+        .iter()
+        .enumerate()
+        .filter(|(_, stats)| stats.count != 0)
+        .map(|(op, stats)| (op, disasm::op_name(op as u32).unwrap_or_default(), stats))
+}
+
+// For SIGUSR1.
+fn write_state(machine: &Machine, w: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        w,
+        "um-32: pc={:#06x} executed={}",
+        machine.pc(),
+        machine.executed()
+    )?;
+    writeln!(w, "registers    {:08x?}", machine.registers())?;
+    write_memory_stats(machine, w)
+}
+
+fn write_memory_stats(machine: &Machine, w: &mut impl Write) -> io::Result<()> {
+    let stats = machine.memory_stats();
+    let mib = |platters: u64| platters as f64 * 4.0 / (1 << 20) as f64;
+    writeln!(
+        w,
+        "live arrays  {:>12}  peak {:>12}",
+        stats.live_arrays, stats.peak_arrays
+    )?;
+    writeln!(
+        w,
+        "platters     {:>12}  peak {:>12}  ({:.1} MiB, peak {:.1} MiB)",
+        stats.live_platters,
+        stats.peak_platters,
+        mib(stats.live_platters),
+        mib(stats.peak_platters)
+    )?;
+    writeln!(
+        w,
+        "allocations  {:>12}  of   {:>12} platters",
+        stats.allocations, stats.allocated_platters
+    )?;
+    writeln!(
+        w,
+        "free list    {:>12}  with {:>12} platters reserved",
+        stats.free_arrays, stats.free_platters
+    )
+}
+
+fn write_allocation_audit(machine: &Machine, w: &mut impl Write) -> io::Result<()> {
+    let sites = machine.allocation_sites().unwrap_or_default();
+    writeln!(
+        w,
+        "{:>8}  {:>12}  {:>14}  {:>12}  {:>8}  never abandoned",
+        "pc", "allocations", "platters", "abandonments", "live"
+    )?;
+    for site in &sites {
+        let mut live: Vec<String> = site.live.iter().take(8).map(u32::to_string).collect();
+        if site.live.len() > live.len() {
+            live.push(format!("and {} more", site.live.len() - live.len()));
+        }
+        let line = format!(
+            "{:08x}  {:>12}  {:>14}  {:>12}  {:>8}  {}",
+            site.pc,
+            site.allocations,
+            site.platters,
+            site.abandonments,
+            site.live.len(),
+            live.join(" ")
+        );
+        writeln!(w, "{}", line.trim_end())?;
+    }
+    let live: usize = sites.iter().map(|site| site.live.len()).sum();
+    writeln!(
+        w,
+        "{live} arrays never abandoned from {} allocation sites",
+        sites.iter().filter(|site| !site.live.is_empty()).count()
+    )
+}
+
+// How many of the last instructions executed --core and --verbose-errors
+// show.
+const RECENT_INSTRUCTIONS: usize = 64;
+
+// The last instructions executed, for --panic-trace.
+struct Recent {
+    limit: usize,
+    count: u64,
+    entries: VecDeque<(u64, u32, u32, [u32; 8])>,
+}
+
+impl Recent {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            count: 0,
+            entries: VecDeque::with_capacity(limit),
+        }
+    }
+
+    fn push(&mut self, pc: u32, inst: u32, registers: &[u32; 8]) {
+        if self.entries.len() == self.limit {
+            self.entries.pop_front();
+        }
+        if self.limit > 0 {
+            self.entries.push_back((self.count, pc, inst, *registers));
+        }
+        self.count += 1;
+    }
+}
+
+/// Lists instructions from [`Machine::recent_instructions`] or a core file,
+/// one per line with its disassembly.
+pub fn write_recent(w: &mut impl Write, recent: &[(u32, u32)]) -> io::Result<()> {
+    for (pc, word) in recent {
+        writeln!(
+            w,
+            "    {pc:08x}: {word:08x}  {}",
+            disasm::mnemonic(*word, false)
+        )?;
+    }
+    Ok(())
+}
+
+// Saves what --panic-save and --panic-trace ask for, reporting rather
+// than returning failures, since the panic is what matters.
+fn save_after_panic(machine: &Machine, path: &Path, recent: Option<&Mutex<Recent>>) {
+    match machine.save_snapshot(path) {
+        Ok(()) => eprintln!(
+            "um-32: saved the machine at pc {:#x} to {}",
+            machine.pc(),
+            path.display()
+        ),
+        Err(e) => eprintln!("um-32: could not save {}: {e}", path.display()),
+    }
+    let Some(recent) = recent else {
+        return;
+    };
+    // The panic may have come from the hook that holds the lock.
+    let recent = recent.lock().unwrap_or_else(|e| e.into_inner());
+    let mut trace_path = path.as_os_str().to_owned();
+    trace_path.push(".trace");
+    let trace_path = PathBuf::from(trace_path);
+    let res = std::fs::File::create(&trace_path).and_then(|file| {
+        let mut w = io::BufWriter::new(file);
+        for (count, pc, inst, registers) in &recent.entries {
+            trace::write_line(&mut w, *count, *pc, *inst, registers)?;
+        }
+        w.flush()
+    });
+    match res {
+        Ok(()) => eprintln!(
+            "um-32: wrote the last {} instructions to {}",
+            recent.entries.len(),
+            trace_path.display()
+        ),
+        Err(e) => eprintln!("um-32: could not write {}: {e}", trace_path.display()),
+    }
+}
+
+fn write_hot_spots(machine: &Machine, w: &mut impl Write, top: usize) -> io::Result<()> {
+    let counts = machine.pc_counts().unwrap_or_default();
+    let total: u64 = counts.iter().sum();
+    let mut hot: Vec<(usize, u64)> = counts
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, n)| *n > 0)
+        .collect();
+    hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    writeln!(w, "{:>8}  {:>14}  {:>6}  instruction", "pc", "count", "%")?;
+    let program = machine.array(0).unwrap_or_default();
+    for (pc, count) in hot.into_iter().take(top) {
+        // What is at the pc now, which a later program load may have
+        // replaced.
+        let inst = program
+            .get(pc)
+            .map_or(String::new(), |w| disasm::mnemonic(*w, false));
+        writeln!(
+            w,
+            "{pc:08x}  {count:>14}  {:>6.2}  {inst}",
+            count as f64 * 100.0 / total.max(1) as f64
+        )?;
+    }
+    Ok(())
+}
+
+// Passes output on until it fails with a broken pipe, then, if `ignore` is
+// set, discards the rest instead of failing.
+struct PipeGuard<W> {
+    inner: W,
+    ignore: bool,
+    closed: bool,
+}
+
+impl<W: Write> PipeGuard<W> {
+    fn new(inner: W, ignore: bool) -> Self {
+        Self {
+            inner,
+            ignore,
+            closed: false,
+        }
+    }
+
+    fn check<T>(&mut self, res: io::Result<T>, ok: T) -> io::Result<T> {
+        match res {
+            Err(e) if self.ignore && e.kind() == io::ErrorKind::BrokenPipe => {
+                self.closed = true;
+                Ok(ok)
+            }
+            res => res,
+        }
+    }
+}
+
+impl<W: Write> Write for PipeGuard<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Ok(buf.len());
+        }
+        let res = self.inner.write(buf);
+        self.check(res, buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        let res = self.inner.flush();
+        self.check(res, ())
+    }
+}
+
+// Watch hits are reported on stderr and the machine carries on; the
+// debugger is the place to stop at them. Breakpoints only stop the run when
+// an action's command fails, which has been reported already.
+fn run_reporting_watches(
+    machine: &mut Machine,
+    actions: &mut Actions,
+) -> Result<Stop, um_core::Error> {
+    loop {
+        match actions.run_until_stop(machine)? {
+            stop @ (Stop::Halt
+            | Stop::Breakpoint { .. }
+            | Stop::StepLimit { .. }
+            | Stop::Timeout { .. }) => return Ok(stop),
+            stop @ Stop::Interrupted { .. } => {
+                if signals::take_dump() {
+                    write_state(machine, &mut io::stderr())?;
+                }
+                if signals::interrupted() {
+                    return Ok(stop);
+                }
+            }
+            stop => {
+                if let Some(hit) = describe_watch(&stop) {
+                    eprintln!("um-32: {hit}");
+                }
+            }
+        }
+    }
+}
+
+/// Describes a [`Stop::Watch`], e.g. `pc=0x0481 write to array 2 at offset 5`.
+pub fn describe_watch(stop: &Stop) -> Option<String> {
+    let Stop::Watch {
+        pc,
+        array,
+        offset,
+        access,
+    } = stop
+    else {
+        return None;
+    };
+    let access = match access {
+        Access::Read => "read of",
+        Access::Write => "write to",
+        Access::Allocate => "allocation of",
+        Access::Abandon => "abandonment of",
+    };
+    Some(match offset {
+        Some(offset) => format!("pc={pc:#06x} {access} array {array} at offset {offset}"),
+        None => format!("pc={pc:#06x} {access} array {array}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs three Orthographies and a Halt with statistics kept, and makes
+    // up counters for it, one with a name that needs escaping.
+    fn counted() -> (Machine, BTreeMap<String, u64>) {
+        let image: Vec<u8> = [0xd000_0000u32, 0xd000_0000, 0xd000_0000, 0x7000_0000]
+            .iter()
+            .flat_map(|w| w.to_be_bytes())
+            .collect();
+        let mut machine = Machine::builder()
+            .stats(true)
+            .stdin(io::empty())
+            .stdout(io::sink())
+            .build();
+        machine.extend_from(image.as_slice()).unwrap();
+        machine.run().unwrap();
+        let counters = [("say \"hi\"".to_string(), 2), ("unused".to_string(), 0)];
+        (machine, counters.into_iter().collect())
+    }
+
+    fn report(format: StatsFormat) -> String {
+        let (machine, counters) = counted();
+        let mut out = Vec::new();
+        write_stats(&machine, &counters, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn stats_table() {
+        let out = report(StatsFormat::Text);
+        let counters = out.split_once("\n\n").unwrap().1;
+        let lines: Vec<Vec<&str>> = counters
+            .lines()
+            .map(|line| {
+                line.split("  ")
+                    .filter(|s| !s.is_empty())
+                    .map(str::trim)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                vec!["counter", "count"],
+                vec!["say \"hi\"", "2"],
+                vec!["unused", "0"]
+            ]
+        );
+    }
+
+    #[test]
+    fn stats_json() {
+        let out = report(StatsFormat::Json);
+        // Only the opcodes that ran.
+        assert!(
+            out.starts_with(r#"{"ops":[{"op":13,"name":"ORTHO","count":3,"cycles":"#),
+            "{out}"
+        );
+        assert!(
+            out.ends_with("],\"counters\":{\"say \\\"hi\\\"\":2,\"unused\":0}}\n"),
+            "{out}"
+        );
+        assert_eq!(out.matches(r#""op":"#).count(), 1);
+    }
+
+    #[test]
+    fn stats_prometheus() {
+        let out = report(StatsFormat::Prometheus);
+        let samples: Vec<&str> = out.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(samples.len(), 4, "{out}");
+        assert_eq!(
+            samples[0],
+            r#"um32_instructions_total{op="13",name="ORTHO"} 3"#
+        );
+        assert!(samples[1].starts_with(r#"um32_cycles_total{op="13",name="ORTHO"} "#));
+        assert_eq!(
+            &samples[2..],
+            [
+                r#"um32_counter{name="say \"hi\""} 2"#,
+                r#"um32_counter{name="unused"} 0"#
+            ]
+        );
+        assert!(out.contains("# TYPE um32_counter counter\n"));
+    }
+}
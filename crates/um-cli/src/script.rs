@@ -0,0 +1,203 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Input scripts, as written by `--record` and read by `--script`.
+///
+/// Each line is a comment starting with `#`, `send "TEXT"` to feed a line
+/// of input to the program, `expect "TEXT"` to check that the program
+/// has printed TEXT since the previous `send` before the next one is fed,
+/// or `prompt` to check that its output stops at a shell [`Prompt`]:
+///
+/// ```text
+/// expect "login:"
+/// send "guest\n"
+/// prompt
+/// send "ls\n"
+/// ```
+///
+/// Strings use `\n`, `\t`, `\\`, `\"` and `\xNN` escapes.
+pub struct Script {
+    steps: VecDeque<(usize, Step)>,
+    prompt: Prompt,
+}
+
+enum Step {
+    Expect(Vec<u8>),
+    Prompt,
+    Send(Vec<u8>),
+}
+
+/// Recognizes a program waiting at its shell prompt: the output since the
+/// last input ends with the prompt's text, `% ` for UMIX.
+#[derive(Clone)]
+pub struct Prompt(Vec<u8>);
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Self(b"% ".to_vec())
+    }
+}
+
+impl Prompt {
+    pub fn new(text: &str) -> Self {
+        Self(text.as_bytes().to_vec())
+    }
+
+    /// Whether `output` stops at the prompt.
+    pub fn at_end(&self, output: &[u8]) -> bool {
+        !self.0.is_empty() && output.ends_with(&self.0)
+    }
+}
+
+impl Script {
+    pub fn load(path: impl AsRef<Path>, prompt: Prompt) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::parse(&text, prompt).map_err(|e| format!("{}:{e}", path.display()))
+    }
+
+    fn parse(text: &str, prompt: Prompt) -> Result<Self, String> {
+        let mut steps = VecDeque::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "prompt" {
+                steps.push_back((i + 1, Step::Prompt));
+                continue;
+            }
+            let (word, arg) = line.split_once(' ').unwrap_or((line, ""));
+            let bytes = unquote(arg.trim())
+                .ok_or_else(|| format!("{}: expected a quoted string", i + 1))?;
+            let step = match word {
+                "expect" => Step::Expect(bytes),
+                "send" => Step::Send(bytes),
+                _ => return Err(format!("{}: unknown step {word:?}", i + 1)),
+            };
+            steps.push_back((i + 1, step));
+        }
+        Ok(Self { steps, prompt })
+    }
+
+    /// Returns the next line of input, checking the script's expectations
+    /// against `output`, everything printed since the last input line.
+    /// Returns `None` once the script is used up.
+    pub fn next_line(&mut self, output: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        while let Some((line, step)) = self.steps.pop_front() {
+            match step {
+                Step::Expect(text) => {
+                    if !output.windows(text.len()).any(|w| w == text) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "script line {line}: expected output {} before the next input, got {}",
+                                quote(&text),
+                                quote(anchor(output))
+                            ),
+                        ));
+                    }
+                }
+                Step::Prompt => {
+                    if !self.prompt.at_end(output) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "script line {line}: expected the prompt {} before the next input, got {}",
+                                quote(&self.prompt.0),
+                                quote(anchor(output))
+                            ),
+                        ));
+                    }
+                }
+                Step::Send(bytes) => return Ok(Some(bytes)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Writes the lines typed in a session as a [`Script`].
+pub struct Recorder {
+    file: BufWriter<File>,
+    // The prompt to anchor on, if anchors are wanted.
+    anchors: Option<Prompt>,
+}
+
+impl Recorder {
+    /// With `anchors`, each `send` is preceded by a `prompt` step if the
+    /// program was waiting at that prompt, and otherwise by an `expect`
+    /// for the last line it printed before asking for input.
+    pub fn create(path: impl AsRef<Path>, anchors: Option<Prompt>) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "# um-32 input script, replay with --script")?;
+        file.flush()?;
+        Ok(Self { file, anchors })
+    }
+
+    pub fn record(&mut self, output: &[u8], line: &[u8]) -> io::Result<()> {
+        let anchor = anchor(output);
+        match &self.anchors {
+            Some(prompt) if prompt.at_end(output) => writeln!(self.file, "prompt")?,
+            Some(_) if !anchor.is_empty() => writeln!(self.file, "expect {}", quote(anchor))?,
+            _ => {}
+        }
+        writeln!(self.file, "send {}", quote(line))?;
+        self.file.flush()
+    }
+}
+
+// The last non-blank line of `output` without surrounding whitespace, cut
+// to its final 40 bytes.
+pub fn anchor(output: &[u8]) -> &[u8] {
+    let line = output
+        .split(|b| *b == b'\n')
+        .map(|line| line.trim_ascii())
+        .rfind(|line| !line.is_empty())
+        .unwrap_or_default();
+    &line[line.len().saturating_sub(40)..]
+}
+
+pub fn quote(bytes: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for b in bytes {
+        match b {
+            b'\n' => s.push_str("\\n"),
+            b'\t' => s.push_str("\\t"),
+            b'\\' => s.push_str("\\\\"),
+            b'"' => s.push_str("\\\""),
+            b' '..=b'~' => s.push(*b as char),
+            _ => s.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    s.push('"');
+    s
+}
+
+pub fn unquote(s: &str) -> Option<Vec<u8>> {
+    let s = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = Vec::new();
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        out.push(match bytes.next()? {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'\\' => b'\\',
+            b'"' => b'"',
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            _ => return None,
+        });
+    }
+    Some(out)
+}
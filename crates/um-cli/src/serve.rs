@@ -0,0 +1,781 @@
+//! `serve`: the console of a machine on a TCP connection, or on a WebSocket
+//! with --websocket.
+//!
+//! Each client gets a session: a fresh machine whose Input reads from the
+//! connection and whose Output writes to it, built from the program images
+//! or the snapshot given, so a snapshot of UMIX already booted can serve
+//! every client warm. A client disconnecting is the end of input. When the
+//! program halts or fails, or the session runs out of time, the connection
+//! is closed.
+//!
+//! With --sessions N, up to N clients are served at once, each on a thread
+//! of its own; clients beyond that are turned away. Otherwise clients are
+//! served one at a time, and --reconnect can keep a machine waiting for the
+//! next client when one disconnects, in which case output written with no
+//! client connected is lost.
+//!
+//! WebSocket clients may send input in text or binary messages, and get
+//! output in binary messages, one for each time the machine flushes its
+//! output, so the flush policy, --flush, decides how promptly they see it.
+//!
+//! With --inspect, a web page lists the sessions and shows each one's
+//! console, registers and statistics, with buttons to pause, resume and
+//! snapshot it; see the `inspect` module.
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "websocket")]
+use tungstenite::{Message, WebSocket};
+use um_core::{Machine, Stop};
+
+#[cfg(feature = "inspector")]
+use crate::inspect::{self, Inspector, Shared};
+use crate::{run, Error, ServeArgs};
+
+enum Client {
+    Tcp(TcpStream),
+    #[cfg(feature = "websocket")]
+    WebSocket(Box<WebSocket<TcpStream>>),
+}
+
+impl Client {
+    fn peer(&self) -> io::Result<SocketAddr> {
+        match self {
+            Client::Tcp(stream) => stream.peer_addr(),
+            #[cfg(feature = "websocket")]
+            Client::WebSocket(ws) => ws.get_ref().peer_addr(),
+        }
+    }
+
+    // Reads into `pending` until something has arrived, returning false at
+    // the end of input.
+    fn receive(&mut self, pending: &mut Vec<u8>) -> io::Result<bool> {
+        match self {
+            Client::Tcp(stream) => {
+                let mut buf = [0; 4096];
+                let n = loop {
+                    match stream.read(&mut buf) {
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        res => break res?,
+                    }
+                };
+                pending.extend_from_slice(&buf[..n]);
+                Ok(n != 0)
+            }
+            #[cfg(feature = "websocket")]
+            Client::WebSocket(ws) => loop {
+                match ws.read().map_err(ws_error)? {
+                    Message::Text(text) => pending.extend_from_slice(text.as_bytes()),
+                    Message::Binary(bytes) => pending.extend_from_slice(&bytes),
+                    Message::Close(_) => return Ok(false),
+                    Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+                }
+                if !pending.is_empty() {
+                    return Ok(true);
+                }
+            },
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Client::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(feature = "websocket")]
+            Client::WebSocket(ws) => ws.get_ref().set_read_timeout(timeout),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Client::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(feature = "websocket")]
+            Client::WebSocket(ws) => ws.get_ref().set_write_timeout(timeout),
+        }
+    }
+
+    fn send(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Client::Tcp(stream) => stream.write_all(buf),
+            #[cfg(feature = "websocket")]
+            Client::WebSocket(ws) => ws.send(Message::binary(buf.to_vec())).map_err(ws_error),
+        }
+    }
+
+    fn close(self) {
+        match self {
+            Client::Tcp(stream) => {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            #[cfg(feature = "websocket")]
+            Client::WebSocket(mut ws) => {
+                let _ = ws.close(None);
+                let _ = ws.flush();
+            }
+        }
+    }
+}
+
+// Keeps the kind of I/O errors, so that timeouts can be told apart.
+#[cfg(feature = "websocket")]
+fn ws_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+const TIMED_OUT: &str = "the session ran out of time";
+
+// How long the message saying why a session ended may take to send, so that
+// a client which has stopped reading can't keep the session open.
+const FAREWELL_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How long a client has to complete the WebSocket handshake, so that one
+// which connects and sends nothing doesn't hold up every client after it.
+#[cfg(feature = "websocket")]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Accepts clients, completing the WebSocket handshake with --websocket.
+struct Acceptor {
+    listener: TcpListener,
+    #[cfg(feature = "websocket")]
+    websocket: bool,
+    // Lists the sessions for the web page, with --inspect.
+    #[cfg(feature = "inspector")]
+    inspector: Option<Inspector>,
+}
+
+impl Acceptor {
+    fn accept(&self) -> io::Result<Client> {
+        loop {
+            let (stream, peer) = self.listener.accept()?;
+            match self.connect(stream) {
+                Ok(client) => return Ok(client),
+                Err(e) => eprintln!("um-32: {peer}: {e}"),
+            }
+        }
+    }
+
+    #[cfg(feature = "websocket")]
+    fn connect(&self, stream: TcpStream) -> io::Result<Client> {
+        if !self.websocket {
+            return Ok(Client::Tcp(stream));
+        }
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        match tungstenite::accept(stream) {
+            Ok(ws) => {
+                ws.get_ref().set_read_timeout(None)?;
+                Ok(Client::WebSocket(Box::new(ws)))
+            }
+            Err(e) => Err(io::Error::other(format!("WebSocket handshake failed: {e}"))),
+        }
+    }
+
+    #[cfg(not(feature = "websocket"))]
+    fn connect(&self, stream: TcpStream) -> io::Result<Client> {
+        Ok(Client::Tcp(stream))
+    }
+}
+
+struct State {
+    session: u64,
+    client: Option<Client>,
+    // Input received but not yet read by the machine.
+    pending: Vec<u8>,
+    // Where the next client comes from when one disconnects, with
+    // --reconnect.
+    reconnect: Option<Arc<Acceptor>>,
+    // When the session runs out of time, with --session-timeout.
+    deadline: Option<Instant>,
+    // What the session shares with the inspector, with --inspect.
+    #[cfg(feature = "inspector")]
+    inspected: Option<Arc<Shared>>,
+}
+
+impl State {
+    fn connect(&mut self, client: Client) {
+        if let Ok(peer) = client.peer() {
+            eprintln!("um-32: session {}: {peer} connected", self.session);
+        }
+        self.client = Some(client);
+        self.pending.clear();
+    }
+
+    // How long to wait for input at a time: no longer than the session has
+    // left, nor than the inspector should wait for the machine.
+    fn wait(&self) -> Option<Duration> {
+        let left = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        #[cfg(feature = "inspector")]
+        if self.inspected.is_some() {
+            return Some(left.map_or(inspect::POLL, |left| left.min(inspect::POLL)));
+        }
+        left
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(client) = self.client.take() {
+            if let Ok(peer) = client.peer() {
+                eprintln!("um-32: session {}: {peer} disconnected", self.session);
+            }
+            client.close();
+        }
+    }
+}
+
+// The connection as the machine's stdin and stdout. The machine reads and
+// writes from one thread, so the lock is never waited on.
+#[derive(Clone)]
+struct Link(Arc<Mutex<State>>);
+
+impl Read for Link {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.0.lock().unwrap();
+        let state = &mut *state;
+        while state.pending.is_empty() {
+            let wait = state.wait();
+            let Some(client) = state.client.as_mut() else {
+                let Some(acceptor) = state.reconnect.clone() else {
+                    return Ok(0);
+                };
+                state.connect(acceptor.accept()?);
+                continue;
+            };
+            if let Some(wait) = wait {
+                // A zero timeout would mean none at all.
+                if wait.is_zero() {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, TIMED_OUT));
+                }
+                client.set_read_timeout(Some(wait))?;
+            }
+            match client.receive(&mut state.pending) {
+                Ok(true) => {}
+                // A TCP client may only have shut down its side, and still
+                // be reading what the program writes after the end of input.
+                Ok(false) if state.reconnect.is_none() => return Ok(0),
+                // Waited as long as it could; the next turn finds out if the
+                // session has run out of time.
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    // The inspector is waiting for the machine.
+                    #[cfg(feature = "inspector")]
+                    if state.inspected.as_ref().is_some_and(|i| i.requested()) {
+                        return Err(inspect::requested());
+                    }
+                }
+                Ok(false) | Err(_) => {
+                    state.disconnect();
+                    if state.reconnect.is_none() {
+                        return Ok(0);
+                    }
+                }
+            }
+        }
+        let n = buf.len().min(state.pending.len());
+        buf[..n].copy_from_slice(&state.pending[..n]);
+        state.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for Link {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.lock().unwrap();
+        #[cfg(feature = "inspector")]
+        if let Some(inspected) = &state.inspected {
+            inspected.record(buf);
+        }
+        let deadline = state.deadline;
+        if let Some(client) = state.client.as_mut() {
+            if let Some(deadline) = deadline {
+                // A client that stops reading fills the socket's buffer, and
+                // then sending waits until the session runs out of time.
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, TIMED_OUT));
+                }
+                client.set_write_timeout(Some(left))?;
+            }
+            match client.send(buf) {
+                Ok(()) => {}
+                Err(e)
+                    if deadline.is_some()
+                        && matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, TIMED_OUT));
+                }
+                Err(_) => state.disconnect(),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Runs a session for `client` until the program halts or fails, or the
+// session runs out of time, and closes the connection.
+fn session(
+    id: u64,
+    client: Client,
+    args: &ServeArgs,
+    acceptor: &Arc<Acceptor>,
+) -> Result<(), Error> {
+    let deadline = args.session_timeout.map(|t| Instant::now() + t);
+    #[cfg(feature = "inspector")]
+    let peer = client
+        .peer()
+        .map_or_else(|_| "unknown".to_string(), |peer| peer.to_string());
+    #[cfg(feature = "inspector")]
+    let inspected = acceptor
+        .inspector
+        .as_ref()
+        .map(|_| Arc::new(Shared::default()));
+    let link = Link(Arc::new(Mutex::new(State {
+        session: id,
+        client: None,
+        pending: Vec::new(),
+        reconnect: args.reconnect.then(|| acceptor.clone()),
+        deadline,
+        #[cfg(feature = "inspector")]
+        inspected: inspected.clone(),
+    })));
+    link.0.lock().unwrap().connect(client);
+    let builder = Machine::builder()
+        .stdin(link.clone())
+        .stdout(link.clone())
+        .flush_policy(args.flush.into());
+    let res = run::load(&args.machine, builder).and_then(|mut machine| {
+        machine.set_deadline(deadline);
+        #[cfg(feature = "inspector")]
+        let stop = match (&acceptor.inspector, inspected) {
+            (Some(inspector), Some(shared)) => inspector
+                .add(id, peer, shared, machine.interrupter())
+                .run(&mut machine, deadline),
+            _ => machine.run_until_stop(),
+        };
+        #[cfg(not(feature = "inspector"))]
+        let stop = machine.run_until_stop();
+        match stop? {
+            Stop::Timeout { .. } => Err(Error::InvalidArgument(TIMED_OUT.to_string())),
+            _ => Ok(()),
+        }
+    });
+    let mut state = link.0.lock().unwrap();
+    if let Some(client) = state.client.as_ref() {
+        let _ = client.set_write_timeout(Some(FAREWELL_TIMEOUT));
+    }
+    match &res {
+        Ok(()) => eprintln!("um-32: session {id} ended: the program halted"),
+        Err(Error::Machine(um_core::Error::IO(e))) if e.kind() == io::ErrorKind::TimedOut => {
+            eprintln!("um-32: session {id} ended: {TIMED_OUT}");
+            if let Some(client) = state.client.as_mut() {
+                let _ = client.send(format!("um-32: {TIMED_OUT}\n").as_bytes());
+            }
+        }
+        Err(e) => {
+            eprintln!("um-32: session {id} ended: {e}");
+            if let Some(client) = state.client.as_mut() {
+                let _ = client.send(format!("um-32: {e}\n").as_bytes());
+            }
+        }
+    }
+    state.disconnect();
+    res
+}
+
+pub fn serve(args: ServeArgs) -> Result<(), Error> {
+    // Fail now rather than when the first client connects.
+    run::load(&args.machine, Machine::builder().stdin(io::empty()))?;
+    let acceptor = Arc::new(Acceptor {
+        listener: TcpListener::bind(&args.listen)?,
+        #[cfg(feature = "websocket")]
+        websocket: args.websocket,
+        #[cfg(feature = "inspector")]
+        inspector: match &args.inspect {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                eprintln!("um-32: inspector on http://{}/", listener.local_addr()?);
+                Some(Inspector::start(listener))
+            }
+            None => None,
+        },
+    });
+    eprintln!("um-32: listening on {}", acceptor.listener.local_addr()?);
+    accept_sessions(acceptor, args)
+}
+
+// Serves each client `acceptor` accepts.
+fn accept_sessions(acceptor: Arc<Acceptor>, args: ServeArgs) -> Result<(), Error> {
+    let mut id = 0;
+    let Some(sessions) = args.sessions else {
+        loop {
+            id += 1;
+            let res = session(id, acceptor.accept()?, &args, &acceptor);
+            if args.once {
+                return res;
+            }
+        }
+    };
+
+    let args = Arc::new(args);
+    let active = Arc::new(AtomicUsize::new(0));
+    let next = AtomicU64::new(1);
+    loop {
+        let (stream, peer) = acceptor.listener.accept()?;
+        // Decided here rather than after the handshake, so that no more
+        // than `sessions` ever start.
+        let id = (active.load(Ordering::SeqCst) < sessions.get()).then(|| {
+            active.fetch_add(1, Ordering::SeqCst);
+            next.fetch_add(1, Ordering::Relaxed)
+        });
+        let (acceptor, args, active) = (acceptor.clone(), args.clone(), active.clone());
+        // The handshake happens on a thread of its own, so that a client
+        // slow to complete it holds up no one else.
+        std::thread::spawn(move || {
+            match (acceptor.connect(stream), id) {
+                (Err(e), _) => eprintln!("um-32: {peer}: {e}"),
+                (Ok(mut client), None) => {
+                    eprintln!("um-32: {peer}: turned away, with {sessions} sessions running");
+                    let _ = client.set_write_timeout(Some(FAREWELL_TIMEOUT));
+                    let _ = client.send(b"um-32: too many sessions, try again later\n");
+                    client.close();
+                }
+                // Ending with an error is reported to the client and logged.
+                (Ok(client), Some(id)) => {
+                    let _ = session(id, client, &args, &acceptor);
+                }
+            }
+            if id.is_some() {
+                active.fetch_sub(1, Ordering::SeqCst);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::{Cli, Command};
+
+    // Echoes its input and then prints how many bytes there were.
+    const COUNT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../tests/golden/count.um");
+
+    // Writes a program image for a test to serve.
+    fn program(name: &str, words: &[u32]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("um-32-serve-{}-{name}.um", std::process::id()));
+        let image: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        std::fs::write(&path, image).unwrap();
+        path
+    }
+
+    fn args(options: &[&str], program: &str) -> ServeArgs {
+        let command = ["um-32", "serve"].iter().chain(options).chain([&program]);
+        match Cli::try_parse_from(command).unwrap().command {
+            Command::Serve(args) => args,
+            _ => unreachable!(),
+        }
+    }
+
+    // Serves clients with `args` on a port of its own, returning the
+    // address and the thread serving.
+    fn serve(args: ServeArgs) -> (SocketAddr, std::thread::JoinHandle<Result<(), Error>>) {
+        let acceptor = Acceptor {
+            listener: TcpListener::bind("127.0.0.1:0").unwrap(),
+            #[cfg(feature = "websocket")]
+            websocket: args.websocket,
+            #[cfg(feature = "inspector")]
+            inspector: None,
+        };
+        let addr = acceptor.listener.local_addr().unwrap();
+        let serving = std::thread::spawn(move || accept_sessions(Arc::new(acceptor), args));
+        (addr, serving)
+    }
+
+    // Sends `input` and ends it, returning everything received.
+    fn talk(client: &mut TcpStream, input: &[u8]) -> Vec<u8> {
+        client.write_all(input).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut out = Vec::new();
+        client.read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn tcp() {
+        let (addr, serving) = serve(args(&["--once"], COUNT));
+        let mut client = TcpStream::connect(addr).unwrap();
+        // Output still reaches a client that has ended its input.
+        assert_eq!(talk(&mut client, b"hi"), b"hi2\n");
+        serving.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn reconnect() {
+        // IN r1 ; IN r2 ; OUT r1 ; OUT r2 ; HALT
+        let path = program(
+            "reconnect",
+            &[
+                0xb000_0001,
+                0xb000_0002,
+                0xa000_0001,
+                0xa000_0002,
+                0x7000_0000,
+            ],
+        );
+        let (addr, serving) = serve(args(&["--once", "--reconnect"], path.to_str().unwrap()));
+        let mut first = TcpStream::connect(addr).unwrap();
+        first.write_all(b"h").unwrap();
+        drop(first);
+        // The next client carries on with the same machine.
+        let mut second = TcpStream::connect(addr).unwrap();
+        let out = talk(&mut second, b"i");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(out, b"hi");
+        serving.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn faults() {
+        // OUT r0 ; .word 0xf0000000
+        let path = program("faults", &[0xa000_0000, 0xf000_0000]);
+        let (addr, serving) = serve(args(&["--once"], path.to_str().unwrap()));
+        let mut client = TcpStream::connect(addr).unwrap();
+        let out = talk(&mut client, b"");
+        std::fs::remove_file(&path).unwrap();
+        // The program's output, then the error, and the connection closes.
+        assert!(out.starts_with(b"\0um-32: "), "{out:?}");
+        assert!(matches!(
+            serving.join().unwrap(),
+            Err(Error::Machine(um_core::Error::InvalidOp { pc: 1, .. }))
+        ));
+    }
+
+    #[test]
+    fn session_timeout() {
+        // Waiting for input.
+        let (addr, serving) = serve(args(&["--once", "--session-timeout", "0.1"], COUNT));
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut out = Vec::new();
+        client.read_to_end(&mut out).unwrap();
+        assert_eq!(out, format!("um-32: {TIMED_OUT}\n").as_bytes());
+        match serving.join().unwrap() {
+            Err(Error::Machine(um_core::Error::IO(e))) => {
+                assert_eq!(e.kind(), io::ErrorKind::TimedOut)
+            }
+            res => panic!("{res:?}"),
+        }
+
+        // Running: LOADPROG r0, r0 jumps to itself.
+        let path = program("session-timeout", &[0xc000_0000]);
+        let (addr, serving) = serve(args(
+            &["--once", "--session-timeout", "0.1"],
+            path.to_str().unwrap(),
+        ));
+        let mut client = TcpStream::connect(addr).unwrap();
+        let out = String::from_utf8(talk(&mut client, b"")).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            out.starts_with("um-32: ") && out.contains(TIMED_OUT),
+            "{out}"
+        );
+        assert!(serving.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn stops_reading() {
+        // OUT r0 ; LOADPROG r0, r0 writes forever to a client that never
+        // reads, until the socket's buffers are full.
+        let path = program("stops-reading", &[0xa000_0000, 0xc000_0000]);
+        let (addr, serving) = serve(args(
+            &["--once", "--session-timeout", "0.2"],
+            path.to_str().unwrap(),
+        ));
+        let _client = TcpStream::connect(addr).unwrap();
+        let res = serving.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        match res {
+            Err(Error::Machine(um_core::Error::IO(e))) => {
+                assert_eq!(e.kind(), io::ErrorKind::TimedOut)
+            }
+            res => panic!("{res:?}"),
+        }
+    }
+
+    #[test]
+    fn sessions() {
+        // Serves until the test ends.
+        let (addr, _serving) = serve(args(&["--sessions", "1"], COUNT));
+        let mut first = TcpStream::connect(addr).unwrap();
+        let mut second = TcpStream::connect(addr).unwrap();
+        let mut out = Vec::new();
+        second.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"um-32: too many sessions, try again later\n");
+        assert_eq!(talk(&mut first, b"hi"), b"hi2\n");
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn websocket() {
+        // IN r1 ; IN r2 ; OUT r1 ; OUT r2 ; HALT
+        let path = program(
+            "websocket",
+            &[
+                0xb000_0001,
+                0xb000_0002,
+                0xa000_0001,
+                0xa000_0002,
+                0x7000_0000,
+            ],
+        );
+        // The output messages of a session served with `options`.
+        let messages = |options: &[&str]| {
+            let options = [&["--once", "--websocket"], options].concat();
+            let (addr, serving) = serve(args(&options, path.to_str().unwrap()));
+            let stream = TcpStream::connect(addr).unwrap();
+            let (mut ws, _) = tungstenite::client(format!("ws://{addr}/"), stream).unwrap();
+            // Input comes in text and binary messages alike.
+            ws.send(Message::text("h")).unwrap();
+            ws.send(Message::binary(b"i".to_vec())).unwrap();
+            let mut out = Vec::new();
+            loop {
+                match ws.read() {
+                    Ok(Message::Binary(bytes)) => out.push(bytes.to_vec()),
+                    Ok(Message::Close(_)) | Err(_) => break,
+                    Ok(message) => panic!("unexpected {message:?}"),
+                }
+            }
+            serving.join().unwrap().unwrap();
+            out
+        };
+        // With no newline, output waits for the program to halt unless
+        // --flush says otherwise.
+        assert_eq!(messages(&[]), [b"hi"]);
+        assert_eq!(messages(&["--flush", "byte"]), [b"h", b"i"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn stalled_handshake() {
+        // IN r1 ; IN r2 ; OUT r1 ; OUT r2 ; HALT
+        let path = program(
+            "stalled-handshake",
+            &[
+                0xb000_0001,
+                0xb000_0002,
+                0xa000_0001,
+                0xa000_0002,
+                0x7000_0000,
+            ],
+        );
+        // Serves until the test ends.
+        let (addr, _serving) = serve(args(
+            &["--websocket", "--sessions", "2"],
+            path.to_str().unwrap(),
+        ));
+        // Connects and never sends the upgrade request.
+        let _stalled = TcpStream::connect(addr).unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        let (mut ws, _) = tungstenite::client(format!("ws://{addr}/"), stream).unwrap();
+        ws.send(Message::text("hi")).unwrap();
+        let out = ws.read().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(out, Message::binary(b"hi".to_vec()));
+    }
+
+    // Sends one HTTP request to the inspector, returning the status and
+    // the body of the response.
+    #[cfg(feature = "inspector")]
+    fn http(addr: SocketAddr, method: &str, path: &str) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "{method} {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(response[..end].to_vec()).unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, response[end + 4..].to_vec())
+    }
+
+    #[cfg(feature = "inspector")]
+    #[test]
+    fn inspector() {
+        let page = TcpListener::bind("127.0.0.1:0").unwrap();
+        let inspect = page.local_addr().unwrap();
+        let args = args(&["--once", "--flush", "byte"], COUNT);
+        let acceptor = Acceptor {
+            listener: TcpListener::bind("127.0.0.1:0").unwrap(),
+            #[cfg(feature = "websocket")]
+            websocket: false,
+            inspector: Some(Inspector::start(page)),
+        };
+        let addr = acceptor.listener.local_addr().unwrap();
+        let serving = std::thread::spawn(move || accept_sessions(Arc::new(acceptor), args));
+        let (status, body) = http(inspect, "GET", "/");
+        assert_eq!(status, 200);
+        assert!(String::from_utf8(body).unwrap().contains("<title>um-32"));
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"hi").unwrap();
+
+        // The session answers while it waits for more input.
+        let mut status = String::new();
+        for _ in 0..100 {
+            let (code, body) = http(inspect, "GET", "/sessions/1");
+            status = String::from_utf8(body).unwrap();
+            if code == 200 && status.contains(r#""console":"hi""#) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(status.contains(r#""console":"hi""#), "{status}");
+        assert!(status.contains(r#""paused":false"#), "{status}");
+        let (_, sessions) = http(inspect, "GET", "/sessions");
+        assert!(sessions.starts_with(br#"[{"id":1,"peer":"127.0.0.1:"#));
+
+        assert_eq!(http(inspect, "POST", "/sessions/1/pause").0, 204);
+        let (code, snapshot) = http(inspect, "POST", "/sessions/1/snapshot");
+        assert_eq!(code, 200);
+        let machine = Machine::read_snapshot(&mut snapshot.as_slice()).unwrap();
+        assert!(machine.executed() > 0);
+        let (_, status) = http(inspect, "GET", "/sessions/1");
+        assert!(String::from_utf8(status)
+            .unwrap()
+            .contains(r#""paused":true"#));
+        // Input that comes while paused waits for the session to resume.
+        client.write_all(b"!").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(http(inspect, "POST", "/sessions/1/resume").0, 204);
+        let mut out = Vec::new();
+        client.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hi!3\n");
+        serving.join().unwrap().unwrap();
+
+        assert_eq!(http(inspect, "GET", "/sessions/1").0, 404);
+        assert_eq!(http(inspect, "GET", "/sessions").1, b"[]");
+        assert_eq!(http(inspect, "DELETE", "/sessions/1").0, 405);
+    }
+}
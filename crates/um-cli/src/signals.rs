@@ -0,0 +1,133 @@
+//! Ctrl-C and SIGUSR1 for `run`.
+//!
+//! The handlers only set a pending flag and interrupt the machine, which
+//! stops at its next check, within 65536 instructions, for `run` to see
+//! what is pending. SIGINT also makes a read blocked on console input give
+//! up with an error, leaving the Input instruction to be executed again, so
+//! a session waiting for a command can be saved as well. A second Ctrl-C
+//! kills the process as usual.
+
+use std::{
+    io::{self, BufRead, Read},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        OnceLock,
+    },
+};
+
+use um_core::Interrupter;
+
+const INT: u8 = 1;
+const USR1: u8 = 2;
+
+static PENDING: AtomicU8 = AtomicU8::new(0);
+static INTERRUPTER: OnceLock<Interrupter> = OnceLock::new();
+
+/// Installs the handlers, which interrupt the machine `interrupter` is
+/// for. Only the first call has any effect.
+#[cfg(unix)]
+pub fn install(interrupter: Interrupter) {
+    if INTERRUPTER.set(interrupter).is_err() {
+        return;
+    }
+    // SIGINT without SA_RESTART, so that blocked reads fail with EINTR.
+    for (sig, flags) in [(libc::SIGINT, 0), (libc::SIGUSR1, libc::SA_RESTART)] {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = flags;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(sig, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install(_: Interrupter) {}
+
+#[cfg(unix)]
+extern "C" fn handle(sig: libc::c_int) {
+    let bit = if sig == libc::SIGINT { INT } else { USR1 };
+    if PENDING.fetch_or(bit, Ordering::SeqCst) & bit == INT {
+        crate::raw::restore();
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::raise(libc::SIGINT);
+        }
+    }
+    if let Some(interrupter) = INTERRUPTER.get() {
+        interrupter.interrupt();
+    }
+}
+
+/// Whether Ctrl-C has been pressed. It stays pending: the run is over.
+pub fn interrupted() -> bool {
+    PENDING.load(Ordering::SeqCst) & INT != 0
+}
+
+/// Whether SIGUSR1 has arrived since the last call.
+pub fn take_dump() -> bool {
+    PENDING.fetch_and(!USR1, Ordering::SeqCst) & USR1 != 0
+}
+
+/// Does what Ctrl-C does, for front-ends that read it as a key, returning
+/// the error the read that saw it should give up with.
+pub fn interrupt() -> io::Error {
+    PENDING.fetch_or(INT, Ordering::SeqCst);
+    if let Some(interrupter) = INTERRUPTER.get() {
+        interrupter.interrupt();
+    }
+    interrupted_error()
+}
+
+// What reads give up with once Ctrl-C has been pressed. Std retries reads
+// that fail with ErrorKind::Interrupted, so it needs a kind of its own.
+fn interrupted_error() -> io::Error {
+    io::Error::other("interrupted")
+}
+
+/// A reader that gives up once Ctrl-C has been pressed instead of
+/// retrying reads it interrupted.
+pub struct Interruptible<R>(pub R);
+
+impl<R: Read> Read for Interruptible<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    if interrupted() {
+                        return Err(interrupted_error());
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// [`BufRead::read_until`], but giving up once Ctrl-C has been pressed.
+pub fn read_until(r: &mut impl BufRead, byte: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut read = 0;
+    loop {
+        let available = match r.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                if interrupted() {
+                    return Err(interrupted_error());
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let (done, used) = match available.iter().position(|b| *b == byte) {
+            Some(i) => (true, i + 1),
+            None => (available.is_empty(), available.len()),
+        };
+        buf.extend_from_slice(&available[..used]);
+        r.consume(used);
+        read += used;
+        if done {
+            return Ok(read);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use um_core::Machine;
+
+use crate::{Error, StateCommand};
+
+pub fn state(command: StateCommand) -> Result<(), Error> {
+    match command {
+        StateCommand::Upgrade { from, to } => {
+            let version = Machine::snapshot_version(&from)?;
+            Machine::load_snapshot(&from)?.save_snapshot(&to)?;
+            eprintln!(
+                "{}: version {version} -> {}: version {}",
+                from.display(),
+                to.display(),
+                Machine::SNAPSHOT_VERSION
+            );
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,520 @@
+#[cfg(feature = "sqlite")]
+use std::path::Path;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::RangeInclusive,
+    path::PathBuf,
+};
+
+use clap::{ArgGroup, Args};
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+use um_tools::disasm;
+
+use crate::parse_u32;
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("trace_output").multiple(true)))]
+pub struct TraceArgs {
+    /// Write executed instructions to FILE, one line each with the
+    /// instruction count, pc, platter, mnemonic and the registers before
+    /// the instruction executes
+    #[arg(long, value_name = "FILE", group = "trace_output")]
+    pub trace_file: Option<PathBuf>,
+    /// Write executed instructions to FILE as JSON lines, for scripts: one
+    /// object per instruction with its `count`, `pc`, `inst`, `op`,
+    /// `operands`, the registers it `changed`, and its effect on memory,
+    /// one of `read`, `amend`, `alloc`, `abandon`, `output` or `load`
+    #[arg(long, value_name = "FILE", group = "trace_output")]
+    pub trace_json: Option<PathBuf>,
+    /// Store executed instructions in the SQLite database FILE, in a table
+    /// `trace(instr_count, pc, op, inst, r0, ..., r7)` indexed on instr_count,
+    /// pc and op. Registers are those before the instruction executes
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "FILE", group = "trace_output")]
+    pub trace_sqlite: Option<PathBuf>,
+    /// Store only every Nth instruction that passes the other filters
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "trace_output", value_parser = parse_every)]
+    pub trace_every: u64,
+    /// Store only instructions at pc START through END, or at PC; may be
+    /// repeated
+    #[arg(long, value_name = "START[-END]", requires = "trace_output", value_parser = parse_pcs)]
+    pub trace_pc: Vec<RangeInclusive<u32>>,
+    /// Store only instructions with opcode OP, given as a number or a
+    /// mnemonic such as AMEND; may be repeated
+    #[arg(long, value_name = "OP", requires = "trace_output", value_parser = parse_op)]
+    pub trace_op: Vec<u32>,
+    /// Store only the first N times each pc is executed, which keeps loops
+    /// from swamping the trace
+    #[arg(long, value_name = "N", requires = "trace_output")]
+    pub trace_first: Option<u64>,
+}
+
+fn parse_every(s: &str) -> Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        res => res.map_err(|e| e.to_string()),
+    }
+}
+
+fn parse_pcs(s: &str) -> Result<RangeInclusive<u32>, String> {
+    match s.split_once('-') {
+        Some((start, end)) => Ok(parse_u32(start)?..=parse_u32(end)?),
+        None => parse_u32(s).map(|pc| pc..=pc),
+    }
+}
+
+fn parse_op(s: &str) -> Result<u32, String> {
+    (0..14)
+        .find(|op| {
+            disasm::op_name(*op).is_some_and(|name| name.eq_ignore_ascii_case(s))
+                || parse_u32(s) == Ok(*op)
+        })
+        .ok_or_else(|| format!("expected an opcode 0-13 or its mnemonic, got {s:?}"))
+}
+
+// Decides which instructions are stored. Filtering happens as the machine
+// runs, since an unfiltered trace of a long run is gigabytes.
+struct Filter {
+    every: u64,
+    pcs: Vec<RangeInclusive<u32>>,
+    ops: Vec<u32>,
+    first: Option<u64>,
+    // How often each pc passed the pc and opcode filters, for `first`.
+    seen: HashMap<u32, u64>,
+    // Instructions executed so far, and those that passed the filters.
+    count: u64,
+    matched: u64,
+}
+
+impl Filter {
+    // Returns the instruction's count if it is to be stored.
+    fn pass(&mut self, pc: u32, op: u32) -> Option<u64> {
+        let count = self.count;
+        self.count += 1;
+        if !self.pcs.is_empty() && !self.pcs.iter().any(|pcs| pcs.contains(&pc))
+            || !self.ops.is_empty() && !self.ops.contains(&op)
+        {
+            return None;
+        }
+        if let Some(first) = self.first {
+            let seen = self.seen.entry(pc).or_default();
+            if *seen >= first {
+                return None;
+            }
+            *seen += 1;
+        }
+        self.matched += 1;
+        (self.matched - 1)
+            .is_multiple_of(self.every)
+            .then_some(count)
+    }
+}
+
+struct Trace {
+    filter: Filter,
+    file: Option<BufWriter<File>>,
+    json: Option<JsonTrace>,
+    #[cfg(feature = "sqlite")]
+    sqlite: Option<SqliteTrace>,
+}
+
+/// An instruction hook, as passed to
+/// [`MachineBuilder::on_instruction`](um_core::MachineBuilder::on_instruction).
+pub type Hook = Box<dyn FnMut(u32, u32, &[u32; 8]) -> io::Result<()> + Send>;
+
+impl TraceArgs {
+    /// Returns the instruction hook that writes the trace, if one was
+    /// asked for.
+    pub fn hook(&self) -> io::Result<Option<Hook>> {
+        let file = self.trace_file.as_ref().map(File::create).transpose()?;
+        let json = self.trace_json.as_ref().map(File::create).transpose()?;
+        #[cfg(feature = "sqlite")]
+        let sqlite = self
+            .trace_sqlite
+            .as_ref()
+            .map(|path| SqliteTrace::create(path))
+            .transpose()
+            .map_err(io::Error::other)?;
+        let mut trace = Trace {
+            filter: Filter {
+                every: self.trace_every,
+                pcs: self.trace_pc.clone(),
+                ops: self.trace_op.clone(),
+                first: self.trace_first,
+                seen: HashMap::new(),
+                count: 0,
+                matched: 0,
+            },
+            file: file.map(BufWriter::new),
+            json: json.map(|file| JsonTrace {
+                out: BufWriter::new(file),
+                pending: None,
+            }),
+            #[cfg(feature = "sqlite")]
+            sqlite,
+        };
+        if !trace.writes() {
+            return Ok(None);
+        }
+        Ok(Some(Box::new(move |pc, inst, registers| {
+            trace.event(pc, inst, registers)
+        })))
+    }
+}
+
+impl Trace {
+    fn writes(&self) -> bool {
+        #[cfg(feature = "sqlite")]
+        if self.sqlite.is_some() {
+            return true;
+        }
+        self.file.is_some() || self.json.is_some()
+    }
+
+    fn event(&mut self, pc: u32, inst: u32, r: &[u32; 8]) -> io::Result<()> {
+        let op = inst >> 28;
+        // The registers before this instruction are those after the one
+        // the JSON trace is holding back.
+        if let Some(json) = self.json.as_mut() {
+            json.finish(Some(r))?;
+        }
+        let Some(count) = self.filter.pass(pc, op) else {
+            return Ok(());
+        };
+        if let Some(json) = self.json.as_mut() {
+            json.pending = Some((count, pc, inst, *r));
+        }
+        if let Some(file) = self.file.as_mut() {
+            write_line(file, count, pc, inst, r)?;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = self.sqlite.as_mut() {
+            sqlite
+                .insert(count, pc, op, inst, r)
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the line `--trace-file` writes for an instruction: its count,
+/// pc, platter, mnemonic and the registers before it executes.
+pub fn write_line(
+    w: &mut impl Write,
+    count: u64,
+    pc: u32,
+    inst: u32,
+    r: &[u32; 8],
+) -> io::Result<()> {
+    write!(
+        w,
+        "{count:>12}  {pc:08x}: {inst:08x}  {:<24}",
+        disasm::mnemonic(inst, false)
+    )?;
+    for value in r {
+        write!(w, " {value:08x}")?;
+    }
+    writeln!(w)
+}
+
+// Writes each instruction once the next one shows what it changed.
+struct JsonTrace {
+    out: BufWriter<File>,
+    // The instruction count, pc, platter and registers before it.
+    pending: Option<(u64, u32, u32, [u32; 8])>,
+}
+
+impl JsonTrace {
+    // Writes the pending instruction, given the registers after it, if
+    // they are known.
+    fn finish(&mut self, after: Option<&[u32; 8]>) -> io::Result<()> {
+        let Some((count, pc, inst, r)) = self.pending.take() else {
+            return Ok(());
+        };
+        let out = &mut self.out;
+        let op = inst >> 28;
+        let (a, b, c) = (
+            (inst >> 6 & 7) as usize,
+            (inst >> 3 & 7) as usize,
+            (inst & 7) as usize,
+        );
+        write!(
+            out,
+            r#"{{"count":{count},"pc":{pc},"inst":{inst},"op":"{}","operands":"#,
+            disasm::op_name(op).unwrap_or("INVALID")
+        )?;
+        match op {
+            13 => write!(
+                out,
+                r#"{{"a":{},"value":{}}}"#,
+                inst >> 25 & 7,
+                inst & 0x1ff_ffff
+            )?,
+            _ => write!(out, r#"{{"a":{a},"b":{b},"c":{c}}}"#)?,
+        }
+        match after {
+            Some(after) => {
+                write!(out, r#","changed":{{"#)?;
+                let changed = (0..8).filter(|i| after[*i] != r[*i]);
+                for (n, i) in changed.enumerate() {
+                    let sep = if n == 0 { "" } else { "," };
+                    write!(out, r#"{sep}"r{i}":{}"#, after[i])?;
+                }
+                write!(out, "}}")?;
+            }
+            // The run ended before the instruction finished.
+            None => write!(out, r#","changed":null"#)?,
+        }
+        match op {
+            1 => write!(out, r#","read":{{"array":{},"offset":{}}}"#, r[b], r[c])?,
+            2 => write!(
+                out,
+                r#","amend":{{"array":{},"offset":{},"value":{}}}"#,
+                r[a], r[b], r[c]
+            )?,
+            8 => write!(out, r#","alloc":{{"size":{}}}"#, r[c])?,
+            9 => write!(out, r#","abandon":{{"array":{}}}"#, r[c])?,
+            10 => write!(out, r#","output":{}"#, r[c])?,
+            12 => write!(out, r#","load":{{"array":{},"pc":{}}}"#, r[b], r[c])?,
+            _ => {}
+        }
+        writeln!(out, "}}")
+    }
+}
+
+impl Drop for JsonTrace {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish(None).and_then(|_| self.out.flush()) {
+            eprintln!("um-32: could not finish the trace: {e}");
+        }
+    }
+}
+
+// Rows are written in transactions of this many.
+#[cfg(feature = "sqlite")]
+const BATCH: u64 = 100_000;
+
+#[cfg(feature = "sqlite")]
+struct SqliteTrace {
+    db: Connection,
+    stored: u64,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteTrace {
+    fn create(path: &Path) -> rusqlite::Result<Self> {
+        // A trace describes one run, so an old one is replaced.
+        let _ = std::fs::remove_file(path);
+        let db = Connection::open(path)?;
+        db.execute_batch(
+            "PRAGMA journal_mode = OFF;
+             PRAGMA synchronous = OFF;
+             CREATE TABLE ops (op INTEGER PRIMARY KEY, name TEXT NOT NULL);
+             CREATE TABLE trace (
+                 instr_count INTEGER PRIMARY KEY,
+                 pc INTEGER NOT NULL,
+                 op INTEGER NOT NULL,
+                 inst INTEGER NOT NULL,
+                 r0 INTEGER NOT NULL, r1 INTEGER NOT NULL,
+                 r2 INTEGER NOT NULL, r3 INTEGER NOT NULL,
+                 r4 INTEGER NOT NULL, r5 INTEGER NOT NULL,
+                 r6 INTEGER NOT NULL, r7 INTEGER NOT NULL
+             );
+             CREATE INDEX trace_pc ON trace (pc);
+             CREATE INDEX trace_op ON trace (op);",
+        )?;
+        for op in 0..14 {
+            db.execute(
+                "INSERT INTO ops VALUES (?1, ?2)",
+                params![op, disasm::op_name(op)],
+            )?;
+        }
+        db.execute_batch("BEGIN")?;
+        Ok(Self { db, stored: 0 })
+    }
+
+    fn insert(
+        &mut self,
+        count: u64,
+        pc: u32,
+        op: u32,
+        inst: u32,
+        r: &[u32; 8],
+    ) -> rusqlite::Result<()> {
+        self.db
+            .prepare_cached(
+                "INSERT INTO trace VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            )?
+            .execute(params![
+                count as i64,
+                pc,
+                op,
+                inst,
+                r[0],
+                r[1],
+                r[2],
+                r[3],
+                r[4],
+                r[5],
+                r[6],
+                r[7]
+            ])?;
+        self.stored += 1;
+        if self.stored.is_multiple_of(BATCH) {
+            self.db.execute_batch("COMMIT; BEGIN")?;
+        }
+        Ok(())
+    }
+}
+
+// The hook is dropped with the machine, however the run ends.
+#[cfg(feature = "sqlite")]
+impl Drop for SqliteTrace {
+    fn drop(&mut self) {
+        if let Err(e) = self.db.execute_batch("COMMIT") {
+            eprintln!("um-32: could not finish the trace: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(
+        every: u64,
+        pcs: Vec<RangeInclusive<u32>>,
+        ops: Vec<u32>,
+        first: Option<u64>,
+    ) -> Filter {
+        Filter {
+            every,
+            pcs,
+            ops,
+            first,
+            seen: HashMap::new(),
+            count: 0,
+            matched: 0,
+        }
+    }
+
+    // The counts of the instructions `filter` stores from `run`.
+    fn stored(mut filter: Filter, run: &[(u32, u32)]) -> Vec<u64> {
+        run.iter()
+            .filter_map(|&(pc, op)| filter.pass(pc, op))
+            .collect()
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(parse_every("3"), Ok(3));
+        assert_eq!(parse_every("0"), Err("must be at least 1".to_string()));
+        assert!(parse_every("x").is_err());
+        assert_eq!(parse_pcs("0x10-0x20"), Ok(0x10..=0x20));
+        assert_eq!(parse_pcs("7"), Ok(7..=7));
+        assert!(parse_pcs("1-").is_err());
+        assert_eq!(parse_op("amend"), Ok(2));
+        assert_eq!(parse_op("LOADPROG"), Ok(12));
+        assert_eq!(parse_op("13"), Ok(13));
+        assert_eq!(
+            parse_op("14"),
+            Err(r#"expected an opcode 0-13 or its mnemonic, got "14""#.to_string())
+        );
+        assert!(parse_op("host").is_err());
+    }
+
+    #[test]
+    fn filters() {
+        // A loop over pcs 0-3 with opcodes 13, 3, 10 and 12, run 3 times.
+        let run: Vec<(u32, u32)> = (0..3)
+            .flat_map(|_| [(0, 13), (1, 3), (2, 10), (3, 12)])
+            .collect();
+        assert_eq!(
+            stored(filter(1, vec![], vec![], None), &run),
+            (0..12).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            stored(filter(1, vec![1..=2], vec![], None), &run),
+            [1, 2, 5, 6, 9, 10]
+        );
+        assert_eq!(
+            stored(filter(1, vec![0..=0, 3..=3], vec![], None), &run),
+            [0, 3, 4, 7, 8, 11]
+        );
+        assert_eq!(
+            stored(filter(1, vec![], vec![10, 12], None), &run),
+            [2, 3, 6, 7, 10, 11]
+        );
+        // The pc and opcode filters must both pass.
+        assert_eq!(
+            stored(filter(1, vec![0..=2], vec![10, 12], None), &run),
+            [2, 6, 10]
+        );
+        assert_eq!(
+            stored(filter(1, vec![], vec![], Some(1)), &run),
+            [0, 1, 2, 3]
+        );
+        assert_eq!(
+            stored(filter(1, vec![2..=3], vec![], Some(2)), &run),
+            [2, 3, 6, 7]
+        );
+        // Every Nth of those that pass the rest, starting with the first.
+        assert_eq!(stored(filter(5, vec![], vec![], None), &run), [0, 5, 10]);
+        assert_eq!(stored(filter(2, vec![1..=1], vec![], None), &run), [1, 9]);
+        assert_eq!(
+            stored(filter(2, vec![], vec![], Some(2)), &run),
+            [0, 2, 4, 6]
+        );
+    }
+
+    #[test]
+    fn trace_file() {
+        let path = std::env::temp_dir().join(format!("um-32-trace-{}.txt", std::process::id()));
+        let args = TraceArgs {
+            trace_file: Some(path.clone()),
+            trace_json: None,
+            #[cfg(feature = "sqlite")]
+            trace_sqlite: None,
+            trace_every: 1,
+            trace_pc: vec![],
+            trace_op: vec![7],
+            trace_first: None,
+        };
+        let mut hook = args.hook().unwrap().unwrap();
+        hook(0, 0xd200_0001, &[0; 8]).unwrap();
+        hook(1, 0x7000_0000, &[0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        drop(hook);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut line = Vec::new();
+        write_line(&mut line, 1, 1, 0x7000_0000, &[0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(text.as_bytes(), line);
+
+        let none = TraceArgs {
+            trace_file: None,
+            ..args
+        };
+        assert!(none.hook().unwrap().is_none());
+    }
+
+    #[test]
+    fn lines() {
+        let mut out = Vec::new();
+        write_line(
+            &mut out,
+            42,
+            0x1c,
+            0xd200_0041,
+            &[0, 1, 2, 3, 4, 5, 6, 0xffff_ffff],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "          42  0000001c: d2000041  ORTHO r1, 0x41           \
+             00000000 00000001 00000002 00000003 00000004 00000005 00000006 ffffffff\n"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use um_core::{FlushPolicy, Journal, Machine};
+use um_tools::{
+    disasm,
+    verify::{self, Kind},
+};
+
+use crate::{
+    compare::{show, Compare, Progress},
+    run, Error, MachineArgs, VerifyArgs,
+};
+
+/// Replays the input recorded in `transcript`, a journal written with
+/// --journal, checking that the program writes the recorded output again,
+/// byte for byte. Reports the first difference and returns whether there
+/// was none.
+pub fn verify(args: &MachineArgs, transcript: &Path) -> Result<bool, Error> {
+    let file = File::open(transcript).map_err(run::naming(transcript))?;
+    let journal = Journal::read(BufReader::new(file))?;
+    let expected = journal.output().to_vec();
+
+    let progress = Arc::new(Mutex::new(Progress::default()));
+    let builder = Machine::builder()
+        .stdin(io::empty())
+        .echo(false)
+        .stdout(Compare {
+            expected: expected.clone(),
+            progress: progress.clone(),
+        })
+        .flush_policy(FlushPolicy::Byte)
+        .replay_journal(journal);
+    let mut machine = run::load(args, builder)?;
+    let res = machine.run();
+    let progress = progress.lock().unwrap();
+    let at = format!(
+        "at pc={:#010x} after {} instructions",
+        machine.pc(),
+        machine.executed()
+    );
+
+    let report = if let Some(got) = progress.diverged {
+        let written = progress.written;
+        match expected.get(written) {
+            Some(want) => format!(
+                "output differs at byte {written}: the transcript has {}, um-32 wrote {} {at}",
+                show(*want),
+                show(got)
+            ),
+            None => format!(
+                "um-32 wrote {} after the transcript's {written} bytes of output {at}",
+                show(got)
+            ),
+        }
+    } else if let Err(e @ um_core::Error::ReplayDiverged { .. }) = &res {
+        format!("{e}, having written {} bytes of output", progress.written)
+    } else if progress.written < expected.len() {
+        let ended = match &res {
+            Ok(()) => "halted".to_string(),
+            Err(e) => format!("stopped ({e})"),
+        };
+        format!(
+            "um-32 {ended} {at}, having written {} of the transcript's {} bytes of output",
+            progress.written,
+            expected.len()
+        )
+    } else {
+        let ended = match &res {
+            Ok(()) => "halted".to_string(),
+            Err(e) => format!("stopped ({e})"),
+        };
+        println!(
+            "output matches the transcript: {} bytes; um-32 {ended} {at}",
+            expected.len()
+        );
+        return Ok(true);
+    };
+    eprintln!("{report}");
+    Ok(false)
+}
+
+/// Checks a program image, writing the report, and returns whether nothing
+/// looked malformed.
+pub fn image(args: VerifyArgs) -> Result<bool, Error> {
+    let image = std::fs::read(&args.file).map_err(run::naming(&args.file))?;
+    let report = verify::verify(&image, args.extensions);
+    let mut out = io::stdout().lock();
+    if args.json {
+        report.write_json(&mut out)?;
+        out.flush()?;
+        return Ok(report.is_clean());
+    }
+
+    let code = report.code_platters();
+    writeln!(
+        out,
+        "{}: {} platters, {code} code and {} data in {} regions",
+        args.file.display(),
+        report.platters,
+        report.platters - code,
+        report.regions.len(),
+    )?;
+    let ops: Vec<String> = (0..16)
+        .filter(|&op| report.ops[op as usize] != 0)
+        .map(|op| {
+            let count = report.ops[op as usize];
+            match (op, disasm::op_name(op)) {
+                (14, _) if args.extensions => format!("HOST {count}"),
+                (_, Some(name)) => format!("{name} {count}"),
+                (op, None) => format!("opcode {op} {count}"),
+            }
+        })
+        .collect();
+    writeln!(out, "opcodes: {}", ops.join(", "))?;
+    if !report.invalid.is_empty() {
+        writeln!(out, "{} platters hold no instruction", report.invalid.len())?;
+    }
+    for region in &report.regions {
+        let kind = match region.kind {
+            Kind::Code => "code",
+            Kind::Data => "data",
+        };
+        writeln!(
+            out,
+            "{:08x}-{:08x}  {kind}",
+            region.start,
+            region.start + region.len - 1
+        )?;
+    }
+    for &addr in &report.falls_through {
+        let into = if addr + 1 < report.platters {
+            "into data"
+        } else {
+            "off the end of the image"
+        };
+        writeln!(out, "code runs on {into} after {addr:08x}")?;
+    }
+    if report.trailing_bytes != 0 {
+        writeln!(
+            out,
+            "{} bytes after the last whole platter",
+            report.trailing_bytes
+        )?;
+    }
+    out.flush()?;
+    Ok(report.is_clean())
+}
@@ -0,0 +1,181 @@
+//! A machine for async code, with the `feature = "tokio"`: its input and
+//! output are tokio's [`AsyncRead`] and [`AsyncWrite`], so a server can run
+//! a session per task instead of a thread per session.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::{self, Read, Write},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{Error, Machine, MachineBuilder, Stop};
+
+// How long the machine runs before giving other tasks a turn.
+const SLICE: Duration = Duration::from_millis(1);
+
+/// A [`Machine`] that reads from `R` and writes to `W`, awaiting input
+/// when the program wants more than has arrived, and giving other tasks a
+/// turn every millisecond or so while it computes.
+pub struct AsyncMachine<R, W> {
+    machine: Machine,
+    input: Arc<Mutex<Feed>>,
+    output: Arc<Mutex<Vec<u8>>>,
+    reader: R,
+    writer: W,
+}
+
+// What has been read from `reader` and not consumed yet.
+#[derive(Default)]
+struct Feed {
+    bytes: VecDeque<u8>,
+    eof: bool,
+}
+
+// The machine's stdin, which would block once it has given out what has
+// been read, failing so that the Input instruction is executed again once
+// there is more. The machine doesn't show the Input to its hook, observers
+// and trace again.
+struct FeedIn(Arc<Mutex<Feed>>);
+
+impl Read for FeedIn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut feed = self.0.lock().unwrap();
+        if feed.bytes.is_empty() && !feed.eof {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = buf.len().min(feed.bytes.len());
+        for (i, byte) in feed.bytes.drain(..n).enumerate() {
+            buf[i] = byte;
+        }
+        Ok(n)
+    }
+}
+
+// The machine's stdout, written out to `writer` whenever the machine
+// stops.
+struct DrainOut(Arc<Mutex<Vec<u8>>>);
+
+impl Write for DrainOut {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<R, W> AsyncMachine<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    /// Builds the machine `builder` describes, with `reader` and `writer`
+    /// in place of its stdin and stdout. Input it queues is consumed
+    /// first, as usual.
+    pub fn new(builder: MachineBuilder, reader: R, writer: W) -> Self {
+        let input = Arc::new(Mutex::new(Feed::default()));
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let machine = builder
+            .stdin(FeedIn(input.clone()))
+            .stdout(DrainOut(output.clone()))
+            .eof_error(false)
+            .build();
+        AsyncMachine {
+            machine,
+            input,
+            output,
+            reader,
+            writer,
+        }
+    }
+
+    /// The machine, to look at its state between runs.
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// The machine, to load a program into, set breakpoints on and so on.
+    /// Its stdin and stdout belong to the `AsyncMachine`.
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Takes the machine, reader and writer back, with the machine's stdin
+    /// and stdout still those of the `AsyncMachine`. Input read from
+    /// `reader` that the program hasn't consumed is lost.
+    pub fn into_inner(self) -> (Machine, R, W) {
+        (self.machine, self.reader, self.writer)
+    }
+
+    /// Runs until the program halts, or stops for any of the reasons
+    /// [`Machine::run_until_stop`] does, with the output written and
+    /// flushed. The end of `reader`'s input is the end of the program's.
+    pub async fn run_until_stop(&mut self) -> Result<Stop, Error> {
+        let deadline = self.machine.deadline();
+        let res = self.run_slices(deadline).await;
+        self.machine.set_deadline(deadline);
+        res
+    }
+
+    async fn run_slices(&mut self, deadline: Option<Instant>) -> Result<Stop, Error> {
+        loop {
+            let now = Instant::now();
+            let slice = now + SLICE;
+            self.machine
+                .set_deadline(Some(deadline.map_or(slice, |d| d.min(slice))));
+            let res = self.machine.run_until_stop();
+            self.write_output().await?;
+            match res {
+                Ok(Stop::Timeout { .. }) if deadline.is_none_or(|d| Instant::now() < d) => {
+                    YieldNow(false).await;
+                }
+                Err(Error::IO(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.read_input().await?;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn read_input(&mut self) -> io::Result<()> {
+        let mut buf = [0; 4096];
+        let n = self.reader.read(&mut buf).await?;
+        let mut feed = self.input.lock().unwrap();
+        feed.bytes.extend(&buf[..n]);
+        feed.eof = n == 0;
+        Ok(())
+    }
+
+    async fn write_output(&mut self) -> io::Result<()> {
+        let output = std::mem::take(&mut *self.output.lock().unwrap());
+        if !output.is_empty() {
+            self.writer.write_all(&output).await?;
+            self.writer.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+// Returns to the executor once, to be polled again straight away.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
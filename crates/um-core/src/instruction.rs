@@ -0,0 +1,261 @@
+//! Platters as instructions: decoding, encoding and mnemonics, shared by
+//! the interpreter and the assembler and disassembler in `um-tools`.
+
+use core::fmt;
+
+use crate::Error;
+
+/// The instruction a platter holds. Registers are numbered 0 through 7.
+///
+/// Decoding ignores the bits an instruction doesn't use, so encoding gives
+/// them back as 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    Cmov {
+        a: u32,
+        b: u32,
+        c: u32,
+    },
+    Index {
+        a: u32,
+        b: u32,
+        c: u32,
+    },
+    Amend {
+        a: u32,
+        b: u32,
+        c: u32,
+    },
+    Add {
+        a: u32,
+        b: u32,
+        c: u32,
+    },
+    Mul {
+        a: u32,
+        b: u32,
+        c: u32,
+    },
+    Div {
+        a: u32,
+        b: u32,
+        c: u32,
+    },
+    Nand {
+        a: u32,
+        b: u32,
+        c: u32,
+    },
+    Halt,
+    Alloc {
+        b: u32,
+        c: u32,
+    },
+    Abandon {
+        c: u32,
+    },
+    Output {
+        c: u32,
+    },
+    Input {
+        c: u32,
+    },
+    LoadProgram {
+        b: u32,
+        c: u32,
+    },
+    Ortho {
+        a: u32,
+        value: u32,
+    },
+    /// Host Call, opcode 14, which is not in the spec and only executes on
+    /// machines with host calls.
+    Host {
+        a: u32,
+        b: u32,
+        c: u32,
+    },
+}
+
+// The opcode and registers of `inst`. For Orthography `a` is the register
+// and `b` the value.
+#[inline(always)]
+pub(crate) fn fields(inst: u32) -> (u32, u32, u32, u32) {
+    let op = inst >> 28;
+    if op != 13 {
+        (op, (inst >> 6) & 0b111, (inst >> 3) & 0b111, inst & 0b111)
+    } else {
+        (op, (inst >> 25) & 0b111, inst & !(!0 << 25), 0)
+    }
+}
+
+impl Instruction {
+    /// Fails with [`Error::InvalidInstruction`] for opcode 15.
+    pub fn decode(inst: u32) -> Result<Self, Error> {
+        let (op, a, b, c) = fields(inst);
+        Ok(match op {
+            0 => Self::Cmov { a, b, c },
+            1 => Self::Index { a, b, c },
+            2 => Self::Amend { a, b, c },
+            3 => Self::Add { a, b, c },
+            4 => Self::Mul { a, b, c },
+            5 => Self::Div { a, b, c },
+            6 => Self::Nand { a, b, c },
+            7 => Self::Halt,
+            8 => Self::Alloc { b, c },
+            9 => Self::Abandon { c },
+            10 => Self::Output { c },
+            11 => Self::Input { c },
+            12 => Self::LoadProgram { b, c },
+            13 => Self::Ortho { a, value: b },
+            14 => Self::Host { a, b, c },
+            _ => return Err(Error::InvalidInstruction { inst }),
+        })
+    }
+
+    /// # Panics
+    ///
+    /// If a register is over 7 or an Orthography value doesn't fit in 25
+    /// bits.
+    pub fn encode(&self) -> u32 {
+        let (a, b, c) = match *self {
+            Self::Cmov { a, b, c }
+            | Self::Index { a, b, c }
+            | Self::Amend { a, b, c }
+            | Self::Add { a, b, c }
+            | Self::Mul { a, b, c }
+            | Self::Div { a, b, c }
+            | Self::Nand { a, b, c }
+            | Self::Host { a, b, c } => (a, b, c),
+            Self::Halt => (0, 0, 0),
+            Self::Alloc { b, c } | Self::LoadProgram { b, c } => (0, b, c),
+            Self::Abandon { c } | Self::Output { c } | Self::Input { c } => (0, 0, c),
+            Self::Ortho { a, value } => {
+                assert!(a < 8 && value < 1 << 25, "orthography value out of range");
+                return 13 << 28 | a << 25 | value;
+            }
+        };
+        assert!(a < 8 && b < 8 && c < 8, "register out of range");
+        self.op() << 28 | a << 6 | b << 3 | c
+    }
+
+    pub fn op(&self) -> u32 {
+        match self {
+            Self::Cmov { .. } => 0,
+            Self::Index { .. } => 1,
+            Self::Amend { .. } => 2,
+            Self::Add { .. } => 3,
+            Self::Mul { .. } => 4,
+            Self::Div { .. } => 5,
+            Self::Nand { .. } => 6,
+            Self::Halt => 7,
+            Self::Alloc { .. } => 8,
+            Self::Abandon { .. } => 9,
+            Self::Output { .. } => 10,
+            Self::Input { .. } => 11,
+            Self::LoadProgram { .. } => 12,
+            Self::Ortho { .. } => 13,
+            Self::Host { .. } => 14,
+        }
+    }
+
+    /// The mnemonic, e.g. `AMEND`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cmov { .. } => "CMOV",
+            Self::Index { .. } => "INDEX",
+            Self::Amend { .. } => "AMEND",
+            Self::Add { .. } => "ADD",
+            Self::Mul { .. } => "MUL",
+            Self::Div { .. } => "DIV",
+            Self::Nand { .. } => "NAND",
+            Self::Halt => "HALT",
+            Self::Alloc { .. } => "ALLOC",
+            Self::Abandon { .. } => "ABANDON",
+            Self::Output { .. } => "OUTPUT",
+            Self::Input { .. } => "INPUT",
+            Self::LoadProgram { .. } => "LOADPROG",
+            Self::Ortho { .. } => "ORTHO",
+            Self::Host { .. } => "HOST",
+        }
+    }
+}
+
+/// The instruction as the assembler reads it, e.g. `CMOV r1, r2, r3` or
+/// `ORTHO r0, 0x42`, showing only the registers it uses.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name();
+        match *self {
+            Self::Cmov { a, b, c }
+            | Self::Index { a, b, c }
+            | Self::Amend { a, b, c }
+            | Self::Add { a, b, c }
+            | Self::Mul { a, b, c }
+            | Self::Div { a, b, c }
+            | Self::Nand { a, b, c }
+            | Self::Host { a, b, c } => write!(f, "{name} r{a}, r{b}, r{c}"),
+            Self::Halt => f.write_str(name),
+            Self::Alloc { b, c } | Self::LoadProgram { b, c } => write!(f, "{name} r{b}, r{c}"),
+            Self::Abandon { c } | Self::Output { c } | Self::Input { c } => {
+                write!(f, "{name} r{c}")
+            }
+            Self::Ortho { a, value } => write!(f, "{name} r{a}, {value:#x}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let cases = [
+            (
+                0x0000_00d3,
+                Instruction::Cmov { a: 3, b: 2, c: 3 },
+                "CMOV r3, r2, r3",
+            ),
+            (0x7000_0000, Instruction::Halt, "HALT"),
+            (
+                0x8000_000a,
+                Instruction::Alloc { b: 1, c: 2 },
+                "ALLOC r1, r2",
+            ),
+            (0xa000_0005, Instruction::Output { c: 5 }, "OUTPUT r5"),
+            (
+                0xc000_0011,
+                Instruction::LoadProgram { b: 2, c: 1 },
+                "LOADPROG r2, r1",
+            ),
+            (
+                0xd200_0042,
+                Instruction::Ortho { a: 1, value: 0x42 },
+                "ORTHO r1, 0x42",
+            ),
+            (
+                0xe000_0061,
+                Instruction::Host { a: 1, b: 4, c: 1 },
+                "HOST r1, r4, r1",
+            ),
+        ];
+        for (inst, instruction, text) in cases {
+            assert_eq!(Instruction::decode(inst).unwrap(), instruction);
+            assert_eq!(instruction.encode(), inst);
+            assert_eq!(instruction.op(), inst >> 28);
+            assert_eq!(instruction.to_string(), text);
+        }
+        // Bits the instruction doesn't use are dropped.
+        assert_eq!(
+            Instruction::decode(0x7000_01ff).unwrap().encode(),
+            0x7000_0000
+        );
+        assert!(matches!(
+            Instruction::decode(0xf000_0000),
+            Err(Error::InvalidInstruction { inst: 0xf000_0000 })
+        ));
+    }
+}
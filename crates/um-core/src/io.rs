@@ -0,0 +1,20 @@
+//! The I/O traits and types the machine's stdin, stdout, trace, snapshots
+//! and journals go through: `std::io` itself with the `std` feature, and
+//! without it a small stand-in with the same names, for targets with an
+//! allocator but no operating system. Code written against these names
+//! builds either way.
+
+#[cfg(feature = "std")]
+pub use std::io::{
+    copy, empty, sink, BufRead, BufReader, BufWriter, Empty, Error, ErrorKind, Lines, Read, Result,
+    Sink, Take, Write,
+};
+
+#[cfg(not(feature = "std"))]
+pub use bare::{
+    copy, empty, sink, BufRead, BufReader, BufWriter, Empty, Error, ErrorKind, Lines, Read, Result,
+    Sink, Take, Write,
+};
+
+#[cfg(not(feature = "std"))]
+mod bare;
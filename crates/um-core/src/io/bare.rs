@@ -0,0 +1,571 @@
+//! The parts of `std::io` the machine uses, for builds without the `std`
+//! feature. They behave as their namesakes there do, except that errors
+//! carry a static message instead of any error.
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{cmp, fmt};
+
+// The buffer size of BufReader and BufWriter, as in std.
+const BUFFER: usize = 8 * 1024;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// What kind of failure an [`Error`] is, with the names `std::io` uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    BrokenPipe,
+    Interrupted,
+    InvalidData,
+    InvalidInput,
+    Other,
+    TimedOut,
+    UnexpectedEof,
+    WouldBlock,
+    WriteZero,
+}
+
+impl ErrorKind {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::BrokenPipe => "broken pipe",
+            Self::Interrupted => "operation interrupted",
+            Self::InvalidData => "invalid data",
+            Self::InvalidInput => "invalid input parameter",
+            Self::Other => "other error",
+            Self::TimedOut => "timed out",
+            Self::UnexpectedEof => "unexpected end of file",
+            Self::WouldBlock => "operation would block",
+            Self::WriteZero => "write zero",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    message: &'static str,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: &'static str) -> Error {
+        Error { kind, message }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error::new(kind, kind.describe())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+impl core::error::Error for Error {}
+
+fn invalid_utf8() -> Error {
+    Error::new(ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+}
+
+pub trait Read {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf) {
+                Ok(0) => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => buf = &mut buf[n..],
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let start = buf.len();
+        let mut chunk = [0; 512];
+        loop {
+            match self.read(&mut chunk) {
+                Ok(0) => return Ok(buf.len() - start),
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn read_to_string(&mut self, buf: &mut String) -> Result<usize> {
+        let mut bytes = Vec::new();
+        let n = self.read_to_end(&mut bytes)?;
+        buf.push_str(core::str::from_utf8(&bytes).map_err(|_| invalid_utf8())?);
+        Ok(n)
+    }
+
+    fn take(self, limit: u64) -> Take<Self>
+    where
+        Self: Sized,
+    {
+        Take { inner: self, limit }
+    }
+}
+
+pub trait Write {
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    fn flush(&mut self) -> Result<()>;
+
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf) {
+                Ok(0) => {
+                    return Err(Error::new(
+                        ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => buf = &buf[n..],
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+        // Keeps the I/O error that fmt::Error can't carry.
+        struct Adapter<'a, W: ?Sized> {
+            inner: &'a mut W,
+            error: Result<()>,
+        }
+
+        impl<W: Write + ?Sized> fmt::Write for Adapter<'_, W> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.inner.write_all(s.as_bytes()).map_err(|e| {
+                    self.error = Err(e);
+                    fmt::Error
+                })
+            }
+        }
+
+        let mut adapter = Adapter {
+            inner: self,
+            error: Ok(()),
+        };
+        match fmt::write(&mut adapter, args) {
+            Ok(()) => Ok(()),
+            Err(_) => adapter
+                .error
+                .and(Err(Error::new(ErrorKind::Other, "formatter error"))),
+        }
+    }
+}
+
+pub trait BufRead: Read {
+    fn fill_buf(&mut self) -> Result<&[u8]>;
+
+    fn consume(&mut self, amt: usize);
+
+    fn lines(self) -> Lines<Self>
+    where
+        Self: Sized,
+    {
+        Lines { buf: self }
+    }
+}
+
+impl<R: Read + ?Sized> Read for &mut R {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+}
+
+impl<R: Read + ?Sized> Read for Box<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+}
+
+impl Read for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = cmp::min(buf.len(), self.len());
+        let (a, b) = self.split_at(n);
+        buf[..n].copy_from_slice(a);
+        *self = b;
+        Ok(n)
+    }
+}
+
+impl<B: BufRead + ?Sized> BufRead for &mut B {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        (**self).fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        (**self).consume(amt)
+    }
+}
+
+impl BufRead for &[u8] {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        Ok(self)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        *self = &self[amt..];
+    }
+}
+
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+impl<W: Write + ?Sized> Write for Box<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+impl Write for Vec<u8> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads nothing, from [`empty`].
+pub struct Empty;
+
+pub fn empty() -> Empty {
+    Empty
+}
+
+impl Read for Empty {
+    fn read(&mut self, _: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Discards everything written, from [`sink`].
+pub struct Sink;
+
+pub fn sink() -> Sink {
+    Sink
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads at most `limit` bytes from another reader, from [`Read::take`].
+pub struct Take<R> {
+    inner: R,
+    limit: u64,
+}
+
+impl<R> Take<R> {
+    /// How many more bytes may be read.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+impl<R: Read> Read for Take<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let max = cmp::min(buf.len() as u64, self.limit) as usize;
+        if max == 0 {
+            return Ok(0);
+        }
+        let n = self.inner.read(&mut buf[..max])?;
+        self.limit -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Copies everything `reader` has to `writer`, returning the number of
+/// bytes copied.
+pub fn copy<R: Read + ?Sized, W: Write + ?Sized>(reader: &mut R, writer: &mut W) -> Result<u64> {
+    let mut chunk = [0; 512];
+    let mut copied = 0;
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => return Ok(copied),
+            Ok(n) => {
+                writer.write_all(&chunk[..n])?;
+                copied += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub struct BufReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    filled: usize,
+}
+
+impl<R: Read> BufReader<R> {
+    pub fn new(inner: R) -> BufReader<R> {
+        BufReader {
+            inner,
+            buf: alloc::vec![0; BUFFER].into_boxed_slice(),
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    /// What has been read from the inner reader and not consumed yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.filled]
+    }
+}
+
+impl<R: Read> Read for BufReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> Result<usize> {
+        // Large reads go straight to the inner reader.
+        if self.pos == self.filled && out.len() >= self.buf.len() {
+            return self.inner.read(out);
+        }
+        let available = self.fill_buf()?;
+        let n = cmp::min(available.len(), out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for BufReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.pos == self.filled {
+            self.filled = self.inner.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        Ok(self.buffer())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.filled);
+    }
+}
+
+pub struct BufWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> BufWriter<W> {
+    pub fn new(inner: W) -> BufWriter<W> {
+        BufWriter {
+            inner,
+            buf: Vec::with_capacity(BUFFER),
+        }
+    }
+
+    // Writes out the buffer, keeping whatever the inner writer didn't take
+    // if it fails.
+    fn flush_buf(&mut self) -> Result<()> {
+        let mut written = 0;
+        let res = loop {
+            if written == self.buf.len() {
+                break Ok(());
+            }
+            match self.inner.write(&self.buf[written..]) {
+                Ok(0) => {
+                    break Err(Error::new(
+                        ErrorKind::WriteZero,
+                        "failed to write the buffered data",
+                    ))
+                }
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.buf.drain(..written);
+        res
+    }
+}
+
+impl<W: Write> Write for BufWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.buf.len() + buf.len() > BUFFER {
+            self.flush_buf()?;
+        }
+        if buf.len() >= BUFFER {
+            self.inner.write(buf)
+        } else {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_buf()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for BufWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush_buf();
+    }
+}
+
+/// The lines of a [`BufRead`], without their line endings, from
+/// [`BufRead::lines`].
+pub struct Lines<B> {
+    buf: B,
+}
+
+impl<B: BufRead> Iterator for Lines<B> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
+        let mut line = Vec::new();
+        loop {
+            let available = match self.buf.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            if available.is_empty() {
+                break;
+            }
+            match available.iter().position(|&b| b == b'\n') {
+                Some(i) => {
+                    line.extend_from_slice(&available[..=i]);
+                    self.buf.consume(i + 1);
+                    break;
+                }
+                None => {
+                    line.extend_from_slice(available);
+                    let n = available.len();
+                    self.buf.consume(n);
+                }
+            }
+        }
+        if line.is_empty() {
+            return None;
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        Some(String::from_utf8(line).map_err(|_| invalid_utf8()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Only for the test harness, which has it anyway.
+    extern crate std;
+
+    use alloc::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::Machine;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buffered_round_trip() {
+        let out = Shared::default();
+        let mut w = BufWriter::new(out.clone());
+        write!(w, "one\r\ntwo {}\n", 2).unwrap();
+        w.write_all(b"three").unwrap();
+        assert!(out.0.lock().unwrap().is_empty());
+        w.flush().unwrap();
+        let text = out.0.lock().unwrap().clone();
+        let lines: Vec<String> = BufReader::new(&text[..])
+            .lines()
+            .map(|l| l.unwrap())
+            .collect();
+        assert_eq!(lines, ["one", "two 2", "three"]);
+
+        let mut r = BufReader::new(&text[..]).take(3);
+        let mut one = String::new();
+        r.read_to_string(&mut one).unwrap();
+        assert_eq!((one.as_str(), r.limit()), ("one", 0));
+        let mut buf = [0; 8];
+        let e = (&b"abc"[..]).read_exact(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn machine() {
+        // IN r1 ; OUT r1 ; IN r1 ; OUT r1 ; HALT
+        let image: Vec<u8> = [
+            0xb000_0001u32,
+            0xa000_0001,
+            0xb000_0001,
+            0xa000_0001,
+            0x7000_0000,
+        ]
+        .iter()
+        .flat_map(|w| w.to_be_bytes())
+        .collect();
+        let out = Shared::default();
+        let mut machine = Machine::builder()
+            .stdin(&b"hi"[..])
+            .stdout(out.clone())
+            .build();
+        machine.extend_from(&image[..]).unwrap();
+        machine.run().unwrap();
+        assert_eq!(*out.0.lock().unwrap(), b"hi");
+
+        let mut snapshot = Vec::new();
+        machine.write_snapshot(&mut snapshot).unwrap();
+        let restored = Machine::read_snapshot(&mut &snapshot[..]).unwrap();
+        assert_eq!(restored.state_hash(), machine.state_hash());
+    }
+}
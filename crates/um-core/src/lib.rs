@@ -0,0 +1,283 @@
+//! The machine at the heart of um-32, an interpreter for the UM-32
+//! "Universal Machine" from the ICFP 2006 programming contest, for
+//! embedders that only need to run programs. The assembler, disassembler
+//! and other tools are in `um-tools`, and the `um-32` crate has both.
+//!
+//! The public API is everything re-exported from [`prelude`]: [`Machine`],
+//! [`MachineBuilder`], [`Backend`], [`Error`], the [`Watch`], [`Access`],
+//! and [`Stop`] types for watching arrays, [`Interrupter`], [`OpStats`],
+//! [`MemoryStats`], [`AllocationSite`], [`FlushPolicy`], [`CoreDump`],
+//! [`Journal`], [`Observer`], [`MachineEvent`], [`Instruction`],
+//! [`Spawned`] and [`Control`] for [`Machine::spawn`], and [`Supervisor`]
+//! with its [`Event`], [`MachineId`] and [`pipe`], plus `AsyncMachine` with
+//! the `tokio` feature and `HostCall` with the `extensions` feature.
+//! Anything not reachable from there is an implementation detail and may
+//! change between releases. [`Error`] is `#[non_exhaustive]` so new failure
+//! modes can be added without breaking downstream matches.
+//!
+//! Without the default `std` feature the crate is `no_std`, needing only
+//! `alloc`: machines run, step, debug, keep history and read and write
+//! snapshots and journals through the traits in [`io`], which are
+//! `std::io`'s own with the feature. What needs an operating system goes:
+//! the process's stdin and stdout as the default console, files, deadlines,
+//! [`MachineBuilder::events`], [`Machine::spawn`], the [`Supervisor`], and
+//! the `extensions` and `tokio` features, which turn `std` on.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use core::fmt;
+
+#[cfg(feature = "tokio")]
+pub use async_machine::AsyncMachine;
+pub use instruction::Instruction;
+#[cfg(feature = "extensions")]
+pub use machine::HostCall;
+pub use machine::{
+    Access, AllocationSite, Backend, CoreDump, Interrupter, Journal, Machine, MachineBuilder,
+    MachineEvent, MemoryStats, Observer, OpStats, Stop, Watch,
+};
+#[cfg(feature = "std")]
+pub use machine::{Control, Spawned};
+pub use output::FlushPolicy;
+#[cfg(feature = "std")]
+pub use supervisor::{pipe, Event, MachineId, PipeReader, PipeWriter, Supervisor};
+
+#[cfg(feature = "tokio")]
+mod async_machine;
+mod instruction;
+pub mod io;
+mod machine;
+mod output;
+#[cfg(feature = "std")]
+mod supervisor;
+
+pub mod prelude {
+    #[cfg(feature = "tokio")]
+    pub use crate::AsyncMachine;
+    #[cfg(feature = "extensions")]
+    pub use crate::HostCall;
+    #[cfg(feature = "std")]
+    pub use crate::{pipe, Control, Event, MachineId, PipeReader, PipeWriter, Spawned, Supervisor};
+    pub use crate::{
+        Access, AllocationSite, Backend, CoreDump, Error, FlushPolicy, Instruction, Interrupter,
+        Journal, Machine, MachineBuilder, MachineEvent, MemoryStats, Observer, OpStats, Stop,
+        Watch,
+    };
+}
+
+// `pc` is the address of the faulting platter and `inst` the platter itself.
+// `inst` is `None` when no instruction was involved: fetching from outside
+// the program array, or loading a program with `extend_from`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    AbandonedProgramArray {
+        pc: u32,
+        inst: u32,
+    },
+    AllocationTooLarge {
+        pc: u32,
+        inst: u32,
+        requested: u32,
+        limit: u32,
+    },
+    DivisionByZero {
+        pc: u32,
+        inst: u32,
+    },
+    DoubleAbandon {
+        pc: u32,
+        inst: u32,
+        array: u32,
+    },
+    IO(io::Error),
+    InfiniteLoop {
+        pc: u32,
+        inst: u32,
+    },
+    InactiveArray {
+        pc: u32,
+        inst: Option<u32>,
+        array: u32,
+    },
+    InvalidArgument(String),
+    InvalidChar {
+        pc: u32,
+        inst: u32,
+        ch: u32,
+    },
+    // A platter with no instruction, from Instruction::decode, which
+    // knows nothing of where it is.
+    InvalidInstruction {
+        inst: u32,
+    },
+    InvalidOp {
+        pc: u32,
+        inst: u32,
+        op: u32,
+    },
+    InvalidSnapshot(&'static str),
+    OutOfBounds {
+        pc: u32,
+        inst: Option<u32>,
+        array: u32,
+        offset: u32,
+        len: u32,
+    },
+    // `limit` is on the platters in all active arrays together.
+    OutOfMemory {
+        pc: u32,
+        inst: u32,
+        requested: u32,
+        limit: u64,
+    },
+    // `executed` is the instruction count of the Input, and `expected`
+    // that of the next one in the journal, if any.
+    ReplayDiverged {
+        pc: u32,
+        executed: u64,
+        expected: Option<u64>,
+    },
+    // `len` is the length of the image in bytes.
+    TruncatedProgram {
+        len: usize,
+    },
+    // A Host Call selecting a service there is none of.
+    UnknownHostCall {
+        pc: u32,
+        inst: u32,
+        service: u32,
+    },
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct At(u32, Option<u32>);
+        impl fmt::Display for At {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "pc={:#06x}", self.0)?;
+                if let Some(inst) = self.1 {
+                    write!(f, " inst={inst:#010x}")?;
+                }
+                Ok(())
+            }
+        }
+
+        match self {
+            Self::AbandonedProgramArray { pc, inst } => {
+                write!(
+                    f,
+                    "abandonment of the program array, {}",
+                    At(*pc, Some(*inst))
+                )
+            }
+            Self::AllocationTooLarge {
+                pc,
+                inst,
+                requested,
+                limit,
+            } => write!(
+                f,
+                "allocation of {requested} platters exceeds the limit of {limit}, {}",
+                At(*pc, Some(*inst))
+            ),
+            Self::DivisionByZero { pc, inst } => {
+                write!(f, "division by zero, {}", At(*pc, Some(*inst)))
+            }
+            Self::DoubleAbandon { pc, inst, array } => write!(
+                f,
+                "abandonment of array {array}, already abandoned, {}",
+                At(*pc, Some(*inst))
+            ),
+            Self::IO(e) => write!(f, "I/O error: {e}"),
+            Self::InfiniteLoop { pc, inst } => {
+                write!(f, "program jumps to itself, {}", At(*pc, Some(*inst)))
+            }
+            Self::InactiveArray { pc, inst, array } => {
+                write!(f, "access to inactive array {array}, {}", At(*pc, *inst))
+            }
+            Self::InvalidArgument(msg) => write!(f, "invalid argument: {msg}"),
+            Self::InvalidChar { pc, inst, ch } => {
+                write!(f, "output of non-byte value {ch}, {}", At(*pc, Some(*inst)))
+            }
+            Self::InvalidInstruction { inst } => {
+                write!(f, "invalid opcode {} in platter {inst:#010x}", inst >> 28)
+            }
+            Self::InvalidOp { pc, inst, op } => {
+                write!(f, "invalid opcode {op}, {}", At(*pc, Some(*inst)))
+            }
+            Self::InvalidSnapshot(reason) => write!(f, "invalid snapshot: {reason}"),
+            Self::OutOfBounds {
+                pc,
+                inst,
+                array,
+                offset,
+                len,
+            } => {
+                let access = match inst.map(|inst| inst >> 28) {
+                    None => "fetch",
+                    Some(2) => "write",
+                    Some(_) => "read",
+                };
+                write!(
+                    f,
+                    "out-of-bounds {access} of array {array} at offset {offset} (len {len}), {}",
+                    At(*pc, *inst)
+                )
+            }
+            Self::OutOfMemory {
+                pc,
+                inst,
+                requested,
+                limit,
+            } => write!(
+                f,
+                "allocation of {requested} platters would exceed the memory limit of {limit}, {}",
+                At(*pc, Some(*inst))
+            ),
+            Self::ReplayDiverged {
+                pc,
+                executed,
+                expected: Some(expected),
+            } => write!(
+                f,
+                "input after {executed} instructions, where the journal has it after {expected}, {}",
+                At(*pc, None)
+            ),
+            Self::ReplayDiverged {
+                pc,
+                executed,
+                expected: None,
+            } => write!(
+                f,
+                "input after {executed} instructions, past the end of the journal, {}",
+                At(*pc, None)
+            ),
+            Self::TruncatedProgram { len } => write!(
+                f,
+                "program image of {len} bytes ends partway through a platter"
+            ),
+            Self::UnknownHostCall { pc, inst, service } => {
+                write!(f, "unknown host call {service}, {}", At(*pc, Some(*inst)))
+            }
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::IO(e) => Some(e),
+            _ => None,
+        }
+    }
+}
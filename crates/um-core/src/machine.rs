@@ -0,0 +1,1307 @@
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic;
+#[cfg(feature = "std")]
+use std::{sync::mpsc::Sender, time::Instant};
+
+use crate::{
+    instruction,
+    io::{self, BufReader, BufWriter, Read, Write},
+    output::SpanWriter,
+    Error,
+};
+
+pub use builder::MachineBuilder;
+pub use debug::{Access, Interrupter, Stop, Watch};
+pub use event::MachineEvent;
+#[cfg(feature = "extensions")]
+pub use host::HostCall;
+pub use journal::Journal;
+pub use observer::Observer;
+pub use snapshot::CoreDump;
+#[cfg(feature = "std")]
+pub use spawn::{Control, Spawned};
+
+mod builder;
+mod debug;
+mod decoded;
+mod event;
+mod history;
+#[cfg(feature = "extensions")]
+mod host;
+mod journal;
+mod observer;
+#[cfg(feature = "serde")]
+mod serialize;
+mod slab;
+mod snapshot;
+#[cfg(feature = "std")]
+mod spawn;
+mod threaded;
+
+pub struct Machine {
+    pc: u32,
+    registers: [u32; 8],
+    arrays: slab::Slab,
+    input: VecDeque<u8>,
+    // Per-opcode counts, kept only with `stats` set.
+    stats: bool,
+    op_stats: [OpStats; 14],
+    // How often each pc executed, kept only with `profile` set.
+    profile: bool,
+    pc_counts: Vec<u64>,
+    // Allocations and abandonments by the pc that allocated the array, and
+    // that pc for each active array, kept only with `audit` set.
+    audit: bool,
+    audit_sites: BTreeMap<u32, AllocationSite>,
+    allocated_at: BTreeMap<u32, u32>,
+    // The pc and platter of the last `recent_limit` instructions, oldest
+    // first.
+    recent_limit: usize,
+    recent: VecDeque<(u32, u32)>,
+    // Allocation counters and peaks, plus the platters in the active arrays,
+    // kept up to date by the instructions that change them; the rest is
+    // filled in by memory_stats.
+    memory: MemoryStats,
+    index_cache: ArrayCache,
+    amend_cache: ArrayCache,
+    stdin: BufReader<Box<dyn Read + Send>>,
+    stdout: SpanWriter<Box<dyn Write + Send>>,
+    echo: bool,
+    eof_error: bool,
+    // The instruction count and pc of an Input that failed because reading
+    // stdin would block, which the hook, observers and trace have seen
+    // already when it is executed again.
+    blocked_input: Option<(u64, u32)>,
+    tee: Option<BufWriter<Box<dyn Write + Send>>>,
+    load_program_hook: Option<LoadProgramHook>,
+    instruction_hook: Option<InstructionHook>,
+    observers: Vec<Box<dyn Observer>>,
+    // Where the instructions with TRACE set send events.
+    #[cfg(feature = "std")]
+    events: Option<Sender<MachineEvent>>,
+    trace: Option<BufWriter<Box<dyn Write + Send>>>,
+    max_alloc: u32,
+    // Checked against memory.live_platters by Allocation.
+    max_memory: u64,
+    // Whether a Load Program that jumps to itself is an error.
+    detect_hangs: bool,
+    // Per-array watch flags, indexed by array identifier.
+    watches: Vec<u8>,
+    cell_watches: BTreeMap<(u32, u32), u8>,
+    breakpoints: BTreeSet<u32>,
+    // The executed count and pc of the instruction last checked for
+    // breakpoints and conditions, so that resuming from a stop there does
+    // not stop again before executing it.
+    checked: Option<(u64, u32)>,
+    conditions: Vec<(u32, Condition)>,
+    next_condition: u32,
+    executed: u64,
+    // Instruction count at which run_until_stop and step take the next
+    // checkpoint; u64::MAX without history.
+    next_checkpoint: u64,
+    // Instruction count at which run_until_stop and step stop; u64::MAX
+    // without a limit.
+    step_limit: u64,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
+    // Whether the run loops check for an interrupt or timeout; unset while
+    // `run` runs, which ignores both.
+    polling: bool,
+    history: Option<history::History>,
+    // Where consumed input is recorded, and the recording input comes from
+    // instead of the queue and stdin.
+    journal: Option<journal::Recorder>,
+    replay: Option<Journal>,
+    interrupter: Interrupter,
+    // Array 0 decoded, with `decode` set; empty until the run loop first
+    // fetches from it. `threaded` also dispatches through handlers where
+    // no checks or instrumentation are needed.
+    decode: bool,
+    threaded: bool,
+    decoded: Vec<decoded::Decoded>,
+    // Opcode 14 is Host Call when there are any host calls; `extensions`
+    // is whether the built-in ones were asked for.
+    #[cfg(feature = "extensions")]
+    extensions: bool,
+    #[cfg(feature = "extensions")]
+    host_calls: host::HostCalls,
+    // While replaying history, the results of the Host Calls being
+    // executed again, which are used instead of calling again.
+    #[cfg(feature = "extensions")]
+    replayed_host_calls: Option<VecDeque<u32>>,
+}
+
+/// How a [`Machine`] executes instructions, chosen with
+/// [`MachineBuilder::backend`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backend {
+    /// Decodes each instruction as it executes.
+    #[default]
+    Interpreter,
+    /// Decodes array 0 ahead of time, once each time a program is loaded,
+    /// and executes the decoded instructions. Slower than
+    /// [`Backend::Interpreter`], and kept to compare against it.
+    Decoded,
+    /// Decodes array 0 ahead of time like [`Backend::Decoded`], and calls
+    /// a handler for each instruction through a table instead of matching
+    /// on its opcode. Runs with breakpoints, conditions, watches, history or
+    /// instrumentation use the decoded match loop instead. Slower than
+    /// [`Backend::Interpreter`] too, and kept to compare against it.
+    Threaded,
+}
+
+/// How often one opcode ran and how long it took, from
+/// [`Machine::op_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpStats {
+    pub count: u64,
+    /// Processor cycles spent on the opcode, measured with the timestamp
+    /// counter on x86-64 and in nanoseconds elsewhere, or not at all, as 0,
+    /// elsewhere without the `std` feature.
+    pub cycles: u64,
+}
+
+impl OpStats {
+    pub fn average_cycles(&self) -> f64 {
+        self.cycles as f64 / self.count.max(1) as f64
+    }
+}
+
+/// How much memory the program's arrays take, from
+/// [`Machine::memory_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Active arrays, the program among them.
+    pub live_arrays: usize,
+    /// Platters in the active arrays.
+    pub live_platters: u64,
+    /// The most arrays and platters active at once.
+    pub peak_arrays: usize,
+    pub peak_platters: u64,
+    /// Allocations executed, and the platters they asked for in total.
+    pub allocations: u64,
+    pub allocated_platters: u64,
+    /// Abandoned identifiers waiting to be reused, and the platters their
+    /// buffers keep reserved for the allocations that will reuse them.
+    pub free_arrays: usize,
+    pub free_platters: u64,
+}
+
+/// What the Allocation instruction at one pc did, from
+/// [`Machine::allocation_sites`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocationSite {
+    pub pc: u32,
+    /// Allocations executed, and the platters they asked for in total.
+    pub allocations: u64,
+    pub platters: u64,
+    /// How many of the arrays were abandoned since.
+    pub abandonments: u64,
+    /// The arrays still active, in identifier order.
+    pub live: Vec<u32>,
+}
+
+// Checked before each instruction while any are set, with the pc and the
+// registers; see Machine::add_condition.
+pub(crate) type Condition = Box<dyn FnMut(u32, &[u32; 8]) -> bool + Send>;
+
+// Called with the pc, the instruction and the registers before each
+// instruction executes.
+pub(crate) type InstructionHook = Box<dyn FnMut(u32, u32, &[u32; 8]) -> io::Result<()> + Send>;
+
+// Called with the source array, the entry point, and the new program
+// whenever Load Program replaces array 0.
+pub(crate) type LoadProgramHook = Box<dyn FnMut(u32, u32, &[u32]) -> io::Result<()> + Send>;
+
+// Last array touched by an Index or Amendment instruction. The pointer stays
+// valid until that array's storage is reallocated or dropped, which only
+// happens on Allocation, Abandonment, Load Program, or extend_from, so each
+// of those must call invalidate_caches for the array it touches. The amend
+// cache only ever points at storage no other array shares, so sharing an
+// array's storage must invalidate its caches too.
+#[derive(Clone, Copy)]
+struct ArrayCache {
+    array: u32,
+    ptr: *mut u32,
+    len: u32,
+}
+
+unsafe impl Send for ArrayCache {}
+
+impl Default for ArrayCache {
+    fn default() -> Self {
+        Self {
+            array: 0,
+            ptr: core::ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        MachineBuilder::new().build()
+    }
+}
+
+impl Machine {
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::new()
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u32) {
+        self.touched();
+        self.pc = pc;
+    }
+
+    pub fn registers(&self) -> &[u32; 8] {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [u32; 8] {
+        self.touched();
+        &mut self.registers
+    }
+
+    pub fn array(&self, array: u32) -> Option<&[u32]> {
+        self.arrays.get(array).map(|a| a.as_slice())
+    }
+
+    /// The backend executing instructions, which is
+    /// [`Backend::Interpreter`] if another was asked for but is not
+    /// supported on this host.
+    pub fn backend(&self) -> Backend {
+        if self.threaded {
+            return Backend::Threaded;
+        }
+        if self.decode {
+            return Backend::Decoded;
+        }
+        Backend::Interpreter
+    }
+
+    pub fn array_mut(&mut self, array: u32) -> Option<&mut [u32]> {
+        self.touched();
+        self.invalidate_caches(array);
+        self.arrays
+            .get_mut(array)
+            .map(|a| Arc::make_mut(a).as_mut_slice())
+    }
+
+    /// Allocates an array of `platters` zeroes as Allocation would, within
+    /// the same limits, for host calls to return data in. Errors report
+    /// the instruction at the pc.
+    pub fn allocate_array(&mut self, platters: u32) -> Result<u32, Error> {
+        self.touched();
+        let inst = self.read_value(0, self.pc).unwrap_or(0);
+        self.allocate(inst, platters)
+    }
+
+    pub fn active_array_count(&self) -> usize {
+        self.arrays.active_count()
+    }
+
+    /// The memory in use now, the peaks so far and the allocations made.
+    /// Allocations made while replaying history for [`Machine::rewind`]
+    /// are not counted again.
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            live_arrays: self.active_array_count(),
+            free_arrays: self.arrays.free().len(),
+            free_platters: self.arrays.free_capacity(),
+            ..self.memory
+        }
+    }
+
+    // Kept out of the instruction loop, which runs measurably slower with
+    // this inlined.
+    #[inline(never)]
+    fn count_allocation(&mut self, platters: u64) {
+        self.memory.allocations += 1;
+        self.memory.allocated_platters += platters;
+        self.memory.live_platters += platters;
+        self.memory.peak_platters = self.memory.peak_platters.max(self.memory.live_platters);
+        self.memory.peak_arrays = self.memory.peak_arrays.max(self.active_array_count());
+    }
+
+    // Counts the platters in the active arrays again after they have been
+    // replaced wholesale.
+    fn recount_memory(&mut self) {
+        self.memory.live_platters = self.arrays().map(|(_, a)| a.len() as u64).sum();
+        self.memory.peak_platters = self.memory.peak_platters.max(self.memory.live_platters);
+        self.memory.peak_arrays = self.memory.peak_arrays.max(self.active_array_count());
+    }
+
+    /// Iterates over the active arrays and their identifiers, in order.
+    pub fn arrays(&self) -> impl Iterator<Item = (u32, &[u32])> + '_ {
+        self.arrays
+            .slots()
+            .enumerate()
+            .filter_map(|(id, a)| Some((id as u32, a?)))
+    }
+
+    /// A copy of the machine's state to run on its own, for trying inputs
+    /// one after another from the same point: the pc, registers, arrays,
+    /// queued input and instruction count, with the same backend and
+    /// limits on allocation.
+    ///
+    /// The arrays are shared until one machine or the other amends them,
+    /// when that array alone is copied, so forking costs little more than
+    /// the number of arrays. The fork reads no stdin and writes its output
+    /// nowhere; give it I/O with [`MachineBuilder::build_from`], which also
+    /// sets its backend and limits again. It has none of this machine's
+    /// hooks, registered host calls, trace, journal, statistics or
+    /// history, nor its breakpoints and watches.
+    pub fn fork(&mut self) -> Machine {
+        // The amend cache writes through to storage without copying it.
+        self.amend_cache = ArrayCache::default();
+        let fork = Machine::builder()
+            .stdin(io::empty())
+            .stdout(io::sink())
+            .max_alloc(self.max_alloc)
+            .max_memory(self.max_memory)
+            .detect_hangs(self.detect_hangs)
+            .backend(self.backend());
+        #[cfg(feature = "extensions")]
+        let fork = fork.extensions(self.extensions);
+        let mut fork = fork.build();
+        fork.pc = self.pc;
+        fork.registers = self.registers;
+        fork.arrays = self.arrays.clone();
+        fork.input = self.input.clone();
+        fork.executed = self.executed;
+        fork.memory = self.memory;
+        fork
+    }
+
+    /// Queues the UTF-8 encoding of `input`, one byte per Input
+    /// instruction, to be consumed before anything is read from stdin.
+    pub fn add_input(&mut self, input: &str) {
+        self.add_input_bytes(input.as_bytes());
+    }
+
+    /// Queues `input`, one byte per Input instruction, to be consumed
+    /// before anything is read from stdin.
+    pub fn add_input_bytes(&mut self, input: &[u8]) {
+        self.touched();
+        self.input.extend(input);
+    }
+
+    /// Appends the big-endian program image read from `r` to array 0,
+    /// leaving the machine unchanged if the image is not a whole number of
+    /// platters.
+    pub fn extend_from(&mut self, mut r: impl Read) -> Result<(), Error> {
+        let mut array = Vec::new();
+        r.read_to_end(&mut array)?;
+        if array.len() % 4 != 0 {
+            return Err(Error::TruncatedProgram { len: array.len() });
+        }
+        let mut array = array
+            .chunks_exact(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        self.touched();
+        self.invalidate_caches(0);
+        match self.arrays.get_mut(0) {
+            Some(a) => {
+                Arc::make_mut(a).append(&mut array);
+                self.recount_memory();
+            }
+            _ => {
+                return Err(Error::InactiveArray {
+                    pc: self.pc,
+                    inst: None,
+                    array: 0,
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    // Checks the invariants a deserialized machine must uphold.
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.arrays.get(0).is_none() {
+            return Err("program array is not active");
+        }
+        let slots: Vec<bool> = self.arrays.slots().map(|a| a.is_none()).collect();
+        let mut free = self.arrays.free().to_vec();
+        if free
+            .iter()
+            .any(|idx| !slots.get(*idx as usize).copied().unwrap_or(false))
+        {
+            return Err("free list entry is not a free slot");
+        }
+        free.sort_unstable();
+        free.dedup();
+        if free.len() != self.arrays.free().len()
+            || free.len() != slots.iter().filter(|free| **free).count()
+        {
+            return Err("free list does not match free slots");
+        }
+        Ok(())
+    }
+
+    fn read_value(&self, array: u32, offset: u32) -> Result<u32, Error> {
+        match self.arrays.get(array) {
+            Some(a) => match a.get(offset as usize) {
+                Some(v) => Ok(*v),
+                None => Err(Error::OutOfBounds {
+                    pc: self.pc,
+                    inst: None,
+                    array,
+                    offset,
+                    len: a.len() as u32,
+                }),
+            },
+            _ => Err(Error::InactiveArray {
+                pc: self.pc,
+                inst: None,
+                array,
+            }),
+        }
+    }
+
+    fn invalidate_caches(&mut self, array: u32) {
+        if array == 0 {
+            self.decoded.clear();
+        }
+        if self.index_cache.array == array {
+            self.index_cache = ArrayCache::default();
+        }
+        if self.amend_cache.array == array {
+            self.amend_cache = ArrayCache::default();
+        }
+    }
+
+    // With `unique` set, first copies the array's storage if another array
+    // shares it, leaving the index cache pointing at the old storage to be
+    // filled again.
+    fn fill_cache(&mut self, inst: u32, array: u32, unique: bool) -> Result<ArrayCache, Error> {
+        match self.arrays.get_mut(array) {
+            Some(a) => {
+                if unique {
+                    if Arc::strong_count(a) != 1 {
+                        Arc::make_mut(a);
+                        if self.index_cache.array == array {
+                            self.index_cache = ArrayCache::default();
+                        }
+                    }
+                    // Pairs with the Release decrement that dropped the last
+                    // other reference, perhaps on another thread, so that
+                    // its reads of the storage come before these writes, as
+                    // in Arc::get_mut.
+                    atomic::fence(atomic::Ordering::Acquire);
+                }
+                // No Weak is ever made, so with a count of one this is the
+                // only reference. Checked without Arc::get_mut, whose atomic
+                // compare-exchange costs about 20% on midmark.
+                let ptr = if unique {
+                    unsafe { (*Arc::as_ptr(a).cast_mut()).as_mut_ptr() }
+                } else {
+                    a.as_ptr().cast_mut()
+                };
+                Ok(ArrayCache {
+                    array,
+                    ptr,
+                    len: a.len() as u32,
+                })
+            }
+            _ => Err(Error::InactiveArray {
+                pc: self.pc,
+                inst: Some(inst),
+                array,
+            }),
+        }
+    }
+
+    #[inline(always)]
+    fn index(&mut self, inst: u32, array: u32, offset: u32) -> Result<u32, Error> {
+        if self.index_cache.array != array || self.index_cache.ptr.is_null() {
+            self.index_cache = self.fill_cache(inst, array, false)?;
+        }
+        let cache = self.index_cache;
+        if offset >= cache.len {
+            return Err(Error::OutOfBounds {
+                pc: self.pc,
+                inst: Some(inst),
+                array,
+                offset,
+                len: cache.len,
+            });
+        }
+        Ok(unsafe { *cache.ptr.add(offset as usize) })
+    }
+
+    #[inline(always)]
+    fn amend(&mut self, inst: u32, array: u32, offset: u32, val: u32) -> Result<(), Error> {
+        if self.amend_cache.array != array || self.amend_cache.ptr.is_null() {
+            self.amend_cache = self.fill_cache(inst, array, true)?;
+        }
+        let cache = self.amend_cache;
+        if offset >= cache.len {
+            return Err(Error::OutOfBounds {
+                pc: self.pc,
+                inst: Some(inst),
+                array,
+                offset,
+                len: cache.len,
+            });
+        }
+        unsafe { *cache.ptr.add(offset as usize) = val };
+        Ok(())
+    }
+
+    fn read_reg(&mut self, reg: u32) -> u32 {
+        unsafe { *self.registers.get_unchecked_mut(reg as usize) }
+    }
+
+    fn write_reg(&mut self, reg: u32, val: u32) {
+        unsafe {
+            *self.registers.get_unchecked_mut(reg as usize) = val;
+        }
+    }
+
+    /// Counts and timings for each opcode, indexed by opcode, if the
+    /// machine was built with [`MachineBuilder::stats`].
+    pub fn op_stats(&self) -> Option<&[OpStats; 14]> {
+        self.stats.then_some(&self.op_stats)
+    }
+
+    /// How many times the instruction at each pc has executed, indexed by
+    /// pc and ending at the highest pc executed, if the machine was built
+    /// with [`MachineBuilder::profile`]. Counts are kept by pc alone, so
+    /// after loading a program from another array they mix the two.
+    pub fn pc_counts(&self) -> Option<&[u64]> {
+        self.profile.then_some(&self.pc_counts)
+    }
+
+    /// The pc and platter of each of the last instructions executed, oldest
+    /// first, as many as [`MachineBuilder::recent_instructions`] asked for.
+    /// An instruction that failed is the last one.
+    pub fn recent_instructions(&self) -> Vec<(u32, u32)> {
+        self.recent.iter().copied().collect()
+    }
+
+    fn record_recent(&mut self, pc: u32, inst: u32) {
+        if self.recent.len() == self.recent_limit {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((pc, inst));
+    }
+
+    /// Every pc that executed an Allocation, in order, with the arrays it
+    /// allocated that were never abandoned, if the machine was built with
+    /// [`MachineBuilder::audit`]. Arrays that were already active when
+    /// auditing started are not listed.
+    pub fn allocation_sites(&self) -> Option<Vec<AllocationSite>> {
+        if !self.audit {
+            return None;
+        }
+        let mut sites = self.audit_sites.clone();
+        for (array, pc) in &self.allocated_at {
+            if let Some(site) = sites.get_mut(pc) {
+                site.live.push(*array);
+            }
+        }
+        Some(sites.into_values().collect())
+    }
+
+    #[inline(never)]
+    fn audit_allocation(&mut self, pc: u32, array: u32, platters: u64) {
+        let site = self
+            .audit_sites
+            .entry(pc)
+            .or_insert_with(|| AllocationSite {
+                pc,
+                ..Default::default()
+            });
+        site.allocations += 1;
+        site.platters += platters;
+        self.allocated_at.insert(array, pc);
+    }
+
+    #[inline(never)]
+    fn audit_abandonment(&mut self, array: u32) {
+        if let Some(pc) = self.allocated_at.remove(&array) {
+            if let Some(site) = self.audit_sites.get_mut(&pc) {
+                site.abandonments += 1;
+            }
+        }
+    }
+
+    // Nobody is listening once the receiver has been dropped.
+    #[cfg(feature = "std")]
+    fn send_event(&self, event: MachineEvent) {
+        if let Some(events) = self.events.as_ref() {
+            let _ = events.send(event);
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn send_event(&self, _: MachineEvent) {}
+
+    // The parts of Allocation, Abandonment, Output, Input and Load Program
+    // shared by every backend, leaving the pc alone.
+    fn allocate(&mut self, inst: u32, cap: u32) -> Result<u32, Error> {
+        if cap > self.max_alloc {
+            return Err(Error::AllocationTooLarge {
+                pc: self.pc,
+                inst,
+                requested: cap,
+                limit: self.max_alloc,
+            });
+        }
+        let retained = self.arrays.free_capacity();
+        if self.memory.live_platters + retained + cap as u64 > self.max_memory && retained > 0 {
+            // Storage kept for reuse gives way before the program is refused.
+            self.arrays.release();
+        }
+        if self.memory.live_platters + cap as u64 > self.max_memory {
+            return Err(Error::OutOfMemory {
+                pc: self.pc,
+                inst,
+                requested: cap,
+                limit: self.max_memory,
+            });
+        }
+        let array = self.arrays.allocate(cap as usize);
+        self.invalidate_caches(array);
+        self.count_allocation(cap as u64);
+        Ok(array)
+    }
+
+    fn abandon(&mut self, inst: u32, array: u32) -> Result<(), Error> {
+        if array == 0 {
+            return Err(Error::AbandonedProgramArray { pc: self.pc, inst });
+        }
+        self.invalidate_caches(array);
+        let Some(len) = self.arrays.abandon(array) else {
+            // Every identifier below the slot count was allocated once.
+            if (array as usize) < self.arrays.slots().len() {
+                return Err(Error::DoubleAbandon {
+                    pc: self.pc,
+                    inst,
+                    array,
+                });
+            }
+            return Err(Error::InactiveArray {
+                pc: self.pc,
+                inst: Some(inst),
+                array,
+            });
+        };
+        self.memory.live_platters -= len as u64;
+        Ok(())
+    }
+
+    fn output(&mut self, inst: u32, ch: u32) -> Result<(), Error> {
+        if ch > 255 {
+            return Err(Error::InvalidChar {
+                pc: self.pc,
+                inst,
+                ch,
+            });
+        }
+        self.stdout.put(ch as u8)?;
+        if let Some(tee) = self.tee.as_mut() {
+            tee.write_all(&[ch as u8])?;
+        }
+        self.journal_output(ch as u8);
+        Ok(())
+    }
+
+    fn take_input(&mut self) -> Result<u32, Error> {
+        // Replays queue input that came from the journal the first time.
+        let ch = if let Some(ch) = self.input.pop_front() {
+            Some(ch)
+        } else if self.replay.is_some() {
+            self.replay_input()?
+        } else {
+            // Only when the read would wait, so that output isn't flushed
+            // byte by byte while buffered input is consumed.
+            if self.stdin.buffer().is_empty() {
+                self.stdout.flush_for_input()?;
+                if let Some(trace) = self.trace.as_mut() {
+                    trace.flush()?;
+                }
+                if let Some(journal) = self.journal.as_mut() {
+                    journal.flush()?;
+                }
+            }
+            let mut buf = [0];
+            match self.stdin.read_exact(&mut buf) {
+                Ok(()) => Some(buf[0]),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !self.eof_error => None,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.blocked_input = Some((self.executed, self.pc));
+                    return Err(e.into());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        self.journal_input(ch)?;
+        let Some(ch) = ch else {
+            return Ok(!0);
+        };
+        if let Some(history) = self.history.as_mut() {
+            history.log_input(self.executed, ch);
+        }
+        if self.echo {
+            self.stdout.put(ch)?;
+        }
+        Ok(ch as u32)
+    }
+
+    fn load_program(&mut self, inst: u32, array: u32, entry: u32) -> Result<(), Error> {
+        if self.detect_hangs && array == 0 && entry == self.pc {
+            return Err(Error::InfiniteLoop { pc: self.pc, inst });
+        }
+        if array == 0 {
+            return Ok(());
+        }
+        self.invalidate_caches(0);
+        self.invalidate_caches(array);
+        match self.arrays.get(array) {
+            Some(a) => {
+                if let Some(hook) = self.load_program_hook.as_mut() {
+                    hook(array, entry, a)?;
+                }
+                // Copied only once either is amended.
+                let a = Arc::clone(a);
+                let len = a.len() as u64;
+                if let Some(old) = self.arrays.get(0) {
+                    self.memory.live_platters -= old.len() as u64;
+                }
+                self.memory.live_platters += len;
+                self.memory.peak_platters =
+                    self.memory.peak_platters.max(self.memory.live_platters);
+                self.arrays.replace(0, a);
+                Ok(())
+            }
+            _ => Err(Error::InactiveArray {
+                pc: self.pc,
+                inst: Some(inst),
+                array,
+            }),
+        }
+    }
+
+    /// Runs until the program halts, without checking for breakpoints,
+    /// watched arrays, the step limit, the deadline or interrupts. An
+    /// interrupt sent meanwhile stays pending.
+    pub fn run(&mut self) -> Result<(), Error> {
+        // Without checks or polling, the loop only stops to halt.
+        self.polling = false;
+        let res = self.run_with(self.run_loop_for::<false, false>());
+        self.polling = true;
+        res.map(drop)
+    }
+
+    /// Runs until the program halts, reaches a breakpoint, meets a
+    /// condition, touches a watched array, reaches the step limit, or runs
+    /// past the deadline. At least one instruction is
+    /// executed, so calling this again after stopping at a breakpoint
+    /// carries on past it.
+    pub fn run_until_stop(&mut self) -> Result<Stop, Error> {
+        if self.watches.is_empty()
+            && self.breakpoints.is_empty()
+            && self.conditions.is_empty()
+            && self.history.is_none()
+            && self.step_limit == u64::MAX
+        {
+            self.run_with(self.run_loop_for::<false, false>())
+        } else {
+            self.run_with(self.run_loop_for::<true, false>())
+        }
+    }
+
+    /// Executes a single instruction, returning [`Stop::Step`] unless it
+    /// halted or touched a watched array.
+    pub fn step(&mut self) -> Result<Stop, Error> {
+        self.run_with(self.run_loop_for::<true, true>())
+    }
+
+    // Only the variants with TRACE set call the instruction hook and the
+    // observers, write the trace, keep statistics, audit allocations and
+    // send events.
+    fn run_loop_for<const CHECKS: bool, const STEP: bool>(
+        &self,
+    ) -> fn(&mut Self) -> Result<Stop, Error> {
+        #[cfg(feature = "std")]
+        let events = self.events.is_some();
+        #[cfg(not(feature = "std"))]
+        let events = false;
+        let trace = self.instruction_hook.is_some()
+            || !self.observers.is_empty()
+            || events
+            || self.trace.is_some()
+            || self.stats
+            || self.profile
+            || self.audit
+            || self.recent_limit != 0;
+        if !CHECKS && !trace && self.threaded {
+            return Self::run_threaded;
+        }
+        match (trace, self.decode) {
+            (true, false) => Self::run_loop::<CHECKS, STEP, true, false>,
+            (true, true) => Self::run_loop::<CHECKS, STEP, true, true>,
+            (false, false) => Self::run_loop::<CHECKS, STEP, false, false>,
+            (false, true) => Self::run_loop::<CHECKS, STEP, false, true>,
+        }
+    }
+
+    fn run_with(&mut self, run_loop: fn(&mut Self) -> Result<Stop, Error>) -> Result<Stop, Error> {
+        let res = run_loop(self);
+        let flushed = self.stdout.flush();
+        let teed = self.tee.as_mut().map_or(Ok(()), |tee| tee.flush());
+        let traced = self.trace.as_mut().map_or(Ok(()), |trace| trace.flush());
+        let stop = res?;
+        flushed?;
+        teed?;
+        traced?;
+        Ok(stop)
+    }
+
+    // With CHECKS set, stops at breakpoints, met conditions and the step limit,
+    // after each instruction that touches a watched array, and with STEP also
+    // after the first instruction. It also takes history checkpoints. With
+    // TRACE set, calls the instruction hook and the observers and writes the
+    // trace line before each instruction, times it for the statistics, and
+    // calls the observers again after it. With DECODED set, fetches
+    // instructions already decoded. All are const so that the checks
+    // compile away when unset; a runtime step flag alone costs about 20% on
+    // midmark. Every 65536 instructions it flushes stale output and, unless
+    // called from `run`, checks for an interrupt or timeout.
+    fn run_loop<const CHECKS: bool, const STEP: bool, const TRACE: bool, const DECODED: bool>(
+        &mut self,
+    ) -> Result<Stop, Error> {
+        let mut ticks: u16 = 0;
+        loop {
+            ticks = ticks.wrapping_add(1);
+            if ticks == 0 {
+                self.stdout.flush_if_stale()?;
+            }
+            let pc = self.pc;
+            if CHECKS {
+                if self.executed >= self.step_limit {
+                    return Ok(Stop::StepLimit {
+                        pc,
+                        executed: self.executed,
+                    });
+                }
+                if self.executed >= self.next_checkpoint {
+                    self.checkpoint();
+                }
+                // Conditions see every instruction, even the first, so
+                // that those tracking changes stay up to date.
+                let met = self.check_conditions(pc);
+                // A step always executes its instruction.
+                let resumed =
+                    STEP || self.checked.replace((self.executed, pc)) == Some((self.executed, pc));
+                if !resumed && self.breakpoints.contains(&pc) {
+                    return Ok(Stop::Breakpoint { pc });
+                }
+                if let Some(id) = met.filter(|_| !resumed) {
+                    return Ok(Stop::Condition { pc, id });
+                }
+            }
+            // After the checks, so that resuming does not skip a breakpoint.
+            if ticks == 0 {
+                if let Some(stop) = self.poll_stop() {
+                    return Ok(stop);
+                }
+            }
+            let mut hit = None;
+
+            let (inst, op, a, b, c) = if DECODED {
+                let d = self.fetch_decoded()?;
+                (d.inst, d.op as u32, d.a as u32, d.b, d.c as u32)
+            } else {
+                let inst = self.read_value(0, self.pc)?;
+                let (op, a, b, c) = instruction::fields(inst);
+                (inst, op, a, b, c)
+            };
+            // An Input executed again after its read would have blocked is
+            // not shown to the hook, observers and trace a second time.
+            let again = TRACE && self.blocked_input.take() == Some((self.executed, pc));
+            if TRACE && !again {
+                if let Some(hook) = self.instruction_hook.as_mut() {
+                    hook(pc, inst, &self.registers)?;
+                }
+                for observer in &mut self.observers {
+                    observer.before(pc, inst, &self.registers)?;
+                }
+                if self.recent_limit != 0 {
+                    self.record_recent(pc, inst);
+                }
+            }
+            let start = if TRACE && self.stats { cycles() } else { 0 };
+
+            macro_rules! trace {
+                ($($tt:tt)*) => {
+                    if TRACE && !again {
+                        if let Some(trace) = self.trace.as_mut() {
+                            write!(trace,
+                                "pc:{pc:04x}  op:{op:02}  a:{a:02x}  b:{b:02x}  c:{c:02x}  regs:{regs:02x?}  inst:{inst:032b}  ",
+                                regs = self.registers)?;
+                            writeln!(trace, $($tt)*)?;
+                        }
+                    }
+                };
+            }
+
+            match op {
+                0 => {
+                    /*
+                        #0. Conditional Move.
+
+                        The register A receives the value in register B,
+                        unless the register C contains 0.
+                    */
+                    trace!("IF REG[{c}], REG[{a}] = REG[{b}]");
+                    let val = if self.read_reg(c) != 0 {
+                        self.read_reg(b)
+                    } else {
+                        self.read_reg(a)
+                    };
+                    self.write_reg(a, val);
+                    self.pc += 1;
+                }
+
+                1 => {
+                    /*
+                        #1. Array Index.
+
+                        The register A receives the value stored at offset
+                        in register C in the array identified by B.
+                    */
+                    trace!("REG[{a}] = ARRAY[REG[{b}], REG[{c}]]");
+                    let b = self.read_reg(b);
+                    let c = self.read_reg(c);
+                    let val = self.index(inst, b, c)?;
+                    if CHECKS && self.watched_at(b, c, debug::READ) {
+                        hit = Some((b, Some(c), Access::Read));
+                    }
+                    self.write_reg(a, val);
+                    self.pc += 1;
+                }
+
+                2 => {
+                    /*
+                        #2. Array Amendment.
+
+                        The array identified by A is amended at the offset
+                        in register B to store the value in register C.
+                    */
+                    trace!("ARRAY[REG[{a}], REG[{b}]] = REG[{c}]");
+                    let a = self.read_reg(a);
+                    let b = self.read_reg(b);
+                    let c = self.read_reg(c);
+                    self.amend(inst, a, b, c)?;
+                    if DECODED && a == 0 {
+                        self.redecode(b);
+                    }
+                    if CHECKS && self.watched_at(a, b, debug::WRITE) {
+                        hit = Some((a, Some(b), Access::Write));
+                    }
+                    self.pc += 1;
+                }
+
+                3 => {
+                    /*
+                        #3. Addition.
+
+                        The register A receives the value in register B plus
+                        the value in register C, modulo 2^32.
+                    */
+                    trace!("REG[{a}] = REG[{b}] + REG[{c}]");
+                    let val = self.read_reg(b).wrapping_add(self.read_reg(c));
+                    self.write_reg(a, val);
+                    self.pc += 1;
+                }
+
+                4 => {
+                    /*
+                        #4. Multiplication.
+
+                        The register A receives the value in register B times
+                        the value in register C, modulo 2^32.
+                    */
+                    trace!("REG[{a}] = REG[{b}] * REG[{c}]");
+                    let val = self.read_reg(b).wrapping_mul(self.read_reg(c));
+                    self.write_reg(a, val);
+                    self.pc += 1;
+                }
+
+                5 => {
+                    /*
+                        #5. Division.
+
+                        The register A receives the value in register B
+                        divided by the value in register C, if any, where
+                        each quantity is treated as an unsigned 32 bit number.
+                    */
+                    trace!("REG[{a}] = REG[{b}] / REG[{c}]");
+                    let divisor = self.read_reg(c);
+                    if divisor == 0 {
+                        return Err(Error::DivisionByZero { pc: self.pc, inst });
+                    }
+                    let val = self.read_reg(b) / divisor;
+                    self.write_reg(a, val);
+                    self.pc += 1;
+                }
+                6 => {
+                    /*
+                        #6. Not-And.
+
+                        Each bit in the register A receives the 1 bit if
+                        either register B or register C has a 0 bit in that
+                        position.  Otherwise the bit in register A receives
+                        the 0 bit.
+                    */
+                    trace!("REG[{a}] = !(REG[{b}] & REG[{c}])");
+                    let val = !(self.read_reg(b) & self.read_reg(c));
+                    self.write_reg(a, val);
+                    self.pc += 1;
+                }
+
+                7 => {
+                    /*
+                        #7. Halt.
+
+                        The universal machine stops computation.
+                    */
+                    trace!("HALT");
+                    break;
+                }
+
+                8 => {
+                    /*
+                        #8. Allocation.
+
+                        A new array is created with a capacity of platters
+                        commensurate to the value in the register C. This
+                        new array is initialized entirely with platters
+                        holding the value 0. A bit pattern not consisting of
+                        exclusively the 0 bit, and that identifies no other
+                        active allocated array, is placed in the B register.
+                    */
+                    trace!("REG[{b}] = allocate REG[{c}] words");
+                    let cap = self.read_reg(c);
+                    let array = self.allocate(inst, cap)?;
+                    if TRACE && self.audit {
+                        self.audit_allocation(pc, array, cap as u64);
+                    }
+                    if TRACE {
+                        self.send_event(MachineEvent::Allocated {
+                            pc,
+                            array,
+                            platters: cap,
+                        });
+                    }
+                    if CHECKS && self.watched(array, debug::LIFECYCLE) {
+                        hit = Some((array, None, Access::Allocate));
+                    }
+                    self.write_reg(b, array);
+                    self.pc += 1;
+                }
+
+                9 => {
+                    /*
+                        #9. Abandonment.
+
+                        The array identified by the register C is abandoned.
+                        Future allocations may then reuse that identifier.
+                    */
+                    trace!("deallocate REGS[{c}]");
+                    let array = self.read_reg(c);
+                    self.abandon(inst, array)?;
+                    if CHECKS && self.watched(array, debug::LIFECYCLE) {
+                        hit = Some((array, None, Access::Abandon));
+                    }
+                    if TRACE && self.audit {
+                        self.audit_abandonment(array);
+                    }
+                    if TRACE {
+                        self.send_event(MachineEvent::Abandoned { pc, array });
+                    }
+                    self.pc += 1;
+                }
+
+                10 => {
+                    /*
+                        #10. Output.
+
+                        The value in the register C is displayed on the console
+                        immediately. Only values between and including 0 and 255
+                        are allowed.
+                    */
+                    trace!("Output REGS[{c}]");
+                    let ch = self.read_reg(c);
+                    self.output(inst, ch)?;
+                    if TRACE {
+                        let byte = ch as u8;
+                        self.send_event(MachineEvent::Output { pc, byte });
+                    }
+                    self.pc += 1;
+                }
+
+                11 => {
+                    /*
+                        #11. Input.
+
+                        The universal machine waits for input on the console.
+                        When input arrives, the register C is loaded with the
+                        input, which must be between and including 0 and 255.
+                        If the end of input has been signaled, then the
+                        register C is endowed with a uniform value pattern
+                        where every place is pregnant with the 1 bit.
+                    */
+                    trace!("REGS[{c}] = input");
+                    let ch = self.take_input()?;
+                    if TRACE {
+                        let byte = u8::try_from(ch).ok();
+                        self.send_event(MachineEvent::Input { pc, byte });
+                    }
+                    self.write_reg(c, ch);
+                    self.pc += 1;
+                }
+
+                12 => {
+                    /*
+                        #12. Load Program.
+
+                        The array identified by the B register is duplicated
+                        and the duplicate shall replace the '0' array,
+                        regardless of size. The execution finger is placed
+                        to indicate the platter of this array that is
+                        described by the offset given in C, where the value
+                        0 denotes the first platter, 1 the second, et
+                        cetera.
+
+                        The '0' array shall be the most sublime choice for
+                        loading, and shall be handled with the utmost
+                        velocity.
+                    */
+                    trace!("program load: duplicate memory in REG[{b}] into code space, and set instruction pointer to REG[{c}]");
+                    let array = self.read_reg(b);
+                    let entry = self.read_reg(c);
+                    self.load_program(inst, array, entry)?;
+                    if TRACE && array != 0 {
+                        self.send_event(MachineEvent::Loaded { pc, array, entry });
+                    }
+                    if CHECKS && array != 0 && self.watched(array, debug::READ) {
+                        hit = Some((array, None, Access::Read));
+                    }
+                    self.pc = entry;
+                }
+
+                13 => {
+                    /*
+                        #13. Orthography.
+
+                        The value indicated is loaded into the register A
+                        forthwith.
+                    */
+                    trace!("REG[{a}] = {b}");
+                    self.write_reg(a, b);
+                    self.pc += 1;
+                }
+
+                #[cfg(feature = "extensions")]
+                14 if !self.host_calls.is_empty() => {
+                    /*
+                        #14. Host Call.
+
+                        Not in the spec: the host call registered under the
+                        number in register B is carried out, with register A
+                        holding its argument and receiving its result, and
+                        register C holding a second argument. See host.rs.
+                    */
+                    trace!("REG[{a}] = host call REG[{b}] (REG[{a}], REG[{c}])");
+                    self.host_call(inst, a, b, c)?;
+                    self.pc += 1;
+                }
+
+                _ => {
+                    return Err(Error::InvalidOp {
+                        pc: self.pc,
+                        inst,
+                        op,
+                    })
+                }
+            }
+
+            self.executed += 1;
+
+            if TRACE {
+                for observer in &mut self.observers {
+                    observer.after(pc, inst, &self.registers)?;
+                }
+            }
+            // Host calls are not counted.
+            if TRACE && self.stats && op < 14 {
+                let stats = &mut self.op_stats[op as usize];
+                stats.cycles += cycles() - start;
+                stats.count += 1;
+            }
+            if TRACE && self.profile {
+                let idx = pc as usize;
+                if idx >= self.pc_counts.len() {
+                    self.pc_counts.resize(idx + 1, 0);
+                }
+                self.pc_counts[idx] += 1;
+            }
+
+            if CHECKS {
+                if let Some((array, offset, access)) = hit {
+                    return Ok(Stop::Watch {
+                        pc,
+                        array,
+                        offset,
+                        access,
+                    });
+                }
+                if STEP {
+                    return Ok(Stop::Step);
+                }
+            }
+        }
+
+        Ok(Stop::Halt)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cycles() -> u64 {
+    unsafe {
+        let mut aux = 0;
+        core::arch::x86_64::__rdtscp(&mut aux)
+    }
+}
+
+#[cfg(all(not(target_arch = "x86_64"), feature = "std"))]
+fn cycles() -> u64 {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START
+        .get_or_init(std::time::Instant::now)
+        .elapsed()
+        .as_nanos() as u64
+}
+
+#[cfg(all(not(target_arch = "x86_64"), not(feature = "std")))]
+fn cycles() -> u64 {
+    0
+}
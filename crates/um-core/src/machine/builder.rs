@@ -0,0 +1,462 @@
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+#[cfg(feature = "std")]
+use std::{io::IsTerminal, sync::mpsc::Sender};
+
+#[cfg(feature = "std")]
+use super::MachineEvent;
+use super::{
+    journal, ArrayCache, Backend, InstructionHook, Journal, LoadProgramHook, Machine, Observer,
+};
+use crate::{
+    io::{self, BufReader, BufWriter, Read, Write},
+    output::{FlushPolicy, SpanWriter},
+};
+
+/// Configures how a [`Machine`] talks to the outside world.
+///
+/// By default the machine reads the process's stdin and writes to its
+/// stdout. Output is written byte by byte when stdout is a terminal and a
+/// line at a time otherwise. Without the `std` feature there is no process
+/// to talk to, and a machine given no stdin or stdout reads nothing and
+/// writes nowhere.
+pub struct MachineBuilder {
+    input: VecDeque<u8>,
+    stdin: Option<Box<dyn Read + Send>>,
+    stdout: Option<Box<dyn Write + Send>>,
+    flush_policy: Option<FlushPolicy>,
+    echo: bool,
+    eof_error: bool,
+    tee: Option<Box<dyn Write + Send>>,
+    load_program_hook: Option<LoadProgramHook>,
+    instruction_hook: Option<InstructionHook>,
+    observers: Vec<Box<dyn Observer>>,
+    #[cfg(feature = "std")]
+    events: Option<Sender<MachineEvent>>,
+    trace: Option<Box<dyn Write + Send>>,
+    journal: Option<Box<dyn Write + Send>>,
+    replay: Option<Journal>,
+    stats: bool,
+    profile: bool,
+    audit: bool,
+    recent_instructions: usize,
+    max_alloc: u32,
+    max_memory: u64,
+    detect_hangs: bool,
+    #[cfg(feature = "extensions")]
+    extensions: bool,
+    #[cfg(feature = "extensions")]
+    host_calls: super::host::HostCalls,
+    backend: Backend,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        Self {
+            input: VecDeque::new(),
+            stdin: None,
+            stdout: None,
+            flush_policy: None,
+            echo: false,
+            eof_error: false,
+            tee: None,
+            load_program_hook: None,
+            instruction_hook: None,
+            observers: Vec::new(),
+            #[cfg(feature = "std")]
+            events: None,
+            trace: None,
+            journal: None,
+            replay: None,
+            stats: false,
+            profile: false,
+            audit: false,
+            recent_instructions: 0,
+            max_alloc: Self::DEFAULT_MAX_ALLOC,
+            max_memory: u64::MAX,
+            detect_hangs: false,
+            #[cfg(feature = "extensions")]
+            extensions: false,
+            #[cfg(feature = "extensions")]
+            host_calls: Default::default(),
+            backend: Backend::default(),
+        }
+    }
+}
+
+impl MachineBuilder {
+    /// The default limit on the size of a single allocation, in platters
+    /// (1 GiB).
+    pub const DEFAULT_MAX_ALLOC: u32 = 1 << 28;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the UTF-8 encoding of `input`, one byte per Input
+    /// instruction, to be consumed before anything is read from stdin.
+    pub fn input(self, input: &str) -> Self {
+        self.input_bytes(input.as_bytes())
+    }
+
+    /// Queues `input`, one byte per Input instruction, to be consumed
+    /// before anything is read from stdin.
+    pub fn input_bytes(mut self, input: &[u8]) -> Self {
+        self.input.extend(input);
+        self
+    }
+
+    /// Reads console input from `stdin`. Reads are buffered, so the machine
+    /// may take more from `stdin` than the program has consumed yet.
+    pub fn stdin(mut self, stdin: impl Read + Send + 'static) -> Self {
+        self.stdin = Some(Box::new(stdin));
+        self
+    }
+
+    pub fn stdout(mut self, stdout: impl Write + Send + 'static) -> Self {
+        self.stdout = Some(Box::new(stdout));
+        self
+    }
+
+    /// When buffered output is written. Defaults to
+    /// [`FlushPolicy::Byte`] when no stdout handle is given and the
+    /// process's stdout is a terminal, and to [`FlushPolicy::Newline`]
+    /// otherwise.
+    pub fn flush_policy(mut self, policy: FlushPolicy) -> Self {
+        self.flush_policy = Some(policy);
+        self
+    }
+
+    /// Writes every output byte as soon as it is produced, the same as
+    /// [`FlushPolicy::Byte`], or a line at a time, the same as
+    /// [`FlushPolicy::Newline`].
+    pub fn immediate_output(self, immediate: bool) -> Self {
+        self.flush_policy(if immediate {
+            FlushPolicy::Byte
+        } else {
+            FlushPolicy::Newline
+        })
+    }
+
+    /// Fails Input with an [`io::ErrorKind::UnexpectedEof`] I/O error
+    /// at the end of stdin, leaving the pc at the Input so that a snapshot
+    /// taken then resumes there, instead of loading the all-ones pattern
+    /// the spec calls for.
+    pub fn eof_error(mut self, eof_error: bool) -> Self {
+        self.eof_error = eof_error;
+        self
+    }
+
+    /// Also writes each input byte the program consumes to stdout, for
+    /// terminals that do not echo what is typed. Off by default.
+    pub fn echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    /// Also writes every Output opcode byte to `tee`. Echoed input is not
+    /// included.
+    pub fn tee_output(mut self, tee: impl Write + Send + 'static) -> Self {
+        self.tee = Some(Box::new(tee));
+        self
+    }
+
+    /// Calls `hook` with the source array, the entry point, and the new
+    /// program each time Load Program replaces array 0 with another array.
+    pub fn on_load_program(
+        mut self,
+        hook: impl FnMut(u32, u32, &[u32]) -> io::Result<()> + Send + 'static,
+    ) -> Self {
+        self.load_program_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Calls `hook` with the pc, the instruction, and the registers before
+    /// each instruction executes. Machines without a hook pay nothing for
+    /// this; with one, every instruction makes a call.
+    pub fn on_instruction(
+        mut self,
+        hook: impl FnMut(u32, u32, &[u32; 8]) -> io::Result<()> + Send + 'static,
+    ) -> Self {
+        self.instruction_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Adds `observer`, to be told of each instruction before and after it
+    /// executes; observers are called in the order they were added.
+    pub fn observe(mut self, observer: impl Observer + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Writes a line to `trace` before each instruction executes, with the
+    /// pc, the decoded instruction, the registers, and what the instruction
+    /// does. Like an instruction hook, this costs nothing unless set.
+    pub fn trace(mut self, trace: impl Write + Send + 'static) -> Self {
+        self.trace = Some(Box::new(trace));
+        self
+    }
+
+    /// Records every value Input consumes, with the instruction count it
+    /// was consumed at, and the output written, as a [`Journal`] that
+    /// [`MachineBuilder::replay_journal`] can feed to another run.
+    pub fn record_journal(mut self, journal: impl Write + Send + 'static) -> Self {
+        self.journal = Some(Box::new(journal));
+        self
+    }
+
+    /// Takes input from `journal` instead of the queue and stdin, failing
+    /// with [`Error::ReplayDiverged`](crate::Error::ReplayDiverged) if an
+    /// Input executes at another instruction count than it was recorded
+    /// at, or once the journal runs out. Given the same program, the run
+    /// repeats the recorded one exactly.
+    pub fn replay_journal(mut self, journal: Journal) -> Self {
+        self.replay = Some(journal);
+        self
+    }
+
+    /// Counts the instructions executed with each opcode and times them, for
+    /// [`Machine::op_stats`]. Timing every instruction makes the machine
+    /// several times slower.
+    pub fn stats(mut self, stats: bool) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Counts how often the instruction at each pc executes, for
+    /// [`Machine::pc_counts`].
+    pub fn profile(mut self, profile: bool) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Sends a [`MachineEvent`] to `events` for each allocation,
+    /// abandonment, program load, byte output and byte of input consumed.
+    /// Like an observer, this has every instruction interpreted. Needs the
+    /// `std` feature.
+    #[cfg(feature = "std")]
+    pub fn events(mut self, events: Sender<MachineEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Records the pc of every Allocation and which arrays it made are
+    /// abandoned, for [`Machine::allocation_sites`].
+    pub fn audit(mut self, audit: bool) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Keeps the pc and platter of the last `count` instructions executed,
+    /// for [`Machine::recent_instructions`], to show what led up to an
+    /// error. Like an instruction hook, this costs every instruction a
+    /// little.
+    pub fn recent_instructions(mut self, count: usize) -> Self {
+        self.recent_instructions = count;
+        self
+    }
+
+    /// Makes Allocation fail with [`Error::AllocationTooLarge`](crate::Error::AllocationTooLarge) when asked
+    /// for more than `platters` platters, instead of trying to reserve the
+    /// memory. Defaults to
+    /// [`MachineBuilder::DEFAULT_MAX_ALLOC`].
+    pub fn max_alloc(mut self, platters: u32) -> Self {
+        self.max_alloc = platters;
+        self
+    }
+
+    /// Makes Allocation fail with
+    /// [`Error::OutOfMemory`](crate::Error::OutOfMemory) when it would bring
+    /// the platters in active arrays to more than `platters`. Storage kept
+    /// for reusing abandoned identifiers is let go first. Unlimited by
+    /// default.
+    pub fn max_memory(mut self, platters: u64) -> Self {
+        self.max_memory = platters;
+        self
+    }
+
+    /// Makes Load Program fail with
+    /// [`Error::InfiniteLoop`](crate::Error::InfiniteLoop) when it jumps to
+    /// itself within array 0, a loop nothing can end, instead of spinning
+    /// there as the spec says. Off by default, since programs may park
+    /// themselves that way on purpose.
+    pub fn detect_hangs(mut self, detect: bool) -> Self {
+        self.detect_hangs = detect;
+        self
+    }
+
+    /// Makes opcode 14 Host Call, with the built-in host calls that give
+    /// programs the time, random numbers and the host's files, instead of
+    /// an invalid opcode. Off by default, as the spec has it. Host calls
+    /// registered with [`MachineBuilder::host_call`] take the place of
+    /// built-in ones with the same number. Needs the `extensions` feature.
+    #[cfg(feature = "extensions")]
+    pub fn extensions(mut self, extensions: bool) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Registers `call` for Host Calls with `service` in register B,
+    /// replacing any registered before, and makes opcode 14 Host Call.
+    /// Needs the `extensions` feature.
+    #[cfg(feature = "extensions")]
+    pub fn host_call(mut self, service: u32, call: impl super::HostCall + 'static) -> Self {
+        self.host_calls.insert(service, Box::new(call));
+        self
+    }
+
+    /// How the machine executes instructions. Defaults to
+    /// [`Backend::Interpreter`]; backends the host does not support fall
+    /// back to it.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn build(self) -> Machine {
+        self.build_from(Machine {
+            pc: 0,
+            registers: [0; 8],
+            arrays: Default::default(),
+            input: VecDeque::new(),
+            stats: false,
+            op_stats: Default::default(),
+            profile: false,
+            pc_counts: Vec::new(),
+            audit: false,
+            audit_sites: Default::default(),
+            allocated_at: Default::default(),
+            recent_limit: 0,
+            recent: VecDeque::new(),
+            memory: Default::default(),
+            index_cache: ArrayCache::default(),
+            amend_cache: ArrayCache::default(),
+            stdin: BufReader::new(Box::new(io::empty())),
+            stdout: SpanWriter::new(Box::new(io::sink()), FlushPolicy::Halt),
+            echo: false,
+            eof_error: false,
+            blocked_input: None,
+            tee: None,
+            load_program_hook: None,
+            instruction_hook: None,
+            observers: Vec::new(),
+            #[cfg(feature = "std")]
+            events: None,
+            trace: None,
+            journal: None,
+            replay: None,
+            max_alloc: Self::DEFAULT_MAX_ALLOC,
+            max_memory: u64::MAX,
+            detect_hangs: false,
+            watches: Vec::new(),
+            cell_watches: Default::default(),
+            breakpoints: Default::default(),
+            checked: None,
+            conditions: Vec::new(),
+            next_condition: 0,
+            executed: 0,
+            next_checkpoint: u64::MAX,
+            step_limit: u64::MAX,
+            #[cfg(feature = "std")]
+            deadline: None,
+            polling: true,
+            history: None,
+            interrupter: Default::default(),
+            decode: false,
+            threaded: false,
+            decoded: Vec::new(),
+            #[cfg(feature = "extensions")]
+            extensions: false,
+            #[cfg(feature = "extensions")]
+            host_calls: Default::default(),
+            #[cfg(feature = "extensions")]
+            replayed_host_calls: None,
+        })
+    }
+
+    /// Applies this configuration to an existing machine, such as one
+    /// restored from a snapshot, keeping its state. Queued input is added
+    /// after any input the machine already has pending.
+    pub fn build_from(mut self, mut machine: Machine) -> Machine {
+        #[cfg(feature = "std")]
+        let terminal = self.stdout.is_none() && std::io::stdout().is_terminal();
+        #[cfg(not(feature = "std"))]
+        let terminal = false;
+        let policy = self.flush_policy.unwrap_or(if terminal {
+            FlushPolicy::Byte
+        } else {
+            FlushPolicy::Newline
+        });
+        machine.stdin = BufReader::new(self.stdin.take().unwrap_or_else(default_stdin));
+        machine.stdout = SpanWriter::new(self.stdout.take().unwrap_or_else(default_stdout), policy);
+        machine.echo = self.echo;
+        machine.eof_error = self.eof_error;
+        machine.tee = self.tee.map(BufWriter::new);
+        machine.load_program_hook = self.load_program_hook;
+        machine.instruction_hook = self.instruction_hook;
+        machine.observers = self.observers;
+        #[cfg(feature = "std")]
+        {
+            machine.events = self.events;
+        }
+        machine.trace = self.trace.map(BufWriter::new);
+        machine.journal = self.journal.map(journal::Recorder::new);
+        machine.replay = self.replay;
+        machine.stats = self.stats;
+        machine.profile = self.profile;
+        machine.audit = self.audit;
+        machine.recent_limit = self.recent_instructions;
+        machine.recent.clear();
+        machine.max_alloc = self.max_alloc;
+        machine.max_memory = self.max_memory;
+        machine.detect_hangs = self.detect_hangs;
+        #[cfg(feature = "extensions")]
+        {
+            machine.extensions = self.extensions;
+            machine.host_calls = self.host_calls;
+            if self.extensions {
+                for (service, call) in super::host::built_in() {
+                    machine.host_calls.entry(service).or_insert(call);
+                }
+            }
+        }
+        machine.decode = false;
+        machine.threaded = false;
+        machine.decoded.clear();
+        match self.backend {
+            Backend::Interpreter => {}
+            Backend::Decoded => machine.decode = true,
+            Backend::Threaded => {
+                machine.decode = true;
+                machine.threaded = true;
+            }
+        }
+        if machine.replay.is_some() {
+            machine.input.clear();
+        } else {
+            machine.input.append(&mut self.input);
+        }
+        machine
+    }
+}
+
+// The console of a machine given none: the process's with the `std`
+// feature, and nothing without.
+#[cfg(feature = "std")]
+fn default_stdin() -> Box<dyn Read + Send> {
+    Box::new(std::io::stdin())
+}
+
+#[cfg(feature = "std")]
+fn default_stdout() -> Box<dyn Write + Send> {
+    Box::new(std::io::stdout())
+}
+
+#[cfg(not(feature = "std"))]
+fn default_stdin() -> Box<dyn Read + Send> {
+    Box::new(io::empty())
+}
+
+#[cfg(not(feature = "std"))]
+fn default_stdout() -> Box<dyn Write + Send> {
+    Box::new(io::sink())
+}
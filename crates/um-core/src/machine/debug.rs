@@ -0,0 +1,319 @@
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use super::Machine;
+
+/// Which accesses to a watched array stop the machine. Allocating or
+/// abandoning a watched identifier always does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// How an instruction touched a watched array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Array Index, or Load Program copying the array into array 0.
+    Read,
+    /// Array Amendment.
+    Write,
+    Allocate,
+    Abandon,
+}
+
+/// Why [`Machine::run_until_stop`] or [`Machine::step`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Stop {
+    Halt,
+    /// The instruction at `pc`, which has not been executed yet, has a
+    /// breakpoint.
+    Breakpoint {
+        pc: u32,
+    },
+    /// The condition `id` returned by [`Machine::add_condition`] held before
+    /// the instruction at `pc`, which has not been executed yet.
+    Condition {
+        pc: u32,
+        id: u32,
+    },
+    /// An [`Interrupter`] asked the machine to stop. The instruction at `pc`
+    /// has not been executed yet.
+    Interrupted {
+        pc: u32,
+    },
+    /// [`Machine::step`] executed its instruction.
+    Step,
+    /// The machine has executed the `executed` instructions
+    /// [`Machine::set_step_limit`] allows. The instruction at `pc` has not
+    /// been executed yet; raising or clearing the limit lets it carry on.
+    StepLimit {
+        pc: u32,
+        executed: u64,
+    },
+    /// The deadline set with [`Machine::set_deadline`] has passed, after
+    /// `executed` instructions. The instruction at `pc` has not been
+    /// executed yet.
+    Timeout {
+        pc: u32,
+        executed: u64,
+    },
+    /// The instruction at `pc` touched a watched array. It has completed,
+    /// so the machine's pc already points at the next instruction.
+    /// `offset` is `None` for accesses to the array as a whole.
+    Watch {
+        pc: u32,
+        array: u32,
+        offset: Option<u32>,
+        access: Access,
+    },
+}
+
+/// Stops a machine running on another thread: [`Machine::run_until_stop`]
+/// returns [`Stop::Interrupted`] soon after [`Interrupter::interrupt`] is
+/// called, or the next time it runs if it was not running. [`Machine::run`]
+/// does not check for interrupts, so one sent while it runs is left for the
+/// next [`Machine::run_until_stop`].
+#[derive(Clone, Debug, Default)]
+pub struct Interrupter(Arc<AtomicBool>);
+
+impl Interrupter {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Clears a pending interrupt, returning whether there was one: for
+    /// when the machine stopped for another reason first.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+pub(super) const READ: u8 = 1;
+pub(super) const WRITE: u8 = 2;
+pub(super) const LIFECYCLE: u8 = 4;
+// Set on arrays with at least one watched cell, so other arrays skip the
+// cell lookup.
+pub(super) const CELLS: u8 = 8;
+const ARRAY: u8 = READ | WRITE | LIFECYCLE;
+
+fn flags(watch: Watch) -> u8 {
+    match watch {
+        Watch::Read => READ,
+        Watch::Write => WRITE,
+        Watch::ReadWrite => READ | WRITE,
+    }
+}
+
+fn watch(flags: u8) -> Watch {
+    match flags & (READ | WRITE) {
+        READ => Watch::Read,
+        WRITE => Watch::Write,
+        _ => Watch::ReadWrite,
+    }
+}
+
+impl Machine {
+    /// Stops [`Machine::run_until_stop`] after each instruction that
+    /// accesses `array` as described by `watch`. Watches belong to the
+    /// array identifier, so they survive abandonment and reallocation.
+    pub fn watch_array(&mut self, array: u32, watch: Watch) {
+        let slot = self.watch_flags(array);
+        *slot = *slot & CELLS | flags(watch) | LIFECYCLE;
+    }
+
+    pub fn unwatch_array(&mut self, array: u32) {
+        if let Some(flags) = self.watches.get_mut(array as usize) {
+            *flags &= !ARRAY;
+        }
+        self.trim_watches();
+    }
+
+    /// Stops [`Machine::run_until_stop`] after each Array Index or Array
+    /// Amendment of platter `offset` of `array`, as described by `watch`.
+    pub fn watch_cell(&mut self, array: u32, offset: u32, watch: Watch) {
+        *self.watch_flags(array) |= CELLS;
+        self.cell_watches.insert((array, offset), flags(watch));
+    }
+
+    pub fn unwatch_cell(&mut self, array: u32, offset: u32) {
+        self.cell_watches.remove(&(array, offset));
+        let others = self
+            .cell_watches
+            .range((array, 0)..=(array, u32::MAX))
+            .next()
+            .is_some();
+        if !others {
+            if let Some(flags) = self.watches.get_mut(array as usize) {
+                *flags &= !CELLS;
+            }
+            self.trim_watches();
+        }
+    }
+
+    /// Lists the watched cells as `(array, offset, watch)`, in order.
+    pub fn watched_cells(&self) -> Vec<(u32, u32, Watch)> {
+        self.cell_watches
+            .iter()
+            .map(|((array, offset), flags)| (*array, *offset, watch(*flags)))
+            .collect()
+    }
+
+    fn watch_flags(&mut self, array: u32) -> &mut u8 {
+        let idx = array as usize;
+        if self.watches.len() <= idx {
+            self.watches.resize(idx + 1, 0);
+        }
+        &mut self.watches[idx]
+    }
+
+    fn trim_watches(&mut self) {
+        while self.watches.last() == Some(&0) {
+            self.watches.pop();
+        }
+    }
+
+    /// Lists the watched arrays in identifier order.
+    pub fn watched_arrays(&self) -> Vec<(u32, Watch)> {
+        self.watches
+            .iter()
+            .enumerate()
+            .filter(|(_, flags)| *flags & ARRAY != 0)
+            .map(|(idx, flags)| (idx as u32, watch(*flags)))
+            .collect()
+    }
+
+    /// Makes [`Machine::run_until_stop`] and [`Machine::step`] return
+    /// [`Stop::StepLimit`] instead of executing more than `limit`
+    /// instructions in all, counting those already executed. `None`
+    /// removes the limit.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit.unwrap_or(u64::MAX);
+    }
+
+    pub fn step_limit(&self) -> Option<u64> {
+        (self.step_limit != u64::MAX).then_some(self.step_limit)
+    }
+
+    /// Makes [`Machine::run_until_stop`] return [`Stop::Timeout`] once
+    /// `deadline` has passed. The clock is checked every 65536
+    /// instructions, and not while Input waits for stdin. `None` removes
+    /// the deadline. Needs the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    #[cfg(feature = "std")]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // What the run loops check every 65536 instructions, except under
+    // `run`.
+    pub(super) fn poll_stop(&self) -> Option<Stop> {
+        if !self.polling {
+            return None;
+        }
+        if self.interrupter.take() {
+            return Some(Stop::Interrupted { pc: self.pc });
+        }
+        #[cfg(feature = "std")]
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Some(Stop::Timeout {
+                pc: self.pc,
+                executed: self.executed,
+            });
+        }
+        None
+    }
+
+    /// Returns a handle for interrupting this machine from another thread.
+    pub fn interrupter(&self) -> Interrupter {
+        self.interrupter.clone()
+    }
+
+    /// Stops [`Machine::run_until_stop`] before executing the instruction
+    /// at `pc`.
+    pub fn add_breakpoint(&mut self, pc: u32) {
+        self.breakpoints.insert(pc);
+    }
+
+    /// Returns whether there was a breakpoint at `pc`.
+    pub fn remove_breakpoint(&mut self, pc: u32) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    /// Lists the breakpoints in address order.
+    pub fn breakpoints(&self) -> Vec<u32> {
+        self.breakpoints.iter().copied().collect()
+    }
+
+    /// Stops [`Machine::run_until_stop`] before any instruction for which
+    /// `condition`, called with the pc and the registers, returns true.
+    /// It is called before every instruction, so it should be cheap; it
+    /// may keep state, for example to stop when a register changes.
+    /// Returns an identifier for [`Stop::Condition`] and
+    /// [`Machine::remove_condition`].
+    pub fn add_condition(
+        &mut self,
+        condition: impl FnMut(u32, &[u32; 8]) -> bool + Send + 'static,
+    ) -> u32 {
+        let id = self.next_condition;
+        self.next_condition += 1;
+        self.conditions.push((id, Box::new(condition)));
+        id
+    }
+
+    /// Returns whether there was a condition `id`.
+    pub fn remove_condition(&mut self, id: u32) -> bool {
+        let before = self.conditions.len();
+        self.conditions.retain(|(other, _)| *other != id);
+        self.conditions.len() != before
+    }
+
+    /// Lists the identifiers of the conditions, oldest first.
+    pub fn conditions(&self) -> Vec<u32> {
+        self.conditions.iter().map(|(id, _)| *id).collect()
+    }
+
+    // Calls every condition, returning the first that held.
+    pub(super) fn check_conditions(&mut self, pc: u32) -> Option<u32> {
+        let mut met = None;
+        for (id, condition) in &mut self.conditions {
+            if condition(pc, &self.registers) && met.is_none() {
+                met = Some(*id);
+            }
+        }
+        met
+    }
+
+    #[inline(always)]
+    pub(super) fn watched(&self, array: u32, flag: u8) -> bool {
+        self.watches
+            .get(array as usize)
+            .is_some_and(|flags| flags & flag != 0)
+    }
+
+    // Whether an access to platter `offset` of `array` should stop: either
+    // the whole array or that cell is watched for `flag`.
+    #[inline(always)]
+    pub(super) fn watched_at(&self, array: u32, offset: u32, flag: u8) -> bool {
+        let Some(flags) = self.watches.get(array as usize) else {
+            return false;
+        };
+        flags & flag != 0
+            || flags & CELLS != 0
+                && self
+                    .cell_watches
+                    .get(&(array, offset))
+                    .is_some_and(|cell| cell & flag != 0)
+    }
+}
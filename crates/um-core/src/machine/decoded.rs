@@ -0,0 +1,66 @@
+//! Array 0 decoded ahead of time, for [`Backend::Decoded`](super::Backend).
+//!
+//! The decoded program is thrown away whenever array 0 is replaced or
+//! changed from outside, and decoded again the next time the run loop
+//! fetches from it. An Amendment of array 0 decodes the one platter again.
+
+use super::Machine;
+use crate::{instruction, Error};
+
+// One platter split into its fields. For Orthography `a` is the register
+// and `b` the value.
+#[derive(Clone, Copy)]
+pub(super) struct Decoded {
+    pub inst: u32,
+    pub b: u32,
+    pub op: u8,
+    pub a: u8,
+    pub c: u8,
+}
+
+impl Decoded {
+    fn new(inst: u32) -> Self {
+        let (op, a, b, c) = instruction::fields(inst);
+        Self {
+            inst,
+            b,
+            op: op as u8,
+            a: a as u8,
+            c: c as u8,
+        }
+    }
+}
+
+impl Machine {
+    #[inline(always)]
+    pub(super) fn fetch_decoded(&mut self) -> Result<Decoded, Error> {
+        match self.decoded.get(self.pc as usize) {
+            Some(d) => Ok(*d),
+            None => self.decode_program(),
+        }
+    }
+
+    // Decodes array 0 if it has not been since it changed, then fetches the
+    // instruction at pc, failing as a plain fetch would.
+    #[cold]
+    fn decode_program(&mut self) -> Result<Decoded, Error> {
+        if let Some(program) = self.arrays.get(0) {
+            if self.decoded.len() != program.len() {
+                self.decoded.clear();
+                self.decoded
+                    .extend(program.iter().map(|inst| Decoded::new(*inst)));
+            }
+        }
+        match self.decoded.get(self.pc as usize) {
+            Some(d) => Ok(*d),
+            None => self.read_value(0, self.pc).map(Decoded::new),
+        }
+    }
+
+    pub(super) fn redecode(&mut self, offset: u32) {
+        let inst = self.read_value(0, offset);
+        if let (Some(d), Ok(inst)) = (self.decoded.get_mut(offset as usize), inst) {
+            *d = Decoded::new(inst);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+/// Something a program did, sent to the channel given to
+/// [`MachineBuilder::events`](crate::MachineBuilder::events) as the
+/// instruction that did it completes. `pc` is that instruction's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MachineEvent {
+    Allocated {
+        pc: u32,
+        array: u32,
+        platters: u32,
+    },
+    Abandoned {
+        pc: u32,
+        array: u32,
+    },
+    /// A Load Program that replaced array 0 with a copy of `array`; one
+    /// that only jumps within array 0 is not an event.
+    Loaded {
+        pc: u32,
+        array: u32,
+        entry: u32,
+    },
+    Output {
+        pc: u32,
+        byte: u8,
+    },
+    /// Input consumed, or None at the end of the input.
+    Input {
+        pc: u32,
+        byte: Option<u8>,
+    },
+}
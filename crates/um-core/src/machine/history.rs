@@ -0,0 +1,329 @@
+use alloc::{boxed::Box, collections::VecDeque, format, string::ToString, vec::Vec};
+use core::mem;
+
+use super::{slab::Slab, ArrayCache, Machine, Stop};
+use crate::{
+    io::{self, Write},
+    output::{FlushPolicy, SpanWriter},
+    Error,
+};
+
+// What Machine::rewind needs to reconstruct earlier states: copies of the
+// machine taken every `interval` instructions, sharing array storage with it
+// until either side amends the array, and the input consumed since
+// the oldest copy, which replaying must consume again.
+pub(super) struct History {
+    interval: u64,
+    keep: usize,
+    checkpoints: VecDeque<Checkpoint>,
+    // Each value with the instruction count of the Input that consumed it,
+    // whether it came from the queue or from stdin.
+    input: VecDeque<(u64, u8)>,
+    // The result of each Host Call with its instruction count. A checkpoint
+    // is taken after every Host Call, since replaying can only restore
+    // the result, not what else the call did.
+    #[cfg(feature = "extensions")]
+    host_calls: VecDeque<(u64, u32)>,
+}
+
+struct Checkpoint {
+    executed: u64,
+    pc: u32,
+    registers: [u32; 8],
+    arrays: Slab,
+}
+
+impl History {
+    pub(super) fn log_input(&mut self, executed: u64, ch: u8) {
+        self.input.push_back((executed, ch));
+    }
+
+    // The input consumed by instructions start..end.
+    fn input_between(&self, start: u64, end: u64) -> VecDeque<u8> {
+        self.input
+            .iter()
+            .filter(|(n, _)| (start..end).contains(n))
+            .map(|(_, ch)| *ch)
+            .collect()
+    }
+
+    #[cfg(feature = "extensions")]
+    pub(super) fn log_host_call(&mut self, executed: u64, result: u32) {
+        self.host_calls.push_back((executed, result));
+    }
+
+    // The results of the Host Calls among instructions start..end.
+    #[cfg(feature = "extensions")]
+    fn host_calls_between(&self, start: u64, end: u64) -> VecDeque<u32> {
+        self.host_calls
+            .iter()
+            .filter(|(n, _)| (start..end).contains(n))
+            .map(|(_, result)| *result)
+            .collect()
+    }
+}
+
+impl Machine {
+    /// The number of instructions executed so far.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Starts keeping the history [`Machine::rewind`] needs: a copy of the
+    /// machine's state every `interval` instructions, at most `keep` of them,
+    /// and everything read from stdin since the oldest. Copies are taken
+    /// by [`Machine::run_until_stop`] and [`Machine::step`];
+    /// [`Machine::run`] ignores history.
+    pub fn record_history(&mut self, interval: u64, keep: usize) {
+        self.history = Some(History {
+            interval: interval.max(1),
+            keep: keep.max(1),
+            checkpoints: VecDeque::new(),
+            input: VecDeque::new(),
+            #[cfg(feature = "extensions")]
+            host_calls: VecDeque::new(),
+        });
+        self.checkpoint();
+    }
+
+    /// Stops keeping history and frees what was kept.
+    pub fn forget_history(&mut self) {
+        self.history = None;
+        self.next_checkpoint = u64::MAX;
+    }
+
+    /// The earliest instruction count [`Machine::rewind`] can go back to,
+    /// or `None` without history.
+    pub fn history_start(&self) -> Option<u64> {
+        Some(self.history.as_ref()?.checkpoints.front()?.executed)
+    }
+
+    /// Puts the machine back into its state after `executed` instructions,
+    /// by restoring the nearest earlier checkpoint and running forward from
+    /// it with output discarded, feeding it the input it consumed the first
+    /// time. Input consumed after that point is queued again, so the program
+    /// sees the same input if it runs on. Host Calls are not made again;
+    /// those run forward over give the results they gave the first time.
+    pub fn rewind(&mut self, executed: u64) -> Result<(), Error> {
+        self.pending_checkpoint();
+        let Some(history) = self.history.as_mut() else {
+            return Err(Error::InvalidArgument(
+                "no history is being recorded".to_string(),
+            ));
+        };
+        if executed > self.executed {
+            return Err(Error::InvalidArgument(format!(
+                "cannot rewind forward to instruction {executed}"
+            )));
+        }
+        let Some(idx) = history
+            .checkpoints
+            .iter()
+            .rposition(|c| c.executed <= executed)
+        else {
+            return Err(Error::InvalidArgument(format!(
+                "instruction {executed} is before the oldest checkpoint"
+            )));
+        };
+        history.checkpoints.truncate(idx + 1);
+        let mut pending = history.input_between(executed, u64::MAX);
+        history.input.retain(|(n, _)| *n < executed);
+        #[cfg(feature = "extensions")]
+        history.host_calls.retain(|(n, _)| *n < executed);
+        self.replay(idx, executed, false)?;
+        pending.append(&mut self.input);
+        self.input = pending;
+        Ok(())
+    }
+
+    /// Rewinds to the last point before now at which
+    /// [`Machine::run_until_stop`] stopped or would have stopped for a
+    /// breakpoint or watch, returning that stop. Conditions are not
+    /// considered. Without such a point, rewinds as far as the history
+    /// goes and returns `None`.
+    pub fn reverse_continue(&mut self) -> Result<Option<Stop>, Error> {
+        self.pending_checkpoint();
+        let Some(history) = self.history.as_ref() else {
+            return Err(Error::InvalidArgument(
+                "no history is being recorded".to_string(),
+            ));
+        };
+        let now = self.executed;
+        let starts: Vec<u64> = history.checkpoints.iter().map(|c| c.executed).collect();
+        for idx in (0..starts.len()).rev() {
+            let end = starts.get(idx + 1).copied().unwrap_or(now);
+            let stops = self.replay(idx, end, true)?;
+            if let Some((executed, stop)) = stops.into_iter().rev().find(|(n, _)| *n < now) {
+                self.rewind(executed)?;
+                if let Stop::Breakpoint { pc } = stop {
+                    self.checked = Some((executed, pc));
+                }
+                return Ok(Some(stop));
+            }
+        }
+        self.rewind(starts[0])?;
+        Ok(None)
+    }
+
+    // Called whenever the state is changed from outside, so that the next
+    // checkpoint records the change for replays to start from.
+    pub(super) fn touched(&mut self) {
+        if self.history.is_some() {
+            self.next_checkpoint = 0;
+        }
+    }
+
+    // Takes the checkpoint the run loop would take before the next
+    // instruction, so that rewinding keeps changes made since the last one.
+    fn pending_checkpoint(&mut self) {
+        if self.executed >= self.next_checkpoint {
+            self.checkpoint();
+        }
+    }
+
+    pub(super) fn checkpoint(&mut self) {
+        let Some(history) = self.history.as_mut() else {
+            self.next_checkpoint = u64::MAX;
+            return;
+        };
+        let checkpoint = Checkpoint {
+            executed: self.executed,
+            pc: self.pc,
+            registers: self.registers,
+            arrays: self.arrays.clone(),
+        };
+        // The checkpoint now shares the storage the amend cache points at.
+        self.amend_cache = ArrayCache::default();
+        // A change made between instructions replaces the checkpoint taken
+        // before it at the same count.
+        if history
+            .checkpoints
+            .back()
+            .is_some_and(|c| c.executed == self.executed)
+        {
+            history.checkpoints.pop_back();
+        }
+        history.checkpoints.push_back(checkpoint);
+        if history.checkpoints.len() > history.keep {
+            history.checkpoints.pop_front();
+            let oldest = history.checkpoints[0].executed;
+            while history.input.front().is_some_and(|(n, _)| *n < oldest) {
+                history.input.pop_front();
+            }
+            #[cfg(feature = "extensions")]
+            while history.host_calls.front().is_some_and(|(n, _)| *n < oldest) {
+                history.host_calls.pop_front();
+            }
+        }
+        self.next_checkpoint = self.executed + history.interval;
+    }
+
+    // Restores checkpoint `idx` and steps to instruction count `end`,
+    // returning where run_until_stop would have stopped along the way if
+    // `stops` is set. The input is replaced by what those instructions
+    // consumed and Host Calls give the results they gave, and output,
+    // hooks, observers, events, the trace, the journal, statistics, the
+    // profile, the allocation audit, memory counters, conditions and
+    // history are set aside; all are put back afterwards.
+    fn replay(&mut self, idx: usize, end: u64, stops: bool) -> Result<Vec<(u64, Stop)>, Error> {
+        self.stdout.flush()?;
+        if let Some(tee) = self.tee.as_mut() {
+            tee.flush()?;
+        }
+        let history = self.history.take().expect("replay needs history");
+        let checkpoint = &history.checkpoints[idx];
+        self.executed = checkpoint.executed;
+        self.pc = checkpoint.pc;
+        self.registers = checkpoint.registers;
+        self.arrays.clone_from(&checkpoint.arrays);
+        // The cached pointers and decoded program refer to the storage just
+        // replaced.
+        self.index_cache = ArrayCache::default();
+        self.amend_cache = ArrayCache::default();
+        self.decoded.clear();
+
+        let input = mem::replace(
+            &mut self.input,
+            history.input_between(checkpoint.executed, end),
+        );
+        #[cfg(feature = "extensions")]
+        {
+            self.replayed_host_calls = Some(history.host_calls_between(checkpoint.executed, end));
+        }
+        let stdin = mem::replace(&mut self.stdin, io::BufReader::new(Box::new(io::empty())));
+        let sink: Box<dyn Write + Send> = Box::new(io::sink());
+        let stdout = mem::replace(&mut self.stdout, SpanWriter::new(sink, FlushPolicy::Halt));
+        let tee = self.tee.take();
+        let load_program_hook = self.load_program_hook.take();
+        let instruction_hook = self.instruction_hook.take();
+        let observers = mem::take(&mut self.observers);
+        #[cfg(feature = "std")]
+        let events = self.events.take();
+        let trace = self.trace.take();
+        let journal = self.journal.take();
+        let replay = self.replay.take();
+        let stats = mem::take(&mut self.stats);
+        let profile = mem::take(&mut self.profile);
+        let audit = mem::take(&mut self.audit);
+        // Replaying records the instructions since the checkpoint again.
+        self.recent.clear();
+        let memory = self.memory;
+        let conditions = mem::take(&mut self.conditions);
+        let step_limit = mem::replace(&mut self.step_limit, u64::MAX);
+        #[cfg(feature = "std")]
+        let deadline = self.deadline.take();
+        self.next_checkpoint = u64::MAX;
+
+        let res = self.replay_steps(end, stops);
+
+        self.input = input;
+        #[cfg(feature = "extensions")]
+        {
+            self.replayed_host_calls = None;
+        }
+        self.stdin = stdin;
+        self.stdout = stdout;
+        self.tee = tee;
+        self.load_program_hook = load_program_hook;
+        self.instruction_hook = instruction_hook;
+        self.observers = observers;
+        #[cfg(feature = "std")]
+        {
+            self.events = events;
+        }
+        self.trace = trace;
+        self.journal = journal;
+        self.replay = replay;
+        self.stats = stats;
+        self.profile = profile;
+        self.audit = audit;
+        self.memory = memory;
+        self.recount_memory();
+        self.conditions = conditions;
+        self.step_limit = step_limit;
+        #[cfg(feature = "std")]
+        {
+            self.deadline = deadline;
+        }
+        self.next_checkpoint = history.checkpoints[idx].executed + history.interval;
+        self.history = Some(history);
+        res
+    }
+
+    fn replay_steps(&mut self, end: u64, stops: bool) -> Result<Vec<(u64, Stop)>, Error> {
+        let mut found = Vec::new();
+        while self.executed < end {
+            let pc = self.pc;
+            if stops && self.breakpoints.contains(&pc) {
+                found.push((self.executed, Stop::Breakpoint { pc }));
+            }
+            match self.step()? {
+                Stop::Halt => break,
+                Stop::Watch { .. } if !stops => {}
+                Stop::Step => {}
+                stop => found.push((self.executed, stop)),
+            }
+        }
+        Ok(found)
+    }
+}
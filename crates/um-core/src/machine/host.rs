@@ -0,0 +1,160 @@
+//! Host Call, opcode 14, for machines with host calls registered with
+//! [`MachineBuilder::host_call`](crate::MachineBuilder::host_call) or
+//! built in with [`MachineBuilder::extensions`](crate::MachineBuilder::extensions).
+//!
+//! Register B selects the service. Register A holds its argument and
+//! receives its result, and register C holds a second argument where one
+//! is needed. The built-in services are:
+//!
+//! ```text
+//! 0  time        A = seconds since the Unix epoch, modulo 2^32
+//! 1  random      A = a random platter
+//! 2  read file   A = a new array holding the file named by array A, one
+//!                    byte per platter, or !0 if it can't be read
+//! 3  write file  writes the low byte of each platter in array C to the
+//!                file named by array A; A = 0, or !0 if it can't be written
+//! ```
+//!
+//! File names are UTF-8, one byte per platter. [`Machine::rewind`] doesn't
+//! call services again: Host Calls it runs forward over give the results
+//! they gave the first time. Journals don't record them, so a replayed
+//! journal calls them again.
+
+use std::{
+    collections::BTreeMap,
+    hash::{BuildHasher, RandomState},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::Machine;
+use crate::Error;
+
+/// A service programs ask for with Host Call, registered under its
+/// service number with [`MachineBuilder::host_call`](crate::MachineBuilder::host_call).
+///
+/// Closures taking the same arguments as [`HostCall::call`] are host calls.
+pub trait HostCall: Send {
+    /// Carries out the call, with `a` and `c` the values of registers A and
+    /// C, returning the value for register A. The machine's pc is still on
+    /// the Host Call. An error stops the run with it, leaving the pc there.
+    ///
+    /// Each Host Call executed calls this once. [`Machine::rewind`] doesn't
+    /// call it again for the calls it runs forward over, using the result
+    /// returned the first time, and the machine's state afterwards is
+    /// restored from a copy. Replaying a journal with
+    /// [`MachineBuilder::replay_journal`](crate::MachineBuilder::replay_journal)
+    /// runs the program again, and calls this again, side effects and all.
+    fn call(&mut self, machine: &mut Machine, a: u32, c: u32) -> Result<u32, Error>;
+}
+
+impl<F> HostCall for F
+where
+    F: FnMut(&mut Machine, u32, u32) -> Result<u32, Error> + Send,
+{
+    fn call(&mut self, machine: &mut Machine, a: u32, c: u32) -> Result<u32, Error> {
+        self(machine, a, c)
+    }
+}
+
+pub(super) type HostCalls = BTreeMap<u32, Box<dyn HostCall>>;
+
+// The services `extensions` adds, under their numbers.
+pub(super) fn built_in() -> [(u32, Box<dyn HostCall>); 4] {
+    [
+        (0, Box::new(time)),
+        (1, Box::new(Random(0))),
+        (2, Box::new(read_file)),
+        (3, Box::new(write_file)),
+    ]
+}
+
+impl Machine {
+    // Carries out the service in register `b`, leaving the pc alone. The
+    // call is taken out of the registry while it runs, since it gets the
+    // machine. Replaying history reuses the result recorded the first
+    // time instead.
+    pub(super) fn host_call(&mut self, inst: u32, a: u32, b: u32, c: u32) -> Result<(), Error> {
+        if let Some(replayed) = self.replayed_host_calls.as_mut() {
+            if let Some(result) = replayed.pop_front() {
+                self.write_reg(a, result);
+                return Ok(());
+            }
+        }
+        let service = self.read_reg(b);
+        let Some(mut call) = self.host_calls.remove(&service) else {
+            return Err(Error::UnknownHostCall {
+                pc: self.pc,
+                inst,
+                service,
+            });
+        };
+        let (arg_a, arg_c) = (self.read_reg(a), self.read_reg(c));
+        let res = call.call(self, arg_a, arg_c);
+        self.host_calls.insert(service, call);
+        let result = res?;
+        self.write_reg(a, result);
+        if let Some(history) = self.history.as_mut() {
+            history.log_host_call(self.executed, result);
+        }
+        self.touched();
+        Ok(())
+    }
+
+    // The file named by `array`, if it is active and holds UTF-8 bytes.
+    fn file_name(&self, array: u32) -> Option<String> {
+        let name = self.arrays.get(array)?;
+        let bytes = name
+            .iter()
+            .map(|&platter| u8::try_from(platter).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        String::from_utf8(bytes).ok()
+    }
+}
+
+fn time(_: &mut Machine, _: u32, _: u32) -> Result<u32, Error> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32))
+}
+
+// Xorshift, seeded on first use by std's per-process random keys.
+struct Random(u64);
+
+impl HostCall for Random {
+    fn call(&mut self, _: &mut Machine, _: u32, _: u32) -> Result<u32, Error> {
+        if self.0 == 0 {
+            self.0 = RandomState::new().hash_one(SystemTime::now()) | 1;
+        }
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        Ok((self.0 >> 32) as u32)
+    }
+}
+
+fn read_file(machine: &mut Machine, name: u32, _: u32) -> Result<u32, Error> {
+    let Some(bytes) = machine
+        .file_name(name)
+        .and_then(|name| std::fs::read(name).ok())
+    else {
+        return Ok(!0);
+    };
+    let len = u32::try_from(bytes.len()).unwrap_or(u32::MAX);
+    let array = machine.allocate_array(len)?;
+    let platters = machine.array_mut(array).unwrap();
+    for (platter, byte) in platters.iter_mut().zip(bytes) {
+        *platter = byte as u32;
+    }
+    Ok(array)
+}
+
+fn write_file(machine: &mut Machine, name: u32, data: u32) -> Result<u32, Error> {
+    let bytes: Option<Vec<u8>> = machine
+        .array(data)
+        .map(|data| data.iter().map(|&platter| platter as u8).collect());
+    let written = machine
+        .file_name(name)
+        .zip(bytes)
+        .is_some_and(|(name, bytes)| std::fs::write(name, bytes).is_ok());
+    Ok(if written { 0 } else { !0 })
+}
@@ -0,0 +1,180 @@
+//! Input journals: every value Input consumes, with the instruction count
+//! it was consumed at, so that a run can be repeated exactly, and the
+//! output the run wrote, to check a repeat against.
+//!
+//! A journal is text: a `um-32 journal` header line, then a line for each
+//! Input with the number of instructions executed before it and the byte,
+//! in decimal, or `eof` for the end of input. The output written between
+//! them is on lines of its own, `out` and up to 32 bytes in hex. A run
+//! rewound with [`Machine::rewind`] records the input it consumes again,
+//! and those entries replace the ones from the instructions it went back
+//! over; the output is kept as it was written, with the output of the
+//! instructions executed again repeated.
+
+use alloc::{boxed::Box, collections::VecDeque, format, vec::Vec};
+
+use super::Machine;
+use crate::{
+    io::{self, BufRead, BufWriter, Write},
+    Error,
+};
+
+const HEADER: &str = "um-32 journal";
+
+/// The input a run consumed and the output it wrote, recorded with
+/// [`MachineBuilder::record_journal`](crate::MachineBuilder::record_journal),
+/// for [`MachineBuilder::replay_journal`](crate::MachineBuilder::replay_journal)
+/// to feed to another run of the same program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Journal {
+    // The instruction count of each Input and the byte it consumed, or
+    // None at the end of input.
+    entries: VecDeque<(u64, Option<u8>)>,
+    output: Vec<u8>,
+}
+
+impl Journal {
+    /// Parses a journal.
+    pub fn read(r: impl BufRead) -> Result<Journal, Error> {
+        let mut lines = r.lines();
+        match lines.next().transpose()? {
+            Some(line) if line.trim_end() == HEADER => {}
+            _ => return Err(Error::InvalidArgument("not an input journal".into())),
+        }
+        let mut entries = VecDeque::new();
+        let mut output = Vec::new();
+        for (n, line) in lines.enumerate() {
+            let line = line?;
+            let invalid = || Error::InvalidArgument(format!("journal line {}: {line:?}", n + 2));
+            let Some((executed, value)) = line.trim_end().split_once(' ') else {
+                return Err(invalid());
+            };
+            if executed == "out" {
+                if value.len() % 2 != 0 {
+                    return Err(invalid());
+                }
+                for i in (0..value.len()).step_by(2) {
+                    let byte = value.get(i..i + 2).ok_or_else(invalid)?;
+                    output.push(u8::from_str_radix(byte, 16).map_err(|_| invalid())?);
+                }
+                continue;
+            }
+            let executed = executed.parse().map_err(|_| invalid())?;
+            let value = match value {
+                "eof" => None,
+                value => Some(value.parse().map_err(|_| invalid())?),
+            };
+            while entries.back().is_some_and(|(n, _)| *n >= executed) {
+                entries.pop_back();
+            }
+            entries.push_back((executed, value));
+        }
+        Ok(Journal { entries, output })
+    }
+
+    /// The output the recorded run wrote, without echoed input.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// The number of Inputs recorded.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// Writes the journal as the machine consumes input.
+pub(super) struct Recorder {
+    out: BufWriter<Box<dyn Write + Send>>,
+    // Whether the header has been written.
+    started: bool,
+    // Output not written out yet.
+    output: Vec<u8>,
+}
+
+impl Recorder {
+    pub(super) fn new(out: Box<dyn Write + Send>) -> Self {
+        Recorder {
+            out: BufWriter::new(out),
+            started: false,
+            output: Vec::new(),
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            writeln!(self.out, "{HEADER}")?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    fn write_output(&mut self) -> io::Result<()> {
+        self.start()?;
+        for chunk in self.output.chunks(32) {
+            write!(self.out, "out ")?;
+            for byte in chunk {
+                write!(self.out, "{byte:02x}")?;
+            }
+            writeln!(self.out)?;
+        }
+        self.output.clear();
+        Ok(())
+    }
+
+    fn log(&mut self, executed: u64, value: Option<u8>) -> io::Result<()> {
+        self.write_output()?;
+        match value {
+            Some(byte) => writeln!(self.out, "{executed} {byte}"),
+            None => writeln!(self.out, "{executed} eof"),
+        }
+    }
+
+    pub(super) fn flush(&mut self) -> io::Result<()> {
+        self.write_output()?;
+        self.out.flush()
+    }
+}
+
+// A run that consumed no input still leaves a journal to replay.
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl Machine {
+    // The next value from the journal being replayed, failing if the run
+    // has diverged from the one recorded.
+    pub(super) fn replay_input(&mut self) -> Result<Option<u8>, Error> {
+        let replay = self.replay.as_mut().unwrap();
+        match replay.entries.front() {
+            Some(&(executed, value)) if executed == self.executed => {
+                replay.entries.pop_front();
+                Ok(value)
+            }
+            next => Err(Error::ReplayDiverged {
+                pc: self.pc,
+                executed: self.executed,
+                expected: next.map(|&(executed, _)| executed),
+            }),
+        }
+    }
+
+    pub(super) fn journal_output(&mut self, byte: u8) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.output.push(byte);
+        }
+    }
+
+    pub(super) fn journal_input(&mut self, value: Option<u8>) -> Result<(), Error> {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.log(self.executed, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use crate::io;
+
+/// Watches a machine execute, for tracers, profilers, coverage and the
+/// like, installed with [`MachineBuilder::observe`](crate::MachineBuilder::observe).
+///
+/// Machines without observers pay nothing for them. With one, every
+/// instruction is interpreted, whatever the backend, so that the observer
+/// sees them all. An error from either method stops the run with
+/// [`Error::IO`](crate::Error::IO).
+pub trait Observer: Send {
+    /// Called before the instruction `inst` at `pc` executes, with the
+    /// registers as it finds them.
+    fn before(&mut self, pc: u32, inst: u32, registers: &[u32; 8]) -> io::Result<()> {
+        let _ = (pc, inst, registers);
+        Ok(())
+    }
+
+    /// Called once the instruction `inst` at `pc` has executed, with the
+    /// registers as it left them. Halt, and instructions that fail, such as
+    /// an Input that finds no more input with
+    /// [`MachineBuilder::eof_error`](crate::MachineBuilder::eof_error) set,
+    /// are not followed by a call.
+    fn after(&mut self, pc: u32, inst: u32, registers: &[u32; 8]) -> io::Result<()> {
+        let _ = (pc, inst, registers);
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use alloc::{collections::VecDeque, vec::Vec};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{slab::Slab, Machine};
+
+// Free arrays only keep their buffers around for reuse, so just the
+// identifiers are persisted. Instrumentation counters and array caches are
+// runtime details and start fresh on deserialization.
+#[derive(Serialize)]
+struct StateRef<'a> {
+    pc: u32,
+    registers: &'a [u32; 8],
+    arrays: Vec<Option<&'a [u32]>>,
+    free_arrays: &'a [u32],
+    input: &'a VecDeque<u8>,
+    executed: u64,
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: u32,
+    registers: [u32; 8],
+    arrays: Vec<Option<Vec<u32>>>,
+    free_arrays: Vec<u32>,
+    input: VecDeque<u8>,
+    // Missing from machines serialized before it was kept.
+    #[serde(default)]
+    executed: u64,
+}
+
+impl Serialize for Machine {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        StateRef {
+            pc: self.pc,
+            registers: &self.registers,
+            arrays: self.arrays.slots().collect(),
+            free_arrays: self.arrays.free(),
+            input: &self.input,
+            executed: self.executed,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Machine {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = State::deserialize(deserializer)?;
+        let mut arrays = Slab::from_slots(state.arrays);
+        arrays.set_free(state.free_arrays);
+        let mut machine = Self {
+            pc: state.pc,
+            registers: state.registers,
+            arrays,
+            input: state.input,
+            executed: state.executed,
+            ..Self::default()
+        };
+        machine.validate().map_err(serde::de::Error::custom)?;
+        machine.recount_memory();
+        Ok(machine)
+    }
+}
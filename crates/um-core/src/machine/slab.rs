@@ -0,0 +1,198 @@
+//! Storage for the machine's arrays, indexed by identifier.
+//!
+//! Every identifier ever handed out has a slot, active or free. A free slot
+//! keeps its storage so that the next allocation to reuse the identifier
+//! reuses the storage too, cut down to the new length, and free identifiers
+//! are kept on a stack, so allocating and abandoning are O(1) and
+//! identifiers are reused most-recently-abandoned first.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+
+#[derive(Clone)]
+pub(super) struct Slab {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    // The platters the free slots keep for reuse.
+    retained: u64,
+}
+
+#[derive(Clone)]
+struct Slot {
+    // Shared between array 0 and the array it was loaded from, and with
+    // history checkpoints, until one of them is amended.
+    mem: Arc<Vec<u32>>,
+    active: bool,
+}
+
+impl Default for Slab {
+    // Just an empty array 0.
+    fn default() -> Self {
+        Self {
+            slots: vec![Slot {
+                mem: Arc::default(),
+                active: true,
+            }],
+            free: Vec::new(),
+            retained: 0,
+        }
+    }
+}
+
+impl Slab {
+    // Every slot in order, None for free ones, with no identifiers free;
+    // set_free marks them.
+    pub(super) fn from_slots(slots: impl IntoIterator<Item = Option<Vec<u32>>>) -> Self {
+        Self {
+            slots: slots
+                .into_iter()
+                .map(|mem| Slot {
+                    active: mem.is_some(),
+                    mem: Arc::new(mem.unwrap_or_default()),
+                })
+                .collect(),
+            free: Vec::new(),
+            retained: 0,
+        }
+    }
+
+    // The free identifiers, next to be reused last. Machine::validate checks
+    // they agree with the slots.
+    pub(super) fn set_free(&mut self, free: Vec<u32>) {
+        self.free = free;
+    }
+
+    #[inline(always)]
+    pub(super) fn get(&self, id: u32) -> Option<&Arc<Vec<u32>>> {
+        match self.slots.get(id as usize) {
+            Some(slot) if slot.active => Some(&slot.mem),
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    pub(super) fn get_mut(&mut self, id: u32) -> Option<&mut Arc<Vec<u32>>> {
+        match self.slots.get_mut(id as usize) {
+            Some(slot) if slot.active => Some(&mut slot.mem),
+            _ => None,
+        }
+    }
+
+    // Replaces the storage of array `id`, which must have a slot.
+    pub(super) fn replace(&mut self, id: u32, mem: Arc<Vec<u32>>) {
+        let slot = &mut self.slots[id as usize];
+        slot.mem = mem;
+        slot.active = true;
+    }
+
+    // Every slot in order, None for free ones.
+    pub(super) fn slots(&self) -> impl ExactSizeIterator<Item = Option<&[u32]>> + '_ {
+        self.slots
+            .iter()
+            .map(|slot| slot.active.then_some(slot.mem.as_slice()))
+    }
+
+    pub(super) fn free(&self) -> &[u32] {
+        &self.free
+    }
+
+    pub(super) fn active_count(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    // The platters the free slots keep for reuse.
+    pub(super) fn free_capacity(&self) -> u64 {
+        self.retained
+    }
+
+    // Drops the storage the free slots keep for reuse.
+    pub(super) fn release(&mut self) {
+        for id in &self.free {
+            self.slots[*id as usize].mem = Arc::default();
+        }
+        self.retained = 0;
+    }
+
+    // A zeroed array of `len` platters, reusing the most recently abandoned
+    // identifier and its storage if there is one.
+    pub(super) fn allocate(&mut self, len: usize) -> u32 {
+        let Some(id) = self.free.pop() else {
+            self.slots.push(Slot {
+                mem: Arc::new(vec![0; len]),
+                active: true,
+            });
+            return self.slots.len() as u32 - 1;
+        };
+        let slot = &mut self.slots[id as usize];
+        self.retained -= slot.mem.capacity() as u64;
+        match Arc::get_mut(&mut slot.mem) {
+            Some(mem) => {
+                mem.clear();
+                mem.resize(len, 0);
+                // Otherwise a small array would hold on to a large one's
+                // memory, out of sight of the machine's memory limit.
+                mem.shrink_to(len);
+            }
+            None => slot.mem = Arc::new(vec![0; len]),
+        }
+        slot.active = true;
+        id
+    }
+
+    // Frees array `id`, returning its length, or None if it was not active.
+    pub(super) fn abandon(&mut self, id: u32) -> Option<usize> {
+        let slot = self.slots.get_mut(id as usize).filter(|slot| slot.active)?;
+        slot.active = false;
+        let len = slot.mem.len();
+        // Storage another array still shares stays with it.
+        if Arc::strong_count(&slot.mem) != 1 {
+            slot.mem = Arc::default();
+        }
+        self.retained += slot.mem.capacity() as u64;
+        self.free.push(id);
+        Some(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse_gives_back_extra_storage() {
+        let mut slab = Slab::default();
+        let big = slab.allocate(1 << 20);
+        assert_eq!(slab.abandon(big), Some(1 << 20));
+        assert_eq!(slab.free_capacity(), 1 << 20);
+        assert_eq!(slab.allocate(1), big);
+        assert_eq!(slab.get(big).unwrap().capacity(), 1);
+        assert_eq!(slab.free_capacity(), 0);
+    }
+
+    #[test]
+    fn release_drops_free_storage() {
+        let mut slab = Slab::default();
+        let (a, b) = (slab.allocate(100), slab.allocate(200));
+        slab.abandon(a);
+        slab.abandon(b);
+        assert_eq!(slab.free_capacity(), 300);
+        slab.release();
+        assert_eq!(slab.free_capacity(), 0);
+        // Identifiers are still reused, most recently abandoned first.
+        assert_eq!(slab.allocate(5), b);
+        assert_eq!(slab.get(b).unwrap().as_slice(), &[0; 5]);
+        assert_eq!(slab.allocate(5), a);
+    }
+
+    #[test]
+    fn shared_storage_is_not_reused() {
+        let mut slab = Slab::default();
+        let a = slab.allocate(3);
+        Arc::make_mut(slab.get_mut(a).unwrap())[0] = 7;
+        let shared = slab.get(a).unwrap().clone();
+        slab.abandon(a);
+        assert_eq!(slab.free_capacity(), 0);
+        assert_eq!(slab.allocate(3), a);
+        assert_eq!(slab.get(a).unwrap().as_slice(), &[0; 3]);
+        assert_eq!(shared.as_slice(), &[7, 0, 0]);
+    }
+}
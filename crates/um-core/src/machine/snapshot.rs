@@ -0,0 +1,560 @@
+//! Snapshot file format.
+//!
+//! Every field is a big-endian `u32`, the same byte order used by program
+//! images, unless noted otherwise. A snapshot starts with:
+//!
+//! ```text
+//! magic      8 bytes, "UM32SNAP"
+//! version    3
+//! ```
+//!
+//! followed by sections, each a 4-byte ASCII tag, the payload length in
+//! bytes as a big-endian `u64`, and the payload. Readers skip sections they
+//! don't recognize, so new sections can be added without changing the
+//! version; the version only changes when an existing section changes
+//! meaning.
+//!
+//! ```text
+//! "CPU "     pc, then the 8 registers
+//! "MEM "     slots: number of array identifiers in use, active or free
+//!            for each identifier in order: 0xFFFF_FFFF if it is on the
+//!              free list, otherwise the array length followed by that
+//!              many platters
+//! "FREE"     number of entries on the free list, then the free identifiers
+//!              in free-list order; the last entry is the next one reused
+//!              by an allocation
+//! "INPT"     number of pending input bytes, then each byte as a u32
+//! "EXEC"     number of instructions executed, as a u64; optional, 0 if
+//!              missing
+//! "END "     empty, marks the end of the snapshot
+//! ```
+//!
+//! A core file, written by [`Machine::save_core`] when a program faults, is
+//! a snapshot with two more sections before "END ":
+//!
+//! ```text
+//! "FALT"     the error, as displayed, in UTF-8
+//! "RCNT"     number of instructions, then the pc and platter of each of
+//!              the last instructions executed, oldest first
+//! ```
+//!
+//! Version 2 is the same, except that "INPT" holds pending input
+//! characters as Unicode scalar values. Version 1 has the same fields as
+//! version 2 without section headers, in the order pc, registers, slots,
+//! free list, input. Both are still read, with characters up to 255 taken
+//! as the byte Input would have read and others as their UTF-8 bytes, as
+//! [`Machine::add_input`] queues them now, and no instructions executed;
+//! `um-32 state upgrade` rewrites such files in the current version.
+
+use alloc::{string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use super::{slab::Slab, Machine};
+use crate::{
+    io::{self, Read, Write},
+    Error,
+};
+
+const MAGIC: &[u8; 8] = b"UM32SNAP";
+const FREE_SLOT: u32 = u32::MAX;
+// Version 1 has no section lengths to bound the arrays by, so they are
+// read into buffers reserving at most this many bytes, growing as they
+// fill.
+const V1_RESERVE: u64 = 1 << 20;
+
+const CPU: &[u8; 4] = b"CPU ";
+const MEM: &[u8; 4] = b"MEM ";
+const FREE: &[u8; 4] = b"FREE";
+const INPUT: &[u8; 4] = b"INPT";
+const EXECUTED: &[u8; 4] = b"EXEC";
+const FAULT: &[u8; 4] = b"FALT";
+const RECENT: &[u8; 4] = b"RCNT";
+const END: &[u8; 4] = b"END ";
+
+/// What a core file adds to a snapshot of the machine as it was when it
+/// faulted, for post-mortem debugging.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoreDump {
+    /// The error the machine stopped with, as displayed.
+    pub fault: String,
+    /// The pc and platter of each of the last instructions executed before
+    /// the fault, oldest first.
+    pub recent: Vec<(u32, u32)>,
+}
+
+impl Machine {
+    pub const SNAPSHOT_VERSION: u32 = 3;
+
+    #[cfg(feature = "std")]
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_snapshot(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::read_snapshot(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Reads just the format version of the snapshot at `path`.
+    #[cfg(feature = "std")]
+    pub fn snapshot_version(path: impl AsRef<Path>) -> Result<u32, Error> {
+        read_header(&mut File::open(path)?)
+    }
+
+    pub fn write_snapshot(&self, w: &mut impl Write) -> Result<(), Error> {
+        self.write_sections(w, None)
+    }
+
+    pub fn read_snapshot(r: &mut impl Read) -> Result<Self, Error> {
+        Ok(Self::read_core(r)?.0)
+    }
+
+    /// Saves a snapshot with `core` added, which
+    /// [`Machine::load_snapshot`] loads like any other.
+    #[cfg(feature = "std")]
+    pub fn save_core(&self, path: impl AsRef<Path>, core: &CoreDump) -> Result<(), Error> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_core(&mut w, core)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn write_core(&self, w: &mut impl Write, core: &CoreDump) -> Result<(), Error> {
+        self.write_sections(w, Some(core))
+    }
+
+    /// Loads a snapshot, and what it adds if it is a core file.
+    #[cfg(feature = "std")]
+    pub fn load_core(path: impl AsRef<Path>) -> Result<(Self, Option<CoreDump>), Error> {
+        Self::read_core(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn read_core(r: &mut impl Read) -> Result<(Self, Option<CoreDump>), Error> {
+        let mut machine = Self::default();
+        let core = match read_header(r)? {
+            1 => {
+                machine.read_cpu(r)?;
+                machine.read_mem(r, V1_RESERVE)?;
+                machine.read_free(r)?;
+                machine.read_chars(r)?;
+                None
+            }
+            version @ (2 | 3) => machine.read_sections(r, version)?,
+            _ => return Err(Error::InvalidSnapshot("unsupported version")),
+        };
+        machine.validate().map_err(Error::InvalidSnapshot)?;
+        machine.recount_memory();
+        Ok((machine, core))
+    }
+
+    fn write_sections(&self, w: &mut impl Write, core: Option<&CoreDump>) -> Result<(), Error> {
+        w.write_all(MAGIC)?;
+        write_u32(w, Self::SNAPSHOT_VERSION)?;
+        write_section(w, CPU, |w| self.write_cpu(w))?;
+        write_section(w, MEM, |w| self.write_mem(w))?;
+        write_section(w, FREE, |w| self.write_free(w))?;
+        write_section(w, INPUT, |w| self.write_input(w))?;
+        write_section(w, EXECUTED, |w| {
+            w.extend_from_slice(&self.executed.to_be_bytes());
+            Ok(())
+        })?;
+        if let Some(core) = core {
+            write_section(w, FAULT, |w| {
+                w.extend_from_slice(core.fault.as_bytes());
+                Ok(())
+            })?;
+            write_section(w, RECENT, |w| {
+                write_u32(w, core.recent.len() as u32)?;
+                for (pc, inst) in &core.recent {
+                    write_u32(w, *pc)?;
+                    write_u32(w, *inst)?;
+                }
+                Ok(())
+            })?;
+        }
+        write_section(w, END, |_| Ok(()))?;
+        Ok(())
+    }
+
+    /// A hash of the state a snapshot saves: the pc, the registers, the
+    /// arrays, the free list and pending input, but not the number of
+    /// instructions executed. It is FNV-1a over the values in a fixed
+    /// order, so it is the same on every platform and release, and two
+    /// machines in the same state hash the same.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = Fnv::default();
+        hash.u32(self.pc);
+        self.registers.iter().for_each(|reg| hash.u32(*reg));
+        hash.u32(self.arrays.slots().len() as u32);
+        for array in self.arrays.slots() {
+            match array {
+                Some(a) => {
+                    hash.u32(a.len() as u32);
+                    a.iter().for_each(|v| hash.u32(*v));
+                }
+                None => hash.u32(FREE_SLOT),
+            }
+        }
+        hash.u32(self.arrays.free().len() as u32);
+        self.arrays.free().iter().for_each(|idx| hash.u32(*idx));
+        hash.u32(self.input.len() as u32);
+        self.input.iter().for_each(|ch| hash.u32(*ch as u32));
+        hash.0
+    }
+
+    // Returns the core dump if there is a FALT section.
+    fn read_sections(
+        &mut self,
+        r: &mut impl Read,
+        version: u32,
+    ) -> Result<Option<CoreDump>, Error> {
+        let mut fault = None;
+        let mut recent = Vec::new();
+        let mut seen = [false; 4];
+        loop {
+            let mut tag = [0; 4];
+            r.read_exact(&mut tag)?;
+            let mut len = [0; 8];
+            r.read_exact(&mut len)?;
+            let mut section = r.take(u64::from_be_bytes(len));
+            let known = [CPU, MEM, FREE, INPUT].iter().position(|t| **t == tag);
+            match &tag {
+                CPU => self.read_cpu(&mut section)?,
+                MEM => {
+                    let limit = section.limit();
+                    self.read_mem(&mut section, limit)?
+                }
+                FREE => self.read_free(&mut section)?,
+                INPUT if version == 2 => self.read_chars(&mut section)?,
+                INPUT => self.read_input(&mut section)?,
+                EXECUTED => {
+                    let mut executed = [0; 8];
+                    section.read_exact(&mut executed)?;
+                    self.executed = u64::from_be_bytes(executed);
+                }
+                FAULT => {
+                    let mut text = String::new();
+                    section
+                        .read_to_string(&mut text)
+                        .map_err(|_| Error::InvalidSnapshot("invalid fault"))?;
+                    fault = Some(text);
+                }
+                RECENT => {
+                    recent.clear();
+                    for _ in 0..read_u32(&mut section)? {
+                        recent.push((read_u32(&mut section)?, read_u32(&mut section)?));
+                    }
+                }
+                END => break,
+                _ => {
+                    io::copy(&mut section, &mut io::sink())?;
+                }
+            }
+            if section.limit() != 0 {
+                return Err(Error::InvalidSnapshot("section length mismatch"));
+            }
+            if let Some(idx) = known {
+                seen[idx] = true;
+            }
+        }
+        if seen.contains(&false) {
+            return Err(Error::InvalidSnapshot("missing section"));
+        }
+        Ok(fault.map(|fault| CoreDump { fault, recent }))
+    }
+
+    fn write_cpu(&self, w: &mut impl Write) -> Result<(), Error> {
+        write_u32(w, self.pc)?;
+        for reg in self.registers {
+            write_u32(w, reg)?;
+        }
+        Ok(())
+    }
+
+    fn read_cpu(&mut self, r: &mut impl Read) -> Result<(), Error> {
+        self.pc = read_u32(r)?;
+        for reg in self.registers.iter_mut() {
+            *reg = read_u32(r)?;
+        }
+        Ok(())
+    }
+
+    fn write_mem(&self, w: &mut impl Write) -> Result<(), Error> {
+        write_u32(w, self.arrays.slots().len() as u32)?;
+        for array in self.arrays.slots() {
+            match array {
+                Some(a) => {
+                    write_u32(w, a.len() as u32)?;
+                    for v in a.iter() {
+                        write_u32(w, *v)?;
+                    }
+                }
+                None => write_u32(w, FREE_SLOT)?,
+            }
+        }
+        Ok(())
+    }
+
+    // `limit` is the most bytes the arrays can take up, which keeps a
+    // corrupt length from reserving more memory than the file could fill.
+    fn read_mem(&mut self, r: &mut impl Read, limit: u64) -> Result<(), Error> {
+        let mut slots = Vec::new();
+        for _ in 0..read_u32(r)? {
+            let len = read_u32(r)?;
+            if len == FREE_SLOT {
+                slots.push(None);
+                continue;
+            }
+            let mut a = Vec::with_capacity(u64::from(len).min(limit / 4) as usize);
+            for _ in 0..len {
+                a.push(read_u32(r)?);
+            }
+            slots.push(Some(a));
+        }
+        // Keeps a FREE section read before this one.
+        let free = self.arrays.free().to_vec();
+        self.arrays = Slab::from_slots(slots);
+        self.arrays.set_free(free);
+        Ok(())
+    }
+
+    fn write_free(&self, w: &mut impl Write) -> Result<(), Error> {
+        write_u32(w, self.arrays.free().len() as u32)?;
+        for idx in self.arrays.free() {
+            write_u32(w, *idx)?;
+        }
+        Ok(())
+    }
+
+    fn read_free(&mut self, r: &mut impl Read) -> Result<(), Error> {
+        let mut free = Vec::new();
+        for _ in 0..read_u32(r)? {
+            free.push(read_u32(r)?);
+        }
+        self.arrays.set_free(free);
+        Ok(())
+    }
+
+    fn write_input(&self, w: &mut impl Write) -> Result<(), Error> {
+        write_u32(w, self.input.len() as u32)?;
+        for ch in self.input.iter() {
+            write_u32(w, *ch as u32)?;
+        }
+        Ok(())
+    }
+
+    fn read_input(&mut self, r: &mut impl Read) -> Result<(), Error> {
+        self.input.clear();
+        for _ in 0..read_u32(r)? {
+            let ch = u8::try_from(read_u32(r)?)
+                .map_err(|_| Error::InvalidSnapshot("invalid input byte"))?;
+            self.input.push_back(ch);
+        }
+        Ok(())
+    }
+
+    // Pending input as versions 1 and 2 saved it.
+    fn read_chars(&mut self, r: &mut impl Read) -> Result<(), Error> {
+        self.input.clear();
+        for _ in 0..read_u32(r)? {
+            let ch = read_u32(r)?;
+            match u8::try_from(ch) {
+                Ok(byte) => self.input.push_back(byte),
+                Err(_) => {
+                    let ch = char::from_u32(ch)
+                        .ok_or(Error::InvalidSnapshot("invalid input character"))?;
+                    self.input.extend(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn u32(&mut self, v: u32) {
+        for b in v.to_be_bytes() {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+fn read_header(r: &mut impl Read) -> Result<u32, Error> {
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::InvalidSnapshot("bad magic"));
+    }
+    read_u32(r)
+}
+
+fn write_section<W: Write>(
+    w: &mut W,
+    tag: &[u8; 4],
+    body: impl FnOnce(&mut Vec<u8>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut payload = Vec::new();
+    body(&mut payload)?;
+    w.write_all(tag)?;
+    w.write_all(&(payload.len() as u64).to_be_bytes())?;
+    w.write_all(&payload)?;
+    Ok(())
+}
+
+fn write_u32(w: &mut impl Write, v: u32) -> Result<(), Error> {
+    w.write_all(&v.to_be_bytes())?;
+    Ok(())
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32, Error> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn image(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut machine = Machine::default();
+        // ORTHO r1, 1 ; HALT
+        machine
+            .extend_from(&image(&[0xd200_0001, 0x7000_0000])[..])
+            .unwrap();
+        machine.add_input("abc");
+        machine.registers_mut()[5] = 9;
+        machine.step().unwrap();
+
+        let mut buf = Vec::new();
+        machine.write_snapshot(&mut buf).unwrap();
+        let restored = Machine::read_snapshot(&mut &buf[..]).unwrap();
+        assert_eq!(restored.registers(), machine.registers());
+        assert_eq!(restored.array(0), machine.array(0));
+        assert_eq!(restored.state_hash(), machine.state_hash());
+        assert_eq!(restored.executed(), 1);
+
+        let mut again = Vec::new();
+        restored.write_snapshot(&mut again).unwrap();
+        assert_eq!(buf, again);
+
+        assert!(matches!(
+            Machine::read_snapshot(&mut &b"not a snapshot"[..]),
+            Err(Error::InvalidSnapshot(_))
+        ));
+    }
+
+    #[test]
+    fn old_versions() {
+        // Pending input 'a', U+00E9 and U+20AC as versions 1 and 2 saved it,
+        // one character per u32.
+        let cpu: Vec<u32> = [7].into_iter().chain(1..=8).collect();
+        let mem = [1, 1, 0x7000_0000];
+        let free = [0];
+        let input = [3, 'a' as u32, 0xe9, 0x20ac];
+        let mut v2 = MAGIC.to_vec();
+        v2.extend(image(&[2]));
+        for (tag, words) in [
+            (b"CPU ", &cpu[..]),
+            (b"MEM ", &mem),
+            (b"FREE", &free),
+            (b"INPT", &input),
+            (b"END ", &[]),
+        ] {
+            write_section(&mut v2, tag, |w| {
+                w.extend(image(words));
+                Ok(())
+            })
+            .unwrap();
+        }
+        let mut v1 = MAGIC.to_vec();
+        v1.extend(image(&[1]));
+        v1.extend(image(&[&cpu[..], &mem, &free, &input].concat()));
+
+        let mut expected = Machine::default();
+        expected.set_pc(7);
+        expected.registers_mut().copy_from_slice(&cpu[1..]);
+        expected.extend_from(&image(&[0x7000_0000])[..]).unwrap();
+        // Characters up to 255 are the byte Input read; others are UTF-8.
+        expected.add_input_bytes(&[b'a', 0xe9, 0xe2, 0x82, 0xac]);
+        let mut current = Vec::new();
+        expected.write_snapshot(&mut current).unwrap();
+        assert_eq!(current[8..12], Machine::SNAPSHOT_VERSION.to_be_bytes());
+        for old in [v1, v2] {
+            let machine = Machine::read_snapshot(&mut &old[..]).unwrap();
+            assert_eq!(machine.state_hash(), expected.state_hash());
+            assert_eq!(machine.executed(), 0);
+            // Saving it again writes the current version.
+            let mut buf = Vec::new();
+            machine.write_snapshot(&mut buf).unwrap();
+            assert_eq!(buf, current);
+        }
+    }
+
+    #[test]
+    fn bad_lengths() {
+        // One array claiming nearly 2^32 platters with none following must
+        // fail to read rather than reserve memory for all of them.
+        let cpu = [0; 9];
+        let mem = [1, 0xffff_fffe];
+        let mut v3 = MAGIC.to_vec();
+        v3.extend(image(&[3]));
+        for (tag, words) in [(b"CPU ", &cpu[..]), (b"MEM ", &mem), (b"END ", &[])] {
+            write_section(&mut v3, tag, |w| {
+                w.extend(image(words));
+                Ok(())
+            })
+            .unwrap();
+        }
+        let mut v1 = MAGIC.to_vec();
+        v1.extend(image(&[1]));
+        v1.extend(image(&[&cpu[..], &mem].concat()));
+        for bytes in [v1, v3] {
+            assert!(Machine::read_snapshot(&mut &bytes[..]).is_err());
+        }
+    }
+
+    #[test]
+    fn core_round_trip() {
+        let mut machine = Machine::default();
+        machine.extend_from(&image(&[0x7000_0000])[..]).unwrap();
+        let core = CoreDump {
+            fault: "division by zero, pc=0x0000".into(),
+            recent: vec![(3, 0xd200_0005), (4, 0x5000_004a)],
+        };
+
+        let mut buf = Vec::new();
+        machine.write_core(&mut buf, &core).unwrap();
+        let (restored, read) = Machine::read_core(&mut &buf[..]).unwrap();
+        assert_eq!(read, Some(core));
+        assert_eq!(restored.state_hash(), machine.state_hash());
+        // Core files are snapshots, and snapshots aren't core files.
+        assert!(Machine::read_snapshot(&mut &buf[..]).is_ok());
+        buf.clear();
+        machine.write_snapshot(&mut buf).unwrap();
+        assert_eq!(Machine::read_core(&mut &buf[..]).unwrap().1, None);
+    }
+}
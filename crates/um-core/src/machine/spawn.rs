@@ -0,0 +1,254 @@
+//! A machine running on a thread of its own, with channels for its
+//! console, for front ends that would rather not manage the thread.
+
+use std::{
+    io::{self, BufRead, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{Machine, Stop};
+use crate::{output::SpanWriter, Error, FlushPolicy, Interrupter};
+
+// How often Input waiting for a byte checks for commands.
+const POLL: Duration = Duration::from_millis(10);
+
+/// The handles to a machine started with [`Machine::spawn`].
+pub struct Spawned {
+    /// Console input, a byte at a time; dropping it ends the input.
+    pub input: Sender<u8>,
+    /// Console output, a byte at a time as the program writes it.
+    pub output: Receiver<u8>,
+    pub control: Control,
+}
+
+/// Pauses, resumes, snapshots and stops a machine started with
+/// [`Machine::spawn`], or waits for it to finish.
+pub struct Control {
+    commands: Sender<Command>,
+    // Set with each command, for Input waiting for a byte.
+    pending: Arc<AtomicBool>,
+    interrupter: Interrupter,
+    thread: JoinHandle<(Machine, Result<Stop, Error>)>,
+}
+
+enum Command {
+    Pause,
+    Resume,
+    Snapshot(Sender<Result<Vec<u8>, Error>>),
+    Stop,
+}
+
+impl Machine {
+    /// Runs the machine on a new thread, with its console input and output
+    /// replaced by channels. The program runs until it halts, fails or
+    /// stops for any of the reasons [`Machine::run_until_stop`] does, which
+    /// [`Control::join`] reports with the machine.
+    pub fn spawn(mut self) -> Result<Spawned, Error> {
+        let (input, input_rx) = mpsc::channel();
+        let (output_tx, output) = mpsc::channel();
+        let (commands, commands_rx) = mpsc::channel();
+        let pending = Arc::new(AtomicBool::new(false));
+        self.stdin = io::BufReader::new(Box::new(ChannelIn {
+            input: input_rx,
+            pending: pending.clone(),
+        }));
+        self.stdout = SpanWriter::new(Box::new(ChannelOut(output_tx)), FlushPolicy::Byte);
+        let interrupter = self.interrupter();
+        let thread = thread::Builder::new().name("um-32 machine".into()).spawn({
+            let pending = pending.clone();
+            move || self.serve(commands_rx, pending)
+        })?;
+        Ok(Spawned {
+            input,
+            output,
+            control: Control {
+                commands,
+                pending,
+                interrupter,
+                thread,
+            },
+        })
+    }
+
+    // Runs, carrying out commands whenever one interrupts the run.
+    fn serve(
+        mut self,
+        commands: Receiver<Command>,
+        pending: Arc<AtomicBool>,
+    ) -> (Machine, Result<Stop, Error>) {
+        let mut paused = false;
+        loop {
+            pending.store(false, Ordering::SeqCst);
+            loop {
+                // Paused, the thread waits for the next command.
+                let command = if paused {
+                    commands.recv().ok()
+                } else {
+                    commands.try_recv().ok()
+                };
+                let Some(command) = command else {
+                    break;
+                };
+                match command {
+                    Command::Pause => paused = true,
+                    Command::Resume => paused = false,
+                    Command::Snapshot(reply) => {
+                        let _ = reply.send(self.snapshot_bytes());
+                    }
+                    Command::Stop => {
+                        let pc = self.pc;
+                        return (self, Ok(Stop::Interrupted { pc }));
+                    }
+                }
+            }
+            // An interrupt for a command already carried out is stale; one
+            // for a command sent since is not.
+            self.interrupter.take();
+            if pending.load(Ordering::SeqCst) {
+                continue;
+            }
+            match self.run_until_stop() {
+                Ok(Stop::Interrupted { .. }) => {}
+                Err(Error::IO(e)) if e.get_ref().is_some_and(|e| e.is::<CommandSent>()) => {}
+                res => return (self, res),
+            }
+        }
+    }
+
+    // Input read from the channel but not consumed yet is queued, so that
+    // the snapshot has it.
+    fn snapshot_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let buffered = self.stdin.buffer().to_vec();
+        self.stdin.consume(buffered.len());
+        self.input.extend(buffered);
+        let mut bytes = Vec::new();
+        self.write_snapshot(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl Control {
+    fn send(&self, command: Command) {
+        // Fails only once the machine has finished, when there is nothing
+        // left to do.
+        let _ = self.commands.send(command);
+        self.pending.store(true, Ordering::SeqCst);
+        self.interrupter.interrupt();
+    }
+
+    /// Stops executing instructions until [`Control::resume`].
+    pub fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.send(Command::Resume);
+    }
+
+    /// The machine's state as [`Machine::write_snapshot`] writes it, taken
+    /// between two instructions. Input the machine has taken from the
+    /// channel but not consumed yet is queued in it.
+    pub fn snapshot(&self) -> Result<Vec<u8>, Error> {
+        let (reply, snapshot) = mpsc::channel();
+        self.send(Command::Snapshot(reply));
+        snapshot
+            .recv()
+            .unwrap_or_else(|_| Err(Error::InvalidArgument("the machine has finished".into())))
+    }
+
+    /// Whether the machine has finished, so that [`Control::join`] won't
+    /// wait.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Stops the machine between two instructions, returning it with
+    /// [`Stop::Interrupted`], or with how it finished if it already had.
+    pub fn stop(self) -> (Machine, Result<Stop, Error>) {
+        self.send(Command::Stop);
+        self.join()
+    }
+
+    /// Waits for the machine to finish, returning it with the result of
+    /// its run.
+    pub fn join(self) -> (Machine, Result<Stop, Error>) {
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+// What Input waiting for a byte fails with when a command comes, as an
+// ErrorKind::WouldBlock error so that the machine doesn't show the Input to
+// its hook, observers and trace again when it is executed again. Std
+// retries reads that fail with ErrorKind::Interrupted.
+#[derive(Debug)]
+struct CommandSent;
+
+impl std::fmt::Display for CommandSent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("interrupted for a command")
+    }
+}
+
+impl std::error::Error for CommandSent {}
+
+struct ChannelIn {
+    input: Receiver<u8>,
+    pending: Arc<AtomicBool>,
+}
+
+impl Read for ChannelIn {
+    // Waits for a byte, then takes whatever else has arrived. A command
+    // makes it fail, leaving the Input instruction to be executed again.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let first = loop {
+            if self.pending.load(Ordering::SeqCst) {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, CommandSent));
+            }
+            match self.input.recv_timeout(POLL) {
+                Ok(byte) => break byte,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        };
+        buf[0] = first;
+        let mut n = 1;
+        while n < buf.len() {
+            let Ok(byte) = self.input.try_recv() else {
+                break;
+            };
+            buf[n] = byte;
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+struct ChannelOut(Sender<u8>);
+
+impl Write for ChannelOut {
+    /// Fails with [`io::ErrorKind::BrokenPipe`] once the receiver has
+    /// been dropped.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.0
+                .send(byte)
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
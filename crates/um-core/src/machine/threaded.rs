@@ -0,0 +1,174 @@
+//! The run loop for [`Backend::Threaded`](super::Backend).
+//!
+//! Instead of matching on the opcode, each decoded instruction calls its
+//! handler through a table indexed by opcode, so that every handler ends in
+//! its own indirect branch and the predictor learns which usually follows
+//! which. Handlers advance the pc themselves and return `false` on Halt.
+
+use super::{decoded::Decoded, Machine, Stop};
+use crate::Error;
+
+type Handler = fn(&mut Machine, Decoded) -> Result<bool, Error>;
+
+static HANDLERS: [Handler; 16] = [
+    cmov, index, amend, add, mul, div, nand, halt, alloc, abandon, output, input, load, ortho,
+    host, invalid,
+];
+
+impl Machine {
+    // Runs without breakpoints, watches, history or instrumentation. Every
+    // 65536 instructions it flushes stale output and checks for an
+    // interrupt or timeout, as the match loop does.
+    pub(super) fn run_threaded(&mut self) -> Result<Stop, Error> {
+        let mut ticks: u16 = 0;
+        loop {
+            ticks = ticks.wrapping_add(1);
+            if ticks == 0 {
+                self.stdout.flush_if_stale()?;
+                if let Some(stop) = self.poll_stop() {
+                    return Ok(stop);
+                }
+            }
+            let d = self.fetch_decoded()?;
+            if !HANDLERS[d.op as usize](self, d)? {
+                return Ok(Stop::Halt);
+            }
+            self.executed += 1;
+        }
+    }
+}
+
+fn cmov(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    if m.read_reg(d.c as u32) != 0 {
+        let val = m.read_reg(d.b);
+        m.write_reg(d.a as u32, val);
+    }
+    m.pc += 1;
+    Ok(true)
+}
+
+fn index(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let b = m.read_reg(d.b);
+    let c = m.read_reg(d.c as u32);
+    let val = m.index(d.inst, b, c)?;
+    m.write_reg(d.a as u32, val);
+    m.pc += 1;
+    Ok(true)
+}
+
+fn amend(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let a = m.read_reg(d.a as u32);
+    let b = m.read_reg(d.b);
+    let c = m.read_reg(d.c as u32);
+    m.amend(d.inst, a, b, c)?;
+    if a == 0 {
+        m.redecode(b);
+    }
+    m.pc += 1;
+    Ok(true)
+}
+
+fn add(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let val = m.read_reg(d.b).wrapping_add(m.read_reg(d.c as u32));
+    m.write_reg(d.a as u32, val);
+    m.pc += 1;
+    Ok(true)
+}
+
+fn mul(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let val = m.read_reg(d.b).wrapping_mul(m.read_reg(d.c as u32));
+    m.write_reg(d.a as u32, val);
+    m.pc += 1;
+    Ok(true)
+}
+
+fn div(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let divisor = m.read_reg(d.c as u32);
+    if divisor == 0 {
+        return Err(Error::DivisionByZero {
+            pc: m.pc,
+            inst: d.inst,
+        });
+    }
+    let val = m.read_reg(d.b) / divisor;
+    m.write_reg(d.a as u32, val);
+    m.pc += 1;
+    Ok(true)
+}
+
+fn nand(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let val = !(m.read_reg(d.b) & m.read_reg(d.c as u32));
+    m.write_reg(d.a as u32, val);
+    m.pc += 1;
+    Ok(true)
+}
+
+fn halt(_: &mut Machine, _: Decoded) -> Result<bool, Error> {
+    Ok(false)
+}
+
+fn alloc(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let cap = m.read_reg(d.c as u32);
+    let array = m.allocate(d.inst, cap)?;
+    m.write_reg(d.b, array);
+    m.pc += 1;
+    Ok(true)
+}
+
+fn abandon(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let array = m.read_reg(d.c as u32);
+    m.abandon(d.inst, array)?;
+    m.pc += 1;
+    Ok(true)
+}
+
+fn output(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let ch = m.read_reg(d.c as u32);
+    m.output(d.inst, ch)?;
+    m.pc += 1;
+    Ok(true)
+}
+
+fn input(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let ch = m.take_input()?;
+    m.write_reg(d.c as u32, ch);
+    m.pc += 1;
+    Ok(true)
+}
+
+fn load(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    let array = m.read_reg(d.b);
+    let entry = m.read_reg(d.c as u32);
+    m.load_program(d.inst, array, entry)?;
+    m.pc = entry;
+    Ok(true)
+}
+
+fn ortho(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    m.write_reg(d.a as u32, d.b);
+    m.pc += 1;
+    Ok(true)
+}
+
+#[cfg(feature = "extensions")]
+fn host(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    if m.host_calls.is_empty() {
+        return invalid(m, d);
+    }
+    m.host_call(d.inst, d.a as u32, d.b, d.c as u32)?;
+    m.pc += 1;
+    Ok(true)
+}
+
+#[cfg(not(feature = "extensions"))]
+fn host(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    invalid(m, d)
+}
+
+fn invalid(m: &mut Machine, d: Decoded) -> Result<bool, Error> {
+    Err(Error::InvalidOp {
+        pc: m.pc,
+        inst: d.inst,
+        op: d.op as u32,
+    })
+}
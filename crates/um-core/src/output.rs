@@ -0,0 +1,116 @@
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use crate::io::{self, Write};
+
+// How long buffered output may sit before the run loop pushes it out.
+#[cfg(feature = "std")]
+const FLUSH_AFTER: Duration = Duration::from_millis(50);
+
+// How much output is buffered before it is written whatever the policy.
+const SPAN_LIMIT: usize = 64 * 1024;
+
+/// When a [`Machine`](crate::Machine) writes buffered Output opcode bytes
+/// to its stdout, chosen with
+/// [`MachineBuilder::flush_policy`](crate::MachineBuilder::flush_policy).
+///
+/// Whatever the policy, output is written when the machine stops and
+/// whenever 64 KiB of it has built up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FlushPolicy {
+    /// Every byte as soon as it is produced, for interactive use.
+    Byte,
+    /// At each newline, before Input waits for the console, and once
+    /// output has been waiting for 50ms, with the `std` feature to tell
+    /// the time by.
+    #[default]
+    Newline,
+    /// Before Input waits for the console.
+    Input,
+    /// Only when the machine stops.
+    Halt,
+}
+
+/// Collects Output opcode bytes into spans so each one is a single write.
+///
+/// Spans are written as `policy` says, on an explicit `flush` (done when
+/// the machine stops), and before the Input opcode blocks by
+/// `flush_for_input`. With [`FlushPolicy::Newline`], `flush_if_stale`
+/// also writes them once the oldest buffered byte is older than
+/// `FLUSH_AFTER`.
+pub(crate) struct SpanWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    // When the oldest buffered byte was written, kept only for
+    // FlushPolicy::Newline, so that the other policies work where there is
+    // no clock, as in the browser.
+    #[cfg(feature = "std")]
+    since: Option<Instant>,
+    policy: FlushPolicy,
+}
+
+impl<W: Write> SpanWriter<W> {
+    pub fn new(inner: W, policy: FlushPolicy) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            #[cfg(feature = "std")]
+            since: None,
+            policy,
+        }
+    }
+
+    pub fn put(&mut self, byte: u8) -> io::Result<()> {
+        self.stamp();
+        self.buf.push(byte);
+        let flush = match self.policy {
+            FlushPolicy::Byte => true,
+            FlushPolicy::Newline => byte == b'\n',
+            FlushPolicy::Input | FlushPolicy::Halt => false,
+        };
+        if flush || self.buf.len() >= SPAN_LIMIT {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush_if_stale(&mut self) -> io::Result<()> {
+        #[cfg(feature = "std")]
+        if !self.buf.is_empty() && self.since.is_some_and(|t| t.elapsed() >= FLUSH_AFTER) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn stamp(&mut self) {
+        #[cfg(feature = "std")]
+        if self.buf.is_empty() && self.policy == FlushPolicy::Newline {
+            self.since = Some(Instant::now());
+        }
+    }
+
+    pub fn flush_for_input(&mut self) -> io::Result<()> {
+        if self.policy != FlushPolicy::Halt {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SpanWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stamp();
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.inner.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,227 @@
+//! Several machines running at once, each on a thread of its own, with
+//! [`pipe`]s to carry one machine's output to another's input.
+//!
+//! A [`Supervisor`] starts each machine it is given running, and hands it
+//! back in an [`Event`] once it stops, to be inspected and resumed or
+//! dropped. Dropping a machine drops its stdout, so a machine reading from
+//! a pipe it wrote to comes to the end of its input once it has halted and
+//! been dropped.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+};
+
+use crate::{Error, Interrupter, Machine, Stop};
+
+/// Identifies a machine a [`Supervisor`] started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MachineId(u64);
+
+/// A machine that has stopped, from [`Supervisor::next_event`].
+pub struct Event {
+    pub id: MachineId,
+    /// The machine, to resume with [`Supervisor::resume`] or drop.
+    pub machine: Machine,
+    /// What [`Machine::run_until_stop`] returned.
+    pub result: Result<Stop, Error>,
+}
+
+/// Runs machines on threads of their own and reports them stopping.
+pub struct Supervisor {
+    next_id: u64,
+    running: HashMap<MachineId, Interrupter>,
+    sender: Sender<Event>,
+    events: Receiver<Event>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        let (sender, events) = mpsc::channel();
+        Supervisor {
+            next_id: 0,
+            running: HashMap::new(),
+            sender,
+            events,
+        }
+    }
+
+    /// Starts `machine` running on a new thread.
+    pub fn spawn(&mut self, machine: Machine) -> Result<MachineId, Error> {
+        let id = MachineId(self.next_id);
+        self.start(id, machine)?;
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    /// Starts a machine an [`Event`] handed back running again.
+    pub fn resume(&mut self, id: MachineId, machine: Machine) -> Result<(), Error> {
+        if self.running.contains_key(&id) || id.0 >= self.next_id {
+            return Err(Error::InvalidArgument(format!(
+                "machine {} is not stopped",
+                id.0
+            )));
+        }
+        self.start(id, machine)
+    }
+
+    fn start(&mut self, id: MachineId, mut machine: Machine) -> Result<(), Error> {
+        let interrupter = machine.interrupter();
+        let sender = self.sender.clone();
+        thread::Builder::new()
+            .name(format!("um-32 machine {}", id.0))
+            .spawn(move || {
+                let result = machine.run_until_stop();
+                // Nobody is listening once the supervisor is gone.
+                let _ = sender.send(Event {
+                    id,
+                    machine,
+                    result,
+                });
+            })?;
+        self.running.insert(id, interrupter);
+        Ok(())
+    }
+
+    /// The machines running now.
+    pub fn running(&self) -> Vec<MachineId> {
+        let mut running: Vec<_> = self.running.keys().copied().collect();
+        running.sort_unstable();
+        running
+    }
+
+    /// Asks machine `id` to stop, returning whether it is running. A
+    /// machine waiting for input only stops once the input comes.
+    pub fn interrupt(&self, id: MachineId) -> bool {
+        match self.running.get(&id) {
+            Some(interrupter) => {
+                interrupter.interrupt();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Asks every running machine to stop.
+    pub fn interrupt_all(&self) {
+        self.running.values().for_each(Interrupter::interrupt);
+    }
+
+    /// Waits for a machine to stop, returning None once none are running.
+    pub fn next_event(&mut self) -> Option<Event> {
+        if self.running.is_empty() {
+            return None;
+        }
+        // The supervisor keeps a sender, so this waits rather than fails.
+        let event = self.events.recv().ok()?;
+        self.running.remove(&event.id);
+        Some(event)
+    }
+}
+
+// The machines left running stop, and are dropped with their threads.
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.interrupt_all();
+    }
+}
+
+/// A pipe to connect one machine's stdout to another's stdin, or to feed a
+/// machine input, or read its output, from another thread.
+///
+/// The reader blocks until there is something to read, and comes to the
+/// end of its input once every writer has been dropped and it has read
+/// everything written.
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            bytes: VecDeque::new(),
+            writers: 1,
+            reading: true,
+        }),
+        ready: Condvar::new(),
+    });
+    (PipeWriter(shared.clone()), PipeReader(shared))
+}
+
+struct Shared {
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+struct State {
+    bytes: VecDeque<u8>,
+    writers: usize,
+    reading: bool,
+}
+
+/// The writing end of a [`pipe`]; clones write to the same pipe.
+pub struct PipeWriter(Arc<Shared>);
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.0.state.lock().unwrap().writers += 1;
+        PipeWriter(self.0.clone())
+    }
+}
+
+impl Write for PipeWriter {
+    /// Fails with [`io::ErrorKind::BrokenPipe`] once the reader has been
+    /// dropped.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.state.lock().unwrap();
+        if !state.reading {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        state.bytes.extend(buf);
+        self.0.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().writers -= 1;
+        self.0.ready.notify_all();
+    }
+}
+
+/// The reading end of a [`pipe`].
+pub struct PipeReader(Arc<Shared>);
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.0.state.lock().unwrap();
+        while state.bytes.is_empty() && state.writers > 0 && !buf.is_empty() {
+            state = self.0.ready.wait(state).unwrap();
+        }
+        let n = buf.len().min(state.bytes.len());
+        for (i, byte) in state.bytes.drain(..n).enumerate() {
+            buf[i] = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.reading = false;
+        state.bytes.clear();
+    }
+}
@@ -0,0 +1,391 @@
+//! Assembling UM programs from text.
+//!
+//! The source format is the one [`disasm`](crate::disasm) produces, one
+//! instruction or directive per line:
+//!
+//! ```text
+//! ; Prints "Hi" and stops.
+//! start:  ORTHO r1, 'H'
+//!         OUTPUT r1
+//!         ORTHO r1, 0x69
+//!         OUTPUT r1
+//!         HALT
+//! table:  .word 1, 0b10, start   ; data platters
+//!         .zero 4                ; four 0 platters
+//!         .string "text\n"       ; one platter per byte
+//! ```
+//!
+//! Mnemonics and register names are case-insensitive, and registers are
+//! written `r0` through `r7`. Operands follow the register order shown by
+//! the disassembler: `CMOV a, b, c`, `ALLOC b, c`, `LOADPROG b, c`,
+//! `ABANDON c`, `OUTPUT c`, `INPUT c`, and `ORTHO a, value`, plus
+//! `HOST a, b, c` for the Host Call extension. Numbers may be
+//! decimal, `0x` hex, `0b` binary, or a quoted character, and a label may
+//! be used wherever a number is. Comments start with `;` or `#`.
+
+use std::collections::HashMap;
+
+use um_core::Instruction;
+
+use crate::program::{Label, ProgramBuilder};
+
+/// Why assembly failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    /// The 1-based line, or 0 for problems with the source as a whole.
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            0 => write!(f, "assembly failed: {}", self.message),
+            line => write!(f, "line {line}: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+enum Value {
+    Number(u32),
+    Label(Label),
+}
+
+struct Assembler {
+    p: ProgramBuilder,
+    labels: HashMap<String, (Label, Option<usize>)>,
+    line: usize,
+}
+
+/// Assembles `source` into platters.
+pub fn assemble(source: &str) -> Result<Vec<u32>, Error> {
+    let mut asm = Assembler {
+        p: ProgramBuilder::new(),
+        labels: HashMap::new(),
+        line: 0,
+    };
+    for (i, line) in source.lines().enumerate() {
+        asm.line = i + 1;
+        asm.line(line)?;
+    }
+    let mut undefined: Vec<_> = asm
+        .labels
+        .iter()
+        .filter(|(_, (_, defined))| defined.is_none())
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !undefined.is_empty() {
+        undefined.sort_unstable();
+        return Err(Error {
+            line: 0,
+            message: format!("undefined labels: {}", undefined.join(", ")),
+        });
+    }
+    Ok(asm.p.build())
+}
+
+/// Assembles `source` into a big-endian image accepted by
+/// [`Machine::extend_from`](um_core::Machine::extend_from).
+pub fn assemble_image(source: &str) -> Result<Vec<u8>, Error> {
+    Ok(assemble(source)?
+        .iter()
+        .flat_map(|w| w.to_be_bytes())
+        .collect())
+}
+
+impl Assembler {
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), Error> {
+        let mut rest = strip_comment(line).trim();
+        while let Some((name, after)) = rest.split_once(':') {
+            let name = name.trim();
+            if !is_identifier(name) {
+                break;
+            }
+            self.define(name)?;
+            rest = after.trim();
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let (op, operands) = match rest.split_once(char::is_whitespace) {
+            Some((op, operands)) => (op, operands.trim()),
+            None => (rest, ""),
+        };
+        if op == ".string" {
+            return self.string(operands);
+        }
+        let operands: Vec<&str> = if operands.is_empty() {
+            Vec::new()
+        } else {
+            operands.split(',').map(str::trim).collect()
+        };
+
+        type Standard = fn(u32, u32, u32) -> Instruction;
+        let standard: [(&str, Standard); 8] = [
+            ("cmov", |a, b, c| Instruction::Cmov { a, b, c }),
+            ("index", |a, b, c| Instruction::Index { a, b, c }),
+            ("amend", |a, b, c| Instruction::Amend { a, b, c }),
+            ("add", |a, b, c| Instruction::Add { a, b, c }),
+            ("mul", |a, b, c| Instruction::Mul { a, b, c }),
+            ("div", |a, b, c| Instruction::Div { a, b, c }),
+            ("nand", |a, b, c| Instruction::Nand { a, b, c }),
+            ("host", |a, b, c| Instruction::Host { a, b, c }),
+        ];
+        let op = op.to_ascii_lowercase();
+        if let Some((_, instruction)) = standard.iter().find(|(name, _)| *name == op) {
+            let [a, b, c] = self.registers(&operands)?;
+            self.p.instruction(instruction(a, b, c));
+            return Ok(());
+        }
+        match op.as_str() {
+            "halt" => {
+                let [] = self.registers(&operands)?;
+                self.p.halt();
+            }
+            "alloc" => {
+                let [b, c] = self.registers(&operands)?;
+                self.p.alloc(b, c);
+            }
+            "abandon" => {
+                let [c] = self.registers(&operands)?;
+                self.p.abandon(c);
+            }
+            "output" => {
+                let [c] = self.registers(&operands)?;
+                self.p.output(c);
+            }
+            "input" => {
+                let [c] = self.registers(&operands)?;
+                self.p.input(c);
+            }
+            "loadprog" => {
+                let [b, c] = self.registers(&operands)?;
+                self.p.load_program(b, c);
+            }
+            "ortho" => {
+                let [a, value] = operands[..] else {
+                    return Err(self.error("ORTHO takes a register and a value"));
+                };
+                let a = self.register(a)?;
+                match self.value(value)? {
+                    Value::Number(n) if n >= 1 << 25 => {
+                        return Err(self.error(format!("{n:#x} does not fit in 25 bits")))
+                    }
+                    Value::Number(n) => self.p.ortho(a, n),
+                    Value::Label(label) => self.p.ortho_label(a, label),
+                }
+            }
+            ".word" => {
+                if operands.is_empty() {
+                    return Err(self.error(".word needs at least one value"));
+                }
+                for operand in operands {
+                    match self.value(operand)? {
+                        Value::Number(n) => self.p.word(n),
+                        Value::Label(label) => self.p.address(label),
+                    }
+                }
+            }
+            ".zero" => {
+                let [count] = operands[..] else {
+                    return Err(self.error(".zero takes a platter count"));
+                };
+                let Value::Number(count) = self.value(count)? else {
+                    return Err(self.error(".zero takes a number, not a label"));
+                };
+                for _ in 0..count {
+                    self.p.word(0);
+                }
+            }
+            _ => return Err(self.error(format!("unknown instruction {op:?}"))),
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str) -> Result<(), Error> {
+        let line = self.line;
+        let entry = self
+            .labels
+            .entry(name.to_string())
+            .or_insert_with(|| (self.p.label(), None));
+        if let Some(first) = entry.1 {
+            return Err(self.error(format!("label {name} already defined on line {first}")));
+        }
+        entry.1 = Some(line);
+        let label = entry.0;
+        self.p.bind(label);
+        Ok(())
+    }
+
+    fn register(&self, operand: &str) -> Result<u32, Error> {
+        match operand.strip_prefix(['r', 'R']).map(str::parse::<u32>) {
+            Some(Ok(reg)) if reg < 8 => Ok(reg),
+            _ => Err(self.error(format!("expected a register r0-r7, got {operand:?}"))),
+        }
+    }
+
+    fn registers<const N: usize>(&self, operands: &[&str]) -> Result<[u32; N], Error> {
+        if operands.len() != N {
+            return Err(self.error(format!(
+                "expected {N} register operands, got {}",
+                operands.len()
+            )));
+        }
+        let mut regs = [0; N];
+        for (reg, operand) in regs.iter_mut().zip(operands) {
+            *reg = self.register(operand)?;
+        }
+        Ok(regs)
+    }
+
+    fn value(&mut self, operand: &str) -> Result<Value, Error> {
+        if is_identifier(operand) {
+            let label = match self.labels.get(operand) {
+                Some((label, _)) => *label,
+                None => {
+                    let label = self.p.label();
+                    self.labels.insert(operand.to_string(), (label, None));
+                    label
+                }
+            };
+            return Ok(Value::Label(label));
+        }
+        let parsed = if let Some(ch) = operand
+            .strip_prefix('\'')
+            .and_then(|s| s.strip_suffix('\''))
+        {
+            let unescaped = unescape(ch).ok_or_else(|| self.error("invalid escape"))?;
+            let mut chars = unescaped.chars();
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => Some(ch as u32),
+                _ => None,
+            }
+        } else if let Some(hex) = operand.strip_prefix("0x") {
+            u32::from_str_radix(hex, 16).ok()
+        } else if let Some(bin) = operand.strip_prefix("0b") {
+            u32::from_str_radix(bin, 2).ok()
+        } else {
+            operand.parse().ok()
+        };
+        parsed
+            .map(Value::Number)
+            .ok_or_else(|| self.error(format!("expected a number or label, got {operand:?}")))
+    }
+
+    fn string(&mut self, operand: &str) -> Result<(), Error> {
+        let text = operand
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .ok_or_else(|| self.error(".string takes a quoted string"))?;
+        let text = unescape(text).ok_or_else(|| self.error("invalid escape"))?;
+        for ch in text.chars() {
+            self.p.word(ch as u32);
+        }
+        Ok(())
+    }
+}
+
+// Labels look like identifiers, except that register names are reserved.
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    let register = s
+        .strip_prefix(['r', 'R'])
+        .is_some_and(|n| n.parse::<u32>().is_ok());
+    valid && !register
+}
+
+// Comment characters inside quotes are part of the literal.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, ';' | '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            c @ ('\\' | '\'' | '"') => c,
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+
+    #[test]
+    fn disassembly_round_trip() {
+        let words = [
+            0x0000_00d3,
+            0x1000_0001,
+            0x6000_01ff,
+            0x7000_0000,
+            0x8000_000a,
+            0x9000_0003,
+            0xc000_0011,
+            0xd200_0042,
+            0xe000_0000,
+        ];
+        for host_calls in [false, true] {
+            let source: String = words
+                .iter()
+                .map(|w| disasm::mnemonic(*w, host_calls) + "\n")
+                .collect();
+            assert_eq!(assemble(&source).unwrap(), words);
+        }
+    }
+
+    #[test]
+    fn labels_and_directives() {
+        let source = "start: ORTHO r1, end ; comment\n.word start, 'a'\nend: .string \";\\n\"\n";
+        assert_eq!(assemble(source).unwrap(), [0xd200_0003, 0, 97, 59, 10]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        match assemble("HALT\nJUMP r1\n") {
+            Err(Error { line: 2, .. }) => {}
+            res => panic!("unexpected result {res:?}"),
+        }
+    }
+}
@@ -0,0 +1,470 @@
+//! Translating a program image into a standalone Rust program.
+//!
+//! The image is split into blocks of straight-line code, each running up to
+//! and including a Halt or Load Program, or up to a platter that is not an
+//! instruction. Each becomes a function with the registers and operands
+//! resolved at translation time. Load Program jumps to computed addresses,
+//! so a block can be entered wherever a jump is likely to land: at its
+//! start, and at every address an Orthography loads. Entered anywhere else,
+//! the program is interpreted until it reaches an entry.
+//!
+//! Programs that unpack themselves, such as sandmark, spend their time in
+//! code they load from another array. Their stages, as written by
+//! [`OverlayDumper`](crate::overlay::OverlayDumper), can be translated
+//! along with the image: a Load Program of an array the length of a stage
+//! switches to that stage's blocks.
+//!
+//! The generated program also interprets programs loaded that match no
+//! stage, platters that are not instructions, and blocks that are no longer
+//! what was translated because a platter in them was amended or differs in
+//! what was loaded. Faults print a message with the pc and exit with status
+//! 1; the end of input reads as all 1 bits, as the specification says.
+
+use std::fmt::Write;
+
+// Shared by every generated program.
+const RUNTIME: &str = r#"use std::io::{self, BufRead, Write};
+
+enum Next {
+    Pc(u32),
+    Halt,
+}
+
+type Block = fn(&mut M, u32) -> Next;
+
+// In the tables of blocks by pc, pcs in no block are NONE, and the pcs a
+// block can be entered at have ENTRY set.
+const NONE: u32 = u32::MAX;
+const ENTRY: u32 = 1 << 31;
+
+struct M {
+    r: [u32; 8],
+    arrays: Vec<Option<Vec<u32>>>,
+    free: Vec<u32>,
+    // The stage array 0 was loaded as, or STAGES.len() for none, and which
+    // of its blocks are stale because array 0 no longer holds what they
+    // were translated from.
+    stage: usize,
+    tables: Vec<Vec<u32>>,
+    stale: Vec<bool>,
+    out: io::BufWriter<io::Stdout>,
+    inp: io::StdinLock<'static>,
+}
+
+impl M {
+    fn fault(&mut self, pc: u32, msg: std::fmt::Arguments) -> ! {
+        let _ = self.out.flush();
+        eprintln!("um: {msg} at pc {pc:#x}");
+        std::process::exit(1)
+    }
+
+    #[cold]
+    fn bad_access(&mut self, pc: u32, access: &str, array: u32, offset: u32) -> ! {
+        match self.arrays.get(array as usize) {
+            Some(Some(_)) => self.fault(pc, format_args!("out-of-bounds {access} array {array} at offset {offset}")),
+            _ => self.fault(pc, format_args!("{access} inactive array {array}")),
+        }
+    }
+
+    #[inline(always)]
+    fn index(&mut self, pc: u32, array: u32, offset: u32) -> u32 {
+        match self.arrays.get(array as usize).and_then(Option::as_ref).and_then(|a| a.get(offset as usize)) {
+            Some(v) => *v,
+            None => self.bad_access(pc, "read of", array, offset),
+        }
+    }
+
+    // Returns whether a block went stale.
+    #[inline(always)]
+    fn amend(&mut self, pc: u32, array: u32, offset: u32, val: u32) -> bool {
+        match self.arrays.get_mut(array as usize).and_then(Option::as_mut).and_then(|a| a.get_mut(offset as usize)) {
+            Some(v) => *v = val,
+            None => self.bad_access(pc, "write to", array, offset),
+        }
+        if array != 0 {
+            return false;
+        }
+        match self.tables.get(self.stage).and_then(|t| t.get(offset as usize)) {
+            Some(&block) if block != NONE => {
+                self.stale[(block & !ENTRY) as usize] = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    #[inline(always)]
+    fn div(&mut self, pc: u32, b: u32, c: u32) -> u32 {
+        if c == 0 {
+            self.fault(pc, format_args!("division by zero"));
+        }
+        b / c
+    }
+
+    fn alloc(&mut self, len: u32) -> u32 {
+        let mem = vec![0; len as usize];
+        match self.free.pop() {
+            Some(id) => {
+                self.arrays[id as usize] = Some(mem);
+                id
+            }
+            None => {
+                self.arrays.push(Some(mem));
+                self.arrays.len() as u32 - 1
+            }
+        }
+    }
+
+    fn abandon(&mut self, pc: u32, array: u32) {
+        if array == 0 {
+            self.fault(pc, format_args!("abandonment of the program array"));
+        }
+        match self.arrays.get(array as usize).map(Option::is_some) {
+            Some(true) => {}
+            Some(false) => {
+                self.fault(pc, format_args!("abandonment of array {array}, already abandoned"))
+            }
+            None => self.fault(pc, format_args!("abandonment of inactive array {array}")),
+        }
+        self.arrays[array as usize] = None;
+        self.free.push(array);
+    }
+
+    fn output(&mut self, pc: u32, ch: u32) {
+        if ch > 255 {
+            self.fault(pc, format_args!("output of non-byte value {ch}"));
+        }
+        let _ = self.out.write_all(&[ch as u8]);
+        if ch == u32::from(b'\n') {
+            let _ = self.out.flush();
+        }
+    }
+
+    fn input(&mut self) -> u32 {
+        let _ = self.out.flush();
+        let ch = match self.inp.fill_buf() {
+            Ok([ch, ..]) => u32::from(*ch),
+            _ => return !0,
+        };
+        self.inp.consume(1);
+        ch
+    }
+
+    // Returns the new pc.
+    fn load(&mut self, pc: u32, array: u32, entry: u32) -> u32 {
+        if array == 0 {
+            return entry;
+        }
+        let Some(Some(a)) = self.arrays.get(array as usize) else {
+            self.fault(pc, format_args!("load from inactive array {array}"));
+        };
+        // Programs often load a copy of themselves, so the image is a
+        // stage too.
+        self.stage = STAGES.iter().position(|s| s.len() == a.len()).unwrap_or(STAGES.len());
+        if let Some(stage) = STAGES.get(self.stage) {
+            self.stale = BLOCKS[self.stage]
+                .iter()
+                .map(|&(start, end, _)| {
+                    let (start, end) = (start as usize, end as usize);
+                    a[start..end] != stage[start..end]
+                })
+                .collect();
+        }
+        self.arrays[0] = Some(a.clone());
+        entry
+    }
+
+    // Executes the instruction at pc.
+    fn step(&mut self, pc: u32) -> Next {
+        let inst = match self.arrays[0].as_ref().and_then(|a| a.get(pc as usize)) {
+            Some(inst) => *inst,
+            None => self.fault(pc, format_args!("fetch from outside the program")),
+        };
+        let (a, b, c) = (
+            (inst >> 6 & 7) as usize,
+            (inst >> 3 & 7) as usize,
+            (inst & 7) as usize,
+        );
+        let r = self.r;
+        match inst >> 28 {
+            0 => {
+                if r[c] != 0 {
+                    self.r[a] = r[b];
+                }
+            }
+            1 => self.r[a] = self.index(pc, r[b], r[c]),
+            2 => {
+                self.amend(pc, r[a], r[b], r[c]);
+            }
+            3 => self.r[a] = r[b].wrapping_add(r[c]),
+            4 => self.r[a] = r[b].wrapping_mul(r[c]),
+            5 => self.r[a] = self.div(pc, r[b], r[c]),
+            6 => self.r[a] = !(r[b] & r[c]),
+            7 => return Next::Halt,
+            8 => self.r[b] = self.alloc(r[c]),
+            9 => self.abandon(pc, r[c]),
+            10 => self.output(pc, r[c]),
+            11 => self.r[c] = self.input(),
+            12 => return Next::Pc(self.load(pc, r[b], r[c])),
+            13 => self.r[(inst >> 25 & 7) as usize] = inst & 0x1ff_ffff,
+            op => self.fault(pc, format_args!("invalid opcode {op}")),
+        }
+        Next::Pc(pc.wrapping_add(1))
+    }
+}
+
+fn main() {
+    let tables = BLOCKS
+        .iter()
+        .zip(STAGES)
+        .map(|(blocks, stage)| {
+            let mut table = vec![NONE; stage.len()];
+            for (i, (start, end, entries)) in blocks.iter().enumerate() {
+                table[*start as usize..*end as usize].fill(i as u32);
+                for entry in entries.iter() {
+                    table[*entry as usize] |= ENTRY;
+                }
+            }
+            table
+        })
+        .collect();
+    let mut m = M {
+        r: [0; 8],
+        arrays: vec![Some(STAGE_0.to_vec())],
+        free: Vec::new(),
+        stage: 0,
+        tables,
+        stale: vec![false; BLOCKS[0].len()],
+        out: io::BufWriter::new(io::stdout()),
+        inp: io::stdin().lock(),
+    };
+    let mut pc = 0;
+    loop {
+        let block = match m.tables.get(m.stage).and_then(|t| t.get(pc as usize)) {
+            Some(&block) if block != NONE && block & ENTRY != 0 => block & !ENTRY,
+            _ => NONE,
+        };
+        let next = if block != NONE && !m.stale[block as usize] {
+            FUNCTIONS[m.stage][block as usize](&mut m, pc)
+        } else {
+            m.step(pc)
+        };
+        match next {
+            Next::Pc(next) => pc = next,
+            Next::Halt => break,
+        }
+    }
+    let _ = m.out.flush();
+}
+"#;
+
+fn op(word: u32) -> u32 {
+    word >> 28
+}
+
+// The start and end of each block, and the pcs it can be entered at.
+fn blocks(program: &[u32]) -> Vec<(usize, usize, Vec<usize>)> {
+    let mut jumped_to = vec![false; program.len()];
+    for &word in program {
+        if op(word) == 13 {
+            if let Some(target) = jumped_to.get_mut((word & 0x1ff_ffff) as usize) {
+                *target = true;
+            }
+        }
+    }
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (pc, &word) in program.iter().enumerate() {
+        let end = match op(word) {
+            7 | 12 => pc + 1,
+            14.. => pc,
+            _ if pc + 1 == program.len() => pc + 1,
+            _ => continue,
+        };
+        if start < end {
+            let entries = std::iter::once(start)
+                .chain((start + 1..end).filter(|pc| jumped_to[*pc]))
+                .collect();
+            blocks.push((start, end, entries));
+        }
+        start = pc + 1;
+    }
+    blocks
+}
+
+// Writes the statements executing the instruction at pc in a block that
+// ends at `end`.
+fn instruction(s: &mut String, pc: usize, word: u32, end: usize) {
+    let (a, b, c) = (word >> 6 & 7, word >> 3 & 7, word & 7);
+    let _ = match op(word) {
+        0 => writeln!(s, "    if m.r[{c}] != 0 {{ m.r[{a}] = m.r[{b}]; }}"),
+        1 => writeln!(s, "    m.r[{a}] = m.index({pc:#x}, m.r[{b}], m.r[{c}]);"),
+        // Amending a later platter of the block ends it there, since the
+        // rest is stale.
+        2 => writeln!(
+            s,
+            "    let offset = m.r[{b}];\n    \
+             if m.amend({pc:#x}, m.r[{a}], offset, m.r[{c}]) && offset > {pc:#x} && offset < {end:#x} {{ \
+             return Next::Pc({:#x}); }}",
+            pc + 1
+        ),
+        3 => writeln!(s, "    m.r[{a}] = m.r[{b}].wrapping_add(m.r[{c}]);"),
+        4 => writeln!(s, "    m.r[{a}] = m.r[{b}].wrapping_mul(m.r[{c}]);"),
+        5 => writeln!(s, "    m.r[{a}] = m.div({pc:#x}, m.r[{b}], m.r[{c}]);"),
+        6 => writeln!(s, "    m.r[{a}] = !(m.r[{b}] & m.r[{c}]);"),
+        7 => writeln!(s, "    Next::Halt"),
+        8 => writeln!(s, "    m.r[{b}] = m.alloc(m.r[{c}]);"),
+        9 => writeln!(s, "    m.abandon({pc:#x}, m.r[{c}]);"),
+        10 => writeln!(s, "    m.output({pc:#x}, m.r[{c}]);"),
+        11 => writeln!(s, "    m.r[{c}] = m.input();"),
+        12 => writeln!(s, "    Next::Pc(m.load({pc:#x}, m.r[{b}], m.r[{c}]))"),
+        13 => writeln!(
+            s,
+            "    m.r[{}] = {:#x};",
+            word >> 25 & 7,
+            word & 0x1ff_ffff
+        ),
+        _ => unreachable!("blocks end before invalid opcodes"),
+    };
+}
+
+// Writes an image as a static array.
+fn image(s: &mut String, name: &str, program: &[u32]) {
+    let _ = writeln!(
+        s,
+        "\n#[rustfmt::skip]\nstatic {name}: [u32; {}] = [",
+        program.len()
+    );
+    for row in program.chunks(8) {
+        let row: Vec<String> = row.iter().map(|w| format!("{w:#010x}")).collect();
+        let _ = writeln!(s, "    {},", row.join(", "));
+    }
+    s.push_str("];\n");
+}
+
+// Writes a function for each block of stage `n`, and tables of them.
+//
+// Rust has no goto, so a block with several entries nests a labeled block
+// for each, innermost first, and breaks out of the one before the entry
+// asked for:
+//
+//     'e1: { 'e0: { match pc { 0x10 => break 'e0, _ => break 'e1 } }
+//         /* from 0x10 */ }
+//     /* from the second entry */
+fn stage(s: &mut String, n: usize, program: &[u32]) {
+    let blocks = blocks(program);
+    for (start, end, entries) in &blocks {
+        let _ = writeln!(
+            s,
+            "\n#[rustfmt::skip]\nfn block_{n}_{start:x}(m: &mut M, pc: u32) -> Next {{"
+        );
+        if entries.len() > 1 {
+            for i in (0..entries.len()).rev() {
+                let _ = write!(s, "'e{i}: {{ ");
+            }
+            s.push_str("match pc {");
+            for (i, entry) in entries.iter().enumerate().take(entries.len() - 1) {
+                let _ = write!(s, " {entry:#x} => break 'e{i},");
+            }
+            let _ = writeln!(s, " _ => break 'e{} }} }}", entries.len() - 1);
+        } else {
+            s.push_str("    let _ = (&m, pc);\n");
+        }
+        let mut next_entry = 1;
+        for (pc, &word) in program.iter().enumerate().take(*end).skip(*start) {
+            if entries.get(next_entry) == Some(&pc) {
+                next_entry += 1;
+                s.push_str("}\n");
+            }
+            instruction(s, pc, word, *end);
+        }
+        if !matches!(op(program[end - 1]), 7 | 12) {
+            let _ = writeln!(s, "    Next::Pc({end:#x})");
+        }
+        s.push_str("}\n");
+    }
+
+    let _ = writeln!(
+        s,
+        "\n#[rustfmt::skip]\nstatic BLOCKS_{n}: [(u32, u32, &[u32]); {}] = [",
+        blocks.len()
+    );
+    for (start, end, entries) in &blocks {
+        let entries: Vec<String> = entries.iter().map(|pc| format!("{pc:#x}")).collect();
+        let _ = writeln!(s, "    ({start:#x}, {end:#x}, &[{}]),", entries.join(", "));
+    }
+    s.push_str("];\n");
+    let _ = writeln!(
+        s,
+        "\n#[rustfmt::skip]\nstatic FUNCTIONS_{n}: [Block; {}] = [",
+        blocks.len()
+    );
+    for (start, _, _) in &blocks {
+        let _ = writeln!(s, "    block_{n}_{start:x},");
+    }
+    s.push_str("];\n");
+}
+
+/// Translates `program` into the source of a Rust program that runs it,
+/// reading input from stdin and writing output to stdout. Build it with
+/// `rustc -O`.
+///
+/// `stages` are programs it is expected to load from other arrays, each
+/// translated too. Only the first stage of any one length is used.
+pub fn compile(program: &[u32], stages: &[&[u32]]) -> String {
+    let stages: Vec<&[u32]> = std::iter::once(program)
+        .chain(stages.iter().copied())
+        .collect();
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        "// Translated from a UM-32 program image of {} platters, with {} stages,\n\
+         // by `um-32 compile`. Build with `rustc -O`.\n",
+        program.len(),
+        stages.len() - 1
+    );
+    s.push_str(RUNTIME);
+
+    let list = |name: &str| {
+        let items: Vec<String> = (0..stages.len()).map(|i| format!("&{name}_{i}")).collect();
+        items.join(", ")
+    };
+    let count = stages.len();
+    let _ = writeln!(
+        s,
+        "\nstatic STAGES: [&[u32]; {count}] = [{}];\n\
+         static BLOCKS: [&[(u32, u32, &[u32])]; {count}] = [{}];\n\
+         static FUNCTIONS: [&[Block]; {count}] = [{}];",
+        list("STAGE"),
+        list("BLOCKS"),
+        list("FUNCTIONS"),
+    );
+    for (i, program) in stages.iter().enumerate() {
+        image(&mut s, &format!("STAGE_{i}"), program);
+        stage(&mut s, i, program);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_and_entries() {
+        // ORTHO r1, 3 ; LOADPROG r0, r1 ; .word 0xe0000000 ; OUTPUT r1 ; HALT
+        let program = [
+            0xd200_0003,
+            0xc000_0001,
+            0xe000_0000,
+            0xa000_0001,
+            0x7000_0000,
+        ];
+        let source = compile(&program, &[]);
+        assert!(source.contains("fn block_0_0(m: &mut M, pc: u32) -> Next {"));
+        assert!(source.contains("    Next::Pc(m.load(0x1, m.r[0], m.r[1]))\n"));
+        assert!(source.contains("fn block_0_3(m: &mut M, pc: u32) -> Next {"));
+        assert!(source.contains("    (0x3, 0x5, &[0x3]),\n"));
+        assert!(!source.contains("fn block_0_2("));
+    }
+}
@@ -0,0 +1,288 @@
+//! Turning platters back into readable instructions.
+
+use std::io::{self, Write};
+
+use um_core::Instruction;
+
+const NAMES: [&str; 14] = [
+    "CMOV", "INDEX", "AMEND", "ADD", "MUL", "DIV", "NAND", "HALT", "ALLOC", "ABANDON", "OUTPUT",
+    "INPUT", "LOADPROG", "ORTHO",
+];
+
+/// Returns the mnemonic for opcode `op`, e.g. `AMEND` for 2, or `None` for
+/// the unused opcodes 14 and 15.
+pub fn op_name(op: u32) -> Option<&'static str> {
+    NAMES.get(op as usize).copied()
+}
+
+/// Splits a big-endian program image into platters. A trailing partial
+/// platter is ignored.
+pub fn image_words(image: &[u8]) -> Vec<u32> {
+    image
+        .chunks_exact(4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Formats one platter as an instruction, e.g. `CMOV r1, r2, r3` or
+/// `ORTHO r0, 0x42`.
+///
+/// Only the registers an instruction uses are shown. Platters with an
+/// invalid opcode are shown as data, `.word 0xe0000000`. With
+/// `host_calls`, opcode 14 is Host Call, `HOST r1, r2, r3`; otherwise it
+/// is data too, which it far more often is.
+pub fn mnemonic(word: u32, host_calls: bool) -> String {
+    match Instruction::decode(word) {
+        Ok(Instruction::Host { .. }) if !host_calls => format!(".word {word:#010x}"),
+        Ok(instruction) => instruction.to_string(),
+        Err(_) => format!(".word {word:#010x}"),
+    }
+}
+
+/// Writes one line per platter with its address, raw value, and mnemonic:
+///
+/// ```text
+/// 00000000: d2000048  ORTHO r1, 0x48
+/// ```
+///
+/// `base` is the address of the first platter, for listing part of an
+/// array, and `host_calls` is as for [`mnemonic`].
+pub fn disassemble(
+    w: &mut impl Write,
+    base: u32,
+    words: &[u32],
+    host_calls: bool,
+) -> io::Result<()> {
+    for (addr, word) in (base..).zip(words) {
+        writeln!(w, "{addr:08x}: {word:08x}  {}", mnemonic(*word, host_calls))?;
+    }
+    Ok(())
+}
+
+// One line of a diff: a platter in both listings, or in only one.
+#[derive(Clone, Copy)]
+enum Edit {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+// Beyond this many differences the alignment search gives up and lists
+// the rest of the old listing as removed and the new one as added, since
+// its memory use grows with the square of the count.
+const MAX_EDITS: usize = 2048;
+
+/// Writes a unified diff between two listings, `old` and `new`, with
+/// `context` unchanged instructions around each change. Instructions are
+/// aligned by content, so code that moved keeps matching:
+///
+/// ```text
+/// @@ -0x1,3 +0x1,4 @@
+///   00000001 00000001  a0000001  OUTPUT r1
+/// + -------- 00000002  30000049  ADD r1, r1, r1
+///   00000002 00000003  d2000069  ORTHO r1, 0x69
+/// - 00000003 --------  70000000  HALT
+/// + -------- 00000004  a0000001  OUTPUT r1
+/// ```
+///
+/// `host_calls` is as for [`mnemonic`]. Returns whether the listings
+/// differ.
+pub fn diff(
+    w: &mut impl Write,
+    old: &[u32],
+    new: &[u32],
+    context: usize,
+    host_calls: bool,
+) -> io::Result<bool> {
+    let edits = align(old, new);
+    let changed: Vec<usize> = (0..edits.len())
+        .filter(|i| !matches!(edits[*i], Edit::Same(..)))
+        .collect();
+    let mut rest = &changed[..];
+    while let Some(&first) = rest.first() {
+        // A hunk runs until two changes are more than 2 * context apart.
+        let mut last = first;
+        let mut taken = 1;
+        for &next in &rest[1..] {
+            if next - last > 2 * context + 1 {
+                break;
+            }
+            last = next;
+            taken += 1;
+        }
+        rest = &rest[taken..];
+        let hunk = &edits[first.saturating_sub(context)..(last + context + 1).min(edits.len())];
+        hunk_header(w, hunk, &edits[..first.saturating_sub(context)])?;
+        for edit in hunk {
+            let (mark, a, b, word) = match *edit {
+                Edit::Same(a, b) => (' ', Some(a), Some(b), old[a]),
+                Edit::Removed(a) => ('-', Some(a), None, old[a]),
+                Edit::Added(b) => ('+', None, Some(b), new[b]),
+            };
+            let addr = |n: Option<usize>| n.map_or("--------".to_string(), |n| format!("{n:08x}"));
+            writeln!(
+                w,
+                "{mark} {} {}  {word:08x}  {}",
+                addr(a),
+                addr(b),
+                mnemonic(word, host_calls)
+            )?;
+        }
+    }
+    Ok(!changed.is_empty())
+}
+
+fn hunk_header(w: &mut impl Write, hunk: &[Edit], before: &[Edit]) -> io::Result<()> {
+    let old_start = before
+        .iter()
+        .filter(|e| !matches!(e, Edit::Added(_)))
+        .count();
+    let new_start = before
+        .iter()
+        .filter(|e| !matches!(e, Edit::Removed(_)))
+        .count();
+    let old_len = hunk.iter().filter(|e| !matches!(e, Edit::Added(_))).count();
+    let new_len = hunk
+        .iter()
+        .filter(|e| !matches!(e, Edit::Removed(_)))
+        .count();
+    writeln!(
+        w,
+        "@@ -{old_start:#x},{old_len} +{new_start:#x},{new_len} @@"
+    )
+}
+
+// Aligns two listings with Myers' algorithm after trimming the common
+// prefix and suffix.
+fn align(old: &[u32], new: &[u32]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut edits: Vec<Edit> = (0..prefix).map(|i| Edit::Same(i, i)).collect();
+    let middle = shortest_edit(a, b).unwrap_or_else(|| {
+        (0..a.len())
+            .map(Edit::Removed)
+            .chain((0..b.len()).map(Edit::Added))
+            .collect()
+    });
+    edits.extend(middle.into_iter().map(|edit| match edit {
+        Edit::Same(a, b) => Edit::Same(a + prefix, b + prefix),
+        Edit::Removed(a) => Edit::Removed(a + prefix),
+        Edit::Added(b) => Edit::Added(b + prefix),
+    }));
+    let (a_end, b_end) = (old.len() - suffix, new.len() - suffix);
+    edits.extend((0..suffix).map(|i| Edit::Same(a_end + i, b_end + i)));
+    edits
+}
+
+// Myers' O(ND) shortest edit script, or None past MAX_EDITS differences.
+// `trace[d]` keeps the furthest x reached on each diagonal k = x - y before
+// round d, for diagonals -d - 1 ..= d + 1.
+fn shortest_edit(a: &[u32], b: &[u32]) -> Option<Vec<Edit>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_EDITS) as isize;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let index = |k: isize| (k + offset) as usize;
+    let mut found = None;
+    'search: for d in 0..=max {
+        trace.push(v[index(-d - 1)..=index(d + 1)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                found = Some(d);
+                break 'search;
+            }
+        }
+    }
+    found?;
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Same(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Added(prev_y as usize));
+            } else {
+                edits.push(Edit::Removed(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    Some(edits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(mnemonic(0x0000_00d3, false), "CMOV r3, r2, r3");
+        assert_eq!(mnemonic(0x7000_0000, false), "HALT");
+        assert_eq!(mnemonic(0x8000_000a, false), "ALLOC r1, r2");
+        assert_eq!(mnemonic(0xa000_0005, false), "OUTPUT r5");
+        assert_eq!(mnemonic(0xd200_0042, false), "ORTHO r1, 0x42");
+        assert_eq!(mnemonic(0xe000_0000, false), ".word 0xe0000000");
+        // Host Call only with the extension.
+        assert_eq!(mnemonic(0xe000_00d3, true), "HOST r3, r2, r3");
+        assert_eq!(mnemonic(0xf000_0000, true), ".word 0xf0000000");
+
+        let mut out = Vec::new();
+        disassemble(&mut out, 0x10, &image_words(&[0x70, 0, 0, 0, 0xd2]), false).unwrap();
+        assert_eq!(out, b"00000010: 70000000  HALT\n");
+    }
+
+    #[test]
+    fn diffs() {
+        // An OUTPUT is inserted and ORTHO r1, 1 becomes ORTHO r1, 2.
+        let old = [0xd200_0001, 0xa000_0001, 0x7000_0000];
+        let new = [0xd200_0002, 0xa000_0001, 0xa000_0001, 0x7000_0000];
+        let mut out = Vec::new();
+        assert!(diff(&mut out, &old, &new, 1, false).unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "@@ -0x0,2 +0x0,3 @@\n\
+             - 00000000 --------  d2000001  ORTHO r1, 0x1\n\
+             + -------- 00000000  d2000002  ORTHO r1, 0x2\n\
+             + -------- 00000001  a0000001  OUTPUT r1\n\
+             \x20 00000001 00000002  a0000001  OUTPUT r1\n"
+        );
+        assert!(!diff(&mut Vec::new(), &old, &old, 3, false).unwrap());
+    }
+}
@@ -0,0 +1,12 @@
+//! Tools for UM-32 programs, to go with the machine in `um-core`: the
+//! [`program`] and [`asm`] modules for building images from Rust or text,
+//! the [`disasm`] module for reading them back, the [`overlay`] module for
+//! multi-stage images, the [`compile`] module for translating images to
+//! Rust, and the [`verify`] module for checking them.
+
+pub mod asm;
+pub mod compile;
+pub mod disasm;
+pub mod overlay;
+pub mod program;
+pub mod verify;
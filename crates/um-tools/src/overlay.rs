@@ -0,0 +1,52 @@
+//! Tools for multi-stage images such as the codex, which unpack the next
+//! stage of themselves into an array and switch to it with Load Program.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Writes every program loaded from a non-zero array to its own image file.
+///
+/// Stage `n` is written to `stage-n.um` in the target directory, and a line
+/// describing it is appended to `stages.txt` there:
+///
+/// ```text
+/// stage-1.um entry=0x0000 array=3 platters=894994
+/// ```
+///
+/// A stage can then be run directly with `um-32 --entry 0x0000 stage-1.um`.
+/// That only works for stages that don't depend on registers or arrays set
+/// up by earlier ones, which holds for the codex's unpacking stages.
+pub struct OverlayDumper {
+    dir: PathBuf,
+    stage: usize,
+    manifest: File,
+}
+
+impl OverlayDumper {
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let manifest = File::create(dir.join("stages.txt"))?;
+        Ok(Self {
+            dir,
+            stage: 0,
+            manifest,
+        })
+    }
+
+    pub fn dump(&mut self, array: u32, entry: u32, program: &[u32]) -> io::Result<()> {
+        self.stage += 1;
+        let name = format!("stage-{}.um", self.stage);
+        let image: Vec<u8> = program.iter().flat_map(|w| w.to_be_bytes()).collect();
+        std::fs::write(self.dir.join(&name), image)?;
+        writeln!(
+            self.manifest,
+            "{name} entry={entry:#06x} array={array} platters={}",
+            program.len()
+        )?;
+        self.manifest.flush()
+    }
+}
@@ -0,0 +1,232 @@
+//! Building UM program images from Rust.
+
+use um_core::Instruction;
+
+pub use tour::tour;
+
+mod tour;
+
+/// A position in a program that can be jumped to or loaded as a value,
+/// created with [`ProgramBuilder::label`] and placed with
+/// [`ProgramBuilder::bind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Label(usize);
+
+/// Emits platters one instruction at a time.
+///
+/// Registers are numbered 0 through 7. Labels may be used before they are
+/// bound; their addresses are patched in by [`ProgramBuilder::build`].
+#[derive(Default)]
+pub struct ProgramBuilder {
+    words: Vec<u32>,
+    labels: Vec<Option<u32>>,
+    fixups: Vec<(usize, Label)>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> u32 {
+        self.words.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        assert!(self.labels[label.0].is_none(), "label bound twice");
+        self.labels[label.0] = Some(self.len());
+    }
+
+    pub fn here(&mut self) -> Label {
+        let label = self.label();
+        self.bind(label);
+        label
+    }
+
+    pub fn word(&mut self, word: u32) {
+        self.words.push(word);
+    }
+
+    /// Emits `instruction`; the methods named for instructions are
+    /// shorthand for this.
+    ///
+    /// # Panics
+    ///
+    /// As [`Instruction::encode`] does.
+    pub fn instruction(&mut self, instruction: Instruction) {
+        self.word(instruction.encode());
+    }
+
+    pub fn cmov(&mut self, a: u32, b: u32, c: u32) {
+        self.instruction(Instruction::Cmov { a, b, c });
+    }
+
+    pub fn index(&mut self, a: u32, b: u32, c: u32) {
+        self.instruction(Instruction::Index { a, b, c });
+    }
+
+    pub fn amend(&mut self, a: u32, b: u32, c: u32) {
+        self.instruction(Instruction::Amend { a, b, c });
+    }
+
+    pub fn add(&mut self, a: u32, b: u32, c: u32) {
+        self.instruction(Instruction::Add { a, b, c });
+    }
+
+    pub fn mul(&mut self, a: u32, b: u32, c: u32) {
+        self.instruction(Instruction::Mul { a, b, c });
+    }
+
+    pub fn div(&mut self, a: u32, b: u32, c: u32) {
+        self.instruction(Instruction::Div { a, b, c });
+    }
+
+    pub fn nand(&mut self, a: u32, b: u32, c: u32) {
+        self.instruction(Instruction::Nand { a, b, c });
+    }
+
+    pub fn halt(&mut self) {
+        self.instruction(Instruction::Halt);
+    }
+
+    pub fn alloc(&mut self, b: u32, c: u32) {
+        self.instruction(Instruction::Alloc { b, c });
+    }
+
+    pub fn abandon(&mut self, c: u32) {
+        self.instruction(Instruction::Abandon { c });
+    }
+
+    pub fn output(&mut self, c: u32) {
+        self.instruction(Instruction::Output { c });
+    }
+
+    pub fn input(&mut self, c: u32) {
+        self.instruction(Instruction::Input { c });
+    }
+
+    pub fn load_program(&mut self, b: u32, c: u32) {
+        self.instruction(Instruction::LoadProgram { b, c });
+    }
+
+    pub fn ortho(&mut self, a: u32, value: u32) {
+        self.instruction(Instruction::Ortho { a, value });
+    }
+
+    /// A Host Call, for machines built with extensions: the service in
+    /// register `b`, with arguments in `a` and `c` and the result in `a`.
+    pub fn host(&mut self, a: u32, b: u32, c: u32) {
+        self.instruction(Instruction::Host { a, b, c });
+    }
+
+    /// Loads the address of `label` into register `a`.
+    pub fn ortho_label(&mut self, a: u32, label: Label) {
+        self.fixups.push((self.words.len(), label));
+        self.ortho(a, 0);
+    }
+
+    /// Emits the address of `label` as a data platter.
+    pub fn address(&mut self, label: Label) {
+        self.fixups.push((self.words.len(), label));
+        self.word(0);
+    }
+
+    /// Loads any 32-bit value into `a`, using `tmp` for values too wide for
+    /// a single orthography.
+    pub fn load(&mut self, a: u32, tmp: u32, value: u32) {
+        if value < 1 << 25 {
+            self.ortho(a, value);
+        } else {
+            self.ortho(a, value >> 16);
+            self.ortho(tmp, 1 << 16);
+            self.mul(a, a, tmp);
+            self.ortho(tmp, value & 0xffff);
+            self.add(a, a, tmp);
+        }
+    }
+
+    /// `a = b - c`, clobbering `tmp`.
+    pub fn sub(&mut self, a: u32, b: u32, c: u32, tmp: u32) {
+        self.nand(tmp, c, c);
+        self.add(a, b, tmp);
+        self.ortho(tmp, 1);
+        self.add(a, a, tmp);
+    }
+
+    /// Jumps to `label` within array 0. `zero` must hold 0.
+    pub fn jump(&mut self, label: Label, zero: u32, tmp: u32) {
+        self.ortho_label(tmp, label);
+        self.load_program(zero, tmp);
+    }
+
+    /// Jumps to `label` if `cond` is non-zero. `zero` must hold 0.
+    pub fn jump_if(&mut self, cond: u32, label: Label, zero: u32, tmps: [u32; 2]) {
+        let next = self.label();
+        self.ortho_label(tmps[0], next);
+        self.ortho_label(tmps[1], label);
+        self.cmov(tmps[0], tmps[1], cond);
+        self.load_program(zero, tmps[0]);
+        self.bind(next);
+    }
+
+    /// Jumps to `label` if register `reg` equals `value`. `zero` must hold 0.
+    pub fn jump_if_eq(&mut self, reg: u32, value: u32, label: Label, zero: u32, tmps: [u32; 3]) {
+        let [diff, t0, t1] = tmps;
+        self.load(diff, t0, value);
+        self.sub(diff, reg, diff, t0);
+        // diff is zero exactly when reg == value; turn that into a
+        // non-zero condition for jump_if.
+        let next = self.label();
+        self.ortho_label(t0, label);
+        self.ortho_label(t1, next);
+        self.cmov(t0, t1, diff);
+        self.load_program(zero, t0);
+        self.bind(next);
+    }
+
+    /// Calls the subroutine at `label`, which returns by jumping to the
+    /// address left in `ret`. `zero` must hold 0.
+    pub fn call(&mut self, label: Label, ret: u32, zero: u32, tmp: u32) {
+        let back = self.label();
+        self.ortho_label(ret, back);
+        self.jump(label, zero, tmp);
+        self.bind(back);
+    }
+
+    /// Returns from a subroutine entered with [`ProgramBuilder::call`].
+    pub fn ret(&mut self, ret: u32, zero: u32) {
+        self.load_program(zero, ret);
+    }
+
+    /// Outputs each byte of `text`, clobbering `tmp`.
+    pub fn print(&mut self, tmp: u32, text: &str) {
+        for b in text.bytes() {
+            self.ortho(tmp, b as u32);
+            self.output(tmp);
+        }
+    }
+
+    pub fn build(mut self) -> Vec<u32> {
+        for (at, label) in self.fixups.iter() {
+            let addr = self.labels[label.0].expect("label used but never bound");
+            assert!(addr < 1 << 25, "label address out of orthography range");
+            self.words[*at] |= addr;
+        }
+        self.words
+    }
+
+    /// Builds the program as a big-endian image accepted by
+    /// [`Machine::extend_from`](um_core::Machine::extend_from).
+    pub fn build_image(self) -> Vec<u8> {
+        self.build().iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+}
@@ -0,0 +1,192 @@
+use super::ProgramBuilder;
+
+const ZERO: u32 = 0;
+const CH: u32 = 1;
+const T0: u32 = 2;
+const T1: u32 = 3;
+const T2: u32 = 4;
+const ARR: u32 = 5;
+const N: u32 = 6;
+const RET: u32 = 7;
+
+const EOF: u32 = u32::MAX;
+
+const MENU: &str = "\
+UM-32 tour
+  e  echo a line backwards (Input, Allocation, Amendment, Index)
+  a  allocate and abandon arrays, showing identifier reuse
+  d  divide by zero (stops the machine with an error)
+  o  read past the end of an array (stops the machine with an error)
+  x  execute an invalid instruction (stops the machine with an error)
+  h  show this menu again
+  q  quit
+";
+
+/// An interactive program that demonstrates console I/O, allocation, and
+/// the machine's error reporting.
+pub fn tour() -> Vec<u8> {
+    let mut p = ProgramBuilder::new();
+    let menu = p.label();
+    let prompt = p.label();
+    let echo = p.label();
+    let allocs = p.label();
+    let div_zero = p.label();
+    let out_of_bounds = p.label();
+    let invalid = p.label();
+    let quit = p.label();
+    let skip_line = p.label();
+    let print_number = p.label();
+    let slots = [p.label(), p.label(), p.label()];
+    let tmps = [T0, T1, T2];
+
+    p.bind(menu);
+    p.print(T0, MENU);
+
+    p.bind(prompt);
+    p.print(T0, "> ");
+    p.input(CH);
+    p.jump_if_eq(CH, EOF, quit, ZERO, tmps);
+    p.jump_if_eq(CH, b'\n' as u32, prompt, ZERO, tmps);
+    p.call(skip_line, RET, ZERO, T0);
+    for (ch, label) in [
+        (b'e', echo),
+        (b'a', allocs),
+        (b'd', div_zero),
+        (b'o', out_of_bounds),
+        (b'x', invalid),
+        (b'h', menu),
+        (b'q', quit),
+    ] {
+        p.jump_if_eq(N, ch as u32, label, ZERO, tmps);
+    }
+    p.print(T0, "Unknown command, type h for help.\n");
+    p.jump(prompt, ZERO, T0);
+
+    // Reads a line into a fresh 256-platter array, then prints it from the
+    // end back to the start.
+    p.bind(echo);
+    p.print(T0, "Type a line and it will be printed backwards:\n");
+    p.ortho(N, 256);
+    p.alloc(ARR, N);
+    p.ortho(N, 0);
+    let read = p.here();
+    let reverse = p.label();
+    p.input(CH);
+    p.jump_if_eq(CH, b'\n' as u32, reverse, ZERO, tmps);
+    p.jump_if_eq(CH, EOF, reverse, ZERO, tmps);
+    p.jump_if_eq(N, 256, reverse, ZERO, tmps);
+    p.amend(ARR, N, CH);
+    p.ortho(T0, 1);
+    p.add(N, N, T0);
+    p.jump(read, ZERO, T0);
+    p.bind(reverse);
+    let done = p.label();
+    p.jump_if_eq(N, 0, done, ZERO, tmps);
+    p.ortho(T0, 1);
+    p.sub(N, N, T0, T1);
+    p.index(CH, ARR, N);
+    p.output(CH);
+    p.jump(reverse, ZERO, T0);
+    p.bind(done);
+    p.print(T0, "\n");
+    p.abandon(ARR);
+    p.jump(prompt, ZERO, T0);
+
+    // Array identifiers are kept in data platters at the end of array 0.
+    p.bind(allocs);
+    p.print(T0, "Allocating three arrays of 10 platters:\n");
+    for slot in slots {
+        p.ortho(N, 10);
+        p.alloc(ARR, N);
+        p.ortho_label(T0, slot);
+        p.amend(ZERO, T0, ARR);
+        p.print(T0, "  array ");
+        p.add(N, ARR, ZERO);
+        p.call(print_number, RET, ZERO, T0);
+        p.print(T0, "\n");
+    }
+    p.print(T0, "Abandoning the second one.\n");
+    p.ortho_label(T0, slots[1]);
+    p.index(ARR, ZERO, T0);
+    p.abandon(ARR);
+    p.print(T0, "Allocating again reuses its identifier: ");
+    p.ortho(N, 10);
+    p.alloc(ARR, N);
+    p.ortho_label(T0, slots[1]);
+    p.amend(ZERO, T0, ARR);
+    p.add(N, ARR, ZERO);
+    p.call(print_number, RET, ZERO, T0);
+    p.print(T0, "\n");
+    for slot in slots {
+        p.ortho_label(T0, slot);
+        p.index(ARR, ZERO, T0);
+        p.abandon(ARR);
+    }
+    p.jump(prompt, ZERO, T0);
+
+    p.bind(div_zero);
+    p.print(T0, "Dividing 1 by 0...\n");
+    p.ortho(T0, 1);
+    p.div(T0, T0, ZERO);
+
+    p.bind(out_of_bounds);
+    p.print(T0, "Reading offset 10 of a 10-platter array...\n");
+    p.ortho(N, 10);
+    p.alloc(ARR, N);
+    p.index(CH, ARR, N);
+
+    p.bind(invalid);
+    p.print(T0, "Executing opcode 14...\n");
+    p.word(14 << 28);
+
+    p.bind(quit);
+    p.print(T0, "Bye.\n");
+    p.halt();
+
+    // Consumes input up to and including the end of the current line,
+    // keeping the line's first character in N for the dispatcher.
+    p.bind(skip_line);
+    p.add(N, CH, ZERO);
+    let skip = p.here();
+    let skipped = p.label();
+    p.jump_if_eq(CH, b'\n' as u32, skipped, ZERO, tmps);
+    p.jump_if_eq(CH, EOF, skipped, ZERO, tmps);
+    p.input(CH);
+    p.jump(skip, ZERO, T0);
+    p.bind(skipped);
+    p.ret(RET, ZERO);
+
+    // Prints N in decimal, clobbering N, CH, and the temporaries.
+    p.bind(print_number);
+    p.ortho(CH, 1);
+    let grow = p.here();
+    let bigger = p.label();
+    let digits = p.label();
+    p.div(T0, N, CH);
+    p.ortho(T1, 10);
+    p.div(T0, T0, T1);
+    p.jump_if(T0, bigger, ZERO, [T1, T2]);
+    p.jump(digits, ZERO, T1);
+    p.bind(bigger);
+    p.ortho(T1, 10);
+    p.mul(CH, CH, T1);
+    p.jump(grow, ZERO, T1);
+    p.bind(digits);
+    p.div(T0, N, CH);
+    p.ortho(T1, b'0' as u32);
+    p.add(T1, T0, T1);
+    p.output(T1);
+    p.mul(T1, T0, CH);
+    p.sub(N, N, T1, T2);
+    p.ortho(T1, 10);
+    p.div(CH, CH, T1);
+    p.jump_if(CH, digits, ZERO, [T1, T2]);
+    p.ret(RET, ZERO);
+
+    for slot in slots {
+        p.bind(slot);
+        p.word(0);
+    }
+
+    p.build_image()
+}
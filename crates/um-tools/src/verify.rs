@@ -0,0 +1,259 @@
+//! Checking a program image before running it: which platters hold no
+//! instruction, which look like code and which like data, and where code
+//! runs on into data or off the end of the image.
+//!
+//! Telling code from data is a guess. A platter looks like code if it
+//! decodes and is not 0, so text and small numbers look like code too,
+//! mostly Conditional Moves. Runs of fewer than
+//! [`MIN_CODE`] such platters amid data count as data, unless they end
+//! the way code can, in Halt or Load Program, as do platters after the
+//! last Halt or Load Program of a longer run. Code that nothing could
+//! jump into, and data that happens to look like code, both go unnoticed.
+
+use std::io::{self, Write};
+
+use um_core::Instruction;
+
+/// The fewest platters that look like code for a run of them to count as
+/// code on its own.
+pub const MIN_CODE: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Code,
+    Data,
+}
+
+/// A run of platters of the same kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub start: u32,
+    pub len: u32,
+    pub kind: Kind,
+}
+
+/// What [`verify`] found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub platters: u32,
+    /// Bytes after the last whole platter, which a machine won't load.
+    pub trailing_bytes: usize,
+    /// How many platters have each opcode, instruction or not.
+    pub ops: [u32; 16],
+    /// The addresses of platters that hold no instruction: opcode 15, and
+    /// 14 unless Host Call is allowed.
+    pub invalid: Vec<u32>,
+    /// Code and data, in order, covering the image.
+    pub regions: Vec<Region>,
+    /// The addresses of the last platter of each code region that neither
+    /// halts nor loads a program, so that execution would run on into the
+    /// data after it, or off the end of the image.
+    pub falls_through: Vec<u32>,
+}
+
+impl Report {
+    pub fn code_platters(&self) -> u32 {
+        self.regions
+            .iter()
+            .filter(|r| r.kind == Kind::Code)
+            .map(|r| r.len)
+            .sum()
+    }
+
+    /// Whether nothing is malformed: the image holds at least one platter
+    /// and no trailing bytes, and its last code doesn't run off the end.
+    /// Code running on into data is left out, since the data may only look
+    /// like code, and so are invalid platters in data.
+    pub fn is_clean(&self) -> bool {
+        self.platters != 0
+            && self.trailing_bytes == 0
+            && self.falls_through.last() != Some(&(self.platters - 1))
+    }
+
+    /// Writes the report as one JSON object:
+    ///
+    /// ```text
+    /// {"platters":6,"trailing_bytes":0,"ops":[0,0,...],"invalid":[],
+    ///  "code_platters":6,"regions":[{"start":0,"len":6,"kind":"code"}],
+    ///  "falls_through":[],"clean":true}
+    /// ```
+    ///
+    /// on a single line.
+    pub fn write_json(&self, w: &mut impl Write) -> io::Result<()> {
+        let list = |items: &[u32]| {
+            let items: Vec<String> = items.iter().map(u32::to_string).collect();
+            items.join(",")
+        };
+        write!(
+            w,
+            r#"{{"platters":{},"trailing_bytes":{},"ops":[{}],"invalid":[{}],"code_platters":{},"regions":["#,
+            self.platters,
+            self.trailing_bytes,
+            list(&self.ops),
+            list(&self.invalid),
+            self.code_platters(),
+        )?;
+        for (i, region) in self.regions.iter().enumerate() {
+            let kind = match region.kind {
+                Kind::Code => "code",
+                Kind::Data => "data",
+            };
+            write!(
+                w,
+                r#"{}{{"start":{},"len":{},"kind":"{kind}"}}"#,
+                if i == 0 { "" } else { "," },
+                region.start,
+                region.len,
+            )?;
+        }
+        writeln!(
+            w,
+            r#"],"falls_through":[{}],"clean":{}}}"#,
+            list(&self.falls_through),
+            self.is_clean(),
+        )
+    }
+}
+
+/// Checks the big-endian program image `image`. With `host_calls`, opcode
+/// 14 is Host Call rather than invalid.
+pub fn verify(image: &[u8], host_calls: bool) -> Report {
+    let words = crate::disasm::image_words(image);
+    let mut report = Report {
+        platters: words.len() as u32,
+        trailing_bytes: image.len() % 4,
+        ops: [0; 16],
+        invalid: Vec::new(),
+        regions: Vec::new(),
+        falls_through: Vec::new(),
+    };
+    let mut code = Vec::with_capacity(words.len());
+    for (addr, &word) in (0..).zip(&words) {
+        report.ops[(word >> 28) as usize] += 1;
+        let instruction = match Instruction::decode(word) {
+            Ok(Instruction::Host { .. }) if !host_calls => None,
+            Ok(instruction) => Some(instruction),
+            Err(_) => None,
+        };
+        if instruction.is_none() {
+            report.invalid.push(addr);
+        }
+        code.push(instruction.is_some() && word != 0);
+    }
+
+    let ends = |addr: usize| matches!(words[addr] >> 28, 7 | 12);
+    // Runs of code too short to stand alone are data, and so is the tail of
+    // a run after its last Halt or Load Program, as strings and tables of
+    // small numbers after a program tend to be.
+    for (start, end, kind) in runs(&code) {
+        if !kind || ends(end - 1) {
+            continue;
+        }
+        if end - start < MIN_CODE as usize {
+            code[start..end].fill(false);
+        } else if let Some(last) = (start..end).rev().find(|&addr| ends(addr)) {
+            code[last + 1..end].fill(false);
+        }
+    }
+
+    for (start, end, kind) in runs(&code) {
+        report.regions.push(Region {
+            start: start as u32,
+            len: (end - start) as u32,
+            kind: if kind { Kind::Code } else { Kind::Data },
+        });
+        if kind && !ends(end - 1) {
+            report.falls_through.push(end as u32 - 1);
+        }
+    }
+    report
+}
+
+// The runs of equal values in `code`, as start, end and value.
+fn runs(code: &[bool]) -> Vec<(usize, usize, bool)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    while start < code.len() {
+        let kind = code[start];
+        let len = code[start..].iter().take_while(|&&c| c == kind).count();
+        runs.push((start, start + len, kind));
+        start += len;
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::ProgramBuilder;
+
+    #[test]
+    fn code_and_data() {
+        // Code printing text stored after the Halt, then a table of zeros.
+        let mut p = ProgramBuilder::new();
+        p.ortho(1, 7);
+        p.index(2, 0, 1);
+        p.output(2);
+        p.output(2);
+        p.halt();
+        p.word(0);
+        p.word(0);
+        p.word(b'h' as u32);
+        p.word(0xf000_0000);
+        let report = verify(&p.build_image(), false);
+        assert_eq!(report.platters, 9);
+        assert_eq!(report.code_platters(), 5);
+        assert_eq!(report.ops[10], 2);
+        assert_eq!(report.ops[0], 3);
+        assert_eq!(report.invalid, [8]);
+        assert_eq!(
+            report.regions,
+            [
+                Region {
+                    start: 0,
+                    len: 5,
+                    kind: Kind::Code
+                },
+                Region {
+                    start: 5,
+                    len: 4,
+                    kind: Kind::Data
+                },
+            ]
+        );
+        assert!(report.falls_through.is_empty());
+        assert!(report.is_clean());
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with(r#"{"platters":9,"trailing_bytes":0,"ops":[3,1,"#));
+        assert!(json.ends_with(
+            r#""regions":[{"start":0,"len":5,"kind":"code"},{"start":5,"len":4,"kind":"data"}],"falls_through":[],"clean":true}
+"#
+        ));
+    }
+
+    #[test]
+    fn host_calls() {
+        // Host Call is only an instruction when asked for.
+        assert_eq!(verify(&[0xe0, 0, 0, 0x61], false).invalid, [0]);
+        assert!(verify(&[0xe0, 0, 0, 0x61], true).invalid.is_empty());
+    }
+
+    #[test]
+    fn falls_through() {
+        // Without the Halt the code runs off the end, and a partial platter
+        // is left over.
+        let mut p = ProgramBuilder::new();
+        for _ in 0..MIN_CODE {
+            p.output(1);
+        }
+        let mut image = p.build_image();
+        image.push(0);
+        let report = verify(&image, false);
+        assert_eq!(report.falls_through, [3]);
+        assert_eq!(report.trailing_bytes, 1);
+        assert!(!report.is_clean());
+        assert!(!verify(&[], false).is_clean());
+    }
+}
@@ -0,0 +1,134 @@
+//! um-32 in the browser: a [`WebMachine`] that JavaScript runs a slice at a
+//! time, so that a program waiting for input never blocks the page.
+//!
+//! Build the package and serve the front-end with
+//!
+//! ```text
+//! wasm-pack build --target web --out-dir www/pkg crates/um-wasm
+//! python3 -m http.server -d crates/um-wasm/www
+//! ```
+//!
+//! and open the page to pick a program image, such as UMIX's, and run it
+//! in an xterm.js terminal.
+
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use um_core::{Error, FlushPolicy, Machine, Stop};
+use wasm_bindgen::prelude::*;
+
+/// What the machine is doing when [`WebMachine::run`] returns.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// It used up the instructions it was given; call `run` again.
+    Running,
+    /// It is waiting for console input; call `input` and then `run`.
+    Waiting,
+    /// It halted.
+    Halted,
+}
+
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Stands in for stdin once the queued input runs out: would block until
+// `end_input`, then at end of file. Input fails with the WouldBlock error,
+// leaving the instruction to be executed again once there is input.
+#[derive(Clone, Default)]
+struct Console(Arc<AtomicBool>);
+
+impl Read for Console {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        if self.0.load(Ordering::Relaxed) {
+            Ok(0)
+        } else {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+}
+
+/// A machine with its console held in memory.
+#[wasm_bindgen]
+pub struct WebMachine {
+    machine: Machine,
+    output: Output,
+    ended: Console,
+    halted: bool,
+}
+
+#[wasm_bindgen]
+impl WebMachine {
+    /// Loads a program image.
+    #[wasm_bindgen(constructor)]
+    pub fn new(image: &[u8]) -> Result<WebMachine, JsError> {
+        let output = Output::default();
+        let ended = Console::default();
+        let mut machine = Machine::builder()
+            .stdin(ended.clone())
+            .stdout(output.clone())
+            .flush_policy(FlushPolicy::Input)
+            .build();
+        machine.extend_from(image)?;
+        Ok(WebMachine {
+            machine,
+            output,
+            ended,
+            halted: false,
+        })
+    }
+
+    /// Queues console input.
+    pub fn input(&mut self, bytes: &[u8]) {
+        self.machine.add_input_bytes(bytes);
+    }
+
+    /// Signals the end of console input, once what is queued is used up.
+    pub fn end_input(&mut self) {
+        self.ended.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Executes at most `budget` instructions.
+    pub fn run(&mut self, budget: u32) -> Result<Status, JsError> {
+        if self.halted {
+            return Ok(Status::Halted);
+        }
+        let limit = self.machine.executed() + u64::from(budget);
+        self.machine.set_step_limit(Some(limit));
+        match self.machine.run_until_stop() {
+            Ok(Stop::Halt) => {
+                self.halted = true;
+                Ok(Status::Halted)
+            }
+            Ok(_) => Ok(Status::Running),
+            Err(Error::IO(e)) if e.kind() == io::ErrorKind::WouldBlock => Ok(Status::Waiting),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Takes the output written since the last call.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output.0.lock().unwrap())
+    }
+
+    /// The instructions executed so far.
+    pub fn executed(&self) -> f64 {
+        self.machine.executed() as f64
+    }
+}
@@ -0,0 +1,22 @@
+use um_wasm::{Status, WebMachine};
+
+// Copies its input to its output, then prints how many bytes it read.
+const COUNT: &[u8] = include_bytes!("../../../tests/golden/count.um");
+
+#[test]
+fn yields_for_input() {
+    let mut machine = WebMachine::new(COUNT).unwrap();
+    assert_eq!(machine.run(1_000).unwrap(), Status::Waiting);
+    assert_eq!(machine.run(1_000).unwrap(), Status::Waiting);
+
+    machine.input(b"hi\n");
+    assert_eq!(machine.run(1_000).unwrap(), Status::Waiting);
+    assert_eq!(machine.take_output(), b"hi\n");
+
+    machine.input(b"there\n");
+    machine.end_input();
+    assert_eq!(machine.run(5).unwrap(), Status::Running);
+    while machine.run(5).unwrap() == Status::Running {}
+    assert_eq!(machine.take_output(), b"there\n9\n");
+    assert_eq!(machine.run(5).unwrap(), Status::Halted);
+}
@@ -0,0 +1,91 @@
+// Runs a program image picked from disk in an xterm.js terminal. Lines are
+// edited and echoed here, as a terminal in cooked mode would, and sent to
+// the program when Enter is pressed; Ctrl-D ends its input.
+
+import init, { Status, WebMachine } from "./pkg/um_wasm.js";
+
+// Instructions per slice; small enough to keep the page responsive.
+const BUDGET = 2_000_000;
+
+const term = new Terminal({ convertEol: true, cols: 100, rows: 40 });
+term.open(document.getElementById("terminal"));
+const status = document.getElementById("status");
+const encoder = new TextEncoder();
+
+let machine = null;
+let line = "";
+let scheduled = false;
+
+function schedule() {
+  if (!scheduled) {
+    scheduled = true;
+    setTimeout(slice, 0);
+  }
+}
+
+function slice() {
+  scheduled = false;
+  let state;
+  try {
+    state = machine.run(BUDGET);
+  } catch (e) {
+    term.write(`\r\n[um-32: ${e.message ?? e}]\r\n`);
+    status.textContent = "failed";
+    machine = null;
+    return;
+  }
+  term.write(machine.take_output());
+  status.textContent = `${machine.executed().toLocaleString()} instructions`;
+  if (state === Status.Running) {
+    schedule();
+  } else if (state === Status.Halted) {
+    status.textContent += ", halted";
+    machine = null;
+  }
+}
+
+term.onData((data) => {
+  if (!machine) {
+    return;
+  }
+  for (const ch of data) {
+    if (ch === "\r") {
+      term.write("\r\n");
+      machine.input(encoder.encode(line + "\n"));
+      line = "";
+      schedule();
+    } else if (ch === "\x7f") {
+      if (line.length > 0) {
+        line = line.slice(0, -1);
+        term.write("\b \b");
+      }
+    } else if (ch === "\x04") {
+      machine.input(encoder.encode(line));
+      machine.end_input();
+      line = "";
+      schedule();
+    } else if (ch >= " ") {
+      line += ch;
+      term.write(ch);
+    }
+  }
+});
+
+await init();
+
+document.getElementById("image").addEventListener("change", async (event) => {
+  const file = event.target.files[0];
+  if (!file) {
+    return;
+  }
+  term.reset();
+  line = "";
+  try {
+    machine = new WebMachine(new Uint8Array(await file.arrayBuffer()));
+  } catch (e) {
+    term.write(`[um-32: ${e.message ?? e}]\r\n`);
+    return;
+  }
+  term.focus();
+  schedule();
+});
@@ -0,0 +1,40 @@
+//! Runs arbitrary bytes as a program image, which must only ever end in a
+//! halt, an error, or the step limit, never a panic.
+//!
+//! The first byte chooses the backend and the rest is the image, including
+//! images whose length isn't a multiple of 4. Run with
+//! `cargo +nightly fuzz run machine` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use um_core::{Backend, Machine};
+
+// Enough for loops to run a while without slowing the fuzzer down much.
+const STEPS: u64 = 100_000;
+const MEMORY: u64 = 1 << 20;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&choice, image)) = data.split_first() else {
+        return;
+    };
+    let backend = match choice % 3 {
+        0 => Backend::Interpreter,
+        1 => Backend::Decoded,
+        _ => Backend::Threaded,
+    };
+    let mut machine = Machine::builder()
+        .backend(backend)
+        .max_alloc(MEMORY as u32)
+        .max_memory(MEMORY)
+        .input_bytes(b"fuzz\n")
+        .stdin(std::io::empty())
+        .stdout(std::io::sink())
+        .echo(false)
+        .build();
+    if machine.extend_from(image).is_err() {
+        return;
+    }
+    machine.set_step_limit(Some(STEPS));
+    let _ = machine.run_until_stop();
+});
@@ -0,0 +1,21 @@
+//! An interpreter for the UM-32 "Universal Machine" from the ICFP 2006
+//! programming contest.
+//!
+//! This crate brings together the machine from `um-core` and the tools
+//! from `um-tools`; embedders that only run programs can depend on
+//! `um-core` alone. Everything `um-core` exports is re-exported here
+//! unchanged, so its documentation is the one list of the machine's public
+//! API, and [`prelude`] has the same items. On top of that are the
+//! [`program`] and [`asm`] modules for building images from Rust or text,
+//! the [`disasm`] module for reading them back, the [`verify`] module for
+//! checking them, the [`overlay`] module for multi-stage images, and the
+//! [`compile`] module for translating images to Rust. Anything not
+//! reachable from there is an implementation detail and may change between
+//! releases.
+
+pub use um_core::*;
+pub use um_tools::{asm, compile, disasm, overlay, program, verify};
+
+pub mod prelude {
+    pub use um_core::prelude::*;
+}
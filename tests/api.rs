@@ -0,0 +1,144 @@
+// Pins the public API so accidental breaking changes fail to compile.
+
+use std::{path::Path, time::Instant};
+
+use um_32::prelude::*;
+
+#[test]
+fn machine_signatures() {
+    let _: fn() -> Machine = Machine::default;
+    let _: fn(&Machine) -> u32 = Machine::pc;
+    let _: fn(&mut Machine, u32) = Machine::set_pc;
+    let _: fn(&Machine) -> &[u32; 8] = Machine::registers;
+    let _: fn(&mut Machine) -> &mut [u32; 8] = Machine::registers_mut;
+    let _: fn(&Machine, u32) -> Option<&[u32]> = Machine::array;
+    let _: fn(&mut Machine, u32) -> Option<&mut [u32]> = Machine::array_mut;
+    let _: fn(&Machine) -> usize = Machine::active_array_count;
+    let _: fn(&mut Machine, &str) = Machine::add_input;
+    let _: fn(&mut Machine, &[u8]) = Machine::add_input_bytes;
+    let _: fn(&mut Machine, &'static [u8]) -> Result<(), Error> = Machine::extend_from;
+    let _: fn(&mut Machine) -> Result<(), Error> = Machine::run;
+    let _: fn(&mut Machine) -> Result<Stop, Error> = Machine::run_until_stop;
+    let _: fn(&mut Machine) -> Result<Stop, Error> = Machine::step;
+    let _: fn(&mut Machine, u32) = Machine::add_breakpoint;
+    let _: fn(&mut Machine, u32) -> bool = Machine::remove_breakpoint;
+    let _: fn(&Machine) -> Vec<u32> = Machine::breakpoints;
+    let _: fn(&mut Machine, u32) -> bool = Machine::remove_condition;
+    let _: fn(&Machine) -> u64 = Machine::executed;
+    let _: fn(&mut Machine, u64, usize) = Machine::record_history;
+    let _: fn(&mut Machine) = Machine::forget_history;
+    let _: fn(&Machine) -> Option<u64> = Machine::history_start;
+    let _: fn(&mut Machine, u64) -> Result<(), Error> = Machine::rewind;
+    let _: fn(&mut Machine) -> Result<Option<Stop>, Error> = Machine::reverse_continue;
+    let _: fn(&Machine) -> Vec<u32> = Machine::conditions;
+    let _: fn(&Machine) -> Interrupter = Machine::interrupter;
+    let _: fn(&mut Machine, Option<u64>) = Machine::set_step_limit;
+    let _: fn(&Machine) -> Option<u64> = Machine::step_limit;
+    let _: fn(&mut Machine, Option<Instant>) = Machine::set_deadline;
+    let _: fn(&Machine) -> Option<Instant> = Machine::deadline;
+    let _: fn(&Machine) -> Option<&[OpStats; 14]> = Machine::op_stats;
+    let _: fn(&Machine) -> Option<&[u64]> = Machine::pc_counts;
+    let _: fn(&Machine) -> MemoryStats = Machine::memory_stats;
+    let _: fn(&Machine) -> Option<Vec<AllocationSite>> = Machine::allocation_sites;
+    let _: fn(&Machine) -> Vec<(u32, u32)> = Machine::recent_instructions;
+    let _: fn(&mut Machine, u32, Watch) = Machine::watch_array;
+    let _: fn(&mut Machine, u32) = Machine::unwatch_array;
+    let _: fn(&Machine) -> Vec<(u32, Watch)> = Machine::watched_arrays;
+    let _: fn(&mut Machine, u32, u32, Watch) = Machine::watch_cell;
+    let _: fn(&mut Machine, u32, u32) = Machine::unwatch_cell;
+    let _: fn(&Machine) -> Vec<(u32, u32, Watch)> = Machine::watched_cells;
+    let _: fn(&Machine, &'static Path) -> Result<(), Error> = Machine::save_snapshot;
+    let _: fn(&'static Path) -> Result<Machine, Error> = Machine::load_snapshot;
+    let _: fn(&Machine, &mut Vec<u8>) -> Result<(), Error> = Machine::write_snapshot;
+    let _: fn(&mut &'static [u8]) -> Result<Machine, Error> = Machine::read_snapshot;
+    let _: fn(&Machine, &'static Path, &CoreDump) -> Result<(), Error> = Machine::save_core;
+    type Loaded = Result<(Machine, Option<CoreDump>), Error>;
+    let _: fn(&'static Path) -> Loaded = Machine::load_core;
+    let _: fn(&Machine, &mut Vec<u8>, &CoreDump) -> Result<(), Error> = Machine::write_core;
+    let _: fn(&mut &'static [u8]) -> Loaded = Machine::read_core;
+    let _: fn(&Machine) -> u64 = Machine::state_hash;
+    let _: fn(&Machine) -> Backend = Machine::backend;
+    let _: fn(&mut Machine) -> Machine = Machine::fork;
+    let _: fn(&mut Machine, u32) -> Result<u32, Error> = Machine::allocate_array;
+}
+
+#[test]
+fn builder_signatures() {
+    let _: fn() -> MachineBuilder = Machine::builder;
+    let _: fn() -> MachineBuilder = MachineBuilder::new;
+    let _: fn(MachineBuilder, &str) -> MachineBuilder = MachineBuilder::input;
+    let _: fn(MachineBuilder, &[u8]) -> MachineBuilder = MachineBuilder::input_bytes;
+    let _: fn(MachineBuilder, &'static [u8]) -> MachineBuilder = MachineBuilder::stdin;
+    let _: fn(MachineBuilder, Vec<u8>) -> MachineBuilder = MachineBuilder::stdout;
+    let _: fn(MachineBuilder, bool) -> MachineBuilder = MachineBuilder::immediate_output;
+    let _: fn(MachineBuilder, FlushPolicy) -> MachineBuilder = MachineBuilder::flush_policy;
+    let _: fn(MachineBuilder, bool) -> MachineBuilder = MachineBuilder::echo;
+    let _: fn(MachineBuilder, bool) -> MachineBuilder = MachineBuilder::eof_error;
+    let _: fn(MachineBuilder, Vec<u8>) -> MachineBuilder = MachineBuilder::tee_output;
+    let _: fn(MachineBuilder, Vec<u8>) -> MachineBuilder = MachineBuilder::trace;
+    let _: fn(MachineBuilder, bool) -> MachineBuilder = MachineBuilder::stats;
+    let _: fn(MachineBuilder, bool) -> MachineBuilder = MachineBuilder::profile;
+    let _: fn(MachineBuilder, bool) -> MachineBuilder = MachineBuilder::audit;
+    let _: fn(MachineBuilder, usize) -> MachineBuilder = MachineBuilder::recent_instructions;
+    let _: fn(MachineBuilder, u32) -> MachineBuilder = MachineBuilder::max_alloc;
+    let _: fn(MachineBuilder, u64) -> MachineBuilder = MachineBuilder::max_memory;
+    let _: fn(MachineBuilder, bool) -> MachineBuilder = MachineBuilder::detect_hangs;
+    let _: fn(MachineBuilder, Backend) -> MachineBuilder = MachineBuilder::backend;
+    let _: fn(MachineBuilder, Vec<u8>) -> MachineBuilder = MachineBuilder::record_journal;
+    let _: fn(MachineBuilder, Journal) -> MachineBuilder = MachineBuilder::replay_journal;
+    let _: fn(&'static [u8]) -> Result<Journal, Error> = Journal::read;
+    let _: fn(&Journal) -> &[u8] = Journal::output;
+    let _: fn(MachineBuilder) -> Machine = MachineBuilder::build;
+    let _: fn(MachineBuilder, Machine) -> Machine = MachineBuilder::build_from;
+}
+
+#[test]
+fn supervisor_signatures() {
+    let _: fn() -> Supervisor = Supervisor::new;
+    let _: fn(&mut Supervisor, Machine) -> Result<MachineId, Error> = Supervisor::spawn;
+    let _: fn(&mut Supervisor, MachineId, Machine) -> Result<(), Error> = Supervisor::resume;
+    let _: fn(&Supervisor) -> Vec<MachineId> = Supervisor::running;
+    let _: fn(&Supervisor, MachineId) -> bool = Supervisor::interrupt;
+    let _: fn(&Supervisor) = Supervisor::interrupt_all;
+    let _: fn(&mut Supervisor) -> Option<Event> = Supervisor::next_event;
+    let _: fn() -> (PipeWriter, PipeReader) = pipe;
+    let _: fn(Machine) -> Result<Spawned, Error> = Machine::spawn;
+    let _: fn(&Control) = Control::pause;
+    let _: fn(&Control) = Control::resume;
+    let _: fn(&Control) -> Result<Vec<u8>, Error> = Control::snapshot;
+    let _: fn(&Control) -> bool = Control::is_finished;
+    let _: fn(Control) -> (Machine, Result<Stop, Error>) = Control::stop;
+    let _: fn(Control) -> (Machine, Result<Stop, Error>) = Control::join;
+}
+
+#[test]
+fn root_exports() {
+    // The crate root has what the prelude has, under the same names.
+    let _: fn() -> um_32::Supervisor = um_32::Supervisor::new;
+    let _: fn() -> (um_32::PipeWriter, um_32::PipeReader) = um_32::pipe;
+    let _: fn(um_32::Machine) -> Result<um_32::Spawned, um_32::Error> = um_32::Machine::spawn;
+    let _: fn(&um_32::Journal) -> &[u8] = um_32::Journal::output;
+    let _: Option<(
+        &dyn um_32::Observer,
+        um_32::MachineEvent,
+        um_32::Control,
+        um_32::Event,
+        um_32::MachineId,
+    )> = None;
+}
+
+#[test]
+fn error_traits() {
+    fn is_error<E: std::error::Error + Send + Sync + 'static>() {}
+    is_error::<Error>();
+
+    let e = Error::from(std::io::Error::other("boom"));
+    assert!(std::error::Error::source(&e).is_some());
+    assert_eq!(e.to_string(), "I/O error: boom");
+}
+
+#[test]
+fn machine_is_send() {
+    fn is_send<T: Send>() {}
+    is_send::<Machine>();
+}
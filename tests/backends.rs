@@ -0,0 +1,98 @@
+// The decoded and threaded backends against the interpreter, on programs
+// that change their own code or load copies of it.
+
+mod common;
+
+use common::BACKENDS;
+use um_32::{prelude::*, program::ProgramBuilder};
+
+// Adds up the counter from 10000 down to 2, each time rewriting the
+// Orthography at `patched` to load the counter into V on the next pass. The
+// sum ends up in register 2.
+fn self_modifying_program() -> Vec<u8> {
+    const ZERO: u32 = 0;
+    const COUNT: u32 = 1;
+    const SUM: u32 = 2;
+    const T0: u32 = 3;
+    const T1: u32 = 4;
+    const PATCH: u32 = 5;
+    const V: u32 = 6;
+    const ORTHO: u32 = 7;
+
+    let mut p = ProgramBuilder::new();
+    let patched = p.label();
+    p.ortho(COUNT, 10_000);
+    p.ortho_label(PATCH, patched);
+    p.load(ORTHO, T1, 13 << 28 | V << 25);
+    let top = p.here();
+    p.bind(patched);
+    p.ortho(V, 0);
+    p.add(SUM, SUM, V);
+    p.add(T0, ORTHO, COUNT);
+    p.amend(ZERO, PATCH, T0);
+    p.ortho(T0, 1);
+    p.sub(COUNT, COUNT, T0, T1);
+    p.jump_if(COUNT, top, ZERO, [T0, T1]);
+    p.halt();
+    p.build_image()
+}
+
+fn run_with(backend: Backend, program: &[u8]) -> Machine {
+    let mut machine = Machine::builder().backend(backend).build();
+    machine.extend_from(program).unwrap();
+    machine.run().unwrap();
+    machine
+}
+
+#[test]
+fn decoded_matches_interpreter() {
+    let program = self_modifying_program();
+    let interpreted = run_with(Backend::Interpreter, &program);
+    let decoded = run_with(Backend::Decoded, &program);
+    assert_eq!(decoded.backend(), Backend::Decoded);
+    assert_eq!(decoded.registers()[2], 50_004_999);
+    assert_eq!(decoded.executed(), interpreted.executed());
+    assert_eq!(decoded.state_hash(), interpreted.state_hash());
+}
+
+#[test]
+fn threaded_matches_interpreter() {
+    let program = self_modifying_program();
+    let interpreted = run_with(Backend::Interpreter, &program);
+    let threaded = run_with(Backend::Threaded, &program);
+    assert_eq!(threaded.backend(), Backend::Threaded);
+    assert_eq!(threaded.registers()[2], 50_004_999);
+    assert_eq!(threaded.executed(), interpreted.executed());
+    assert_eq!(threaded.state_hash(), interpreted.state_hash());
+}
+
+#[test]
+fn loaded_program_is_a_copy() {
+    // The loaded program amends the array it was loaded from at 1, then
+    // itself at 3, which becomes a Halt.
+    const FIRST: u32 = 2 << 28 | 1 << 6 | 4 << 3 | 5;
+    const SECOND: u32 = 2 << 28 | 6 << 3 | 7;
+    const HALT: u32 = 7 << 28;
+
+    let mut p = ProgramBuilder::new();
+    p.ortho(2, 4);
+    p.alloc(1, 2);
+    p.load(3, 4, FIRST);
+    p.ortho(2, 0);
+    p.amend(1, 2, 3);
+    p.load(3, 4, SECOND);
+    p.ortho(2, 1);
+    p.amend(1, 2, 3);
+    p.ortho(4, 1);
+    p.ortho(5, 0x12345);
+    p.ortho(6, 3);
+    p.load(7, 2, HALT);
+    p.load_program(1, 0);
+    let program = p.build_image();
+
+    for backend in BACKENDS {
+        let machine = run_with(backend, &program);
+        assert_eq!(machine.array(0), Some(&[FIRST, SECOND, 0, HALT][..]));
+        assert_eq!(machine.array(1), Some(&[FIRST, 0x12345, 0, 0][..]));
+    }
+}
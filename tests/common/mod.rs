@@ -0,0 +1,55 @@
+// Fixtures shared by the integration tests. Each test crate uses some of
+// them.
+#![allow(dead_code)]
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use um_32::{Backend, Machine, MachineBuilder};
+
+pub const BACKENDS: [Backend; 3] = [Backend::Interpreter, Backend::Decoded, Backend::Threaded];
+
+// A writer that can be read back while the machine still holds a clone.
+#[derive(Clone, Default)]
+pub struct Shared(pub Arc<Mutex<Vec<u8>>>);
+
+impl Shared {
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn image(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_be_bytes()).collect()
+}
+
+// Builds a machine and loads `program` into array 0.
+pub fn load(builder: MachineBuilder, program: &[u32]) -> Machine {
+    let mut machine = builder.build();
+    machine.extend_from(&image(program)[..]).unwrap();
+    machine
+}
+
+// Counts r1 down from 100000 in 400003 instructions, and halts.
+pub const COUNTDOWN: [u32; 8] = [
+    0xd201_86a0, // ORTHO r1, 100000
+    0x6000_00c0, // NAND r3, r0, r0
+    0xd800_0003, // ORTHO r4, 3
+    0x3000_004b, // ADD r1, r1, r3
+    0xde00_0007, // ORTHO r7, 7
+    0x0000_01e1, // CMOV r7, r4, r1
+    0xc000_0007, // LOADPROG r0, r7
+    0x7000_0000, // HALT
+];
@@ -0,0 +1,263 @@
+// Machines on other threads and tasks: the supervisor and its pipes,
+// spawned machines and their controls, interrupts, and AsyncMachine.
+
+mod common;
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use common::{load, Shared, COUNTDOWN};
+use um_32::prelude::*;
+
+// Records the pc of each instruction about to run.
+struct Before(Arc<Mutex<Vec<u32>>>);
+
+impl Observer for Before {
+    fn before(&mut self, pc: u32, _: u32, _: &[u32; 8]) -> std::io::Result<()> {
+        self.0.lock().unwrap().push(pc);
+        Ok(())
+    }
+}
+
+// IN r1 ; OUT r1 ; HALT
+const ECHO: [u32; 3] = [0xb000_0001, 0xa000_0001, 0x7000_0000];
+
+// ORTHO r1, 0 ; LOADPROG r0, r1
+const SPIN: [u32; 2] = [0xd200_0000, 0xc000_0001];
+
+#[test]
+fn supervisor_pipeline() {
+    // hello prints a line, and count copies it and counts its bytes.
+    let (tx, rx) = pipe();
+    let mut hello = Machine::builder()
+        .stdin(std::io::empty())
+        .stdout(tx)
+        .build();
+    hello
+        .extend_from(&include_bytes!("golden/hello.um")[..])
+        .unwrap();
+    let out = Shared::default();
+    let mut count = Machine::builder().stdin(rx).stdout(out.clone()).build();
+    count
+        .extend_from(&include_bytes!("golden/count.um")[..])
+        .unwrap();
+    count.set_step_limit(Some(10));
+
+    let mut supervisor = Supervisor::new();
+    let count = supervisor.spawn(count).unwrap();
+    let hello = supervisor.spawn(hello).unwrap();
+    assert_eq!(supervisor.running(), [count, hello]);
+    assert!(supervisor.resume(count, Machine::default()).is_err());
+    let mut stops = Vec::new();
+    while let Some(event) = supervisor.next_event() {
+        let stop = event.result.unwrap();
+        stops.push((event.id, stop));
+        if let Stop::StepLimit { .. } = stop {
+            let mut machine = event.machine;
+            machine.set_step_limit(None);
+            supervisor.resume(event.id, machine).unwrap();
+        }
+    }
+    let stopped = |id| -> Vec<Stop> {
+        stops
+            .iter()
+            .filter(|(stopped, _)| *stopped == id)
+            .map(|(_, stop)| *stop)
+            .collect()
+    };
+    assert!(matches!(
+        stopped(count)[..],
+        [Stop::StepLimit { executed: 10, .. }, Stop::Halt]
+    ));
+    assert_eq!(stopped(hello), [Stop::Halt]);
+    assert!(supervisor.running().is_empty());
+    assert_eq!(out.bytes(), b"Hello, world!\n14\n");
+
+    // A reader sees what was written before the writers went away, then
+    // the end of its input; a writer fails once the reader is gone.
+    let (mut tx, mut rx) = pipe();
+    let mut other = tx.clone();
+    tx.write_all(b"ab").unwrap();
+    drop(tx);
+    let reader = std::thread::spawn(move || {
+        let mut read = Vec::new();
+        std::io::Read::read_to_end(&mut rx, &mut read).unwrap();
+        read
+    });
+    other.write_all(b"c").unwrap();
+    drop(other);
+    assert_eq!(reader.join().unwrap(), b"abc");
+    let (mut tx, rx) = pipe();
+    drop(rx);
+    assert_eq!(
+        tx.write(b"x").unwrap_err().kind(),
+        std::io::ErrorKind::BrokenPipe
+    );
+}
+
+#[test]
+fn spawn() {
+    let mut machine = Machine::default();
+    machine
+        .extend_from(&include_bytes!("golden/count.um")[..])
+        .unwrap();
+    let Spawned {
+        input,
+        output,
+        control,
+    } = machine.spawn().unwrap();
+    input.send(b'a').unwrap();
+    input.send(b'b').unwrap();
+    assert_eq!(output.recv().unwrap(), b'a');
+    assert_eq!(output.recv().unwrap(), b'b');
+
+    // Waiting for more input, it still pauses and takes snapshots.
+    control.pause();
+    let snapshot = control.snapshot().unwrap();
+    let mut restored = Machine::builder()
+        .input("c")
+        .stdin(std::io::empty())
+        .stdout(Vec::new())
+        .build_from(Machine::read_snapshot(&mut &snapshot[..]).unwrap());
+    restored.run().unwrap();
+    assert_eq!(restored.registers()[7], 0, "the count has been printed");
+    control.resume();
+    drop(input);
+    let (machine, stop) = control.join();
+    assert_eq!(stop.unwrap(), Stop::Halt);
+    assert_eq!(output.try_iter().collect::<Vec<_>>(), b"2\n");
+    assert!(machine.array(0).is_some());
+
+    let spawned = load(Machine::builder(), &SPIN).spawn().unwrap();
+    assert!(!spawned.control.is_finished());
+    let (machine, stop) = spawned.control.stop();
+    assert!(matches!(stop.unwrap(), Stop::Interrupted { .. }));
+    assert!(machine.pc() <= 1);
+
+    // An Input waiting when a command comes is executed again once input
+    // arrives, but traced and observed once.
+    let trace = Shared::default();
+    let before = Arc::new(Mutex::new(Vec::new()));
+    let builder = Machine::builder()
+        .trace(trace.clone())
+        .observe(Before(before.clone()));
+    let spawned = load(builder, &ECHO).spawn().unwrap();
+    while before.lock().unwrap().is_empty() {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    spawned.control.snapshot().unwrap();
+    spawned.input.send(b'h').unwrap();
+    assert_eq!(spawned.output.recv().unwrap(), b'h');
+    let (_, stop) = spawned.control.join();
+    assert_eq!(stop.unwrap(), Stop::Halt);
+    assert_eq!(*before.lock().unwrap(), [0, 1, 2]);
+    let trace = String::from_utf8(trace.bytes()).unwrap();
+    assert_eq!(trace.lines().count(), 3, "{trace}");
+}
+
+#[test]
+fn interrupt() {
+    // ORTHO r0, 0 ; LOADPROG r0, r0 (jumps back to itself forever)
+    let program = [0xd000_0000, 0xc000_0000];
+    let mut machine = load(Machine::builder().stdout(Vec::new()), &program);
+    let interrupter = machine.interrupter();
+    let running = std::thread::spawn(move || (machine.run_until_stop(), machine));
+    std::thread::sleep(Duration::from_millis(10));
+    interrupter.interrupt();
+    let (stop, machine) = running.join().unwrap();
+    let pc = machine.pc();
+    assert_eq!(stop.unwrap(), Stop::Interrupted { pc });
+    assert!(!interrupter.take());
+}
+
+#[test]
+fn interrupt_during_run() {
+    for backend in [Backend::Interpreter, Backend::Threaded] {
+        let mut machine = load(Machine::builder().backend(backend), &COUNTDOWN);
+        machine.interrupter().interrupt();
+        machine.run().unwrap();
+        assert_eq!(machine.executed(), 400_003);
+        // Still pending for the next run that checks.
+        machine.set_pc(0);
+        assert!(matches!(
+            machine.run_until_stop().unwrap(),
+            Stop::Interrupted { .. }
+        ));
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_machine() {
+    use common::image;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let count = &include_bytes!("golden/count.um")[..];
+    let mut machine = AsyncMachine::new(Machine::builder().input("x"), &b"abc"[..], Vec::new());
+    machine.machine_mut().extend_from(count).unwrap();
+    assert_eq!(machine.run_until_stop().await.unwrap(), Stop::Halt);
+    assert_eq!(machine.into_inner().2, b"xabc4\n");
+
+    // The machine waits for input without holding up the task sending it.
+    let (mut client, server) = tokio::io::duplex(64);
+    let (reader, writer) = tokio::io::split(server);
+    let mut machine = AsyncMachine::new(Machine::builder(), reader, writer);
+    machine.machine_mut().extend_from(count).unwrap();
+    let client = async {
+        client.write_all(b"hi").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut out = Vec::new();
+        client.read_to_end(&mut out).await.unwrap();
+        out
+    };
+    let (stop, out) = tokio::join!(
+        async {
+            let stop = machine.run_until_stop().await;
+            // Closes the machine's side, ending the client's read.
+            drop(machine);
+            stop
+        },
+        client
+    );
+    assert_eq!(stop.unwrap(), Stop::Halt);
+    assert_eq!(out, b"hi2\n");
+
+    // Nor while it computes.
+    let mut machine = AsyncMachine::new(Machine::builder(), tokio::io::empty(), tokio::io::sink());
+    machine
+        .machine_mut()
+        .extend_from(&image(&SPIN)[..])
+        .unwrap();
+    let interrupter = machine.machine().interrupter();
+    let (stop, ()) = tokio::join!(machine.run_until_stop(), async {
+        tokio::task::yield_now().await;
+        interrupter.interrupt();
+    });
+    assert!(matches!(stop.unwrap(), Stop::Interrupted { .. }));
+
+    // An Input that waits is executed again once input arrives, but traced
+    // and observed once.
+    let trace = Shared::default();
+    let before = Arc::new(Mutex::new(Vec::new()));
+    let (mut client, server) = tokio::io::duplex(64);
+    let (reader, writer) = tokio::io::split(server);
+    let builder = Machine::builder()
+        .trace(trace.clone())
+        .observe(Before(before.clone()));
+    let mut machine = AsyncMachine::new(builder, reader, writer);
+    machine
+        .machine_mut()
+        .extend_from(&image(&ECHO)[..])
+        .unwrap();
+    let (stop, ()) = tokio::join!(machine.run_until_stop(), async {
+        tokio::task::yield_now().await;
+        client.write_all(b"h").await.unwrap();
+    });
+    assert_eq!(stop.unwrap(), Stop::Halt);
+    assert_eq!(*before.lock().unwrap(), [0, 1, 2]);
+    let trace = String::from_utf8(trace.bytes()).unwrap();
+    assert_eq!(trace.lines().count(), 3, "{trace}");
+}
@@ -0,0 +1,207 @@
+// The console: input queued and read from stdin, output and its flushing,
+// the end of input, and journals that record and replay a session.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{load, Shared, BACKENDS};
+use um_32::prelude::*;
+
+#[test]
+fn builder_console() {
+    // IN r1 ; OUT r1 ; IN r1 ; OUT r1 ; HALT
+    let program = [
+        0xb000_0001,
+        0xa000_0001,
+        0xb000_0001,
+        0xa000_0001,
+        0x7000_0000,
+    ];
+
+    let out = Shared::default();
+    let builder = Machine::builder()
+        .input("a")
+        .stdin(&b"b"[..])
+        .stdout(out.clone())
+        .echo(false);
+    load(builder, &program).run().unwrap();
+    assert_eq!(out.bytes(), b"ab");
+
+    let out = Shared::default();
+    let builder = Machine::builder()
+        .input("xy")
+        .stdout(out.clone())
+        .echo(true);
+    load(builder, &program).run().unwrap();
+    assert_eq!(out.bytes(), b"xxyy");
+
+    let out = Shared::default();
+    let builder = Machine::builder().input("xy").stdout(out.clone());
+    load(builder, &program).run().unwrap();
+    assert_eq!(out.bytes(), b"xy");
+}
+
+#[test]
+fn flush_policy() {
+    // ORTHO r1, 'a' ; OUT r1 ; ORTHO r2, '\n' ; OUT r2 ; OUT r1 ; HALT
+    let program = [
+        0xd200_0061,
+        0xa000_0001,
+        0xd400_000a,
+        0xa000_0002,
+        0xa000_0001,
+        0x7000_0000,
+    ];
+    let written = |policy| {
+        let out = Shared::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let builder = Machine::builder()
+            .stdout(out.clone())
+            .flush_policy(policy)
+            .on_instruction({
+                let (out, seen) = (out.clone(), seen.clone());
+                move |_, _, _| {
+                    seen.lock().unwrap().push(out.0.lock().unwrap().len());
+                    Ok(())
+                }
+            });
+        load(builder, &program).run().unwrap();
+        assert_eq!(out.bytes(), b"a\na");
+        let seen = seen.lock().unwrap().clone();
+        seen
+    };
+    assert_eq!(written(FlushPolicy::Byte), [0, 0, 1, 1, 2, 3]);
+    assert_eq!(written(FlushPolicy::Newline), [0, 0, 0, 0, 2, 2]);
+    assert_eq!(written(FlushPolicy::Halt), [0; 6]);
+}
+
+#[test]
+fn end_of_input() {
+    // IN r1 ; IN r2 ; HALT
+    let program = [0xb000_0001, 0xb000_0002, 0x7000_0000];
+    let mut machine = load(Machine::builder().stdin(&b"a"[..]), &program);
+    machine.run().unwrap();
+    assert_eq!(machine.registers()[1], u32::from(b'a'));
+    assert_eq!(machine.registers()[2], 0xffff_ffff);
+
+    let mut machine = load(
+        Machine::builder().stdin(&b"a"[..]).eof_error(true),
+        &program,
+    );
+    let Err(Error::IO(e)) = machine.run() else {
+        panic!("expected an I/O error");
+    };
+    assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert_eq!(machine.pc(), 1);
+}
+
+#[test]
+fn binary_input() {
+    // IN r1 ; IN r2 ; IN r3 ; IN r4 ; HALT
+    let program = [
+        0xb000_0001,
+        0xb000_0002,
+        0xb000_0003,
+        0xb000_0004,
+        0x7000_0000,
+    ];
+    let mut machine = load(Machine::builder().input_bytes(&[0xff, 0]), &program);
+    machine.add_input("é");
+    machine.run().unwrap();
+    assert_eq!(machine.registers()[1..5], [0xff, 0, 0xc3, 0xa9]);
+
+    let mut buf = Vec::new();
+    let mut machine = Machine::default();
+    machine.add_input_bytes(&[0x80, 0xfe]);
+    machine.write_snapshot(&mut buf).unwrap();
+    let restored = Machine::read_snapshot(&mut &buf[..]).unwrap();
+    assert_eq!(restored.state_hash(), machine.state_hash());
+}
+
+#[test]
+fn buffered_stdin() {
+    struct Counting(&'static [u8], Arc<Mutex<usize>>);
+
+    impl std::io::Read for Counting {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            *self.1.lock().unwrap() += 1;
+            self.0.read(buf)
+        }
+    }
+
+    // IN r1 ; IN r1 ; IN r1 ; HALT
+    let program = [0xb000_0001, 0xb000_0001, 0xb000_0001, 0x7000_0000];
+    let reads = Arc::new(Mutex::new(0));
+    let builder = Machine::builder()
+        .stdin(Counting(b"abc", reads.clone()))
+        .stdout(Vec::new());
+    let mut machine = load(builder, &program);
+    machine.run().unwrap();
+    assert_eq!(machine.registers()[1], u32::from(b'c'));
+    assert_eq!(*reads.lock().unwrap(), 1);
+}
+
+#[test]
+fn journal_replay() {
+    // IN r1 ; OUT r1 ; IN r1 ; OUT r1 ; IN r2 ; HALT
+    let program = [
+        0xb000_0001,
+        0xa000_0001,
+        0xb000_0001,
+        0xa000_0001,
+        0xb000_0002,
+        0x7000_0000,
+    ];
+    let journal = Shared::default();
+    let builder = Machine::builder()
+        .input("a")
+        .stdin(&b"b"[..])
+        .stdout(Vec::new())
+        .record_journal(journal.clone());
+    let mut machine = load(builder, &program);
+    machine.run().unwrap();
+    let recorded = machine.registers().to_owned();
+    drop(machine);
+    let text = journal.bytes();
+    assert_eq!(
+        String::from_utf8_lossy(&text),
+        "um-32 journal\n0 97\nout 61\n2 98\nout 62\n4 eof\n"
+    );
+    assert_eq!(Journal::read(&text[..]).unwrap().output(), b"ab");
+
+    for backend in BACKENDS {
+        let out = Shared::default();
+        let builder = Machine::builder()
+            .backend(backend)
+            .input("ignored")
+            .stdin(std::io::empty())
+            .stdout(out.clone())
+            .replay_journal(Journal::read(&text[..]).unwrap());
+        let mut machine = load(builder, &program);
+        machine.run().unwrap();
+        assert_eq!(machine.registers(), &recorded, "{backend:?}");
+        assert_eq!(out.bytes(), b"ab", "{backend:?}");
+    }
+
+    // An extra instruction first moves every Input along by one.
+    let builder = Machine::builder()
+        .stdout(Vec::new())
+        .replay_journal(Journal::read(&text[..]).unwrap());
+    let mut machine = load(builder, &[&[0xd000_0000], &program[..]].concat());
+    assert!(matches!(
+        machine.run(),
+        Err(Error::ReplayDiverged {
+            pc: 1,
+            executed: 1,
+            expected: Some(0)
+        })
+    ));
+
+    // Input consumed again after a rewind replaces what came after it.
+    let rewound = Journal::read(&b"um-32 journal\n0 97\n2 98\n2 99\n"[..]).unwrap();
+    assert_eq!(rewound.len(), 2);
+    assert!(Journal::read(&b"0 97\n"[..]).is_err());
+    assert!(Journal::read(&b"um-32 journal\n0 256\n"[..]).is_err());
+    assert!(Journal::read(&b"um-32 journal\nout 6\n"[..]).is_err());
+}
@@ -0,0 +1,312 @@
+// Stopping and going back: breakpoints, conditions and watches, step
+// limits and deadlines, the recent instructions kept for faults, rewinding
+// through recorded history, and forking a machine part way through.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{load, Shared, BACKENDS, COUNTDOWN};
+use um_32::prelude::*;
+
+// ORTHO r1, 1 ; ORTHO r1, 2 ; ORTHO r1, 3 ; HALT
+const COUNT_TO_3: [u32; 4] = [0xd200_0001, 0xd200_0002, 0xd200_0003, 0x7000_0000];
+
+// ORTHO r1, 2 ; ALLOC r2, r1 ; AMEND r2, r0, r1 ; INDEX r3, r2, r0 ;
+// ABANDON r2 ; HALT
+const WATCHED: [u32; 6] = [
+    0xd200_0002,
+    0x8000_0011,
+    0x2000_0081,
+    0x1000_00d0,
+    0x9000_0002,
+    0x7000_0000,
+];
+
+#[test]
+fn breakpoints_and_step() {
+    let mut machine = load(Machine::builder(), &COUNT_TO_3);
+    machine.add_breakpoint(2);
+    assert_eq!(machine.breakpoints(), [2]);
+
+    assert_eq!(machine.step().unwrap(), Stop::Step);
+    assert_eq!((machine.pc(), machine.registers()[1]), (1, 1));
+    assert_eq!(
+        machine.run_until_stop().unwrap(),
+        Stop::Breakpoint { pc: 2 }
+    );
+    assert_eq!(machine.registers()[1], 2);
+    assert_eq!(machine.run_until_stop().unwrap(), Stop::Halt);
+    assert_eq!(machine.registers()[1], 3);
+
+    assert!(machine.remove_breakpoint(2));
+    assert!(!machine.remove_breakpoint(2));
+
+    // A breakpoint at the entry point stops the first run, and resuming
+    // executes the instruction there rather than stopping again.
+    let mut machine = load(Machine::builder(), &COUNT_TO_3);
+    machine.add_breakpoint(0);
+    assert_eq!(
+        machine.run_until_stop().unwrap(),
+        Stop::Breakpoint { pc: 0 }
+    );
+    assert_eq!(machine.executed(), 0);
+    assert_eq!(machine.run_until_stop().unwrap(), Stop::Halt);
+    assert_eq!(machine.registers()[1], 3);
+
+    // Moving back to a breakpoint stops there again.
+    machine.set_pc(0);
+    assert_eq!(
+        machine.run_until_stop().unwrap(),
+        Stop::Breakpoint { pc: 0 }
+    );
+}
+
+#[test]
+fn conditions() {
+    let mut machine = load(Machine::builder(), &COUNT_TO_3);
+    let id = machine.add_condition(|_, registers| registers[1] == 2);
+    assert_eq!(machine.conditions(), [id]);
+
+    assert_eq!(
+        machine.run_until_stop().unwrap(),
+        Stop::Condition { pc: 2, id }
+    );
+    assert_eq!(machine.run_until_stop().unwrap(), Stop::Halt);
+    assert!(machine.remove_condition(id));
+    assert!(machine.conditions().is_empty());
+}
+
+#[test]
+fn array_watches() {
+    let mut machine = load(Machine::builder().stdout(Vec::new()), &WATCHED);
+    machine.watch_array(1, Watch::Write);
+    assert_eq!(machine.watched_arrays(), [(1, Watch::Write)]);
+
+    let mut stops = Vec::new();
+    loop {
+        match machine.run_until_stop().unwrap() {
+            Stop::Halt => break,
+            Stop::Watch { pc, access, .. } => stops.push((pc, access)),
+            stop => panic!("unexpected stop {stop:?}"),
+        }
+    }
+    assert_eq!(
+        stops,
+        [
+            (1, Access::Allocate),
+            (2, Access::Write),
+            (4, Access::Abandon)
+        ]
+    );
+
+    machine.unwatch_array(1);
+    assert!(machine.watched_arrays().is_empty());
+}
+
+#[test]
+fn cell_watches() {
+    let mut machine = load(Machine::builder().stdout(Vec::new()), &WATCHED);
+    machine.watch_cell(1, 0, Watch::Read);
+    machine.watch_cell(1, 1, Watch::ReadWrite);
+    machine.unwatch_cell(1, 1);
+    assert_eq!(machine.watched_cells(), [(1, 0, Watch::Read)]);
+    assert!(machine.watched_arrays().is_empty());
+
+    match machine.run_until_stop().unwrap() {
+        Stop::Watch {
+            pc: 3,
+            array: 1,
+            offset: Some(0),
+            access: Access::Read,
+        } => {}
+        stop => panic!("unexpected stop {stop:?}"),
+    }
+    assert!(matches!(machine.run_until_stop().unwrap(), Stop::Halt));
+}
+
+#[test]
+fn step_limit() {
+    for backend in [Backend::Interpreter, Backend::Threaded] {
+        let mut machine = load(Machine::builder().backend(backend), &COUNT_TO_3);
+        machine.set_step_limit(Some(2));
+        assert_eq!(
+            machine.run_until_stop().unwrap(),
+            Stop::StepLimit { pc: 2, executed: 2 }
+        );
+        assert_eq!(machine.registers()[1], 2);
+        assert_eq!(
+            machine.step().unwrap(),
+            Stop::StepLimit { pc: 2, executed: 2 }
+        );
+
+        machine.set_step_limit(None);
+        assert_eq!(machine.run_until_stop().unwrap(), Stop::Halt);
+        assert_eq!(machine.registers()[1], 3);
+    }
+}
+
+#[test]
+fn deadline() {
+    // BRANCH: ORTHO r1, 0 ; LOADPROG r1, r1
+    let program = [0xd200_0000, 0xc000_0049];
+    for backend in [Backend::Interpreter, Backend::Threaded] {
+        let mut machine = load(Machine::builder().backend(backend), &program);
+        machine.set_deadline(Some(Instant::now() + Duration::from_millis(20)));
+        match machine.run_until_stop().unwrap() {
+            Stop::Timeout { pc, executed } => {
+                assert!(pc < 2);
+                assert!(executed >= 65536);
+            }
+            stop => panic!("unexpected stop {stop:?}"),
+        }
+    }
+}
+
+#[test]
+fn run_ignores_deadline() {
+    for backend in [Backend::Interpreter, Backend::Threaded] {
+        let mut machine = load(Machine::builder().backend(backend), &COUNTDOWN);
+        machine.set_deadline(Some(Instant::now()));
+        machine.run().unwrap();
+        assert_eq!(machine.executed(), 400_003);
+    }
+}
+
+#[test]
+fn self_jump() {
+    // LOADPROG r0, r0, jumping to itself
+    let program = [0xc000_0000];
+    for backend in [Backend::Interpreter, Backend::Threaded] {
+        let mut machine = load(Machine::builder().backend(backend), &program);
+        machine.set_deadline(Some(Instant::now() + Duration::from_millis(20)));
+        assert!(matches!(
+            machine.run_until_stop().unwrap(),
+            Stop::Timeout { pc: 0, .. }
+        ));
+
+        let builder = Machine::builder().backend(backend).detect_hangs(true);
+        assert!(matches!(
+            load(builder, &program).run(),
+            Err(Error::InfiniteLoop {
+                pc: 0,
+                inst: 0xc000_0000
+            })
+        ));
+    }
+}
+
+#[test]
+fn recent_instructions() {
+    // ORTHO r1, 1 ; ORTHO r2, 2 ; ORTHO r3, 0 ; DIV r1, r1, r3
+    let program = [0xd200_0001, 0xd400_0002, 0xd600_0000, 0x5000_004b];
+    let mut machine = load(Machine::builder().recent_instructions(3), &program);
+    assert!(matches!(
+        machine.run(),
+        Err(Error::DivisionByZero { pc: 3, .. })
+    ));
+    assert_eq!(
+        machine.recent_instructions(),
+        [(1, program[1]), (2, program[2]), (3, program[3])]
+    );
+
+    let mut machine = load(Machine::builder(), &program);
+    assert!(machine.run().is_err());
+    assert!(machine.recent_instructions().is_empty());
+}
+
+#[test]
+fn rewind() {
+    // INPUT r1 ; ORTHO r3, 5 ; INPUT r2 ; HALT
+    let program = [0xb000_0001, 0xd600_0005, 0xb000_0002, 0x7000_0000];
+    let mut machine = load(Machine::builder().input("ab").stdout(Vec::new()), &program);
+    machine.record_history(2, 8);
+    machine.add_breakpoint(1);
+    assert_eq!(
+        machine.run_until_stop().unwrap(),
+        Stop::Breakpoint { pc: 1 }
+    );
+    assert_eq!(machine.run_until_stop().unwrap(), Stop::Halt);
+    assert_eq!(machine.executed(), 3);
+    assert_eq!(machine.registers()[..4], [0, 'a' as u32, 'b' as u32, 5]);
+
+    machine.rewind(2).unwrap();
+    assert_eq!((machine.pc(), machine.executed()), (2, 2));
+    assert_eq!(machine.registers()[..4], [0, 'a' as u32, 0, 5]);
+
+    assert_eq!(
+        machine.reverse_continue().unwrap(),
+        Some(Stop::Breakpoint { pc: 1 })
+    );
+    assert_eq!(machine.registers()[..4], [0, 'a' as u32, 0, 0]);
+    assert_eq!(machine.reverse_continue().unwrap(), None);
+    assert_eq!(machine.executed(), 0);
+
+    // The input consumed since is fed again.
+    assert_eq!(
+        machine.run_until_stop().unwrap(),
+        Stop::Breakpoint { pc: 1 }
+    );
+    assert_eq!(machine.run_until_stop().unwrap(), Stop::Halt);
+    assert_eq!(machine.registers()[..4], [0, 'a' as u32, 'b' as u32, 5]);
+    assert!(machine.rewind(4).is_err());
+}
+
+#[test]
+fn fork() {
+    // ORTHO r2, 8 ; IN r1 ; AMEND r0[r2] = r1 ; OUT r1 ; IN r1 ;
+    // AMEND r0[r2] = r1 ; OUT r1 ; HALT ; 0
+    let program = [
+        0xd400_0008,
+        0xb000_0001,
+        0x2000_0011,
+        0xa000_0001,
+        0xb000_0001,
+        0x2000_0011,
+        0xa000_0001,
+        0x7000_0000,
+        0,
+    ];
+    for backend in BACKENDS {
+        let out = Shared::default();
+        let builder = Machine::builder()
+            .backend(backend)
+            .input("a")
+            .stdin(std::io::empty())
+            .stdout(out.clone());
+        let mut machine = load(builder, &program);
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+
+        let mut forks: Vec<_> = ["x", "y"]
+            .into_iter()
+            .map(|input| {
+                let out = Shared::default();
+                let fork = Machine::builder()
+                    .backend(backend)
+                    .input(input)
+                    .stdin(std::io::empty())
+                    .stdout(out.clone())
+                    .build_from(machine.fork());
+                (fork, out)
+            })
+            .collect();
+        machine.add_input("b");
+        machine.run().unwrap();
+        assert_eq!(out.bytes(), b"ab", "{backend:?}");
+        assert_eq!(machine.array(0).unwrap()[8], u32::from(b'b'), "{backend:?}");
+
+        for (fork, out) in &mut forks {
+            assert_eq!(fork.pc(), 4, "{backend:?}");
+            assert_eq!(fork.executed(), 4, "{backend:?}");
+            assert_eq!(fork.array(0).unwrap()[8], u32::from(b'a'), "{backend:?}");
+            fork.run().unwrap();
+            let last = *out.bytes().last().unwrap();
+            assert_eq!(fork.array(0).unwrap()[8], u32::from(last), "{backend:?}");
+        }
+        assert_eq!(forks[0].1.bytes(), b"x");
+        assert_eq!(forks[1].1.bytes(), b"y");
+        assert_eq!(machine.array(0).unwrap()[8], u32::from(b'b'), "{backend:?}");
+    }
+}
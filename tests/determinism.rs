@@ -0,0 +1,115 @@
+// Runs fixture programs and checks the machine's state hash at regular
+// intervals against values recorded once. The hashes cover everything a
+// snapshot saves, so matching them on every platform CI builds for means
+// snapshots and replays taken on one machine resume identically on another.
+
+use um_32::{prelude::*, program::ProgramBuilder};
+
+// Steps the machine to the end, hashing its state every `interval`
+// instructions and once more when it halts.
+fn hashes(mut machine: Machine, interval: u64) -> Vec<u64> {
+    let mut hashes = Vec::new();
+    loop {
+        if machine.executed().is_multiple_of(interval) {
+            hashes.push(machine.state_hash());
+        }
+        if machine.step().unwrap() == Stop::Halt {
+            hashes.push(machine.state_hash());
+            return hashes;
+        }
+    }
+}
+
+fn machine(image: &[u8], input: &str) -> Machine {
+    let mut machine = Machine::builder()
+        .input(input)
+        .stdin(std::io::empty())
+        .stdout(Vec::new())
+        .echo(false)
+        .build();
+    machine.extend_from(image).unwrap();
+    machine
+}
+
+// Allocates arrays of pseudo-random sizes, abandoning each one as the next
+// is made, mixing the identifiers it gets back into the random state, then
+// loads a program built in another array and halts there.
+fn churn() -> Vec<u8> {
+    const ZERO: u32 = 0;
+    const SEED: u32 = 1;
+    const COUNT: u32 = 2;
+    const T0: u32 = 3;
+    const T1: u32 = 4;
+    const SIZE: u32 = 5;
+    const NEW: u32 = 6;
+    const PREV: u32 = 7;
+
+    let mut p = ProgramBuilder::new();
+    p.ortho(SEED, 1);
+    p.ortho(COUNT, 500);
+    p.alloc(PREV, SEED);
+    let top = p.here();
+    p.load(T0, T1, 1_103_515_245);
+    p.mul(SEED, SEED, T0);
+    p.load(T0, T1, 12_345);
+    p.add(SEED, SEED, T0);
+    p.load(T0, T1, 1 << 24);
+    p.div(SIZE, SEED, T0);
+    p.ortho(T0, 1);
+    p.add(SIZE, SIZE, T0);
+    p.alloc(NEW, SIZE);
+    p.amend(NEW, ZERO, SEED);
+    p.abandon(PREV);
+    p.cmov(PREV, NEW, NEW);
+    p.nand(SEED, SEED, NEW);
+    p.ortho(SIZE, 1);
+    p.sub(COUNT, COUNT, SIZE, T0);
+    p.jump_if(COUNT, top, ZERO, [T0, T1]);
+    p.ortho(SIZE, 1);
+    p.alloc(NEW, SIZE);
+    p.load(T0, T1, 0x7000_0000);
+    p.amend(NEW, ZERO, T0);
+    p.load_program(NEW, ZERO);
+    p.build_image()
+}
+
+#[test]
+fn tour() {
+    let machine = machine(&um_32::program::tour(), "e\nstressed\na\nq\n");
+    assert_eq!(
+        hashes(machine, 500),
+        [
+            0x48ccf5eb4ce22026,
+            0xa47b65a19a4a7a91,
+            0xbf512b758050cbc8,
+            0x7fc2d21c5c0a7f7a,
+            0x3714e7ea8b31ce01,
+            0x7ac231751ee2e7ed,
+        ]
+    );
+}
+
+#[test]
+fn allocation_churn() {
+    let machine = machine(&churn(), "");
+    assert_eq!(
+        hashes(machine, 1000),
+        [
+            0xc6ae7907c2190971,
+            0xcbfbdfd2dbee0b3f,
+            0x9b71fa7b3b6ffbbc,
+            0x8153159ab36341aa,
+            0xfe9b8c7c6eb384a0,
+            0x0b0eedfeb399b746,
+            0x736e2a120a40381e,
+            0xe4c0027e10cb998a,
+            0x804e85c3f664898c,
+            0xf630a6f9ff98f0bc,
+            0x596ca6b1f5cdfa31,
+            0xb12f23cbe52131aa,
+            0x5c594b6871b0684d,
+            0x56a263839497b8d0,
+            0x4df91b24fb36f89b,
+        ]
+    );
+}
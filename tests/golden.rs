@@ -0,0 +1,59 @@
+// Runs each program in tests/golden on every backend and checks its output
+// against a transcript. A fixture is NAME.um, the output it must produce in
+// NAME.expected, and optionally console input in NAME.input. The programs
+// are assembled from the NAME.uma sources next to them with
+// `um-32 asm NAME.uma --output NAME.um`.
+
+mod common;
+
+use std::{fs, path::Path};
+
+use common::{Shared, BACKENDS};
+use um_32::{Backend, Machine};
+
+fn output(image: &[u8], input: &[u8], backend: Backend) -> Result<Vec<u8>, um_32::Error> {
+    let stdout = Shared::default();
+    let mut machine = Machine::builder()
+        .backend(backend)
+        .input_bytes(input)
+        .stdin(std::io::empty())
+        .stdout(stdout.clone())
+        .echo(false)
+        .build();
+    machine.extend_from(image)?;
+    machine.run()?;
+    Ok(stdout.bytes())
+}
+
+#[test]
+fn transcripts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut fixtures: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "um"))
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "no fixtures in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in &fixtures {
+        let name = path.file_stem().unwrap().to_string_lossy();
+        let image = fs::read(path).unwrap();
+        let input = fs::read(path.with_extension("input")).unwrap_or_default();
+        let expected = fs::read(path.with_extension("expected"))
+            .unwrap_or_else(|e| panic!("{name}.expected: {e}"));
+        for backend in BACKENDS {
+            match output(&image, &input, backend) {
+                Ok(output) if output == expected => {}
+                Ok(output) => failures.push(format!(
+                    "{name} ({backend:?}): expected {:?}, got {:?}",
+                    String::from_utf8_lossy(&expected),
+                    String::from_utf8_lossy(&output)
+                )),
+                Err(e) => failures.push(format!("{name} ({backend:?}): {e}")),
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,197 @@
+// Host Call, opcode 14, from the `extensions` feature: the built-in
+// services, calls registered on the builder, and rewinding over calls.
+#![cfg(feature = "extensions")]
+
+mod common;
+
+use common::{load, BACKENDS};
+use um_32::{prelude::*, program::ProgramBuilder};
+
+#[test]
+fn host_calls() {
+    // Allocates an array in register `reg` holding `bytes`.
+    fn bytes(p: &mut ProgramBuilder, reg: u32, bytes: &[u8]) {
+        p.ortho(5, bytes.len() as u32);
+        p.alloc(reg, 5);
+        for (i, byte) in bytes.iter().enumerate() {
+            p.ortho(6, i as u32);
+            p.ortho(7, *byte as u32);
+            p.amend(reg, 6, 7);
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("um-32-host-{}", std::process::id()));
+    let mut p = ProgramBuilder::new();
+    bytes(&mut p, 1, path.to_str().unwrap().as_bytes());
+    bytes(&mut p, 2, b"hi\n");
+    // Writes array r2 to the file and reads it back into r6, then asks for
+    // the time in r7 and a random platter in r5.
+    p.add(3, 1, 0);
+    p.ortho(4, 3);
+    p.host(3, 4, 2);
+    p.add(6, 1, 0);
+    p.ortho(4, 2);
+    p.host(6, 4, 0);
+    p.ortho(4, 0);
+    p.host(7, 4, 0);
+    p.ortho(4, 1);
+    p.host(5, 4, 0);
+    p.halt();
+    let program = p.build_image();
+
+    for backend in BACKENDS {
+        let mut machine = Machine::builder().backend(backend).extensions(true).build();
+        machine.extend_from(&program[..]).unwrap();
+        machine.run().unwrap();
+        let registers = *machine.registers();
+        assert_eq!(registers[3], 0, "{backend:?}");
+        assert_eq!(machine.array(registers[6]), Some(&[104, 105, 10][..]));
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        assert!(now.abs_diff(registers[7]) < 60, "{backend:?}");
+        assert_eq!(std::fs::read(&path).unwrap(), b"hi\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Reading a file that isn't there fails with !0 in A.
+    let mut p = ProgramBuilder::new();
+    bytes(&mut p, 1, path.to_str().unwrap().as_bytes());
+    p.ortho(4, 2);
+    p.host(1, 4, 0);
+    p.halt();
+    let mut machine = Machine::builder().extensions(true).build();
+    machine.extend_from(&p.build_image()[..]).unwrap();
+    machine.run().unwrap();
+    assert_eq!(machine.registers()[1], !0);
+
+    // ORTHO r4, 9 ; HOST r1, r4, r0
+    let program = [0xd800_0009, 0xe000_0060];
+    assert!(matches!(
+        load(Machine::builder().extensions(true), &program).run(),
+        Err(Error::UnknownHostCall {
+            pc: 1,
+            service: 9,
+            ..
+        })
+    ));
+    assert!(matches!(
+        load(Machine::builder().backend(Backend::Threaded), &program).run(),
+        Err(Error::InvalidOp { pc: 1, op: 14, .. })
+    ));
+}
+
+#[test]
+fn host_call_registry() {
+    // ORTHO r4, 7 ; ORTHO r1, 3 ; HOST r1, r4, r1 ; ORTHO r4, 0 ; HOST r2, r4, r0 ; HALT
+    let program = [
+        0xd800_0007,
+        0xd200_0003,
+        0xe000_0061,
+        0xd800_0000,
+        0xe000_00a0,
+        0x7000_0000,
+    ];
+    // Service 7 returns a new array of A platters, each C + 1.
+    let fill = |machine: &mut Machine, len: u32, c: u32| {
+        let array = machine.allocate_array(len)?;
+        machine.array_mut(array).unwrap().fill(c + 1);
+        Ok(array)
+    };
+    for backend in BACKENDS {
+        let builder = Machine::builder()
+            .backend(backend)
+            .host_call(7, fill)
+            .host_call(0, |_: &mut Machine, _, _| Ok(42))
+            .extensions(true);
+        let mut machine = load(builder, &program);
+        machine.run().unwrap();
+        let array = machine.registers()[1];
+        assert_eq!(machine.array(array), Some(&[4, 4, 4][..]), "{backend:?}");
+        // The registered call takes the place of the built-in time.
+        assert_eq!(machine.registers()[2], 42, "{backend:?}");
+    }
+
+    // Registered calls alone make opcode 14 Host Call, without the
+    // built-in ones.
+    let mut machine = load(Machine::builder().host_call(7, fill), &program);
+    assert!(matches!(
+        machine.run(),
+        Err(Error::UnknownHostCall {
+            pc: 4,
+            service: 0,
+            ..
+        })
+    ));
+
+    // Errors stop the run on the Host Call, which can be run again.
+    let mut machine = load(Machine::builder().max_alloc(2).host_call(7, fill), &program);
+    assert!(matches!(
+        machine.run(),
+        Err(Error::AllocationTooLarge { pc: 2, .. })
+    ));
+    assert_eq!(machine.pc(), 2);
+    machine.registers_mut()[1] = 2;
+    assert!(matches!(
+        machine.run(),
+        Err(Error::UnknownHostCall { pc: 4, .. })
+    ));
+    let array = machine.registers()[1];
+    assert_eq!(machine.array(array), Some(&[3, 3][..]));
+}
+
+#[test]
+fn host_calls_rewind() {
+    // ORTHO r4, 1 ; HOST r5, r4, r0 ; ORTHO r4, 3 ; HOST r3, r4, r2 ;
+    // ORTHO r6, 1 ; HALT
+    let program = [
+        0xd800_0001,
+        0xe000_0160,
+        0xd800_0003,
+        0xe000_00e2,
+        0xdc00_0001,
+        0x7000_0000,
+    ];
+    let path = std::env::temp_dir().join(format!("um-32-rewind-{}", std::process::id()));
+    let mut machine = load(Machine::builder().extensions(true), &program);
+    let name = path.to_str().unwrap().as_bytes();
+    let name_array = machine.allocate_array(name.len() as u32).unwrap();
+    for (platter, byte) in machine.array_mut(name_array).unwrap().iter_mut().zip(name) {
+        *platter = *byte as u32;
+    }
+    let data = machine.allocate_array(1).unwrap();
+    machine.array_mut(data).unwrap()[0] = b'x' as u32;
+    machine.registers_mut()[3] = name_array;
+    machine.registers_mut()[2] = data;
+    machine.record_history(1000, 8);
+    machine.add_breakpoint(5);
+    assert_eq!(
+        machine.run_until_stop().unwrap(),
+        Stop::Breakpoint { pc: 5 }
+    );
+    let random = machine.registers()[5];
+    assert_eq!(machine.registers()[3], 0);
+    assert_eq!(std::fs::read(&path).unwrap(), b"x");
+    std::fs::remove_file(&path).unwrap();
+
+    // Going back over the calls and forward again doesn't call again: the
+    // file isn't written and the random platter is the same.
+    machine.rewind(4).unwrap();
+    assert_eq!(machine.registers()[5], random);
+    assert_eq!(machine.registers()[3], 0);
+    machine.remove_breakpoint(5);
+    machine.add_breakpoint(2);
+    assert_eq!(
+        machine.reverse_continue().unwrap(),
+        Some(Stop::Breakpoint { pc: 2 })
+    );
+    assert_eq!(machine.registers()[5], random);
+    assert!(!path.exists());
+
+    // Running on from there calls again.
+    machine.remove_breakpoint(2);
+    assert_eq!(machine.run_until_stop().unwrap(), Stop::Halt);
+    assert_eq!(std::fs::read(&path).unwrap(), b"x");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,206 @@
+// Watching a machine run: the instruction hook, observers, the event
+// channel, traces, per-opcode statistics and per-pc profiles.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{load, Shared, BACKENDS};
+use um_32::prelude::*;
+
+// ORTHO r1, 1 ; ORTHO r1, 2 ; HALT
+const TWO_ORTHOS: [u32; 3] = [0xd200_0001, 0xd200_0002, 0x7000_0000];
+
+#[test]
+fn instruction_hook() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let hook_seen = seen.clone();
+    let builder = Machine::builder().on_instruction(move |pc, inst, registers| {
+        hook_seen
+            .lock()
+            .unwrap()
+            .push((pc, inst >> 28, registers[1]));
+        Ok(())
+    });
+    load(builder, &TWO_ORTHOS).run().unwrap();
+    assert_eq!(*seen.lock().unwrap(), [(0, 13, 0), (1, 13, 1), (2, 7, 2)]);
+}
+
+#[test]
+fn observers() {
+    type Seen = Arc<Mutex<Vec<(&'static str, u32, u32)>>>;
+    struct Recorder(Seen);
+
+    impl Observer for Recorder {
+        fn before(&mut self, pc: u32, _: u32, registers: &[u32; 8]) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(("before", pc, registers[1]));
+            Ok(())
+        }
+
+        fn after(&mut self, pc: u32, _: u32, registers: &[u32; 8]) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(("after", pc, registers[1]));
+            Ok(())
+        }
+    }
+
+    // Counts with the default before.
+    struct Counter(Arc<Mutex<u32>>);
+
+    impl Observer for Counter {
+        fn after(&mut self, _: u32, _: u32, _: &[u32; 8]) -> std::io::Result<()> {
+            *self.0.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    for backend in BACKENDS {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let count = Arc::new(Mutex::new(0));
+        let builder = Machine::builder()
+            .backend(backend)
+            .observe(Recorder(seen.clone()))
+            .observe(Counter(count.clone()));
+        load(builder, &TWO_ORTHOS).run().unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                ("before", 0, 0),
+                ("after", 0, 1),
+                ("before", 1, 1),
+                ("after", 1, 2),
+                ("before", 2, 2),
+            ],
+            "{backend:?}"
+        );
+        assert_eq!(*count.lock().unwrap(), 2, "{backend:?}");
+    }
+
+    struct Failing;
+
+    impl Observer for Failing {
+        fn before(&mut self, pc: u32, _: u32, _: &[u32; 8]) -> std::io::Result<()> {
+            match pc {
+                1 => Err(std::io::Error::other("seen enough")),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    let mut machine = load(Machine::builder().observe(Failing), &TWO_ORTHOS);
+    assert!(matches!(machine.run(), Err(Error::IO(_))));
+    assert_eq!(machine.pc(), 1);
+}
+
+#[test]
+fn events() {
+    // ORTHO r1, 1 ; ALLOC r2, r1 ; IN r3 ; OUT r3 ; ALLOC r5, r1 ; ABANDON r5 ;
+    // ORTHO r7, 10 ; INDEX r4, r0, r7 ; AMEND r2, r0, r4 ; LOAD r2, r0 ; HALT
+    let program = [
+        0xd200_0001,
+        0x8000_0011,
+        0xb000_0003,
+        0xa000_0003,
+        0x8000_0029,
+        0x9000_0005,
+        0xde00_000a,
+        0x1000_0107,
+        0x2000_0084,
+        0xc000_0010,
+        0x7000_0000,
+    ];
+    for backend in BACKENDS {
+        let (sender, events) = std::sync::mpsc::channel();
+        let builder = Machine::builder()
+            .backend(backend)
+            .input("x")
+            .stdout(Vec::new())
+            .events(sender);
+        let mut machine = load(builder, &program);
+        machine.run().unwrap();
+        let (loaded, abandoned) = (machine.registers()[2], machine.registers()[5]);
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(
+            events,
+            [
+                MachineEvent::Allocated {
+                    pc: 1,
+                    array: loaded,
+                    platters: 1
+                },
+                MachineEvent::Input {
+                    pc: 2,
+                    byte: Some(b'x')
+                },
+                MachineEvent::Output { pc: 3, byte: b'x' },
+                MachineEvent::Allocated {
+                    pc: 4,
+                    array: abandoned,
+                    platters: 1
+                },
+                MachineEvent::Abandoned {
+                    pc: 5,
+                    array: abandoned
+                },
+                MachineEvent::Loaded {
+                    pc: 9,
+                    array: loaded,
+                    entry: 0
+                },
+            ],
+            "{backend:?}"
+        );
+    }
+
+    // IN r3 ; HALT
+    let (sender, events) = std::sync::mpsc::channel();
+    let builder = Machine::builder().stdin(std::io::empty()).events(sender);
+    load(builder, &[0xb000_0003, 0x7000_0000]).run().unwrap();
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        [MachineEvent::Input { pc: 0, byte: None }]
+    );
+}
+
+#[test]
+fn trace() {
+    // ORTHO r1, 1 ; HALT
+    let trace = Shared::default();
+    let mut machine = load(
+        Machine::builder().trace(trace.clone()),
+        &[0xd200_0001, 0x7000_0000],
+    );
+    machine.run().unwrap();
+    let trace = String::from_utf8(trace.bytes()).unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("pc:0000  op:13"));
+    assert!(lines[1].starts_with("pc:0001  op:07"));
+}
+
+#[test]
+fn stats() {
+    // ORTHO r1, 1 ; ORTHO r2, 2 ; ADD r3, r1, r2 ; HALT
+    let program = [0xd200_0001, 0xd400_0002, 0x3000_00ca, 0x7000_0000];
+    let mut machine = load(Machine::builder(), &program);
+    machine.run().unwrap();
+    assert!(machine.op_stats().is_none());
+
+    let mut machine = load(Machine::builder().stats(true), &program);
+    machine.run().unwrap();
+    let stats = machine.op_stats().unwrap();
+    let counts: Vec<u64> = stats.iter().map(|s| s.count).collect();
+    // Like executed(), the counts leave out the HALT.
+    assert_eq!(counts, [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+    assert_eq!(counts.iter().sum::<u64>(), machine.executed());
+}
+
+#[test]
+fn profile() {
+    // ORTHO r1, 1 ; ORTHO r2, 1 ; LOADPROG r0, r1 (back to 1, forever)
+    let program = [0xd200_0001, 0xd400_0001, 0xc000_0001];
+    let mut machine = load(Machine::builder().profile(true), &program);
+    for _ in 0..7 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.pc_counts().unwrap(), [1, 3, 3]);
+}
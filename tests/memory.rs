@@ -0,0 +1,210 @@
+// Arrays: loading programs, reading and changing machine state, the faults
+// allocation and abandonment raise, limits on memory, and the statistics
+// and audit kept about it.
+
+mod common;
+
+use common::load;
+use um_32::prelude::*;
+
+#[test]
+fn truncated_program() {
+    let mut machine = load(Machine::builder(), &[0x7000_0000]);
+    let err = machine.extend_from(&[0xd0, 0, 0, 1, 0xd0][..]).unwrap_err();
+    assert!(matches!(err, Error::TruncatedProgram { len: 5 }));
+    assert_eq!(machine.array(0), Some(&[0x7000_0000][..]));
+}
+
+#[test]
+fn state_accessors() {
+    // ORTHO r3, 0x42 ; HALT
+    let mut machine = load(Machine::builder(), &[0xd600_0042, 0x7000_0000]);
+
+    assert_eq!(machine.pc(), 0);
+    assert_eq!(machine.active_array_count(), 1);
+    assert_eq!(machine.array(0), Some(&[0xd600_0042, 0x7000_0000][..]));
+    assert_eq!(machine.array(1), None);
+    let arrays: Vec<_> = machine.arrays().collect();
+    assert_eq!(arrays, [(0, &[0xd600_0042, 0x7000_0000][..])]);
+
+    machine.run().unwrap();
+    assert_eq!(machine.registers()[3], 0x42);
+    assert_eq!(machine.pc(), 1);
+
+    machine.registers_mut()[0] = 7;
+    machine.array_mut(0).unwrap()[1] = 0;
+    assert_eq!(machine.registers()[0], 7);
+    assert_eq!(machine.array(0).unwrap()[1], 0);
+}
+
+#[test]
+fn abandonment_errors() {
+    let run = |program: &[u32]| load(Machine::builder(), program).run().unwrap_err();
+    // ABANDON r0
+    let err = run(&[0x9000_0000]);
+    assert!(matches!(err, Error::AbandonedProgramArray { pc: 0, .. }));
+    // ORTHO r1, 1 ; ALLOC r2, r1 ; ABANDON r2 ; ABANDON r2
+    let err = run(&[0xd200_0001, 0x8000_0011, 0x9000_0002, 0x9000_0002]);
+    assert!(matches!(
+        err,
+        Error::DoubleAbandon {
+            pc: 3,
+            array: 1,
+            ..
+        }
+    ));
+    // ORTHO r1, 5 ; ABANDON r1
+    let err = run(&[0xd200_0005, 0x9000_0001]);
+    assert!(matches!(
+        err,
+        Error::InactiveArray {
+            pc: 1,
+            array: 5,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn allocation_limit() {
+    // ORTHO r1, 11 ; ALLOC r2, r1
+    let mut machine = load(
+        Machine::builder().max_alloc(10),
+        &[0xd200_000b, 0x8000_0011],
+    );
+    match machine.run() {
+        Err(Error::AllocationTooLarge {
+            pc: 1,
+            requested: 11,
+            limit: 10,
+            ..
+        }) => {}
+        res => panic!("unexpected result {res:?}"),
+    }
+}
+
+#[test]
+fn memory_limit() {
+    // ORTHO r1, 10 ; ALLOC r2, r1 ; ABANDON r2 ; ALLOC r2, r1 ; ALLOC r3, r1
+    let program = [
+        0xd200_000a,
+        0x8000_0011,
+        0x9000_0002,
+        0x8000_0011,
+        0x8000_0019,
+    ];
+    // Array 0 holds five platters of its own.
+    let mut machine = load(Machine::builder().max_memory(24), &program);
+    match machine.run() {
+        Err(Error::OutOfMemory {
+            pc: 4,
+            requested: 10,
+            limit: 24,
+            ..
+        }) => {}
+        res => panic!("unexpected result {res:?}"),
+    }
+}
+
+#[test]
+fn memory_limit_counts_free_storage() {
+    // ORTHO r1, 500 ; ORTHO r2, 300 ; ORTHO r3, 600 ; ALLOC r4, r1 ;
+    // ALLOC r5, r2 ; ABANDON r5 ; ABANDON r4 ; ALLOC r6, r3 ; HALT
+    let program = [
+        0xd200_01f4,
+        0xd400_012c,
+        0xd600_0258,
+        0x8000_0021,
+        0x8000_002a,
+        0x9000_0005,
+        0x9000_0004,
+        0x8000_0033,
+        0x7000_0000,
+    ];
+    // The 800 platters kept for reuse would take the last allocation over
+    // the limit, so they are let go rather than kept alongside it.
+    let mut machine = load(Machine::builder().max_memory(1010), &program);
+    machine.run().unwrap();
+    assert_eq!(machine.registers()[6], machine.registers()[4]);
+    let stats = machine.memory_stats();
+    assert_eq!(stats.live_platters, 609);
+    assert_eq!(stats.free_arrays, 1);
+    assert_eq!(stats.free_platters, 0);
+}
+
+#[test]
+fn memory_stats() {
+    // ORTHO r1, 3 ; ALLOC r2, r1 ; ALLOC r3, r1 ; ABANDON r2 ; HALT
+    let program = [
+        0xd200_0003,
+        0x8000_0011,
+        0x8000_0019,
+        0x9000_0002,
+        0x7000_0000,
+    ];
+    let mut machine = load(Machine::builder(), &program);
+    machine.run().unwrap();
+    let stats = machine.memory_stats();
+    assert_eq!(stats.live_arrays, 2);
+    assert_eq!(stats.live_platters, 8);
+    assert_eq!(stats.peak_arrays, 3);
+    assert_eq!(stats.peak_platters, 11);
+    assert_eq!(stats.allocations, 2);
+    assert_eq!(stats.allocated_platters, 6);
+    assert_eq!(stats.free_arrays, 1);
+    assert!(stats.free_platters >= 3);
+}
+
+#[test]
+fn reused_arrays_are_zeroed() {
+    // ORTHO r1, 3 ; ALLOC r2, r1 ; ORTHO r3, 5 ; AMEND r2, r0, r3 ;
+    // ABANDON r2 ; ORTHO r5, 2 ; ALLOC r4, r5 ; HALT
+    let program = [
+        0xd200_0003,
+        0x8000_0011,
+        0xd600_0005,
+        0x2000_0083,
+        0x9000_0002,
+        0xda00_0002,
+        0x8000_0025,
+        0x7000_0000,
+    ];
+    let mut machine = load(Machine::builder(), &program);
+    machine.run().unwrap();
+    let id = machine.registers()[4];
+    assert_eq!(id, machine.registers()[2]);
+    assert_eq!(machine.array(id), Some(&[0, 0][..]));
+    assert_eq!(machine.memory_stats().free_arrays, 0);
+}
+
+#[test]
+fn allocation_audit() {
+    // ORTHO r1, 3 ; ALLOC r2, r1 ; ALLOC r3, r1 ; ABANDON r2 ; ALLOC r4, r1 ;
+    // HALT
+    let program = [
+        0xd200_0003,
+        0x8000_0011,
+        0x8000_0019,
+        0x9000_0002,
+        0x8000_0021,
+        0x7000_0000,
+    ];
+    let mut machine = load(Machine::builder(), &program);
+    machine.run().unwrap();
+    assert!(machine.allocation_sites().is_none());
+
+    let mut machine = load(Machine::builder().audit(true), &program);
+    machine.run().unwrap();
+    let site = |pc, abandonments, live: &[u32]| AllocationSite {
+        pc,
+        allocations: 1,
+        platters: 3,
+        abandonments,
+        live: live.to_vec(),
+    };
+    // The identifier abandoned at pc 3 is reused at pc 4.
+    assert_eq!(
+        machine.allocation_sites().unwrap(),
+        [site(1, 1, &[]), site(2, 0, &[2]), site(4, 0, &[1])]
+    );
+}
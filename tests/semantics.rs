@@ -0,0 +1,163 @@
+// Executes single instructions on random registers and checks the result
+// against the spec: the instruction's effect on register A, every other
+// register left alone, and the pc moving on by one. Each case is stepped
+// and then run to the HALT after it on every backend, which must all agree.
+
+use std::{io, mem};
+
+use proptest::prelude::*;
+use um_32::{Backend, Error, Instruction, Machine, Stop};
+
+const HALT: u32 = 0x7000_0000;
+
+const BACKENDS: &[Backend] = &[Backend::Interpreter, Backend::Decoded, Backend::Threaded];
+
+fn standard(op: u32, a: u32, b: u32, c: u32) -> u32 {
+    let inst = op << 28 | a << 6 | b << 3 | c;
+    assert_eq!(Instruction::decode(inst).unwrap().encode(), inst);
+    inst
+}
+
+fn machine(inst: u32, registers: [u32; 8], backend: Backend) -> Machine {
+    let image: Vec<u8> = [inst, HALT].iter().flat_map(|w| w.to_be_bytes()).collect();
+    let mut machine = Machine::builder().backend(backend).build();
+    machine.extend_from(&image[..]).unwrap();
+    *machine.registers_mut() = registers;
+    machine
+}
+
+// The registers after executing `inst`, checked to be the same however it
+// is executed.
+fn execute(inst: u32, registers: [u32; 8]) -> Result<[u32; 8], Error> {
+    let mut stepped = machine(inst, registers, Backend::Interpreter);
+    assert_eq!(stepped.step()?, Stop::Step);
+    assert_eq!(stepped.pc(), 1);
+    assert_eq!(stepped.executed(), 1);
+    for &backend in BACKENDS {
+        let mut run = machine(inst, registers, backend);
+        run.run().unwrap();
+        assert_eq!(run.registers(), stepped.registers(), "{backend:?}");
+    }
+    Ok(*stepped.registers())
+}
+
+// The machine state after stepping `inst`, with errors compared by variant
+// since they carry the platter as it was in memory.
+fn outcome(
+    inst: u32,
+    registers: [u32; 8],
+) -> (Result<Stop, mem::Discriminant<Error>>, [u32; 8], u32) {
+    let image: Vec<u8> = [inst, HALT].iter().flat_map(|w| w.to_be_bytes()).collect();
+    let mut machine = Machine::builder()
+        .stdin(io::empty())
+        .stdout(io::sink())
+        .build();
+    machine.extend_from(&image[..]).unwrap();
+    *machine.registers_mut() = registers;
+    let stop = machine.step().map_err(|e| mem::discriminant(&e));
+    (stop, *machine.registers(), machine.pc())
+}
+
+fn register() -> impl Strategy<Value = u32> {
+    0..8u32
+}
+
+// Checks that only register `a` changed, to `value`.
+fn assert_sets(before: [u32; 8], after: [u32; 8], a: u32, value: u32) {
+    let mut expected = before;
+    expected[a as usize] = value;
+    assert_eq!(after, expected);
+}
+
+proptest! {
+    #[test]
+    fn add_wraps(registers: [u32; 8], a in register(), b in register(), c in register()) {
+        let after = execute(standard(3, a, b, c), registers).unwrap();
+        let sum = registers[b as usize].wrapping_add(registers[c as usize]);
+        assert_sets(registers, after, a, sum);
+    }
+
+    #[test]
+    fn mul_wraps(registers: [u32; 8], a in register(), b in register(), c in register()) {
+        let after = execute(standard(4, a, b, c), registers).unwrap();
+        let product = registers[b as usize].wrapping_mul(registers[c as usize]);
+        assert_sets(registers, after, a, product);
+    }
+
+    #[test]
+    fn div_is_unsigned(
+        registers: [u32; 8],
+        a in register(),
+        b in register(),
+        c in register(),
+        zero in any::<bool>(),
+    ) {
+        let mut registers = registers;
+        if zero {
+            registers[c as usize] = 0;
+        }
+        let (dividend, divisor) = (registers[b as usize], registers[c as usize]);
+        match execute(standard(5, a, b, c), registers) {
+            Ok(after) => assert_sets(registers, after, a, dividend / divisor),
+            Err(e) => {
+                assert_eq!(divisor, 0);
+                assert!(matches!(e, Error::DivisionByZero { pc: 0, .. }), "{}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn nand_truth_table(registers: [u32; 8], a in register(), b in register(), c in register()) {
+        let after = execute(standard(6, a, b, c), registers).unwrap();
+        let (x, y) = (registers[b as usize], registers[c as usize]);
+        // Bit by bit: 0 only where both inputs are 1.
+        let nand = (0..32).fold(0, |nand, bit| {
+            let both = (x >> bit) & (y >> bit) & 1;
+            nand | (1 - both) << bit
+        });
+        assert_sets(registers, after, a, nand);
+    }
+
+    #[test]
+    fn cmov_moves_unless_c_is_zero(
+        registers: [u32; 8],
+        a in register(),
+        b in register(),
+        c in register(),
+        zero in any::<bool>(),
+    ) {
+        let mut registers = registers;
+        if zero {
+            registers[c as usize] = 0;
+        }
+        let after = execute(standard(0, a, b, c), registers).unwrap();
+        let value = if registers[c as usize] != 0 {
+            registers[b as usize]
+        } else {
+            registers[a as usize]
+        };
+        assert_sets(registers, after, a, value);
+    }
+
+    #[test]
+    fn ortho_loads_25_bits(registers: [u32; 8], a in register(), value in 0..1u32 << 25) {
+        let after = execute(Instruction::Ortho { a, value }.encode(), registers).unwrap();
+        assert_sets(registers, after, a, value);
+    }
+
+    // The interpreter and `Instruction::decode` share their field
+    // extraction; this keeps them from drifting apart if either changes.
+    #[test]
+    fn interpreter_agrees_with_decode(registers: [u32; 8], inst: u32) {
+        match Instruction::decode(inst) {
+            Ok(decoded) => {
+                assert_eq!(outcome(inst, registers), outcome(decoded.encode(), registers));
+            }
+            Err(_) => {
+                let (stop, after, _) = outcome(inst, registers);
+                assert!(stop.is_err());
+                assert_eq!(after, registers);
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// Machines through serde, from the `serde` feature.
+#![cfg(feature = "serde")]
+
+mod common;
+
+use common::{load, Shared};
+use um_32::prelude::*;
+
+#[test]
+fn serde_round_trip() {
+    // IN r1 ; ORTHO r2, 3 ; ALLOC r3, r2 ; ALLOC r4, r2 ; ABANDON r3 ;
+    // OUT r1 ; IN r1 ; OUT r1 ; HALT
+    let program = [
+        0xb000_0001,
+        0xd400_0003,
+        0x8000_001a,
+        0x8000_0022,
+        0x9000_0003,
+        0xa000_0001,
+        0xb000_0001,
+        0xa000_0001,
+        0x7000_0000,
+    ];
+    let out = Shared::default();
+    let builder = Machine::builder()
+        .input("xy")
+        .stdout(out.clone())
+        .flush_policy(FlushPolicy::Byte);
+    let mut machine = load(builder, &program);
+    for _ in 0..6 {
+        machine.step().unwrap();
+    }
+    assert_eq!(out.bytes(), b"x");
+
+    let json = serde_json::to_string(&machine).unwrap();
+    let restored: Machine = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.pc(), machine.pc());
+    assert_eq!(restored.registers(), machine.registers());
+    assert!(restored.arrays().eq(machine.arrays()));
+    assert_eq!(restored.state_hash(), machine.state_hash());
+    assert_eq!(restored.executed(), 6);
+
+    let rest = Shared::default();
+    let mut restored = Machine::builder()
+        .stdout(rest.clone())
+        .flush_policy(FlushPolicy::Byte)
+        .build_from(restored);
+    machine.run().unwrap();
+    restored.run().unwrap();
+    assert_eq!(out.bytes(), b"xy");
+    assert_eq!(rest.bytes(), b"y");
+}